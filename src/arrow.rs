@@ -0,0 +1,142 @@
+use super::{state::State, types::FixedFloat};
+use arrow_array::{
+    ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array, UInt32Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use std::sync::Arc;
+
+/// Wide enough for any `FixedFloat`, including the sum of two of them.
+pub const DECIMAL_PRECISION: u8 = 38;
+
+pub fn decimal_type() -> DataType {
+    DataType::Decimal128(DECIMAL_PRECISION, FixedFloat::SCALE as i8)
+}
+
+pub fn accounts_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", decimal_type(), false),
+        Field::new("held", decimal_type(), false),
+        Field::new("total", decimal_type(), false),
+        Field::new("locked", DataType::Boolean, false),
+    ])
+}
+
+pub fn transactions_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("client", DataType::UInt16, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("amount", decimal_type(), false),
+        Field::new("state", DataType::Utf8, false),
+    ])
+}
+
+pub fn decimal_array(values: impl Iterator<Item = FixedFloat>) -> Result<ArrayRef, ArrowError> {
+    Ok(Arc::new(
+        values
+            .map(|value| value.raw() as i128)
+            .collect::<Decimal128Array>()
+            .with_precision_and_scale(DECIMAL_PRECISION, FixedFloat::SCALE as i8)?,
+    ))
+}
+
+impl State {
+    /// Returns the accounts table (ordered by client id) as a single Arrow record batch. Amounts
+    /// are exact `Decimal128` values with four decimal places, so nothing goes through floats or
+    /// strings on the way out.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let accounts = self.sorted_accounts();
+
+        RecordBatch::try_new(
+            Arc::new(accounts_schema()),
+            vec![
+                Arc::new(
+                    accounts
+                        .iter()
+                        .map(|(client_id, _)| client_id.0)
+                        .collect::<UInt16Array>(),
+                ),
+                decimal_array(accounts.iter().map(|(_, account)| account.available()))?,
+                decimal_array(accounts.iter().map(|(_, account)| account.held()))?,
+                decimal_array(accounts.iter().map(|(_, account)| account.total()))?,
+                Arc::new(
+                    accounts
+                        .iter()
+                        .map(|(_, account)| Some(account.locked()))
+                        .collect::<BooleanArray>(),
+                ),
+            ],
+        )
+    }
+
+    /// Returns the cached (deposit and withdrawal) transactions, ordered by transaction id,
+    /// along with their dispute state.
+    pub fn transactions_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let mut txns = self.transactions().collect::<Vec<_>>();
+        txns.sort_unstable_by_key(|txn| txn.transaction_id);
+
+        RecordBatch::try_new(
+            Arc::new(transactions_schema()),
+            vec![
+                Arc::new(
+                    txns.iter()
+                        .map(|txn| txn.transaction_id.0)
+                        .collect::<UInt32Array>(),
+                ),
+                Arc::new(
+                    txns.iter()
+                        .map(|txn| txn.client_id.0)
+                        .collect::<UInt16Array>(),
+                ),
+                Arc::new(
+                    txns.iter()
+                        .map(|txn| Some(txn.inner.type_name()))
+                        .collect::<StringArray>(),
+                ),
+                decimal_array(
+                    txns.iter()
+                        .map(|txn| txn.inner.amount().unwrap_or_default()),
+                )?,
+                Arc::new(
+                    txns.iter()
+                        .map(|txn| Some(txn.state.name()))
+                        .collect::<StringArray>(),
+                ),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use arrow_array::{cast::AsArray, types::Decimal128Type};
+
+    #[test]
+    fn test_to_record_batch() {
+        let mut state = State::default();
+        for (transaction_id, client_id, amount) in [(1, 2, 1.5), (2, 1, 2.25)] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    TransactionInner::Deposit(amount.into()),
+                ))
+                .unwrap();
+        }
+
+        let batch = state.to_record_batch().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+
+        let available = batch
+            .column_by_name("available")
+            .unwrap()
+            .as_primitive::<Decimal128Type>();
+        assert_eq!(available.value_as_string(0), "2.2500");
+        assert_eq!(available.value_as_string(1), "1.5000");
+    }
+}
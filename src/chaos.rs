@@ -0,0 +1,154 @@
+//! Fault injection for exercising recovery paths. A chaos run processes the input twice: once
+//! uninterrupted, and once with I/O errors injected at random points, recovering from each one
+//! the same way an operator would. The two final states must match exactly.
+
+use super::{
+    generate::SplitMix64,
+    io::CsvFileReader,
+    state::{Options, State},
+};
+use std::{cell::Cell, error::Error, fs::File, io::Read, rc::Rc};
+
+pub struct Report {
+    pub seed: u64,
+    pub faults_injected: u32,
+    pub state: State,
+}
+
+/// Runs `input_filename` with `faults` injected I/O errors, returning the faulty run's state if
+/// it matches an uninterrupted run, and an error otherwise.
+pub fn run(
+    input_filename: &str,
+    options: &Options,
+    faults: u32,
+    seed: u64,
+) -> Result<Report, Box<dyn Error>> {
+    let mut expected = State::with_options(options.clone());
+    expected.process_all(CsvFileReader::new(input_filename)?)?;
+
+    let file_len = std::fs::metadata(input_filename)?.len();
+    let mut rng = SplitMix64(seed);
+    let mut state = State::with_options(options.clone());
+    let mut applied = 0;
+    let mut faults_injected = 0;
+
+    loop {
+        // After the last fault, run to completion
+        let tripped = Rc::new(Cell::new(false));
+        let fail_after = (faults_injected < faults).then(|| rng.next() % file_len.max(1));
+        let reader = FaultyReader {
+            inner: File::open(input_filename)?,
+            remaining: fail_after,
+            tripped: tripped.clone(),
+        };
+
+        // Recovery is by re-reading from the start and skipping what's already been applied
+        let mut result = Ok(());
+        for (index, txn) in CsvFileReader::from_reader(Box::new(reader)).enumerate() {
+            if index < applied {
+                if let Err(err) = txn {
+                    result = Err(err);
+                    break;
+                }
+                continue;
+            }
+
+            match txn {
+                Ok(txn) => {
+                    state.process(txn)?;
+                    applied += 1;
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        match result {
+            Ok(()) => break,
+            Err(_) if tripped.get() => faults_injected += 1,
+            Err(err) => return Err(err),
+        }
+    }
+
+    if state.sorted_accounts() != expected.sorted_accounts() {
+        return Err(format!(
+            "State after {} injected faults (seed {}) differs from an uninterrupted run",
+            faults_injected, seed
+        )
+        .into());
+    }
+
+    Ok(Report {
+        seed,
+        faults_injected,
+        state,
+    })
+}
+
+/// Fails with an I/O error once `remaining` bytes have been read.
+struct FaultyReader<R> {
+    inner: R,
+    remaining: Option<u64>,
+    tripped: Rc<Cell<bool>>,
+}
+
+impl<R: Read> Read for FaultyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => return self.inner.read(buf),
+        };
+
+        if remaining == 0 {
+            self.tripped.set(true);
+            return Err(std::io::Error::other("injected fault"));
+        }
+
+        let len = buf.len().min(remaining as usize);
+        let len = self.inner.read(&mut buf[..len])?;
+        self.remaining = Some(remaining - len as u64);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::{state::Options, types::ClientId};
+
+    #[test]
+    fn test_run() {
+        let path = std::env::temp_dir().join(format!("chaos-{}.csv", std::process::id()));
+        let run = |data: &str, faults| {
+            std::fs::write(&path, data).unwrap();
+            run(path.to_str().unwrap(), &Options::default(), faults, 7)
+        };
+
+        let report = run(
+            "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\ndeposit,2,3,1.0\n",
+            5,
+        )
+        .unwrap();
+        assert_eq!(report.faults_injected, 5);
+        assert_eq!(
+            report.state.account(ClientId(1)).unwrap().available(),
+            1.5.into()
+        );
+
+        // Every fault comes before the first byte of an empty file, which still ends up empty
+        let report = run("", 3).unwrap();
+        assert_eq!(report.faults_injected, 3);
+        assert!(report.state.sorted_accounts().is_empty());
+
+        // A malformed row isn't a fault to recover from, so it fails the run
+        assert!(run(
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,x,2,1.0\n",
+            5
+        )
+        .is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//! Checkpoints, so that a long run that's interrupted or crashes can carry on from where it got
+//! to rather than starting again. A checkpoint is the whole state plus how far through the input
+//! the run was, counted in transactions read. Resuming means re-reading the input and skipping
+//! that many transactions before processing the rest, which is much cheaper than applying them.
+//! So that no transaction is applied twice or skipped, the input has to be the same up to that
+//! point, and anything a run writes as it goes has to be consistent with the checkpoint too: an
+//! audit log is truncated back to its length when the checkpoint was written.
+//!
+//! The file starts with the magic bytes `OCTCKPT1`, then the number of transactions (`u64`), then
+//! the input it's for: a `u8` of 1, its length in bytes (`u32`) and the name as UTF-8, or a `u8` of
+//! 0 if there wasn't one (e.g. with a manifest). Then the length of the audit log: a `u8` of 1 and
+//! the length in bytes (`u64`), or a `u8` of 0 if there wasn't one. The rest is a state snapshot
+//! (see `State::write_snapshot`). Integers are little-endian.
+
+use super::{
+    io::{write_file_atomically, TransactionReader},
+    state::State,
+};
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"OCTCKPT1";
+
+pub struct Checkpoint {
+    /// The input as it was given, e.g. a path or `-` for stdin.
+    pub input: Option<String>,
+    /// How many transactions had been read from the input (and processed).
+    pub transactions: u64,
+    /// How long the audit log was, in bytes, if there was one.
+    pub audit_len: Option<u64>,
+    pub state: State,
+}
+
+/// Writes a checkpoint of `state` after `transactions` transactions from `input` to `path`,
+/// atomically and durably, i.e. it's on disk once this returns. `audit_len` is the length of the
+/// audit log, if there is one, which must have been flushed.
+pub fn save(
+    path: &Path,
+    input: Option<&str>,
+    transactions: u64,
+    audit_len: Option<u64>,
+    state: &State,
+) -> Result<(), Error> {
+    write_file_atomically(path, |f| {
+        f.write_all(MAGIC)?;
+        f.write_all(&transactions.to_le_bytes())?;
+        match input {
+            Some(input) => {
+                f.write_all(&[1])?;
+                f.write_all(&(input.len() as u32).to_le_bytes())?;
+                f.write_all(input.as_bytes())?;
+            }
+            None => f.write_all(&[0])?,
+        }
+        match audit_len {
+            Some(audit_len) => {
+                f.write_all(&[1])?;
+                f.write_all(&audit_len.to_le_bytes())?;
+            }
+            None => f.write_all(&[0])?,
+        }
+        state.write_snapshot(f)
+    })?;
+
+    // The rename into place isn't durable until the directory is synced too
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        File::open(dir)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Reads a checkpoint written by `save`. The state has the default options.
+pub fn load(path: &Path) -> Result<Checkpoint, Error> {
+    let mut f = BufReader::new(File::open(path)?);
+
+    let mut header = [0; 17];
+    f.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a checkpoint"));
+    }
+    let transactions = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+    let input = match header[16] {
+        0 => None,
+        _ => {
+            let mut len = [0; 4];
+            f.read_exact(&mut len)?;
+            let mut input = vec![0; u32::from_le_bytes(len) as usize];
+            f.read_exact(&mut input)?;
+            Some(String::from_utf8(input).map_err(|err| Error::new(ErrorKind::InvalidData, err))?)
+        }
+    };
+
+    let mut flag = [0];
+    f.read_exact(&mut flag)?;
+    let audit_len = match flag[0] {
+        0 => None,
+        _ => {
+            let mut len = [0; 8];
+            f.read_exact(&mut len)?;
+            Some(u64::from_le_bytes(len))
+        }
+    };
+
+    Ok(Checkpoint {
+        input,
+        transactions,
+        audit_len,
+        state: State::read_snapshot(f)?,
+    })
+}
+
+/// Skips the first `transactions` transactions of `reader`, which a checkpoint has already
+/// processed. If the input ends before then, the last item is an error rather than nothing, as it
+/// can't be the input the checkpoint was of.
+pub fn skip_processed(mut reader: TransactionReader, transactions: u64) -> TransactionReader {
+    let mut skipped = 0;
+    Box::new(std::iter::from_fn(move || {
+        while skipped < transactions {
+            match reader.next() {
+                Some(Ok(_)) => skipped += 1,
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    // Only reported once
+                    let missing = transactions - skipped;
+                    skipped = transactions;
+                    return Some(Err(format!(
+                        "The input ended {} transactions before the checkpoint, so it isn't \
+                        the input the checkpoint was of",
+                        missing
+                    )
+                    .into()));
+                }
+            }
+        }
+        reader.next()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, save, skip_processed};
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_checkpoint() {
+        let mut state = State::default();
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ))
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        save(&path, Some("input.csv"), 42, Some(1234), &state).unwrap();
+        let checkpoint = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(checkpoint.input.as_deref(), Some("input.csv"));
+        assert_eq!(checkpoint.transactions, 42);
+        assert_eq!(checkpoint.audit_len, Some(1234));
+        assert_eq!(checkpoint.state.sorted_accounts(), state.sorted_accounts());
+
+        // Skipping what was processed, which needs the input to go at least that far
+        let input = |len: u32| -> crate::io::TransactionReader {
+            Box::new((1..=len).map(|transaction_id| {
+                Ok(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    TransactionInner::Deposit(1.0.into()),
+                ))
+            }))
+        };
+        let rest = skip_processed(input(5), 3)
+            .map(|txn| txn.unwrap().transaction_id.0)
+            .collect::<Vec<_>>();
+        assert_eq!(rest, [4, 5]);
+        assert!(skip_processed(input(5), 5).next().is_none());
+        let mut short = skip_processed(input(2), 3);
+        assert!(short.next().unwrap().is_err());
+        assert!(short.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_checkpoint() {
+        let path = std::env::temp_dir().join(format!("bad-checkpoint-{}", std::process::id()));
+        save(&path, None, 7, None, &State::default()).unwrap();
+        let checkpoint = load(&path).unwrap();
+        assert_eq!(checkpoint.input, None);
+        assert_eq!(checkpoint.audit_len, None);
+        assert_eq!(checkpoint.transactions, 7);
+
+        // Cut short, e.g. by a copy that didn't finish, it fails rather than giving part of a state
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(20);
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(load(&path).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+
+        // Something else altogether
+        std::fs::write(&path, b"client,available,held,total,locked\n").unwrap();
+        let err = load(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Not a checkpoint");
+        std::fs::remove_file(&path).unwrap();
+
+        // A malformed row among those skipped still fails the run
+        let input: crate::io::TransactionReader = Box::new(
+            [
+                Err("Malformed row".into()),
+                Ok(Transaction::new(
+                    TransactionId(2),
+                    ClientId(1),
+                    TransactionInner::Deposit(1.0.into()),
+                )),
+            ]
+            .into_iter(),
+        );
+        let mut rest = skip_processed(input, 2);
+        assert_eq!(
+            rest.next().unwrap().err().unwrap().to_string(),
+            "Malformed row"
+        );
+    }
+}
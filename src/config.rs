@@ -0,0 +1,133 @@
+//! Engine settings read from a file, so that a long-running process can have them changed without
+//! a restart (see `daemon::run`). The file has one `key = value` setting per line, with `#`
+//! starting a comment:
+//!
+//! ```text
+//! # Reject rather than ignore invalid transactions
+//! strict = true
+//! monotonic_ids = warn
+//! ```
+//!
+//! The keys are `strict`, `client_monotonic_ids` and `require_client_match` (`true` or `false`),
+//! `monotonic_ids` (`unchecked`, `warn` or `strict`), `duplicate_ids` (`error`, `skip` or
+//! `idempotent`), `withdrawal_disputes` (`reject`, `treat-as-negative` or `hold-positive`),
+//! `locked_accounts` (e.g. `deposit=queue,withdrawal=reject`) and `max_amount` (an amount, or
+//! `none`), as per the command line options of the same names. Settings that aren't in the file
+//! keep the value they were given on startup, though types missing from `locked_accounts` go back
+//! to their defaults. Changing `withdrawal_disputes` while withdrawals are disputed leaves their
+//! held funds inconsistent once they're resolved or charged back. Transactions already queued on a
+//! locked account stay queued until it's unlocked, whatever `locked_accounts` is changed to.
+
+use super::state::Options;
+use std::{error::Error, path::Path};
+
+/// Reads the settings in `path` over the top of `base`.
+pub fn load(path: &Path, base: &Options) -> Result<Options, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    parse(&text, base).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// Applies the settings in `text` over the top of `base`.
+pub fn parse(text: &str, base: &Options) -> Result<Options, String> {
+    let mut options = base.clone();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| format!("Line {}: {}", index + 1, message);
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("Expected \"key = value\", not \"{}\"", line)))?;
+        let (key, value) = (key.trim(), value.trim());
+
+        match key {
+            "strict" => options.strict = parse_bool(value).map_err(error)?,
+            "monotonic_ids" => options.id_ordering = value.parse().map_err(error)?,
+            "client_monotonic_ids" => {
+                options.client_monotonic_ids = parse_bool(value).map_err(error)?
+            }
+            "duplicate_ids" => options.duplicate_ids = value.parse().map_err(error)?,
+            "require_client_match" => {
+                options.require_client_match = parse_bool(value).map_err(error)?
+            }
+            "withdrawal_disputes" => options.withdrawal_disputes = value.parse().map_err(error)?,
+            "locked_accounts" => options.locked_accounts = value.parse().map_err(error)?,
+            "max_amount" => {
+                options.max_amount = match value {
+                    "none" => None,
+                    value => Some(value.parse().map_err(error)?),
+                }
+            }
+            other => return Err(error(format!("Unrecognised setting \"{}\"", other))),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        other => Err(format!("Expected true or false, not \"{}\"", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::state::{DuplicateIds, IdOrdering, LockedAccountMode, Options, WithdrawalDisputes};
+
+    #[test]
+    fn test_parse() {
+        let base = Options {
+            strict: true,
+            ..Default::default()
+        };
+
+        let options = parse("# Comment\n\nmonotonic_ids = warn # inline\n", &base).unwrap();
+        assert_eq!(options.id_ordering, IdOrdering::Warn);
+        assert!(options.strict);
+
+        let options = parse(
+            "strict=off\nmonotonic_ids=strict\nclient_monotonic_ids=on\n\
+            duplicate_ids=idempotent\n\
+            withdrawal_disputes=hold-positive\n\
+            require_client_match=yes\nlocked_accounts = deposit=queue, transfer=allow\n\
+            max_amount = 1000000",
+            &base,
+        )
+        .unwrap();
+        assert_eq!(options.id_ordering, IdOrdering::Strict);
+        assert!(options.client_monotonic_ids);
+        assert_eq!(options.duplicate_ids, DuplicateIds::Idempotent);
+        assert!(!options.strict);
+        assert_eq!(
+            options.withdrawal_disputes,
+            WithdrawalDisputes::HoldPositive
+        );
+        assert_eq!(options.locked_accounts.deposits, LockedAccountMode::Queue);
+        assert_eq!(
+            options.locked_accounts.withdrawals,
+            LockedAccountMode::Reject
+        );
+        assert_eq!(options.locked_accounts.transfers, LockedAccountMode::Allow);
+        assert_eq!(options.max_amount, Some(1_000_000.0.into()));
+        assert_eq!(
+            parse("max_amount = none", &options).unwrap().max_amount,
+            None
+        );
+
+        assert_eq!(
+            parse("strict = true\nlimit = 5\n", &base).unwrap_err(),
+            "Line 2: Unrecognised setting \"limit\""
+        );
+        assert_eq!(
+            parse("strict = maybe", &base).unwrap_err(),
+            "Line 1: Expected true or false, not \"maybe\""
+        );
+    }
+}
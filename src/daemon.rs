@@ -0,0 +1,360 @@
+use super::{
+    config,
+    health::Health,
+    io,
+    notify::Notifier,
+    output::{self, delta::DeltaWriter, OutputOptions},
+    state::{Options, State},
+    wal::Wal,
+};
+use signal_hook::{
+    consts::{SIGHUP, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Where `run` sends each change to an account as it happens, besides the snapshots.
+pub struct Feeds<Writer: std::io::Write> {
+    /// Each change as a row of output (see `DeltaWriter`).
+    pub deltas: Option<DeltaWriter<Writer>>,
+    /// The accounts mirrored into Redis. Everything queued is written before `run` returns.
+    #[cfg(feature = "redis")]
+    pub redis: Option<output::redis::RedisMirror>,
+    /// Each changed account published to Kafka. Sent in batches, and whatever's queued is also
+    /// sent with the first transaction after each snapshot, and before `run` returns.
+    #[cfg(feature = "kafka")]
+    pub kafka: Option<output::kafka::KafkaSink>,
+    /// Works out and sends notifications (see `notify`) as each transaction is processed.
+    pub notifier: Option<Notifier>,
+    /// Each transaction's outcome recorded to the audit log (see `AuditWriter`).
+    pub audit: Option<output::audit::AuditWriter>,
+    /// Told of each transaction processed and each snapshot written, for the health probes (see
+    /// `health`). Set ready once the config has been loaded.
+    pub health: Option<Arc<Health>>,
+}
+
+// How often the config file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs the engine under a supervisor. Transactions are streamed from `reader` (typically stdin or
+/// a named pipe) while a background thread services signals:
+///
+/// - SIGTERM stops reading, as if the input had ended: whatever's been processed is sent to
+///   `feeds` and flushed, and a final snapshot is written before `run` returns. `reader` should
+///   be opened with `stop` so that it ends straight away, even while it's waiting for input
+///   (see `io::stop`); otherwise it ends after the next transaction.
+/// - SIGHUP writes a snapshot and prints a one-line summary to stderr, and reloads `config_path`.
+/// - SIGUSR1 prints the current stats to stderr.
+///
+/// A snapshot is the usual account output (as per `output_options`), atomically written to
+/// `snapshot_path`. One is also written when the input is exhausted. Each change to an account
+/// is also sent to `feeds` as it happens.
+///
+/// With `config_path`, the engine settings in that file (see `config`) are applied over the
+/// state's own options, and reloaded whenever the file changes, without losing any state. If a
+/// reload fails, the current settings are kept.
+///
+/// With `wal`, each transaction is logged before it's processed (see `wal`), and the log is
+/// compacted whenever a snapshot is written. `state` should be the one that `Wal::recover`
+/// returned.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    reader: impl io::TransactionSource,
+    stop: io::stop::Stop,
+    mut state: State,
+    snapshot_path: &Path,
+    output_options: OutputOptions,
+    mut feeds: Feeds<impl std::io::Write>,
+    config_path: Option<&Path>,
+    wal: Option<Wal>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // What the config file's settings are applied over, each time it's loaded
+    let base_options = state.options().clone();
+    if let Some(config_path) = config_path {
+        state.set_options(config::load(config_path, &base_options)?);
+    }
+    if let Some(health) = &feeds.health {
+        health.set_ready();
+    }
+
+    let state = Arc::new(Mutex::new(state));
+    // Always locked after `state`, if both are needed
+    let wal = Arc::new(Mutex::new(wal));
+    let processed = Arc::new(AtomicU64::new(0));
+    // Set whenever a snapshot is written, so that what's queued for Kafka is sent with it
+    let snapshotted = Arc::new(AtomicBool::new(false));
+
+    if let Some(config_path) = config_path {
+        let state = state.clone();
+        let config_path = PathBuf::from(config_path);
+        let base_options = base_options.clone();
+        let mut modified = modified_time(&config_path);
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(CONFIG_POLL_INTERVAL);
+            let now_modified = modified_time(&config_path);
+            if now_modified != modified {
+                modified = now_modified;
+                reload_config(&mut state.lock().unwrap(), &config_path, &base_options);
+            }
+        });
+    }
+
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGUSR1])?;
+    {
+        let state = state.clone();
+        let wal = wal.clone();
+        let config_path = config_path.map(PathBuf::from);
+        let processed = processed.clone();
+        let snapshotted = snapshotted.clone();
+        let snapshot_path = PathBuf::from(snapshot_path);
+        let started = Instant::now();
+        let health = feeds.health.clone();
+        let stop = stop.clone();
+
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                if signal == SIGTERM {
+                    // The main loop takes it from here
+                    stop.stop();
+                    break;
+                }
+
+                // Holding the lock for the whole handler means the snapshot can't interleave with
+                // a half-processed transaction
+                let mut state = state.lock().unwrap();
+                let processed = processed.load(Ordering::Relaxed);
+
+                match signal {
+                    SIGHUP => {
+                        match write_snapshot(&state, &snapshot_path, output_options) {
+                            Ok(()) => {
+                                eprintln!(
+                                    "Snapshot written to {} after {} transactions ({} accounts)",
+                                    snapshot_path.display(),
+                                    processed,
+                                    state.stats().accounts
+                                );
+                                if let Some(health) = &health {
+                                    health.snapshotted();
+                                }
+                                snapshotted.store(true, Ordering::Relaxed);
+                            }
+                            Err(err) => eprintln!("Error: Failed to write snapshot: {}", err),
+                        }
+                        if let Some(wal) = &mut *wal.lock().unwrap() {
+                            if let Err(err) = wal.compact(&state) {
+                                eprintln!("Error: Failed to compact write-ahead log: {}", err);
+                            }
+                        }
+                        if let Some(config_path) = &config_path {
+                            reload_config(&mut state, config_path, &base_options);
+                        }
+                    }
+                    SIGUSR1 => eprintln!(
+                        "processed={} uptime_secs={} {}",
+                        processed,
+                        started.elapsed().as_secs(),
+                        state.stats()
+                    ),
+                    _ => unreachable!(),
+                }
+            }
+        });
+    }
+
+    for result in reader {
+        // Whatever was read after SIGTERM may be cut short, so it isn't processed
+        if stop.is_stopped() {
+            break;
+        }
+        let txn = result?;
+        let (transaction_id, type_name, timestamp) =
+            (txn.transaction_id, txn.inner.type_name(), txn.timestamp);
+        // Held from logging through to processing, so a compaction can't come in between
+        let mut state = state.lock().unwrap();
+        if let Some(wal) = &mut *wal.lock().unwrap() {
+            wal.append(&txn)?;
+        }
+        let outcome = state.process(txn)?;
+        if let Some(audit) = &mut feeds.audit {
+            audit.record(transaction_id, type_name, timestamp, state.events())?;
+        }
+        if let Some(deltas) = &mut feeds.deltas {
+            deltas.record(&state, outcome)?;
+        }
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &mut feeds.redis {
+            redis.record(&state);
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = &mut feeds.kafka {
+            kafka.record(&state)?;
+            if snapshotted.swap(false, Ordering::Relaxed) {
+                kafka.flush()?;
+            }
+        }
+        if let Some(notifier) = &mut feeds.notifier {
+            notifier.record(&state)?;
+        }
+        drop(state);
+        processed.fetch_add(1, Ordering::Relaxed);
+        if let Some(health) = &feeds.health {
+            health.processed();
+        }
+    }
+
+    let state = state.lock().unwrap();
+    write_snapshot(&state, snapshot_path, output_options)?;
+    if let Some(wal) = &mut *wal.lock().unwrap() {
+        wal.compact(&state)?;
+    }
+    #[cfg(feature = "redis")]
+    if let Some(redis) = feeds.redis {
+        redis.finish();
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = &mut feeds.kafka {
+        kafka.flush()?;
+    }
+    if let Some(notifier) = &mut feeds.notifier {
+        notifier.finish()?;
+    }
+    if let Some(audit) = &mut feeds.audit {
+        audit.flush()?;
+    }
+
+    Ok(())
+}
+
+fn reload_config(state: &mut State, config_path: &Path, base_options: &Options) {
+    match config::load(config_path, base_options) {
+        Ok(options) => {
+            state.set_options(options);
+            eprintln!("Reloaded {}", config_path.display());
+        }
+        Err(err) => eprintln!(
+            "Error: Failed to reload config, keeping the current settings: {}",
+            err
+        ),
+    }
+}
+
+// `None` if the file can't be read, e.g. because it's part way through being replaced
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn write_snapshot(
+    state: &State,
+    snapshot_path: &Path,
+    output_options: OutputOptions,
+) -> Result<(), std::io::Error> {
+    io::write_file_atomically(snapshot_path, |f| {
+        output::write_accounts(state, output_options, f)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Feeds};
+    use crate::{
+        io::stop::Stop,
+        output::{audit::AuditWriter, OutputOptions},
+        state::{Options, State},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+        wal::Wal,
+    };
+
+    #[test]
+    fn test_stop() {
+        let dir = std::env::temp_dir().join(format!("daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (snapshot_path, wal_path, audit_path) = (
+            dir.join("accounts.csv"),
+            dir.join("wal"),
+            dir.join("audit.csv"),
+        );
+
+        let stop = Stop::default();
+        let txns = [
+            TransactionInner::Deposit(2.0.into()),
+            TransactionInner::Withdrawal(0.5.into()),
+            // Read after the stop, so never processed
+            TransactionInner::Deposit(4.0.into()),
+        ];
+        let reader = txns.clone().into_iter().enumerate().map({
+            let stop = stop.clone();
+            move |(i, inner)| {
+                if i == 2 {
+                    stop.stop();
+                }
+                Ok(Transaction::new(
+                    TransactionId(i as u32 + 1),
+                    ClientId(1),
+                    inner,
+                ))
+            }
+        });
+
+        let (wal, state) = Wal::recover(&wal_path, Options::default()).unwrap();
+        run(
+            reader,
+            stop,
+            state,
+            &snapshot_path,
+            OutputOptions::default(),
+            Feeds::<std::io::Sink> {
+                deltas: None,
+                #[cfg(feature = "redis")]
+                redis: None,
+                #[cfg(feature = "kafka")]
+                kafka: None,
+                notifier: None,
+                audit: Some(AuditWriter::open(&audit_path).unwrap()),
+                health: None,
+            },
+            None,
+            Some(wal),
+        )
+        .unwrap();
+
+        // The final snapshot
+        assert_eq!(
+            std::fs::read_to_string(&snapshot_path).unwrap(),
+            "client,available,held,total,locked\n1,1.5,0,1.5,false\n"
+        );
+        // The log compacted into its checkpoint, leaving just its header
+        assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 16);
+        let (_, recovered) = Wal::recover(&wal_path, Options::default()).unwrap();
+        let mut expected = State::default();
+        for (i, inner) in txns.into_iter().take(2).enumerate() {
+            expected
+                .process(Transaction::new(
+                    TransactionId(i as u32 + 1),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+        assert_eq!(recovered.sorted_accounts(), expected.sorted_accounts());
+        // And everything processed in the audit log
+        assert_eq!(
+            std::fs::read_to_string(&audit_path).unwrap(),
+            "tx,client,field,delta,reason,timestamp\n\
+            1,1,available,2,deposit,\n\
+            2,1,available,-0.5,withdrawal,\n"
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
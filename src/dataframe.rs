@@ -0,0 +1,190 @@
+use super::{
+    state::State,
+    types::{FixedFloat, Transaction, TransactionFields},
+};
+use polars::prelude::*;
+use std::{convert::TryFrom, error::Error};
+
+/// Wide enough for any `FixedFloat`, including the sum of two of them.
+const DECIMAL_PRECISION: usize = 38;
+
+fn decimal_column(name: &str, values: impl Iterator<Item = FixedFloat>) -> Column {
+    Int128Chunked::from_vec(name.into(), values.map(|v| v.raw() as i128).collect())
+        .into_decimal_unchecked(Some(DECIMAL_PRECISION), FixedFloat::SCALE as usize)
+        .into_series()
+        .into_column()
+}
+
+impl State {
+    /// Returns the accounts table, ordered by client id, with amounts as exact decimals.
+    pub fn accounts_dataframe(&self) -> PolarsResult<DataFrame> {
+        let accounts = self.sorted_accounts();
+
+        DataFrame::new(vec![
+            Column::new(
+                "client".into(),
+                accounts
+                    .iter()
+                    .map(|(client_id, _)| client_id.0)
+                    .collect::<Vec<_>>(),
+            ),
+            decimal_column(
+                "available",
+                accounts.iter().map(|(_, account)| account.available()),
+            ),
+            decimal_column("held", accounts.iter().map(|(_, account)| account.held())),
+            decimal_column("total", accounts.iter().map(|(_, account)| account.total())),
+            Column::new(
+                "locked".into(),
+                accounts
+                    .iter()
+                    .map(|(_, account)| account.locked())
+                    .collect::<Vec<_>>(),
+            ),
+        ])
+    }
+
+    /// Returns the cached (deposit and withdrawal) transactions, ordered by transaction id,
+    /// along with their dispute state.
+    pub fn transactions_dataframe(&self) -> PolarsResult<DataFrame> {
+        let mut txns = self.transactions().collect::<Vec<_>>();
+        txns.sort_unstable_by_key(|txn| txn.transaction_id);
+
+        DataFrame::new(vec![
+            Column::new(
+                "tx".into(),
+                txns.iter()
+                    .map(|txn| txn.transaction_id.0)
+                    .collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "client".into(),
+                txns.iter().map(|txn| txn.client_id.0).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "type".into(),
+                txns.iter()
+                    .map(|txn| txn.inner.type_name())
+                    .collect::<Vec<_>>(),
+            ),
+            decimal_column(
+                "amount",
+                txns.iter()
+                    .map(|txn| txn.inner.amount().unwrap_or_default()),
+            ),
+            Column::new(
+                "state".into(),
+                txns.iter().map(|txn| txn.state.name()).collect::<Vec<_>>(),
+            ),
+        ])
+    }
+}
+
+/// Converts a DataFrame with the same `type`, `client`, `tx`, `amount` and (optional) `to`,
+/// `reference`, `reason` and `timestamp` columns as the CSV input into transactions, ready to be passed to
+/// `State::process` in row order. Numeric columns may have any type that casts losslessly; `amount`
+/// may be a float or a decimal.
+pub fn transactions_from_dataframe(df: &DataFrame) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let types = df.column("type")?.str()?;
+    let client_ids = df.column("client")?.strict_cast(&DataType::UInt16)?;
+    let transaction_ids = df.column("tx")?.strict_cast(&DataType::UInt32)?;
+    let amounts = df.column("amount")?.strict_cast(&DataType::Float64)?;
+    let tos = match df.column("to") {
+        Ok(tos) => Some(tos.strict_cast(&DataType::UInt16)?),
+        Err(_) => None,
+    };
+    let tos = tos.as_ref().map(|tos| tos.u16()).transpose()?;
+    let references = match df.column("reference") {
+        Ok(references) => Some(references.str()?),
+        Err(_) => None,
+    };
+    let reasons = match df.column("reason") {
+        Ok(reasons) => Some(reasons.str()?),
+        Err(_) => None,
+    };
+    let timestamps = match df.column("timestamp") {
+        Ok(timestamps) => Some(timestamps.strict_cast(&DataType::UInt64)?),
+        Err(_) => None,
+    };
+    let timestamps = timestamps
+        .as_ref()
+        .map(|timestamps| timestamps.u64())
+        .transpose()?;
+
+    types
+        .into_iter()
+        .zip(client_ids.u16()?)
+        .zip(transaction_ids.u32()?)
+        .zip(amounts.f64()?)
+        .enumerate()
+        .map(|(row, (((type_, client_id), transaction_id), amount))| {
+            let fields = TransactionFields {
+                type_: type_
+                    .ok_or_else(|| format!("Row {}: \"type\" is null", row))?
+                    .into(),
+                client_id: client_id.ok_or_else(|| format!("Row {}: \"client\" is null", row))?,
+                transaction_id: transaction_id
+                    .ok_or_else(|| format!("Row {}: \"tx\" is null", row))?,
+                amount: amount.map(|amount| amount.to_string()),
+                to: tos.and_then(|tos| tos.get(row)),
+                reference: references
+                    .and_then(|references| references.get(row))
+                    .map(String::from),
+                reason: reasons
+                    .and_then(|reasons| reasons.get(row))
+                    .map(String::from),
+                timestamp: timestamps.and_then(|timestamps| timestamps.get(row)),
+            };
+            Ok(Transaction::try_from(fields)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transactions_from_dataframe;
+    use crate::state::State;
+    use polars::prelude::*;
+
+    #[test]
+    fn test_round_trip() {
+        let df = df!(
+            "type" => ["deposit", "deposit", "withdrawal", "dispute"],
+            "client" => [1i64, 2, 1, 2],
+            "tx" => [1i64, 2, 3, 2],
+            "amount" => [Some(2.5), Some(1.0), Some(0.5), None],
+        )
+        .unwrap();
+
+        let mut state = State::default();
+        for txn in transactions_from_dataframe(&df).unwrap() {
+            state.process(txn).unwrap();
+        }
+
+        let accounts = state.accounts_dataframe().unwrap();
+        assert_eq!(
+            accounts
+                .column("available")
+                .unwrap()
+                .cast(&DataType::Float64)
+                .unwrap()
+                .f64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            vec![2.0, 0.0]
+        );
+
+        let transactions = state.transactions_dataframe().unwrap();
+        assert_eq!(
+            transactions
+                .column("state")
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            vec!["alive", "disputed", "alive"]
+        );
+    }
+}
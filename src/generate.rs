@@ -0,0 +1,109 @@
+//! Synthetic input for load testing and demos. The transactions are random but plausible: mostly
+//! deposits and withdrawals, with disputes of earlier ones and the occasional resolution or
+//! chargeback. The same seed always gives the same output.
+
+use crate::types::FixedFloat;
+use std::io::Write;
+
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Client ids are drawn from `1..=clients`.
+    pub clients: u16,
+    /// The number of rows to write.
+    pub transactions: u64,
+    pub seed: u64,
+}
+
+/// Writes `options.transactions` random transactions to `f` as CSV, in the usual input format.
+pub fn generate<Writer: Write>(options: &GenerateOptions, mut f: Writer) -> std::io::Result<()> {
+    assert!(options.clients > 0, "There must be at least one client");
+
+    let mut rng = SplitMix64(options.seed);
+    // The client of each deposit/withdrawal so far, indexed by transaction id - 1
+    let mut clients = Vec::<u16>::new();
+    // Transaction ids that have been disputed, but not yet resolved or charged back
+    let mut disputed = Vec::<u32>::new();
+
+    writeln!(f, "type,client,tx,amount")?;
+
+    for _ in 0..options.transactions {
+        let roll = rng.next() % 100;
+
+        if roll >= 85 && !clients.is_empty() {
+            if roll < 93 || disputed.is_empty() {
+                let index = (rng.next() % clients.len() as u64) as usize;
+                disputed.push(index as u32 + 1);
+                writeln!(f, "dispute,{},{},", clients[index], index + 1)?;
+            } else {
+                let transaction_id =
+                    disputed.swap_remove((rng.next() % disputed.len() as u64) as usize);
+                let type_ = if roll < 98 { "resolve" } else { "chargeback" };
+                writeln!(
+                    f,
+                    "{},{},{},",
+                    type_,
+                    clients[transaction_id as usize - 1],
+                    transaction_id
+                )?;
+            }
+            continue;
+        }
+
+        let client_id = (rng.next() % options.clients as u64) as u16 + 1;
+        // Up to 1000, in whole ten-thousandths
+        let amount = FixedFloat::from_raw((rng.next() % 10_000_000) as i64);
+        let type_ = if roll < 60 { "deposit" } else { "withdrawal" };
+
+        clients.push(client_id);
+        writeln!(f, "{},{},{},{}", type_, client_id, clients.len(), amount)?;
+    }
+
+    Ok(())
+}
+
+// Good enough randomness without pulling in a dependency
+pub(crate) struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, GenerateOptions};
+    use crate::{io::CsvFileReader, state::State};
+
+    #[test]
+    fn test_generate() {
+        let options = GenerateOptions {
+            clients: 5,
+            transactions: 1000,
+            seed: 42,
+        };
+        let mut output = Vec::new();
+        generate(&options, &mut output).unwrap();
+
+        let mut again = Vec::new();
+        generate(&options, &mut again).unwrap();
+        assert_eq!(output, again);
+
+        // Every row is a valid transaction, even in strict mode
+        let mut state = State::with_options(crate::state::Options {
+            strict: true,
+            ..Default::default()
+        });
+        let mut rows = 0;
+        for txn in CsvFileReader::from_reader(Box::new(std::io::Cursor::new(output))) {
+            state.process(txn.unwrap()).unwrap();
+            rows += 1;
+        }
+        assert_eq!(rows, 1000);
+        assert!(state.stats().accounts <= 5);
+    }
+}
@@ -0,0 +1,193 @@
+//! Health probes for the long-running modes (`serve` and `--daemon`), for orchestrators such as
+//! Kubernetes. `GET /healthz` succeeds as long as the process is up, and `GET /readyz` only once
+//! the state has been restored (from a snapshot, or by replaying a write-ahead log) and
+//! transactions are being taken. Both return the same JSON body, e.g.
+//!
+//! ```text
+//! {"status":"ready","uptime_secs":120.5,"processed":1500,"last_transaction_secs_ago":0.2,"since_snapshot":300}
+//! ```
+//!
+//! `last_transaction_secs_ago` is `null` until a transaction has been processed, and
+//! `since_snapshot` counts the transactions that aren't in a snapshot yet, i.e. that would have
+//! to be processed again after a crash without a write-ahead log.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// A probe that takes longer than this to send its request is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_BYTES: u64 = 8192;
+
+/// What the probes report, updated by whatever is processing transactions.
+pub struct Health {
+    started: Instant,
+    ready: AtomicBool,
+    processed: AtomicU64,
+    processed_at_snapshot: AtomicU64,
+    // Microseconds after `started` that the last transaction was processed, plus one, or zero
+    last_processed: AtomicU64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            ready: AtomicBool::new(false),
+            processed: AtomicU64::new(0),
+            processed_at_snapshot: AtomicU64::new(0),
+            last_processed: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Health {
+    /// Marks the state as restored, and transactions as being taken.
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Counts a transaction as processed.
+    pub fn processed(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        let micros = self.started.elapsed().as_micros() as u64;
+        self.last_processed.store(micros + 1, Ordering::Relaxed);
+    }
+
+    /// How many transactions have been processed.
+    pub fn processed_count(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    /// Notes that a snapshot has been written with everything processed so far.
+    pub fn snapshotted(&self) {
+        self.processed_at_snapshot
+            .store(self.processed_count(), Ordering::Relaxed);
+    }
+
+    fn report(&self) -> String {
+        let uptime = self.started.elapsed();
+        let last_processed = match self.last_processed.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(
+                uptime
+                    .saturating_sub(Duration::from_micros(micros - 1))
+                    .as_secs_f64(),
+            ),
+        };
+        let processed = self.processed_count();
+        let report = Report {
+            status: if self.is_ready() { "ready" } else { "starting" },
+            uptime_secs: uptime.as_secs_f64(),
+            processed,
+            last_transaction_secs_ago: last_processed,
+            since_snapshot: processed
+                .saturating_sub(self.processed_at_snapshot.load(Ordering::Relaxed)),
+        };
+        serde_json::to_string(&report).expect("A Report always serializes")
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Report {
+    status: &'static str,
+    uptime_secs: f64,
+    processed: u64,
+    last_transaction_secs_ago: Option<f64>,
+    since_snapshot: u64,
+}
+
+/// Answers probes on `listener`, on a thread of its own, for as long as the process runs.
+pub fn serve(health: Arc<Health>, listener: TcpListener) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(&health, stream));
+            if let Err(err) = result {
+                tracing::debug!(error = %err, "Health probe failed");
+            }
+        }
+    });
+}
+
+fn respond(health: &Health, stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are read too, though not needed, so that closing the connection doesn't reset it
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let (status, body) = route(health, &request_line);
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}\n",
+        status,
+        body.len() + 1,
+        body
+    )?;
+    stream.flush()
+}
+
+// The status line and body of the response to the request starting with `request_line`
+fn route(health: &Health, request_line: &str) -> (&'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    // Query strings are ignored, e.g. `/readyz?verbose`
+    let path = path.map(|path| path.split('?').next().unwrap_or_default());
+    match (method, path) {
+        (Some("GET"), Some("/healthz")) => ("200 OK", health.report()),
+        (Some("GET"), Some("/readyz")) if health.is_ready() => ("200 OK", health.report()),
+        (Some("GET"), Some("/readyz")) => ("503 Service Unavailable", health.report()),
+        _ => ("404 Not Found", r#"{"error":"not_found"}"#.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{route, Health};
+
+    #[test]
+    fn test_route() {
+        let health = Health::default();
+        let get = |path: &str| route(&health, &format!("GET {} HTTP/1.1\r\n", path));
+
+        assert_eq!(get("/healthz").0, "200 OK");
+        let (status, body) = get("/readyz");
+        assert_eq!(status, "503 Service Unavailable");
+        assert!(body.starts_with(r#"{"status":"starting","uptime_secs":"#));
+        assert!(body
+            .ends_with(r#","processed":0,"last_transaction_secs_ago":null,"since_snapshot":0}"#));
+
+        health.set_ready();
+        for _ in 0..3 {
+            health.processed();
+        }
+        health.snapshotted();
+        health.processed();
+        let (status, body) = get("/readyz?verbose");
+        assert_eq!(status, "200 OK");
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["processed"], 4);
+        assert_eq!(body["since_snapshot"], 1);
+        assert!(body["last_transaction_secs_ago"].as_f64().unwrap() < 1.0);
+
+        assert_eq!(get("/metrics").0, "404 Not Found");
+        assert_eq!(route(&health, "").0, "404 Not Found");
+    }
+}
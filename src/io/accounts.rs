@@ -0,0 +1,94 @@
+//! Reading back accounts that a previous run wrote, for carrying on from them or comparing them.
+
+use crate::{
+    state::{AccountState, State},
+    types::ClientId,
+};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// A previous run's state: either a whole one saved with `State::save_snapshot`, or one built
+/// from its accounts as CSV output, which has no transaction cache and so nothing that can be
+/// disputed. The two are told apart by the snapshot's magic bytes.
+pub fn load(path: &Path) -> Result<State, Box<dyn Error>> {
+    let mut f = BufReader::new(File::open(path)?);
+    if f.fill_buf()?.starts_with(b"OCTSTATE") {
+        return Ok(State::read_snapshot(f)?);
+    }
+    Ok(State::from_accounts(read_csv(f)?))
+}
+
+/// Reads accounts as written by `output::write_csv`, at any precision. Each row's total must be
+/// its available plus held amounts, which catches most files that aren't accounts at all.
+pub fn read_csv(f: impl Read) -> Result<Vec<(ClientId, AccountState)>, Box<dyn Error>> {
+    #[derive(serde::Deserialize)]
+    struct AccountFields {
+        client: u16,
+        available: f64,
+        held: f64,
+        total: f64,
+        locked: bool,
+        // Only there if any account is closed
+        #[serde(default)]
+        closed: bool,
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(f);
+    let mut accounts = Vec::new();
+    for (index, result) in reader.deserialize::<AccountFields>().enumerate() {
+        // Counting the header
+        let line = index + 2;
+        let fields = result.map_err(|err| format!("Line {}: {}", line, err))?;
+
+        let account = AccountState::new(fields.available.into(), fields.held.into(), fields.locked)
+            .with_closed(fields.closed);
+        if account.total() != fields.total.into() {
+            return Err(format!(
+                "Line {}: Total {} isn't available plus held",
+                line, fields.total
+            )
+            .into());
+        }
+        accounts.push((ClientId(fields.client), account));
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_csv;
+    use crate::{state::AccountState, types::ClientId};
+
+    #[test]
+    fn test_read_csv() {
+        let accounts = read_csv(
+            "client,available,held,total,locked\n1,1.5,0.2500,1.75,false\n2, 0, 0, 0, true\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            accounts,
+            [
+                (
+                    ClientId(1),
+                    AccountState::new(1.5.into(), 0.25.into(), false)
+                ),
+                (ClientId(2), AccountState::new(0.0.into(), 0.0.into(), true)),
+            ]
+        );
+
+        assert_eq!(
+            read_csv("client,available,held,total,locked\n1,1,1,3,false\n".as_bytes())
+                .unwrap_err()
+                .to_string(),
+            "Line 2: Total 3 isn't available plus held"
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use super::{RecordError, TransactionResult};
+use crate::types::{Transaction, TransactionFields};
+use apache_avro::Reader;
+
+/// Reads an Avro Object Container File whose records have the same `type`, `client`, `tx` and
+/// (optional) `amount` fields as the CSV columns. The writer's schema comes from the file header;
+/// integer fields may be `int` or `long`, and `amount` may be a `double` or a union with `null`.
+pub struct AvroReader {
+    record_iter: Reader<'static, Box<dyn std::io::Read>>,
+    // How many records have been read
+    row: u64,
+}
+
+impl AvroReader {
+    pub fn from_reader(reader: Box<dyn std::io::Read>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            record_iter: Reader::new(reader)?,
+            row: 0,
+        })
+    }
+}
+
+impl Iterator for AvroReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.record_iter.next().map(|result| {
+            let value = result?;
+            self.row += 1;
+            apache_avro::from_value::<TransactionFields>(&value)
+                .map_err(Box::from)
+                .and_then(|fields| Ok(Transaction::try_from(fields)?))
+                .map_err(|e| RecordError::row(self.row, e).into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AvroReader;
+    use crate::types::{TransactionId, TransactionInner};
+    use apache_avro::{types::Record, Schema, Writer};
+
+    #[test]
+    fn test_avro_reader() {
+        let schema = Schema::parse_str(
+            r#"{
+                "type": "record",
+                "name": "Transaction",
+                "fields": [
+                    {"name": "type", "type": "string"},
+                    {"name": "client", "type": "int"},
+                    {"name": "tx", "type": "long"},
+                    {"name": "amount", "type": ["null", "double"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut writer = Writer::new(&schema, Vec::new());
+        for (type_, tx, amount) in [("deposit", 1, Some(1.5)), ("dispute", 1, None)] {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("type", type_);
+            record.put("client", 7);
+            record.put("tx", tx as i64);
+            record.put("amount", amount);
+            writer.append(record).unwrap();
+        }
+        let data = writer.into_inner().unwrap();
+
+        let txns = AvroReader::from_reader(Box::new(std::io::Cursor::new(data)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].transaction_id, TransactionId(1));
+        assert!(matches!(txns[0].inner, TransactionInner::Deposit(amount) if amount == 1.5.into()));
+        assert!(matches!(txns[1].inner, TransactionInner::Dispute));
+    }
+}
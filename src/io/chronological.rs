@@ -0,0 +1,70 @@
+use super::{TransactionResult, TransactionSource};
+
+/// Passes transactions through from `inner`, checking that their timestamps never go backwards,
+/// since a row from before one already read means the input isn't in the order the transactions
+/// happened in, and time-based rules applied to it would go wrong. A row with an earlier timestamp
+/// than one before it is an error in its place. Rows without a timestamp aren't checked, and
+/// several rows may have the same one.
+pub struct Chronological<I> {
+    inner: I,
+    // The latest timestamp read so far
+    latest: Option<u64>,
+}
+
+impl<I> Chronological<I> {
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            latest: None,
+        }
+    }
+}
+
+impl<I: TransactionSource> Iterator for Chronological<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let txn = match self.inner.next()? {
+            Ok(txn) => txn,
+            Err(e) => return Some(Err(e)),
+        };
+        match (txn.timestamp, self.latest) {
+            (Some(timestamp), Some(latest)) if timestamp < latest => Some(Err(format!(
+                "Transaction {} has timestamp {}, before the {} of an earlier row",
+                txn.transaction_id, timestamp, latest
+            )
+            .into())),
+            (Some(timestamp), _) => {
+                self.latest = Some(timestamp);
+                Some(Ok(txn))
+            }
+            (None, _) => Some(Ok(txn)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chronological;
+    use crate::io::CsvFileReader;
+
+    #[test]
+    fn test_chronological() {
+        let data = b"type,client,tx,amount,timestamp\ndeposit,1,1,1.0,100\ndeposit,1,2,1.0,\n\
+            deposit,1,3,1.0,100\ndeposit,1,4,1.0,99\ndeposit,1,5,1.0,101\n";
+
+        let results = Chronological::new(CsvFileReader::from_reader(Box::new(&data[..])))
+            .map(|result| result.map(|txn| (txn.transaction_id.0, txn.timestamp)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].as_ref().unwrap(), &(1, Some(100)));
+        assert_eq!(results[1].as_ref().unwrap(), &(2, None));
+        assert_eq!(results[2].as_ref().unwrap(), &(3, Some(100)));
+        assert_eq!(
+            results[3].as_ref().unwrap_err().to_string(),
+            "Transaction 4 has timestamp 99, before the 100 of an earlier row"
+        );
+        assert_eq!(results[4].as_ref().unwrap(), &(5, Some(101)));
+    }
+}
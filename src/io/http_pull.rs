@@ -0,0 +1,367 @@
+//! Pulls transactions from a partner's paginated REST API, rather than from a file that something
+//! else has downloaded. The API is described by a file with one `key = value` setting per line,
+//! with `#` at the start of a line or after a space starting a comment:
+//!
+//! ```text
+//! url = https://partner.example.com/v1/transactions
+//! header = Authorization: Bearer ${PARTNER_TOKEN}
+//! query = limit=500
+//! items = data
+//! next_cursor = meta.next
+//! cursor_param = after
+//! poll_interval = 30
+//! ```
+//!
+//! - `url`: what to GET. Required.
+//! - `header`: a header to send with every request, as `Name: value`. Can be repeated.
+//! - `query`: a query parameter to send with every request, as `name=value`. Can be repeated.
+//! - `items`: where the transactions are in the response, as a dotted path (`data.items`, or
+//!   `results.0` for an array element). Without it, the response is the array itself. Each
+//!   transaction is an object as in the JSON Lines input.
+//! - `next_cursor`: where the cursor for the next page is in the response. Without it, there's
+//!   only ever the one page.
+//! - `cursor_param`: the query parameter the cursor is passed in; `cursor` by default.
+//! - `cursor`: the cursor to start from, if not from the beginning.
+//! - `poll_interval`: once caught up, wait this many seconds and ask again, forever, rather than
+//!   ending. Failed requests are then retried after the same wait, rather than ending the input.
+//! - `timeout`: how many seconds a request can take; 30 by default.
+//!
+//! `${NAME}` anywhere in a value is replaced by the environment variable `NAME`, so that secrets
+//! needn't be written in the file.
+//!
+//! The input is caught up once a page has no transactions, or no next cursor. The cursor only
+//! moves on when a response gives one, so an API that leaves it out of the last page will have
+//! that page fetched again by the next poll, and its transactions ignored as duplicates.
+
+use super::TransactionResult;
+use crate::types::{Transaction, TransactionFields};
+use serde_json::Value;
+use std::{error::Error, path::Path, time::Duration};
+
+/// The API to pull from, as read from its settings file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpSource {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub items: Option<String>,
+    pub next_cursor: Option<String>,
+    pub cursor_param: String,
+    pub cursor: Option<String>,
+    pub poll_interval: Option<Duration>,
+    pub timeout: Duration,
+}
+
+impl HttpSource {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut url = None;
+        let mut source = Self {
+            url: String::new(),
+            headers: Vec::new(),
+            query: Vec::new(),
+            items: None,
+            next_cursor: None,
+            cursor_param: "cursor".into(),
+            cursor: None,
+            poll_interval: None,
+            timeout: Duration::from_secs(30),
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("Line {}: {}", index + 1, message);
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("Expected \"key = value\", not \"{}\"", line)))?;
+            let (key, value) = (key.trim(), expand(value.trim()).map_err(error)?);
+
+            match key {
+                "url" => url = Some(value),
+                "header" => source.headers.push(split_pair(&value, ':').ok_or_else(|| {
+                    error(format!("Expected \"Name: value\", not \"{}\"", value))
+                })?),
+                "query" => {
+                    source.query.push(split_pair(&value, '=').ok_or_else(|| {
+                        error(format!("Expected \"name=value\", not \"{}\"", value))
+                    })?)
+                }
+                "items" => source.items = Some(value),
+                "next_cursor" => source.next_cursor = Some(value),
+                "cursor_param" => source.cursor_param = value,
+                "cursor" => source.cursor = Some(value),
+                "poll_interval" => {
+                    source.poll_interval = Some(parse_seconds(&value).map_err(error)?)
+                }
+                "timeout" => source.timeout = parse_seconds(&value).map_err(error)?,
+                other => return Err(error(format!("Unrecognised setting \"{}\"", other))),
+            }
+        }
+
+        source.url = url.ok_or("No url")?;
+        Ok(source)
+    }
+}
+
+// A `#` only starts a comment at the start of a line or after whitespace, since URLs and tokens
+// can have them too
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..index];
+        }
+        previous = c;
+    }
+    line
+}
+
+fn split_pair(value: &str, separator: char) -> Option<(String, String)> {
+    let (name, value) = value.split_once(separator)?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| (name.to_owned(), value.trim().to_owned()))
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("Expected a number of seconds, not \"{}\"", value))
+}
+
+// Replaces each `${NAME}` with the environment variable `NAME`
+fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed \"${{\" in \"{}\"", value))?;
+        let name = &rest[start + 2..start + end];
+        let variable =
+            std::env::var(name).map_err(|_| format!("Environment variable {} isn't set", name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&variable);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// Fetches the page at a cursor (or the first page, for `None`)
+type Fetch = Box<dyn FnMut(&HttpSource, Option<&str>) -> Result<Value, Box<dyn Error>>>;
+
+/// Reads the transactions from an `HttpSource`, a page at a time.
+pub struct HttpPullReader {
+    source: HttpSource,
+    fetch: Fetch,
+    cursor: Option<String>,
+    page: std::vec::IntoIter<Value>,
+    // Whether the last page fetched was the last there is, for now
+    caught_up: bool,
+    // Set once a request has failed without a poll interval to retry it after
+    failed: bool,
+}
+
+/// Reads the transactions from the API described in the settings file at `path`.
+pub fn open(path: &Path) -> Result<HttpPullReader, Box<dyn Error>> {
+    let source = HttpSource::load(path)?;
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(source.timeout))
+        .build()
+        .into();
+    Ok(HttpPullReader::new(
+        source,
+        Box::new(move |source, cursor| {
+            let mut request = agent.get(&source.url);
+            for (name, value) in &source.headers {
+                request = request.header(name, value);
+            }
+            for (name, value) in &source.query {
+                request = request.query(name, value);
+            }
+            if let Some(cursor) = cursor {
+                request = request.query(&source.cursor_param, cursor);
+            }
+            let response = request
+                .call()
+                .map_err(|err| format!("GET {} failed: {}", source.url, err))?
+                .body_mut()
+                .read_json::<Value>()
+                .map_err(|err| format!("GET {} returned invalid JSON: {}", source.url, err))?;
+            Ok(response)
+        }),
+    ))
+}
+
+impl HttpPullReader {
+    fn new(source: HttpSource, fetch: Fetch) -> Self {
+        Self {
+            cursor: source.cursor.clone(),
+            source,
+            fetch,
+            page: Vec::new().into_iter(),
+            caught_up: false,
+            failed: false,
+        }
+    }
+
+    fn fetch_page(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut response = (self.fetch)(&self.source, self.cursor.as_deref())?;
+
+        let items = match &self.source.items {
+            Some(path) => response
+                .pointer_mut(&pointer(path))
+                .map(Value::take)
+                .ok_or_else(|| format!("The response has no \"{}\"", path))?,
+            None => response.take(),
+        };
+        let Value::Array(items) = items else {
+            return Err("The transactions in the response aren't an array".into());
+        };
+
+        let next_cursor = match &self.source.next_cursor {
+            Some(path) => match response.pointer(&pointer(path)) {
+                Some(Value::String(cursor)) => Some(cursor.clone()),
+                Some(Value::Number(cursor)) => Some(cursor.to_string()),
+                _ => None,
+            },
+            None => None,
+        };
+
+        tracing::debug!(
+            transactions = items.len(),
+            cursor = self.cursor.as_deref(),
+            "Fetched a page"
+        );
+        self.caught_up = items.is_empty() || next_cursor.is_none();
+        if next_cursor.is_some() {
+            self.cursor = next_cursor;
+        }
+        self.page = items.into_iter();
+        Ok(())
+    }
+}
+
+// The JSON pointer for a dotted path
+fn pointer(path: &str) -> String {
+    path.split('.').map(|key| format!("/{}", key)).collect()
+}
+
+impl Iterator for HttpPullReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.page.next() {
+                return Some(
+                    serde_json::from_value::<TransactionFields>(item)
+                        .map_err(|err| err.into())
+                        .and_then(|fields| Transaction::try_from(fields).map_err(|err| err.into())),
+                );
+            }
+            if self.failed {
+                return None;
+            }
+            if self.caught_up {
+                std::thread::sleep(self.source.poll_interval?);
+            }
+
+            if let Err(err) = self.fetch_page() {
+                if self.source.poll_interval.is_none() {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+                tracing::warn!(error = %err, "Failed to fetch transactions; will retry");
+                self.caught_up = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpPullReader, HttpSource};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reader() {
+        std::env::set_var("OCT_TEST_HTTP_PULL_TOKEN", "s3cr#t");
+        let source = HttpSource::parse(
+            "# The partner's API\n\
+             url = https://example.com/txns?a=1#frag\n\
+             header = Authorization: Bearer ${OCT_TEST_HTTP_PULL_TOKEN}  # from the environment\n\
+             query = limit=2\n\
+             items = data\n\
+             next_cursor = meta.next\n",
+        )
+        .unwrap();
+        assert_eq!(source.url, "https://example.com/txns?a=1#frag");
+        assert_eq!(
+            source.headers,
+            [("Authorization".into(), "Bearer s3cr#t".into())]
+        );
+        assert_eq!(source.query, [("limit".into(), "2".into())]);
+        assert_eq!(source.cursor_param, "cursor");
+        assert_eq!(source.timeout, Duration::from_secs(30));
+        assert_eq!(
+            HttpSource::parse("url = x\npoll_interval = soon").unwrap_err(),
+            "Line 2: Expected a number of seconds, not \"soon\""
+        );
+        assert_eq!(HttpSource::parse("items = data").unwrap_err(), "No url");
+
+        // Two pages, the second of which says there are no more
+        let mut cursors = Vec::new();
+        let pages = vec![
+            json!({
+                "data": [
+                    {"type": "deposit", "client": 1, "tx": 1, "amount": 2.5},
+                    {"type": "withdrawal", "client": 1, "tx": 2, "amount": 1.0},
+                ],
+                "meta": {"next": "abc"},
+            }),
+            json!({
+                "data": [{"type": "dispute", "client": 1, "tx": 1}],
+                "meta": {"next": null},
+            }),
+        ];
+        let mut pages = pages.into_iter();
+        let reader = HttpPullReader::new(
+            source,
+            Box::new(move |_, cursor| {
+                cursors.push(cursor.map(str::to_owned));
+                assert_eq!(
+                    cursors,
+                    [None, Some("abc".to_owned())][..cursors.len()].to_vec()
+                );
+                Ok(pages.next().expect("No more pages"))
+            }),
+        );
+        let transactions = reader.map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|txn| txn.transaction_id.0)
+                .collect::<Vec<_>>(),
+            [1, 2, 1]
+        );
+
+        // Without a poll interval, a failure ends the input
+        let source = HttpSource::parse("url = x").unwrap();
+        let mut reader =
+            HttpPullReader::new(source, Box::new(|_, _| Err("Connection refused".into())));
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,50 @@
+use super::{TransactionResult, TransactionSource};
+use std::{cell::RefCell, rc::Rc};
+
+/// Where the last row read from a `Located` source came from: the input's name, and how many rows
+/// into it the row was. Rows are counted from 1, after any header, and malformed rows count too,
+/// so the number matches what an editor or `sed -n` would show for a one-line-per-row format.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    pub file: Rc<str>,
+    pub row: u64,
+}
+
+/// The same `Location`, shared between the sources being read and whatever needs to know where
+/// the transaction it was just given came from.
+pub type SharedLocation = Rc<RefCell<Location>>;
+
+/// Passes through everything read from one input, updating a `SharedLocation` as it goes.
+pub struct Located<I> {
+    inner: I,
+    file: Rc<str>,
+    row: u64,
+    location: SharedLocation,
+}
+
+impl<I: TransactionSource> Located<I> {
+    /// Wraps `inner`, which reads the input called `file`.
+    pub fn new(inner: I, file: &str, location: SharedLocation) -> Self {
+        Self {
+            inner,
+            file: file.into(),
+            row: 0,
+            location,
+        }
+    }
+}
+
+impl<I: TransactionSource> Iterator for Located<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.inner.next()?;
+        self.row += 1;
+        let mut location = self.location.borrow_mut();
+        if !Rc::ptr_eq(&location.file, &self.file) {
+            location.file = self.file.clone();
+        }
+        location.row = self.row;
+        Some(result)
+    }
+}
@@ -0,0 +1,117 @@
+//! Logging, as set up by the `--log-level` and `--log-json` switches.
+
+use tracing_subscriber::{filter::LevelFilter, fmt::MakeWriter, registry::LookupSpan, Layer};
+
+/// A layer that logs everything at `level` or above to `writer`, as JSON lines if `json` is set
+/// and as plain text otherwise. Each line names the spans it was logged in, e.g. the `input` span
+/// around everything read from a file (see `open`).
+pub fn layer<S, W>(level: LevelFilter, json: bool, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+    let fmt = if json {
+        fmt.json().boxed()
+    } else {
+        fmt.boxed()
+    };
+    fmt.with_filter(level).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::layer;
+    use crate::{
+        io::{open_tracked, InputOptions},
+        state::State,
+    };
+    use std::{
+        io::Write,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Processes a file in which a withdrawal is rejected, and returns what was logged
+    fn log(level: LevelFilter, json: bool) -> String {
+        let data = b"type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n";
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::registry().with(layer(level, json, {
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        }));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let options = InputOptions {
+                trace_batch_size: Some(10),
+                ..Default::default()
+            };
+            let mut state = State::default();
+            for txn in
+                open_tracked(Box::new(&data[..]), Path::new("a.csv"), None, &options).unwrap()
+            {
+                state.process(txn.unwrap()).unwrap();
+            }
+        });
+
+        let logged = buffer.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[test]
+    fn test_layer() {
+        let lines = log(LevelFilter::DEBUG, true)
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["fields"]["message"], "Opened input");
+        assert_eq!(lines[0]["fields"]["format"], "csv");
+
+        // The rejected withdrawal, and why, while its batch was being applied
+        let rejected = &lines[1];
+        assert_eq!(rejected["level"], "DEBUG");
+        assert_eq!(rejected["fields"]["message"], "Ignored");
+        assert_eq!(rejected["fields"]["type"], "withdrawal");
+        assert_eq!(rejected["fields"]["reason"], "insufficient_funds");
+        assert_eq!(rejected["fields"]["tx"], 2);
+        let spans = rejected["spans"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|span| span["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["input", "batch", "apply"]);
+        assert_eq!(rejected["spans"][0]["path"], "a.csv");
+        assert_eq!(rejected["spans"][1]["rows"], 2);
+
+        // Then the end of the file, back in just its span
+        assert_eq!(lines[2]["fields"]["message"], "Finished input");
+        assert_eq!(lines[2]["spans"].as_array().unwrap().len(), 1);
+
+        // Only what's at the level or above, as (coloured) plain text
+        let logged = log(LevelFilter::INFO, false);
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged
+            .lines()
+            .all(|line| line.contains("INFO") && line.contains("input") && line.contains("a.csv")));
+        assert!(logged.contains("Finished input"));
+        assert!(!logged.contains("Ignored"));
+        assert_eq!(log(LevelFilter::OFF, true), "");
+    }
+}
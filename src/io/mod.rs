@@ -0,0 +1,1189 @@
+pub mod accounts;
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod chronological;
+#[cfg(feature = "http-pull")]
+pub mod http_pull;
+pub mod location;
+pub mod log;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+pub mod progress;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod rejects;
+pub mod stop;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+use super::{
+    manifest::{ManifestEntry, Verifier},
+    types::{Transaction, TransactionFields},
+};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Every reader yields these, so that they can be used interchangeably.
+pub type TransactionResult = Result<Transaction, Box<dyn std::error::Error>>;
+
+/// Anything transactions can be read from, in order: a file in one of the input formats, an API
+/// (see `http_pull`), or an embedder's own queue. Every iterator of `TransactionResult`s is one,
+/// so a source is written as a plain iterator.
+pub trait TransactionSource: Iterator<Item = TransactionResult> {}
+
+impl<I: Iterator<Item = TransactionResult> + ?Sized> TransactionSource for I {}
+
+/// A source chosen at run time, e.g. by the input's format.
+pub type TransactionReader = Box<dyn TransactionSource>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Avro,
+    Csv,
+    JsonLines,
+    MessagePack,
+    Parquet,
+    Protobuf,
+    Xlsx,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Avro => "avro",
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+            Self::MessagePack => "msgpack",
+            Self::Parquet => "parquet",
+            Self::Protobuf => "protobuf",
+            Self::Xlsx => "xlsx",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "avro" => Ok(Self::Avro),
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            "msgpack" => Ok(Self::MessagePack),
+            "parquet" => Ok(Self::Parquet),
+            "protobuf" => Ok(Self::Protobuf),
+            "xlsx" => Ok(Self::Xlsx),
+            other => Err(format!("Unrecognised input format \"{}\"", other)),
+        }
+    }
+}
+
+/// How inputs are read. The defaults are right for the usual comma-separated input.
+#[derive(Debug, Default, Clone)]
+pub struct InputOptions {
+    /// `None` means it's detected for each input.
+    pub format: Option<Format>,
+    pub csv: CsvDialect,
+    /// Print periodic progress lines to stderr; see `progress::Progress`.
+    pub progress: bool,
+    /// Read rows ahead in batches of this many, so that each batch can be traced with `parse` and
+    /// `apply` spans; see `open_tracked`. Nothing is yielded until a batch is full (or the input
+    /// ends), so this isn't for streaming input.
+    pub trace_batch_size: Option<usize>,
+    /// Lets stdin and files be stopped part way through, even while waiting for more input; see
+    /// `stop::Stop`.
+    pub stop: Option<stop::Stop>,
+}
+
+/// The variant of CSV in use.
+#[derive(Debug, Clone)]
+pub struct CsvDialect {
+    /// `None` means a tab for `.tsv` files and a comma otherwise.
+    pub delimiter: Option<u8>,
+    pub quote: u8,
+    /// Without a header row, the columns must be in the usual order: type, client, tx, amount,
+    /// and then optionally to, reference, reason and timestamp.
+    pub has_headers: bool,
+    pub columns: ColumnMap,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: None,
+            quote: b'"',
+            has_headers: true,
+            columns: ColumnMap::default(),
+        }
+    }
+}
+
+impl CsvDialect {
+    fn delimiter_for(&self, path: &Path) -> u8 {
+        let is_tsv = path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy().to_ascii_lowercase();
+            name.ends_with(".tsv") || name.contains(".tsv.")
+        });
+
+        match self.delimiter {
+            Some(delimiter) => delimiter,
+            None if is_tsv => b'\t',
+            None => b',',
+        }
+    }
+}
+
+/// Maps the usual column names onto the ones in a file's header, for CSV files that name them
+/// differently. Parsed from e.g. `type=txn_type,client=customer_id`; unmapped columns keep their
+/// usual names.
+#[derive(Debug, Default, Clone)]
+pub struct ColumnMap(Vec<(String, String)>);
+
+impl ColumnMap {
+    const COLUMNS: [&'static str; 8] = [
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "to",
+        "reference",
+        "reason",
+        "timestamp",
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Adds the mappings in `other`, which take precedence.
+    pub fn extend(&mut self, other: ColumnMap) {
+        for (column, name) in other.0 {
+            self.0.retain(|(existing, _)| *existing != column);
+            self.0.push((column, name));
+        }
+    }
+
+    /// Renames a file's headers to the usual names.
+    fn apply(&self, headers: &csv::StringRecord) -> Result<csv::StringRecord, String> {
+        if let Some((column, name)) = self
+            .0
+            .iter()
+            .find(|(_, name)| !headers.iter().any(|header| header == name))
+        {
+            return Err(format!(
+                "Column \"{}\" (mapped to \"{}\") is not in the header",
+                name, column
+            ));
+        }
+
+        Ok(headers
+            .iter()
+            .map(
+                |header| match self.0.iter().find(|(_, name)| name == header) {
+                    Some((column, _)) => column.as_str(),
+                    // Don't let an unmapped column shadow a mapped one
+                    None if self.0.iter().any(|(column, _)| column == header) => "",
+                    None => header,
+                },
+            )
+            .collect())
+    }
+}
+
+impl std::str::FromStr for ColumnMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|mapping| {
+                let (column, name) = mapping
+                    .split_once('=')
+                    .ok_or_else(|| format!("Column mapping \"{}\" isn't column=name", mapping))?;
+                let (column, name) = (column.trim(), name.trim());
+
+                if !Self::COLUMNS.contains(&column) {
+                    return Err(format!(
+                        "Unrecognised column \"{}\", expected one of: {}",
+                        column,
+                        Self::COLUMNS.join(", ")
+                    ));
+                }
+
+                Ok((column.to_string(), name.to_string()))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// Works out how an input is encoded from its name and its first few bytes. Magic bytes win over
+/// the extension when there are any; otherwise a compressed file's format comes from the
+/// extension under the compression one (e.g. `.jsonl.gz`), and anything unrecognised is CSV.
+pub fn detect(path: &Path, head: &[u8]) -> (Compression, Format) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let (name, compression_by_extension) = if let Some(name) = name.strip_suffix(".gz") {
+        (name, Compression::Gzip)
+    } else if let Some(name) = name.strip_suffix(".zst") {
+        (name, Compression::Zstd)
+    } else {
+        (name.as_str(), Compression::None)
+    };
+
+    let compression = if head.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if head.is_empty() {
+        compression_by_extension
+    } else {
+        Compression::None
+    };
+
+    let format = if compression == Compression::None && head.starts_with(b"PAR1") {
+        Format::Parquet
+    } else if compression == Compression::None && head.starts_with(b"Obj\x01") {
+        Format::Avro
+    } else if name.ends_with(".csv") || name.ends_with(".tsv") {
+        Format::Csv
+    } else if name.ends_with(".jsonl") || name.ends_with(".ndjson") || name.ends_with(".json") {
+        Format::JsonLines
+    } else if name.ends_with(".parquet") {
+        Format::Parquet
+    } else if name.ends_with(".avro") {
+        Format::Avro
+    } else if name.ends_with(".msgpack") || name.ends_with(".mpk") {
+        Format::MessagePack
+    } else if name.ends_with(".pb") || name.ends_with(".binpb") {
+        Format::Protobuf
+    } else if name.ends_with(".xlsx") {
+        Format::Xlsx
+    } else if compression == Compression::None
+        && head.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{')
+    {
+        Format::JsonLines
+    } else {
+        Format::Csv
+    };
+
+    (compression, format)
+}
+
+/// Opens `input_filename` (or stdin if it's "-") with the reader for `options.format`, or for the
+/// detected format if it's `None`.
+pub fn open(
+    input_filename: &str,
+    options: &InputOptions,
+) -> Result<TransactionReader, Box<dyn std::error::Error>> {
+    let (reader, total_bytes) = open_raw(input_filename, options.stop.as_ref())?;
+    open_tracked(reader, Path::new(input_filename), total_bytes, options)
+}
+
+/// Whether `name` is an object store URL (`s3://`, `gs://`, `az://` and the like) rather than a
+/// path. Inputs and outputs can be either, with the object-store feature.
+pub fn is_object_url(name: &str) -> bool {
+    name.split_once("://").is_some_and(|(scheme, _)| {
+        matches!(
+            scheme,
+            "s3" | "s3a" | "gs" | "az" | "azure" | "abfs" | "abfss" | "adl"
+        )
+    })
+}
+
+// Opens `input_filename` as it is, along with its size if it's known: stdin if it's "-", an
+// object if it's an object store URL, and otherwise a file, which `stop` can stop
+fn open_raw(
+    input_filename: &str,
+    stop: Option<&stop::Stop>,
+) -> Result<(Box<dyn std::io::Read>, Option<u64>), std::io::Error> {
+    if input_filename == "-" {
+        return Ok(match stop {
+            Some(stop) => (
+                Box::new(stop::Stoppable::new(std::io::stdin(), stop.clone())),
+                None,
+            ),
+            None => (Box::new(std::io::stdin()), None),
+        });
+    }
+    if is_object_url(input_filename) {
+        #[cfg(feature = "object-store")]
+        {
+            let (reader, size) = object_store::open(input_filename)?;
+            return Ok((Box::new(reader), Some(size)));
+        }
+        #[cfg(not(feature = "object-store"))]
+        return Err(std::io::Error::other(format!(
+            "Reading {} requires the object-store feature",
+            input_filename
+        )));
+    }
+
+    let file = File::open(input_filename)?;
+    let len = file.metadata()?.len();
+    Ok(match stop {
+        Some(stop) => (
+            Box::new(stop::Stoppable::new(file, stop.clone())),
+            Some(len),
+        ),
+        None => (Box::new(file), Some(len)),
+    })
+}
+
+/// Like `open`, but always reads CSV (in `options.csv`'s dialect), giving access to the
+/// `CsvFileReader` itself. Compressed inputs are still decompressed.
+pub fn open_csv(
+    input_filename: &str,
+    options: &InputOptions,
+) -> Result<CsvFileReader, Box<dyn std::error::Error>> {
+    let (reader, _) = open_raw(input_filename, options.stop.as_ref())?;
+    let path = Path::new(input_filename);
+    let (reader, _) = decompress_detected(reader, path)?;
+
+    CsvFileReader::with_dialect(
+        Box::new(reader),
+        &CsvDialect {
+            delimiter: Some(options.csv.delimiter_for(path)),
+            ..options.csv.clone()
+        },
+    )
+}
+
+/// Like `open`, but also checks the file's checksum and row count against its manifest entry as
+/// it's read. A mismatch is reported as an error after the last transaction.
+pub fn open_verified(
+    expected: ManifestEntry,
+    options: &InputOptions,
+) -> Result<TransactionReader, Box<dyn std::error::Error>> {
+    let path = expected.path.clone();
+    let (file, total_bytes) = open_raw(&path.display().to_string(), options.stop.as_ref())?;
+    let (verifier, reader) = Verifier::new(expected, file);
+
+    Ok(Box::new(verifier.wrap(open_tracked(
+        reader,
+        &path,
+        total_bytes,
+        options,
+    )?)))
+}
+
+/// Like `open_reader`, but with progress reporting if it's enabled, and with everything logged
+/// while reading from the file in an `input` span.
+///
+/// With `options.trace_batch_size`, each batch of rows also gets a `batch` span (under `input`),
+/// with a `parse` span for reading and decoding its rows, including checking their fields, and an
+/// `apply` span for the time until the next batch is needed, i.e. while the caller processes them.
+/// The engine's own checks happen as each transaction is applied, so they're part of `apply`.
+fn open_tracked(
+    reader: Box<dyn std::io::Read>,
+    path: &Path,
+    total_bytes: Option<u64>,
+    options: &InputOptions,
+) -> Result<TransactionReader, Box<dyn std::error::Error>> {
+    let span = tracing::info_span!("input", path = %path.display());
+    let _entered = span.enter();
+
+    let reader = if options.progress {
+        let (progress, reader) =
+            progress::Progress::new(&path.display().to_string(), total_bytes, reader);
+        Box::new(progress.wrap(open_reader(reader, path, options)?))
+    } else {
+        open_reader(reader, path, options)?
+    };
+
+    Ok(Box::new(InSpan {
+        inner: reader,
+        span: span.clone(),
+        batch_size: options.trace_batch_size.map(|size| size.max(1)),
+        buffer: VecDeque::new(),
+        batches: 0,
+        apply: None,
+    }))
+}
+
+/// Enters `span` while each item is read from `inner`, and traces batches of items if there's a
+/// `batch_size`.
+struct InSpan {
+    inner: TransactionReader,
+    span: tracing::Span,
+    batch_size: Option<usize>,
+    buffer: VecDeque<TransactionResult>,
+    batches: u64,
+    // The current batch's `apply` span, which stays entered between calls to `next`
+    apply: Option<tracing::span::EnteredSpan>,
+}
+
+impl InSpan {
+    fn read_batch(&mut self, batch_size: usize) {
+        self.apply = None;
+
+        let batch = tracing::info_span!(
+            parent: &self.span,
+            "batch",
+            index = self.batches,
+            rows = tracing::field::Empty
+        );
+        {
+            let _parse = tracing::info_span!(parent: &batch, "parse").entered();
+            self.buffer.extend(self.inner.by_ref().take(batch_size));
+        }
+        self.batches += 1;
+
+        if !self.buffer.is_empty() {
+            batch.record("rows", self.buffer.len());
+            self.apply = Some(tracing::info_span!(parent: &batch, "apply").entered());
+        }
+    }
+}
+
+impl Iterator for InSpan {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match self.batch_size {
+            Some(batch_size) => {
+                if self.buffer.is_empty() {
+                    self.read_batch(batch_size);
+                }
+                self.buffer.pop_front()
+            }
+            None => self.span.in_scope(|| self.inner.next()),
+        };
+        if item.is_none() {
+            self.apply = None;
+            self.span.in_scope(|| tracing::info!("Finished input"));
+        }
+        item
+    }
+}
+
+fn open_reader(
+    reader: Box<dyn std::io::Read>,
+    path: &Path,
+    options: &InputOptions,
+) -> Result<TransactionReader, Box<dyn std::error::Error>> {
+    let (reader, detected_format) = decompress_detected(reader, path)?;
+    let format = options.format.unwrap_or(detected_format);
+    tracing::info!(format = format.name(), "Opened input");
+
+    match format {
+        Format::Csv => {
+            let dialect = CsvDialect {
+                delimiter: Some(options.csv.delimiter_for(path)),
+                ..options.csv.clone()
+            };
+            Ok(Box::new(CsvFileReader::with_dialect(
+                Box::new(reader),
+                &dialect,
+            )?))
+        }
+        Format::JsonLines => Ok(Box::new(JsonLinesReader::from_reader(Box::new(reader)))),
+        #[cfg(feature = "avro")]
+        Format::Avro => Ok(Box::new(avro::AvroReader::from_reader(Box::new(reader))?)),
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack => Ok(Box::new(msgpack::MessagePackReader::from_reader(Box::new(
+            reader,
+        )))),
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => Ok(Box::new(protobuf::ProtobufReader::from_reader(Box::new(
+            reader,
+        )))),
+        #[cfg(feature = "xlsx")]
+        Format::Xlsx => Ok(Box::new(xlsx::XlsxReader::from_reader(Box::new(reader))?)),
+        other => Err(format!(
+            "{} is in {} format, which isn't supported",
+            path.display(),
+            other.name()
+        )
+        .into()),
+    }
+}
+
+/// Wraps `reader` to decompress it as it's read if it's compressed, and works out the format of
+/// the (decompressed) contents.
+fn decompress_detected(
+    reader: Box<dyn std::io::Read>,
+    path: &Path,
+) -> Result<(Box<dyn BufRead>, Format), std::io::Error> {
+    let mut reader: Box<dyn BufRead> = Box::new(BufReader::new(reader));
+    let (compression, mut format) = detect(path, reader.fill_buf()?);
+
+    // Decompress as we go, and look at the decompressed bytes to work out the format
+    if compression != Compression::None {
+        tracing::debug!(?compression, "Decompressing input");
+        reader = Box::new(BufReader::new(decompress(reader, compression)?));
+        format = detect(path, reader.fill_buf()?).1;
+    }
+
+    Ok((reader, format))
+}
+
+fn decompress(
+    reader: Box<dyn BufRead>,
+    compression: Compression,
+) -> Result<Box<dyn std::io::Read>, std::io::Error> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        // Concatenated gzip members are common when files are appended to
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+pub struct CsvFileReader {
+    reader: csv::Reader<Box<dyn std::io::Read>>,
+    // Read on the first call to `next`, if there's a header row
+    headers: Option<csv::StringRecord>,
+    record: csv::StringRecord,
+    delimiter: u8,
+}
+
+impl CsvFileReader {
+    /// Opens `input_filename` for reading, or stdin if it's "-".
+    pub fn new(input_filename: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let reader: Box<dyn std::io::Read> = match input_filename {
+            "-" => Box::new(std::io::stdin()),
+            _ => Box::new(File::open(input_filename)?),
+        };
+
+        Ok(Self::from_reader(reader))
+    }
+
+    pub fn from_reader(reader: Box<dyn std::io::Read>) -> Self {
+        let dialect = CsvDialect::default();
+        Self::from_csv_reader(Self::builder(&dialect).from_reader(reader), &dialect)
+    }
+
+    /// Like `from_reader`, but for `dialect`. Fails if the header can't be read or doesn't
+    /// have the mapped columns.
+    pub fn with_dialect(
+        reader: Box<dyn std::io::Read>,
+        dialect: &CsvDialect,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = Self::builder(dialect).from_reader(reader);
+
+        if dialect.has_headers && !dialect.columns.is_empty() {
+            let headers = dialect.columns.apply(reader.headers()?)?;
+            reader.set_headers(headers);
+        }
+
+        Ok(Self::from_csv_reader(reader, dialect))
+    }
+
+    fn from_csv_reader(reader: csv::Reader<Box<dyn std::io::Read>>, dialect: &CsvDialect) -> Self {
+        Self {
+            reader,
+            headers: None,
+            record: csv::StringRecord::new(),
+            delimiter: dialect.delimiter.unwrap_or(b','),
+        }
+    }
+
+    /// The line (1-based) that the last record read started on.
+    pub fn line(&self) -> u64 {
+        self.record.position().map_or(0, |position| position.line())
+    }
+
+    fn builder(dialect: &CsvDialect) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(dialect.has_headers)
+            .delimiter(dialect.delimiter.unwrap_or(b','))
+            .quote(dialect.quote)
+            .trim(csv::Trim::All);
+        builder
+    }
+
+    fn parse_record(&self) -> TransactionResult {
+        let mut fields = self
+            .record
+            .deserialize::<TransactionFields>(self.headers.as_ref())
+            .map_err(|e| -> Box<dyn std::error::Error> {
+                match e.kind() {
+                    // The position is reported by `RecordError`
+                    csv::ErrorKind::Deserialize { err, .. } => Box::new(err.clone()),
+                    _ => Box::new(e),
+                }
+            })?;
+        // csv reads a field that looks like a number as one, so the amount would already have
+        // been through a float; it's taken from the record's text instead
+        let column = match &self.headers {
+            Some(headers) => headers.iter().position(|header| header == "amount"),
+            None => Some(3),
+        };
+        fields.amount = column
+            .and_then(|column| self.record.get(column))
+            .filter(|amount| !amount.is_empty())
+            .map(String::from);
+
+        Ok(Transaction::try_from(fields)?)
+    }
+}
+
+impl Iterator for CsvFileReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() && self.reader.has_headers() {
+            match self.reader.headers() {
+                Ok(headers) => self.headers = Some(headers.clone()),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+
+        match self.reader.read_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) => Some(self.parse_record().map_err(|source| {
+                let position = self
+                    .record
+                    .position()
+                    .cloned()
+                    .unwrap_or_else(csv::Position::new);
+                let delimiter = (self.delimiter as char).to_string();
+                RecordError {
+                    line: position.line(),
+                    byte: Some(position.byte()),
+                    record: Some(self.record.iter().collect::<Vec<_>>().join(&delimiter)),
+                    source,
+                }
+                .into()
+            })),
+            Err(e) => Some(Err(match e.position().cloned() {
+                Some(position) => RecordError {
+                    line: position.line(),
+                    byte: Some(position.byte()),
+                    record: None,
+                    source: e.into(),
+                }
+                .into(),
+                None => e.into(),
+            })),
+        }
+    }
+}
+
+/// An input record that couldn't be read or turned into a transaction, with enough context to
+/// find it in a large file.
+#[derive(Debug)]
+pub struct RecordError {
+    /// 1-based: the line the record starts on, or for formats without lines, its row (i.e. which
+    /// record it is, or for a spreadsheet, its row number).
+    pub line: u64,
+    /// Where the record starts, for formats with lines.
+    pub byte: Option<u64>,
+    /// The record's fields (after trimming and unquoting), if it could be split into any.
+    pub record: Option<String>,
+    pub source: Box<dyn std::error::Error>,
+}
+
+impl RecordError {
+    /// The error for the `row`th record of a format without lines, which has no more context to
+    /// give.
+    #[cfg(any(
+        feature = "avro",
+        feature = "msgpack",
+        feature = "protobuf",
+        feature = "xlsx"
+    ))]
+    pub(crate) fn row(row: u64, source: Box<dyn std::error::Error>) -> Self {
+        Self {
+            line: row,
+            byte: None,
+            record: None,
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.byte {
+            Some(byte) => write!(
+                f,
+                "Line {} (byte offset {}): {}",
+                self.line, byte, self.source
+            )?,
+            None => write!(f, "Row {}: {}", self.line, self.source)?,
+        }
+        if let Some(record) = &self.record {
+            write!(f, " in record \"{}\"", record)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RecordError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Reads JSON Lines (aka NDJSON), where each line is an object with the same `type`, `client`,
+/// `tx` and (optional) `amount` fields as the CSV columns. Blank lines are skipped, and a line
+/// that can't be parsed is a `RecordError` that the lines after it don't depend on.
+pub struct JsonLinesReader {
+    reader: BufReader<Box<dyn std::io::Read>>,
+    buf: String,
+    // Of the last line read
+    line: u64,
+    // Where the next line starts
+    byte: u64,
+}
+
+impl JsonLinesReader {
+    pub fn from_reader(reader: Box<dyn std::io::Read>) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            buf: String::new(),
+            line: 0,
+            byte: 0,
+        }
+    }
+
+    fn parse_line(line: &str) -> TransactionResult {
+        let fields = serde_json::from_str::<TransactionFields>(line)?;
+        Ok(Transaction::try_from(fields)?)
+    }
+}
+
+impl Iterator for JsonLinesReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            let byte = self.byte;
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    self.line += 1;
+                    self.byte += len as u64;
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+
+            let line = self.buf.trim();
+            if line.is_empty() {
+                continue;
+            }
+            return Some(Self::parse_line(line).map_err(|source| {
+                RecordError {
+                    line: self.line,
+                    byte: Some(byte),
+                    record: Some(line.to_string()),
+                    source,
+                }
+                .into()
+            }));
+        }
+    }
+}
+
+/// Writes to a temporary file next to `path` and renames it into place once `write` has
+/// succeeded, so readers of `path` never see a half-written file.
+pub fn write_file_atomically<F>(path: &Path, write: F) -> Result<(), std::io::Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), std::io::Error>,
+{
+    let mut file = AtomicFile::create(path)?;
+    write(&mut file.writer)?;
+    file.commit()
+}
+
+/// A file that's written bit by bit, e.g. over a whole run, under a temporary name next to
+/// `path`, and only renamed into place by `commit`. If it's dropped without being committed,
+/// the temporary file is removed and `path` is left as it was.
+pub struct AtomicFile {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> Result<Self, std::io::Error> {
+        let mut tmp_path = PathBuf::from(path).into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        Ok(Self {
+            writer: BufWriter::new(File::create(&tmp_path)?),
+            path: path.into(),
+            tmp_path,
+            committed: false,
+        })
+    }
+
+    /// Syncs everything written to disk and renames it to `path`.
+    pub fn commit(mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        std::fs::rename(&self.tmp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        detect, open_reader, open_tracked, write_file_atomically, AtomicFile, ColumnMap,
+        Compression, CsvDialect, CsvFileReader, Format, InputOptions, JsonLinesReader, RecordError,
+    };
+    use crate::types::{ClientId, TransactionId, TransactionInner};
+    use std::{io::Write, path::Path};
+
+    #[test]
+    fn test_detect() {
+        let detect = |name: &str, head: &[u8]| detect(Path::new(name), head);
+
+        // By extension
+        assert_eq!(detect("a.csv", b"type,"), (Compression::None, Format::Csv));
+        assert_eq!(
+            detect("a.jsonl", b""),
+            (Compression::None, Format::JsonLines)
+        );
+        assert_eq!(
+            detect("a.PARQUET", b""),
+            (Compression::None, Format::Parquet)
+        );
+        assert_eq!(
+            detect("a.ndjson.gz", b""),
+            (Compression::Gzip, Format::JsonLines)
+        );
+
+        // By magic bytes
+        assert_eq!(
+            detect("-", b"type,client"),
+            (Compression::None, Format::Csv)
+        );
+        assert_eq!(
+            detect("-", b"  {\"type\""),
+            (Compression::None, Format::JsonLines)
+        );
+        assert_eq!(
+            detect("a.csv", b"PAR1"),
+            (Compression::None, Format::Parquet)
+        );
+        assert_eq!(
+            detect("-", b"Obj\x01\x04"),
+            (Compression::None, Format::Avro)
+        );
+        assert_eq!(
+            detect("a", b"\x1f\x8b\x08"),
+            (Compression::Gzip, Format::Csv)
+        );
+        assert_eq!(
+            detect("a.csv.gz", b"\x28\xb5\x2f\xfd"),
+            (Compression::Zstd, Format::Csv)
+        );
+    }
+
+    #[test]
+    fn test_json_lines_reader() {
+        let input = b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": 1.5}\n\n\
+            {\"type\": \"dispute\", \"client\": 1, \"tx\": 2, \"reason\": \"10.4\"}\n";
+
+        let txns = JsonLinesReader::from_reader(Box::new(&input[..]))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].transaction_id, TransactionId(2));
+        assert_eq!(txns[0].client_id, ClientId(1));
+        assert!(matches!(txns[0].inner, TransactionInner::Deposit(amount) if amount == 1.5.into()));
+        assert!(matches!(txns[1].inner, TransactionInner::Dispute));
+        assert_eq!(txns[1].reason.as_deref(), Some("10.4"));
+    }
+
+    #[test]
+    fn test_exact_amounts() {
+        // The first amount is 1.0 as a float, but has far more than four decimal places
+        let csv = b"type,client,tx,amount\ndeposit,1,1,1.00000000000000001\ndeposit,1,2,2.5000\n";
+        let jsonl =
+            b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.00000000000000001\"}\n\
+            {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":2.5}\n";
+
+        for results in [
+            CsvFileReader::from_reader(Box::new(&csv[..])).collect::<Vec<_>>(),
+            CsvFileReader::with_dialect(
+                Box::new(&csv[22..]),
+                &CsvDialect {
+                    has_headers: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .collect(),
+            JsonLinesReader::from_reader(Box::new(&jsonl[..])).collect(),
+        ] {
+            assert_eq!(results.len(), 2);
+            assert!(results[0]
+                .as_ref()
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("has more than 4 decimal places"));
+            assert!(matches!(
+                results[1].as_ref().unwrap().inner,
+                TransactionInner::Deposit(amount) if amount == 2.5.into()
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decompression() {
+        let csv = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+        let jsonl = b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.0}\n";
+
+        let gzip = |data: &[u8]| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            std::io::Write::write_all(&mut encoder, data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstd = |data: &[u8]| zstd::encode_all(data, 0).unwrap();
+
+        for (data, expected_len) in [
+            (gzip(csv), 2),
+            (zstd(csv), 2),
+            (gzip(jsonl), 1),
+            (zstd(jsonl), 1),
+        ] {
+            let txns = open_reader(
+                Box::new(std::io::Cursor::new(data)),
+                Path::new("-"),
+                &InputOptions::default(),
+            )
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            assert_eq!(txns.len(), expected_len);
+        }
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        let read = |data: &'static [u8], path: &str, csv: CsvDialect| {
+            let options = InputOptions {
+                csv,
+                ..Default::default()
+            };
+            open_reader(Box::new(data), Path::new(path), &options)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        // Tab-separated by extension, without a header
+        let txns = read(
+            b"deposit\t1\t1\t1.0\nwithdrawal\t1\t2\t0.5\n",
+            "a.tsv",
+            CsvDialect {
+                has_headers: false,
+                ..Default::default()
+            },
+        );
+        assert_eq!(txns.len(), 2);
+        assert!(
+            matches!(txns[1].inner, TransactionInner::Withdrawal(amount) if amount == 0.5.into())
+        );
+
+        // Semicolons, with a quoted field
+        let txns = read(
+            b"type;client;tx;amount\n'deposit';1;1;2.0\n",
+            "-",
+            CsvDialect {
+                delimiter: Some(b';'),
+                quote: b'\'',
+                ..Default::default()
+            },
+        );
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].client_id, ClientId(1));
+    }
+
+    #[test]
+    fn test_column_map() {
+        let columns = "client=customer_id, type=txn_type"
+            .parse::<ColumnMap>()
+            .unwrap();
+        let dialect = CsvDialect {
+            columns,
+            ..Default::default()
+        };
+        let read = |data: &'static [u8]| CsvFileReader::with_dialect(Box::new(data), &dialect);
+
+        // The unmapped "client" column is ignored rather than clashing with the mapped one
+        let txns = read(b"txn_type,client,customer_id,tx,amount\ndeposit,9,1,1,1.0\n")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].client_id, ClientId(1));
+
+        assert!(read(b"type,customer_id,tx,amount\n").is_err());
+        assert!("client".parse::<ColumnMap>().is_err());
+        assert!("account=customer_id".parse::<ColumnMap>().is_err());
+    }
+
+    #[test]
+    fn test_record_error() {
+        let data = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit, 1,x,1.0\nwithdrawal,1,3,\n";
+        let errors = CsvFileReader::from_reader(Box::new(&data[..]))
+            .filter_map(Result::err)
+            .map(|e| e.downcast::<RecordError>().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].byte), (3, Some(38)));
+        assert_eq!(errors[0].record.as_deref(), Some("deposit,1,x,1.0"));
+        assert_eq!(
+            errors[1].to_string(),
+            "Line 4 (byte offset 55): Withdrawal \"amount\" field is blank in record \"withdrawal,1,3,\""
+        );
+    }
+
+    #[test]
+    fn test_atomic_file() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", std::process::id()));
+        let tmp_path = path.with_extension("jsonl.tmp");
+
+        // Nothing's left behind by a file that isn't committed
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial\n").unwrap();
+        assert!(!path.exists());
+        drop(file);
+        assert!(!path.exists() && !tmp_path.exists());
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"complete\n").unwrap();
+        file.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "complete\n");
+        assert!(!tmp_path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_atomically() {
+        let path = std::env::temp_dir().join(format!("accounts-{}.csv", std::process::id()));
+        let tmp_path = path.with_extension("csv.tmp");
+        write_file_atomically(&path, |f| f.write_all(b"before\n")).unwrap();
+
+        // A write that fails part way leaves the last output as it was
+        let err = write_file_atomically(&path, |f| {
+            f.write_all(b"half")?;
+            Err(std::io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "before\n");
+        assert!(!tmp_path.exists());
+        std::fs::remove_file(&path).unwrap();
+
+        // As does one to somewhere that can't be written
+        let missing = path.join("accounts.csv");
+        assert!(write_file_atomically(&missing, |f| f.write_all(b"after\n")).is_err());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_json_lines_record_error() {
+        let data = b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.0}\n\
+            {\"type\":\"deposit\",\"client\":1,\n\
+            \n\
+            {\"type\":\"withdrawal\",\"client\":1,\"tx\":3}\n\
+            {\"type\":\"deposit\",\"client\":1,\"tx\":4,\"amount\":2.0}\n";
+        let results = JsonLinesReader::from_reader(Box::new(&data[..])).collect::<Vec<_>>();
+
+        // Each bad line is a `RecordError`, and the lines after it are still read
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        let errors = results[1..3]
+            .iter()
+            .map(|result| {
+                result
+                    .as_ref()
+                    .err()
+                    .unwrap()
+                    .downcast_ref::<RecordError>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!((errors[0].line, errors[0].byte), (2, Some(50)));
+        assert_eq!(
+            errors[1].to_string(),
+            "Line 4 (byte offset 81): Withdrawal \"amount\" field is blank in record \
+            \"{\"type\":\"withdrawal\",\"client\":1,\"tx\":3}\""
+        );
+        assert_eq!(
+            results[3].as_ref().unwrap().transaction_id,
+            TransactionId(4)
+        );
+    }
+
+    #[test]
+    fn test_trace_batches() {
+        let data = b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,x,1.0\ndeposit,1,3,1.0\n";
+        let read = |trace_batch_size| {
+            let options = InputOptions {
+                trace_batch_size,
+                ..Default::default()
+            };
+            open_tracked(Box::new(&data[..]), Path::new("a.csv"), None, &options)
+                .unwrap()
+                .map(|result| {
+                    result
+                        .map(|txn| txn.transaction_id)
+                        .map_err(|e| e.to_string())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Batching doesn't change what's read, or the order
+        assert_eq!(read(Some(2)), read(None));
+        assert_eq!(read(Some(2)).len(), 3);
+    }
+}
@@ -0,0 +1,102 @@
+use super::{RecordError, TransactionResult};
+use crate::types::{Transaction, TransactionFields};
+use rmp_serde::decode::ReadReader;
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read};
+
+/// Reads a stream of concatenated MessagePack values, one per transaction. Each may be a map with
+/// the same `type`, `client`, `tx` and (optional) `amount` keys as the CSV columns, or an array of
+/// those four fields in that order (which is how `rmp_serde::to_vec` encodes structs). A value
+/// that can't be decoded leaves the stream at an unknown position, so it ends the stream.
+pub struct MessagePackReader {
+    deserializer: rmp_serde::Deserializer<ReadReader<BufReader<Box<dyn Read>>>>,
+    failed: bool,
+    // How many values have been started
+    row: u64,
+}
+
+impl MessagePackReader {
+    pub fn from_reader(reader: Box<dyn Read>) -> Self {
+        Self {
+            deserializer: rmp_serde::Deserializer::new(BufReader::new(reader)),
+            failed: false,
+            row: 0,
+        }
+    }
+
+    fn read_fields(&mut self) -> Result<Option<TransactionFields>, Box<dyn std::error::Error>> {
+        // The stream may only end between values
+        if self.deserializer.get_mut().fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        self.row += 1;
+        Ok(Some(
+            TransactionFields::deserialize(&mut self.deserializer)
+                .map_err(|e| RecordError::row(self.row, e.into()))?,
+        ))
+    }
+}
+
+impl Iterator for MessagePackReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.read_fields() {
+            Ok(fields) => fields.map(|fields| {
+                Transaction::try_from(fields)
+                    .map_err(|e| RecordError::row(self.row, e.into()).into())
+            }),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessagePackReader;
+    use crate::types::{ClientId, TransactionFields, TransactionInner};
+
+    #[test]
+    fn test_msgpack_reader() {
+        let fields = |type_: &str, amount| TransactionFields {
+            type_: type_.into(),
+            client_id: 4,
+            transaction_id: 9,
+            amount,
+            to: None,
+            reference: None,
+            reason: None,
+            timestamp: None,
+        };
+
+        // Both the map and array encodings, back to back
+        let mut data = rmp_serde::to_vec_named(&fields("deposit", Some("2.25".into()))).unwrap();
+        data.extend(rmp_serde::to_vec(&fields("dispute", None)).unwrap());
+
+        let txns = MessagePackReader::from_reader(Box::new(std::io::Cursor::new(data.clone())))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(txns.len(), 2);
+        assert_eq!(txns[0].client_id, ClientId(4));
+        assert!(
+            matches!(txns[0].inner, TransactionInner::Deposit(amount) if amount == 2.25.into())
+        );
+        assert!(matches!(txns[1].inner, TransactionInner::Dispute));
+
+        // A truncated value is an error, and ends the stream
+        data.pop();
+        let txns = MessagePackReader::from_reader(Box::new(std::io::Cursor::new(data)))
+            .collect::<Vec<_>>();
+        assert_eq!(txns.len(), 2);
+        assert!(txns[0].is_ok());
+        assert!(txns[1].is_err());
+    }
+}
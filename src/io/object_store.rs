@@ -0,0 +1,121 @@
+//! Reading inputs from, and writing outputs to, object storage (S3, GCS or Azure) by URL, without
+//! going through local disk. Credentials and other settings come from the usual environment
+//! variables for each service, e.g. `AWS_ACCESS_KEY_ID`, `AWS_REGION` and `AWS_ENDPOINT` for S3,
+//! `GOOGLE_SERVICE_ACCOUNT` for GCS, or `AZURE_STORAGE_ACCOUNT_NAME` and
+//! `AZURE_STORAGE_ACCOUNT_KEY` for Azure.
+
+use ::object_store::{path::Path, ObjectStore, PutPayload};
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use std::io::{Error, Read};
+use tokio::runtime::Runtime;
+
+/// Streams an object as it's read, fetching it a chunk at a time.
+pub struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, ::object_store::Result<Bytes>>,
+    // What's left of the last chunk fetched
+    chunk: Bytes,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk.map_err(Error::other)?,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk[..len]);
+        self.chunk = self.chunk.slice(len..);
+        Ok(len)
+    }
+}
+
+/// Opens the object at `url` for reading, returning it along with its size in bytes.
+pub fn open(url: &str) -> Result<(ObjectReader, u64), Error> {
+    let (store, path) = parse(url)?;
+    open_in(store.as_ref(), &path, runtime()?)
+}
+
+/// Uploads `bytes` as the object at `url`, replacing it if there's one already. Object stores
+/// only make an object visible once it's complete, so this is atomic.
+pub fn put(url: &str, bytes: Vec<u8>) -> Result<(), Error> {
+    let (store, path) = parse(url)?;
+    put_in(store.as_ref(), &path, bytes, &runtime()?)
+}
+
+fn open_in(
+    store: &dyn ObjectStore,
+    path: &Path,
+    runtime: Runtime,
+) -> Result<(ObjectReader, u64), Error> {
+    let result = runtime.block_on(store.get(path)).map_err(Error::other)?;
+    let size = result.meta.size;
+
+    Ok((
+        ObjectReader {
+            stream: result.into_stream(),
+            runtime,
+            chunk: Bytes::new(),
+        },
+        size,
+    ))
+}
+
+fn put_in(
+    store: &dyn ObjectStore,
+    path: &Path,
+    bytes: Vec<u8>,
+    runtime: &Runtime,
+) -> Result<(), Error> {
+    runtime
+        .block_on(store.put(path, PutPayload::from(bytes)))
+        .map_err(Error::other)?;
+    Ok(())
+}
+
+fn parse(url: &str) -> Result<(Box<dyn ObjectStore>, Path), Error> {
+    let url = url::Url::parse(url).map_err(Error::other)?;
+    // Each service's builder takes its settings by the lowercased names of its variables
+    let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+    ::object_store::parse_url_opts(&url, options).map_err(Error::other)
+}
+
+fn runtime() -> Result<Runtime, Error> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open_in, put_in, runtime};
+    use ::object_store::{memory::InMemory, path::Path};
+    use std::io::Read;
+
+    #[test]
+    fn test_round_trip() {
+        let store = InMemory::new();
+        let path = Path::from("inputs/transactions.csv");
+        let bytes = b"type,client,tx,amount\ndeposit,1,1,1.5\n".repeat(1000);
+        put_in(&store, &path, bytes.clone(), &runtime().unwrap()).unwrap();
+
+        let (mut reader, size) = open_in(&store, &path, runtime().unwrap()).unwrap();
+        assert_eq!(size, bytes.len() as u64);
+        // Reading a little at a time, so that chunks are split across reads
+        let mut read = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                len => read.extend_from_slice(&buf[..len]),
+            }
+        }
+        assert_eq!(read, bytes);
+
+        assert!(open_in(&store, &Path::from("missing"), runtime().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use super::{TransactionResult, TransactionSource};
+use std::{
+    cell::Cell,
+    io::Read,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+// How often a progress line is printed
+const INTERVAL: Duration = Duration::from_secs(10);
+// The clock is only checked every this many rows, to keep it off the hot path
+const CHECK_EVERY: u64 = 4096;
+
+/// Prints periodic progress lines to stderr while a large input is processed, so that a long run
+/// doesn't look like it's hung. Bytes are counted as they're read from the file (i.e. before any
+/// decompression), so with the file's size that gives a percentage and an estimate of the time
+/// remaining.
+pub struct Progress {
+    name: String,
+    total_bytes: Option<u64>,
+    bytes: Rc<Cell<u64>>,
+    rows: u64,
+    started: Instant,
+    last_printed: Instant,
+}
+
+impl Progress {
+    /// Wraps `reader`, which reads the input called `name` (in progress lines) and is
+    /// `total_bytes` long, if that's known.
+    pub fn new(
+        name: &str,
+        total_bytes: Option<u64>,
+        reader: Box<dyn Read>,
+    ) -> (Self, Box<dyn Read>) {
+        let bytes = Rc::new(Cell::new(0));
+        let reader = CountingReader {
+            inner: reader,
+            bytes: bytes.clone(),
+        };
+        let now = Instant::now();
+
+        (
+            Self {
+                name: name.into(),
+                total_bytes,
+                bytes,
+                rows: 0,
+                started: now,
+                last_printed: now,
+            },
+            Box::new(reader),
+        )
+    }
+
+    /// Counts the rows yielded by `iter`, which must come from the reader returned by `new`.
+    pub fn wrap<I>(self, iter: I) -> WithProgress<I> {
+        WithProgress {
+            iter,
+            progress: Some(self),
+        }
+    }
+
+    fn count_row(&mut self) {
+        self.rows += 1;
+        if self.rows.is_multiple_of(CHECK_EVERY) && self.last_printed.elapsed() >= INTERVAL {
+            self.last_printed = Instant::now();
+            eprintln!("{}", self.line(self.started.elapsed()));
+        }
+    }
+
+    fn line(&self, elapsed: Duration) -> String {
+        let bytes = self.bytes.get();
+        let rate = self.rows as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut line = format!(
+            "Progress: {}: {} rows, {} read",
+            self.name,
+            self.rows,
+            format_bytes(bytes)
+        );
+
+        let total_bytes = self.total_bytes.filter(|total_bytes| *total_bytes > 0);
+        let fraction = total_bytes.map(|total_bytes| bytes as f64 / total_bytes as f64);
+        if let (Some(total_bytes), Some(fraction)) = (total_bytes, fraction) {
+            line += &format!(
+                " of {} ({:.1}%)",
+                format_bytes(total_bytes),
+                fraction * 100.0
+            );
+        }
+
+        line += &format!(", {:.0} rows/s", rate);
+
+        if let Some(fraction) = fraction.filter(|fraction| *fraction > 0.0) {
+            let remaining = elapsed.as_secs_f64() * (1.0 - fraction) / fraction;
+            line += &format!(", ETA {}", format_duration(remaining as u64));
+        }
+
+        line
+    }
+}
+
+pub struct WithProgress<I> {
+    iter: I,
+    // Taken once `iter` is exhausted
+    progress: Option<Progress>,
+}
+
+impl<I: TransactionSource> Iterator for WithProgress<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next();
+        match (&item, &mut self.progress) {
+            (Some(_), Some(progress)) => progress.count_row(),
+            (None, progress) => {
+                if let Some(progress) = progress.take() {
+                    eprintln!(
+                        "Progress: {}: done, {} rows in {}",
+                        progress.name,
+                        progress.rows,
+                        format_duration(progress.started.elapsed().as_secs())
+                    );
+                }
+            }
+            (Some(_), None) => {}
+        }
+        item
+    }
+}
+
+struct CountingReader {
+    inner: Box<dyn Read>,
+    bytes: Rc<Cell<u64>>,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.bytes.set(self.bytes.get() + len as u64);
+        Ok(len)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Progress;
+    use std::{io::Read, time::Duration};
+
+    #[test]
+    fn test_progress_line() {
+        let (mut progress, mut reader) =
+            Progress::new("input.csv", Some(4096), Box::new(&[0u8; 1024][..]));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        progress.rows = 300;
+
+        assert_eq!(
+            progress.line(Duration::from_secs(90)),
+            "Progress: input.csv: 300 rows, 1.0 KiB read of 4.0 KiB (25.0%), 3 rows/s, ETA 4m30s"
+        );
+
+        progress.total_bytes = None;
+        assert_eq!(
+            progress.line(Duration::from_secs(90)),
+            "Progress: input.csv: 300 rows, 1.0 KiB read, 3 rows/s"
+        );
+    }
+}
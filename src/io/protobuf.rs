@@ -0,0 +1,270 @@
+use super::{RecordError, TransactionResult};
+use crate::types::{
+    check_amount, ClientId, FixedFloat, Transaction, TransactionFieldsError, TransactionId,
+    TransactionInner,
+};
+use prost::Message;
+use std::io::{BufRead, BufReader, Read};
+
+// Anything longer than this is a corrupt length prefix rather than a transaction
+const MAX_MESSAGE_LEN: u64 = 1024;
+
+// These mirror proto/transaction.proto
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+#[repr(i32)]
+pub enum TransactionType {
+    Unspecified = 0,
+    Deposit = 1,
+    Withdrawal = 2,
+    Dispute = 3,
+    Resolve = 4,
+    Chargeback = 5,
+    Transfer = 6,
+    Adjustment = 7,
+    Unlock = 8,
+    Close = 9,
+    Refund = 10,
+    Authorize = 11,
+    Capture = 12,
+    Void = 13,
+    Representment = 14,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TransactionMessage {
+    #[prost(enumeration = "TransactionType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(int64, optional, tag = "4")]
+    pub amount: Option<i64>,
+    #[prost(uint32, optional, tag = "5")]
+    pub to: Option<u32>,
+    #[prost(string, optional, tag = "6")]
+    pub reference: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub reason: Option<String>,
+    #[prost(uint64, optional, tag = "8")]
+    pub timestamp: Option<u64>,
+}
+
+impl TryFrom<TransactionMessage> for Transaction {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(message: TransactionMessage) -> Result<Self, Self::Error> {
+        let client_id = u16::try_from(message.client)
+            .map_err(|_| format!("Client id {} is out of range", message.client))?;
+        let amount = message.amount.map(FixedFloat::from_raw);
+
+        let inner = match TransactionType::try_from(message.r#type) {
+            Ok(TransactionType::Deposit) => TransactionInner::Deposit(
+                amount.ok_or(TransactionFieldsError::DepositMissingAmount)?,
+            ),
+            Ok(TransactionType::Withdrawal) => TransactionInner::Withdrawal(
+                amount.ok_or(TransactionFieldsError::WithdrawalMissingAmount)?,
+            ),
+            Ok(TransactionType::Transfer) => {
+                let to = message
+                    .to
+                    .ok_or(TransactionFieldsError::TransferMissingRecipient)?;
+                TransactionInner::Transfer {
+                    to: ClientId(
+                        u16::try_from(to)
+                            .map_err(|_| format!("Client id {} is out of range", to))?,
+                    ),
+                    amount: amount.ok_or(TransactionFieldsError::TransferMissingAmount)?,
+                }
+            }
+            Ok(TransactionType::Adjustment) => TransactionInner::Adjustment {
+                amount: amount.ok_or(TransactionFieldsError::AdjustmentMissingAmount)?,
+                reference: message
+                    .reference
+                    .filter(|reference| !reference.trim().is_empty())
+                    .ok_or(TransactionFieldsError::AdjustmentMissingReference)?,
+            },
+            Ok(TransactionType::Dispute) => match amount {
+                Some(amount) => TransactionInner::PartialDispute(amount),
+                None => TransactionInner::Dispute,
+            },
+            Ok(TransactionType::Resolve) => TransactionInner::Resolve,
+            Ok(TransactionType::Chargeback) => TransactionInner::Chargeback,
+            Ok(TransactionType::Unlock) => TransactionInner::Unlock,
+            Ok(TransactionType::Refund) => TransactionInner::Refund,
+            Ok(TransactionType::Representment) => TransactionInner::Representment,
+            Ok(TransactionType::Authorize) => TransactionInner::Authorize(
+                amount.ok_or(TransactionFieldsError::AuthorizeMissingAmount)?,
+            ),
+            Ok(TransactionType::Capture) => TransactionInner::Capture,
+            Ok(TransactionType::Void) => TransactionInner::Void,
+            Ok(TransactionType::Close) => TransactionInner::Close,
+            Ok(TransactionType::Unspecified) | Err(_) => {
+                return Err(
+                    TransactionFieldsError::UnrecognisedType(message.r#type.to_string()).into(),
+                )
+            }
+        };
+
+        check_amount(&inner)?;
+        let mut txn = Transaction::new(TransactionId(message.tx), ClientId(client_id), inner);
+        if txn.inner.takes_reason() {
+            txn.reason = message.reason.filter(|reason| !reason.trim().is_empty());
+        }
+        txn.timestamp = message.timestamp;
+        Ok(txn)
+    }
+}
+
+/// Reads a stream of length-delimited `Transaction` messages (see proto/transaction.proto).
+/// There's no way to resynchronise after a bad length prefix or a truncated message, so the
+/// first such error ends the stream.
+pub struct ProtobufReader {
+    reader: Box<dyn BufRead>,
+    buf: Vec<u8>,
+    failed: bool,
+    // How many messages have been started
+    row: u64,
+}
+
+impl ProtobufReader {
+    pub fn from_reader(reader: Box<dyn Read>) -> Self {
+        Self {
+            reader: Box::new(BufReader::new(reader)),
+            buf: Vec::new(),
+            failed: false,
+            row: 0,
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Option<TransactionMessage>, Box<dyn std::error::Error>> {
+        // The stream may only end between messages
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        self.row += 1;
+        self.read_body()
+            .map(Some)
+            .map_err(|e| RecordError::row(self.row, e).into())
+    }
+
+    // The length prefix and the message it's followed by
+    fn read_body(&mut self) -> Result<TransactionMessage, Box<dyn std::error::Error>> {
+        let len = self.read_varint()?;
+        if len > MAX_MESSAGE_LEN {
+            return Err(format!("Message length {} is implausibly long", len).into());
+        }
+
+        self.buf.resize(len as usize, 0);
+        self.reader.read_exact(&mut self.buf)?;
+
+        Ok(TransactionMessage::decode(self.buf.as_slice())?)
+    }
+
+    fn read_varint(&mut self) -> Result<u64, Box<dyn std::error::Error>> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte)?;
+            value |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Message length prefix is too long".into())
+    }
+}
+
+impl Iterator for ProtobufReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.read_message() {
+            Ok(message) => message.map(|message| {
+                Transaction::try_from(message).map_err(|e| RecordError::row(self.row, e).into())
+            }),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProtobufReader, TransactionMessage, TransactionType};
+    use crate::types::{ClientId, TransactionInner};
+    use prost::Message;
+
+    fn encode(messages: &[TransactionMessage]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for message in messages {
+            message.encode_length_delimited(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_protobuf_reader() {
+        let messages = [
+            TransactionMessage {
+                r#type: TransactionType::Deposit.into(),
+                client: 3,
+                tx: 1,
+                amount: Some(15_000),
+                to: None,
+                reference: None,
+                reason: None,
+                timestamp: Some(1_700_000_000),
+            },
+            TransactionMessage {
+                r#type: TransactionType::Dispute.into(),
+                client: 3,
+                tx: 1,
+                amount: None,
+                to: None,
+                reference: None,
+                reason: None,
+                timestamp: None,
+            },
+            TransactionMessage {
+                r#type: TransactionType::Deposit.into(),
+                client: 70_000,
+                tx: 2,
+                amount: Some(1),
+                to: None,
+                reference: None,
+                reason: None,
+                timestamp: None,
+            },
+        ];
+
+        let txns = ProtobufReader::from_reader(Box::new(std::io::Cursor::new(encode(&messages))))
+            .collect::<Vec<_>>();
+        assert_eq!(txns.len(), 3);
+        let txn = txns[0].as_ref().unwrap();
+        assert_eq!(txn.client_id, ClientId(3));
+        assert!(matches!(txn.inner, TransactionInner::Deposit(amount) if amount == 1.5.into()));
+        assert_eq!(txn.timestamp, Some(1_700_000_000));
+        assert!(matches!(
+            txns[1].as_ref().unwrap().inner,
+            TransactionInner::Dispute
+        ));
+        assert!(txns[2].is_err());
+
+        // A truncated message is an error, and ends the stream
+        let mut data = encode(&messages[..2]);
+        data.pop();
+        let txns =
+            ProtobufReader::from_reader(Box::new(std::io::Cursor::new(data))).collect::<Vec<_>>();
+        assert_eq!(txns.len(), 2);
+        assert!(txns[0].is_ok());
+        assert!(txns[1].is_err());
+    }
+}
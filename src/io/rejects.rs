@@ -0,0 +1,180 @@
+use super::{RecordError, TransactionResult, TransactionSource};
+use crate::types::TransactionFieldsError;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Passes transactions through from `inner`, but writes any that couldn't be parsed (i.e. that
+/// failed with a `RecordError`) to a CSV rejects file instead of failing, so that one bad row
+/// doesn't abort a long run. Other errors, such as I/O errors or manifest mismatches, are still
+/// passed through.
+///
+/// The rejects file has the columns `line`, `byte_offset`, `reason` and `record`, as in
+/// `RecordError` (so for formats without lines, `line` is the row and the rest may be empty).
+pub struct SkipMalformed<I> {
+    inner: I,
+    // Taken once `inner` is exhausted
+    writer: Option<csv::Writer<File>>,
+    path: PathBuf,
+    skipped: u64,
+}
+
+impl<I> SkipMalformed<I> {
+    pub fn new(inner: I, path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["line", "byte_offset", "reason", "record"])?;
+
+        Ok(Self {
+            inner,
+            writer: Some(writer),
+            path: path.into(),
+            skipped: 0,
+        })
+    }
+
+    fn reject(&mut self, e: &RecordError) -> Result<(), Box<dyn std::error::Error>> {
+        tracing::warn!(line = e.line, byte = e.byte, reason = %e.source, "Skipped malformed row");
+        let writer = self.writer.as_mut().ok_or("Rejects file already closed")?;
+        writer.write_record([
+            e.line.to_string().as_str(),
+            e.byte
+                .map(|byte| byte.to_string())
+                .unwrap_or_default()
+                .as_str(),
+            e.source.to_string().as_str(),
+            e.record.as_deref().unwrap_or_default(),
+        ])?;
+        self.skipped += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+            if self.skipped > 0 {
+                eprintln!(
+                    "Warning: Skipped {} malformed rows, written to {}",
+                    self.skipped,
+                    self.path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: TransactionSource> Iterator for SkipMalformed<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.inner.next() {
+                Some(Ok(txn)) => return Some(Ok(txn)),
+                Some(Err(e)) => match e.downcast::<RecordError>() {
+                    Ok(e) => {
+                        if let Err(e) = self.reject(&e) {
+                            return Some(Err(e));
+                        }
+                    }
+                    Err(e) => return Some(Err(e)),
+                },
+                None => return self.finish().err().map(Err),
+            }
+        }
+    }
+}
+
+/// Passes transactions through from `inner`, but skips deposits, withdrawals, transfers and
+/// authorizations with an amount of zero or less (which otherwise fail as malformed rows), with a
+/// warning, for inputs where they're known to be noise rather than a sign of a broken file.
+pub struct SkipNonPositive<I> {
+    inner: I,
+    skipped: u64,
+}
+
+impl<I> SkipNonPositive<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner, skipped: 0 }
+    }
+}
+
+impl<I: TransactionSource> Iterator for SkipNonPositive<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let e = match self.inner.next() {
+                Some(Err(e)) => e,
+                None => {
+                    if self.skipped > 0 {
+                        eprintln!(
+                            "Warning: Skipped {} rows with an amount of zero or less",
+                            self.skipped
+                        );
+                    }
+                    return None;
+                }
+                other => return other,
+            };
+            // Readers that don't report where a row was return the error by itself
+            let (fields_error, line) = match e.downcast_ref::<RecordError>() {
+                Some(record_error) => (
+                    record_error.source.downcast_ref::<TransactionFieldsError>(),
+                    Some(record_error.line),
+                ),
+                None => (e.downcast_ref::<TransactionFieldsError>(), None),
+            };
+            match fields_error {
+                Some(fields_error @ TransactionFieldsError::NonPositiveAmount { .. }) => {
+                    tracing::warn!(line, reason = %fields_error, "Skipped row");
+                    self.skipped += 1;
+                }
+                _ => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SkipMalformed, SkipNonPositive};
+    use crate::io::CsvFileReader;
+
+    #[test]
+    fn test_skip_malformed() {
+        let data = b"type,client,tx,amount\ndeposit,1,1,x\ndeposit,1,2,1.0\nwithdrawal,1,3,\n";
+        let path = std::env::temp_dir().join(format!("rejects-{}.csv", std::process::id()));
+
+        let txns = SkipMalformed::new(CsvFileReader::from_reader(Box::new(&data[..])), &path)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let rejects = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(txns.len(), 1);
+        assert_eq!(
+            rejects,
+            "line,byte_offset,reason,record\n\
+            2,22,\"Invalid amount \"\"x\"\"\",\"deposit,1,1,x\"\n\
+            4,52,\"Withdrawal \"\"amount\"\" field is blank\",\"withdrawal,1,3,\"\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_non_positive() {
+        let data = b"type,client,tx,amount,reference\ndeposit,1,1,-5.0,\ndeposit,1,2,1.0,\n\
+            withdrawal,1,3,0,\nadjustment,1,4,-1.0,T-1\ndeposit,1,5,x,\n";
+
+        let results = SkipNonPositive::new(CsvFileReader::from_reader(Box::new(&data[..])))
+            .map(|result| result.map(|txn| txn.transaction_id.0))
+            .collect::<Vec<_>>();
+
+        // Negative adjustments are fine, and other malformed rows still fail
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &2);
+        assert!(results[1].is_ok());
+        assert!(results[2].is_err());
+    }
+}
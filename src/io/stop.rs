@@ -0,0 +1,131 @@
+//! Reading that can be stopped part way through, e.g. by `daemon::run` on SIGTERM, even while
+//! it's waiting for more input.
+
+use std::{
+    io::{ErrorKind, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::Duration,
+};
+
+// How often a read waiting for input checks whether it's been stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Shared between whatever stops reading and the inputs opened with it (see
+/// `InputOptions::stop`). Once `stop` is called, each of them ends as if it had reached the end
+/// of its input, within a fraction of a second even if it was waiting for more.
+#[derive(Debug, Clone, Default)]
+pub struct Stop(Arc<AtomicBool>);
+
+impl Stop {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads `inner` on a thread of its own, so that a read waiting for input can give up when
+/// `stop` is called. That thread is left waiting, and is dropped along with the process.
+pub(super) struct Stoppable {
+    chunks: Receiver<std::io::Result<Vec<u8>>>,
+    // The last chunk received, and how much of it has been read
+    chunk: Vec<u8>,
+    read: usize,
+    stop: Stop,
+}
+
+impl Stoppable {
+    pub fn new(mut inner: impl Read + Send + 'static, stop: Stop) -> Self {
+        // Only a chunk ahead, so that reading doesn't run away from processing
+        let (sender, chunks) = mpsc::sync_channel(1);
+        std::thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_LEN];
+            let result = match inner.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(len) => {
+                    chunk.truncate(len);
+                    Ok(chunk)
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+            let failed = result.is_err();
+            if sender.send(result).is_err() || failed {
+                break;
+            }
+        });
+
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            read: 0,
+            stop,
+        }
+    }
+}
+
+impl Read for Stoppable {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.stop.is_stopped() {
+                return Ok(0);
+            }
+            if self.read < self.chunk.len() {
+                break;
+            }
+            match self.chunks.recv_timeout(POLL_INTERVAL) {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.read = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.read);
+        buf[..len].copy_from_slice(&self.chunk[self.read..][..len]);
+        self.read += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stop, Stoppable};
+    use std::{io::Read, time::Duration};
+
+    // An input that never has any more
+    struct Waiting;
+
+    impl Read for Waiting {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            loop {
+                std::thread::sleep(Duration::from_secs(60));
+            }
+        }
+    }
+
+    #[test]
+    fn test_stoppable() {
+        let stop = Stop::default();
+        let mut reader = Stoppable::new((&b"deposit,1,1,1.0\n"[..]).chain(Waiting), stop.clone());
+
+        let mut buf = [0; 64];
+        assert_eq!(reader.read(&mut buf).unwrap(), 16);
+
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stop.stop();
+        });
+        // Waiting for more until it's stopped, and then at the end
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,274 @@
+use super::{RecordError, TransactionResult};
+use crate::types::{Transaction, TransactionFields};
+use calamine::{Data, Range, Reader, Xlsx};
+use std::io::{Cursor, Read};
+
+/// Reads the first sheet of an Excel workbook, which must have a header row naming the same `type`,
+/// `client`, `tx` and (optional) `amount`, `to`, `reference` and `reason` columns as the CSV input,
+/// in any order. Blank rows are skipped. Ids may be number or text cells, but must be whole numbers
+/// in range rather than being rounded.
+///
+/// The format needs random access, so the whole workbook is read into memory first.
+pub struct XlsxReader {
+    range: Range<Data>,
+    columns: Columns,
+    row: usize,
+}
+
+struct Columns {
+    type_: usize,
+    client_id: usize,
+    transaction_id: usize,
+    amount: Option<usize>,
+    to: Option<usize>,
+    reference: Option<usize>,
+    reason: Option<usize>,
+    timestamp: Option<usize>,
+}
+
+impl XlsxReader {
+    pub fn from_reader(mut reader: Box<dyn Read>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+
+        let mut workbook = Xlsx::new(Cursor::new(buf))?;
+        let range = workbook
+            .worksheet_range_at(0)
+            .ok_or("Workbook has no sheets")??;
+
+        let headers = range
+            .rows()
+            .next()
+            .ok_or("Sheet is empty")?
+            .iter()
+            .map(|cell| cell.to_string().trim().to_string())
+            .collect::<Vec<_>>();
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let required =
+            |name: &str| column(name).ok_or_else(|| format!("Sheet has no \"{}\" column", name));
+
+        let columns = Columns {
+            type_: required("type")?,
+            client_id: required("client")?,
+            transaction_id: required("tx")?,
+            amount: column("amount"),
+            to: column("to"),
+            reference: column("reference"),
+            reason: column("reason"),
+            timestamp: column("timestamp"),
+        };
+
+        Ok(Self {
+            range,
+            columns,
+            row: 1,
+        })
+    }
+
+    fn read_fields(&self, row: usize) -> Result<TransactionFields, String> {
+        let cell = |column| self.range.get((row, column)).unwrap_or(&Data::Empty);
+
+        Ok(TransactionFields {
+            type_: cell(self.columns.type_).to_string().trim().to_string(),
+            client_id: integer(cell(self.columns.client_id))
+                .ok_or_else(|| format!("Invalid \"client\" {:?}", cell(self.columns.client_id)))?,
+            transaction_id: integer(cell(self.columns.transaction_id))
+                .ok_or_else(|| format!("Invalid \"tx\" {:?}", cell(self.columns.transaction_id)))?,
+            amount: match self.columns.amount {
+                Some(column) => amount(cell(column))
+                    .ok_or_else(|| format!("Invalid \"amount\" {:?}", cell(column)))?,
+                None => None,
+            },
+            to: match self.columns.to.map(cell) {
+                None | Some(Data::Empty) => None,
+                Some(cell) => {
+                    Some(integer(cell).ok_or_else(|| format!("Invalid \"to\" {:?}", cell))?)
+                }
+            },
+            reference: match self.columns.reference.map(cell) {
+                None | Some(Data::Empty) => None,
+                Some(cell) => Some(cell.to_string().trim().to_string()),
+            },
+            reason: match self.columns.reason.map(cell) {
+                None | Some(Data::Empty) => None,
+                Some(cell) => Some(cell.to_string().trim().to_string()),
+            },
+            timestamp: match self.columns.timestamp.map(cell) {
+                None | Some(Data::Empty) => None,
+                Some(cell) => {
+                    Some(integer(cell).ok_or_else(|| format!("Invalid \"timestamp\" {:?}", cell))?)
+                }
+            },
+        })
+    }
+}
+
+impl Iterator for XlsxReader {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.row < self.range.height() {
+            let row = self.row;
+            self.row += 1;
+
+            let is_blank = self
+                .range
+                .rows()
+                .nth(row)
+                .is_some_and(|cells| cells.iter().all(|cell| *cell == Data::Empty));
+            if is_blank {
+                continue;
+            }
+
+            return Some(
+                self.read_fields(row)
+                    .map_err(Box::from)
+                    .and_then(|fields| Ok(Transaction::try_from(fields)?))
+                    // Spreadsheet row numbers are 1-based
+                    .map_err(|e| RecordError::row(row as u64 + 1, e).into()),
+            );
+        }
+
+        None
+    }
+}
+
+fn integer<T: TryFrom<i64>>(cell: &Data) -> Option<T> {
+    let value = match cell {
+        Data::Int(value) => *value,
+        Data::Float(value) if value.fract() == 0.0 => *value as i64,
+        Data::String(value) => value.trim().parse().ok()?,
+        _ => return None,
+    };
+
+    T::try_from(value).ok()
+}
+
+/// `None` means the cell isn't a valid amount, and `Some(None)` that it's blank.
+fn amount(cell: &Data) -> Option<Option<String>> {
+    match cell {
+        Data::Empty => Some(None),
+        Data::Int(value) => Some(Some(value.to_string())),
+        Data::Float(value) => Some(Some(value.to_string())),
+        Data::String(value) if value.trim().is_empty() => Some(None),
+        // Kept as text, to be parsed exactly
+        Data::String(value) => Some(Some(value.trim().into())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::XlsxReader;
+    use crate::types::{ClientId, TransactionId, TransactionInner};
+    use std::io::Write;
+
+    // Just enough of a workbook for calamine to read, with the cells given as inline strings or
+    // numbers.
+    fn workbook(rows: &[&[&str]]) -> Vec<u8> {
+        let sheet_data = rows
+            .iter()
+            .enumerate()
+            .map(|(row, cells)| {
+                let cells = cells
+                    .iter()
+                    .enumerate()
+                    .map(|(column, cell)| {
+                        let reference = format!("{}{}", (b'A' + column as u8) as char, row + 1);
+                        if cell.is_empty() {
+                            String::new()
+                        } else if cell.parse::<f64>().is_ok() {
+                            format!("<c r=\"{}\"><v>{}</v></c>", reference, cell)
+                        } else {
+                            format!(
+                                "<c r=\"{}\" t=\"inlineStr\"><is><t>{}</t></is></c>",
+                                reference, cell
+                            )
+                        }
+                    })
+                    .collect::<String>();
+                format!("<row r=\"{}\">{}</row>", row + 1, cells)
+            })
+            .collect::<String>();
+
+        let files = [
+            (
+                "[Content_Types].xml",
+                "<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+                <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+                <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+                <Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+                <Override PartName=\"/xl/worksheets/sheet1.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>\
+                </Types>".to_string(),
+            ),
+            (
+                "_rels/.rels",
+                "<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+                <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/>\
+                </Relationships>".to_string(),
+            ),
+            (
+                "xl/workbook.xml",
+                "<workbook xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" \
+                xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\
+                <sheets><sheet name=\"Sheet1\" sheetId=\"1\" r:id=\"rId1\"/></sheets>\
+                </workbook>".to_string(),
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                "<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+                <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet1.xml\"/>\
+                </Relationships>".to_string(),
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                format!(
+                    "<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\">\
+                    <sheetData>{}</sheetData></worksheet>",
+                    sheet_data
+                ),
+            ),
+        ];
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(
+                name,
+                zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored),
+            )
+            .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_xlsx_reader() {
+        let data = workbook(&[
+            &["client", "type", "tx", "amount"],
+            &["1", "deposit", "7", "1.5"],
+            &[],
+            &["1", "dispute", "7", ""],
+            &["1", "deposit", "8.5", "1"],
+        ]);
+
+        let txns = XlsxReader::from_reader(Box::new(std::io::Cursor::new(data)))
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(txns.len(), 3);
+        let txn = txns[0].as_ref().unwrap();
+        assert_eq!(txn.client_id, ClientId(1));
+        assert_eq!(txn.transaction_id, TransactionId(7));
+        assert!(matches!(txn.inner, TransactionInner::Deposit(amount) if amount == 1.5.into()));
+        assert!(matches!(
+            txns[1].as_ref().unwrap().inner,
+            TransactionInner::Dispute
+        ));
+        assert_eq!(
+            txns[2].as_ref().err().unwrap().to_string(),
+            "Row 5: Invalid \"tx\" Float(8.5)"
+        );
+    }
+}
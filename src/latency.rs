@@ -0,0 +1,167 @@
+//! Latency distributions, for seeing where processing time goes. Recording is a couple of
+//! arithmetic operations, so it's cheap enough to do for every transaction; the clock reads
+//! around it are what cost, which is why it's optional (see `state::Options::track_latency`).
+
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+// Each power of two is split into this many buckets, so a bucket is at most 25% wide
+const SUB_BUCKETS: u64 = 4;
+const BUCKETS: usize = 252;
+
+/// A histogram of durations in nanoseconds, with logarithmic buckets. Percentiles are accurate to
+/// within a bucket, i.e. 25%, which is plenty for telling a 100ns lookup from a 1µs one.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+    count: u64,
+    total_nanos: u128,
+    max_nanos: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            count: 0,
+            total_nanos: 0,
+            max_nanos: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket(nanos)] += 1;
+        self.count += 1;
+        self.total_nanos += nanos as u128;
+        self.max_nanos = self.max_nanos.max(nanos);
+    }
+
+    /// Adds everything recorded in `other`, as if it had been recorded here.
+    pub fn merge(&mut self, other: &Self) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        self.total_nanos += other.total_nanos;
+        self.max_nanos = self.max_nanos.max(other.max_nanos);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total_nanos / count as u128) as u64),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max_nanos)
+    }
+
+    /// The duration that `quantile` (between 0 and 1) of the recorded ones are no longer than,
+    /// rounded up to the end of its bucket.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        // Rounded up by hand, as `f64::ceil` needs std
+        let exact = quantile.clamp(0.0, 1.0) * self.count as f64;
+        let rank = match exact as u64 {
+            rank if (rank as f64) < exact => rank + 1,
+            rank => rank,
+        }
+        .max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let end = bucket_start(index + 1).saturating_sub(1);
+                return Duration::from_nanos(end.min(self.max_nanos));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// The latency of `State::process`, by transaction type.
+pub type Latencies = BTreeMap<&'static str, Histogram>;
+
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let octave = 63 - nanos.leading_zeros() as u64;
+    let sub = (nanos >> (octave - 2)) & (SUB_BUCKETS - 1);
+    (SUB_BUCKETS * (octave - 1) + sub) as usize
+}
+
+fn bucket_start(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let octave = index / SUB_BUCKETS + 1;
+    if octave > 63 {
+        return u64::MAX;
+    }
+    (SUB_BUCKETS + index % SUB_BUCKETS) << (octave - 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket, bucket_start, Histogram, BUCKETS};
+    use std::time::Duration;
+
+    #[test]
+    fn test_buckets() {
+        for nanos in [0, 1, 3, 4, 5, 7, 8, 100, 1000, 123_456_789, u64::MAX] {
+            let index = bucket(nanos);
+            assert!(index < BUCKETS);
+            assert!(bucket_start(index) <= nanos);
+            assert!(index + 1 == BUCKETS || nanos < bucket_start(index + 1));
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.mean(), Duration::from_nanos(50_500));
+        assert_eq!(histogram.max(), Duration::from_micros(100));
+        // Within a bucket of the exact value
+        let p50 = histogram.quantile(0.5);
+        assert!(p50 >= Duration::from_micros(50) && p50 <= Duration::from_micros(63));
+        assert_eq!(histogram.quantile(1.0), Duration::from_micros(100));
+    }
+
+    #[test]
+    fn test_quantile_rank() {
+        assert_eq!(Histogram::default().quantile(0.5), Duration::ZERO);
+
+        // Each in a bucket of its own
+        let mut histogram = Histogram::default();
+        for nanos in [1, 2, 3] {
+            histogram.record(Duration::from_nanos(nanos));
+        }
+        // A rank that's a whole number is used as it is, and anything past it rounds up
+        for (quantile, nanos) in [
+            (0.0, 1),
+            (1.0 / 3.0, 1),
+            (0.34, 2),
+            (2.0 / 3.0, 2),
+            (0.7, 3),
+        ] {
+            assert_eq!(histogram.quantile(quantile), Duration::from_nanos(nanos));
+        }
+        // Clamped to between 0 and 1
+        assert_eq!(histogram.quantile(-1.0), Duration::from_nanos(1));
+        assert_eq!(histogram.quantile(2.0), Duration::from_nanos(3));
+    }
+}
@@ -0,0 +1,41 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "io")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(all(unix, feature = "io"))]
+pub mod daemon;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+#[cfg(feature = "io")]
+pub mod generate;
+#[cfg(feature = "io")]
+pub mod health;
+#[cfg(feature = "io")]
+pub mod io;
+pub mod latency;
+#[cfg(feature = "io")]
+pub mod lint;
+#[cfg(all(unix, feature = "io"))]
+pub mod listen;
+#[cfg(feature = "io")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod notify;
+#[cfg(feature = "io")]
+pub mod output;
+#[cfg(feature = "io")]
+pub mod replay;
+#[cfg(feature = "io")]
+pub mod server;
+pub mod state;
+pub mod types;
+#[cfg(feature = "io")]
+pub mod wal;
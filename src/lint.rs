@@ -0,0 +1,200 @@
+//! Checks a CSV input for problems without running it through the engine, so that a partner's
+//! file can be vetted (and every problem in it listed) before it's processed.
+
+use crate::{
+    io::{CsvFileReader, RecordError},
+    types::{TransactionFieldsError, TransactionId, TransactionInner},
+};
+use std::{collections::HashSet, error::Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FindingKind {
+    /// The row couldn't be parsed at all, e.g. a non-numeric id or an amount with more than four
+    /// decimal places.
+    Malformed,
+    UnknownType,
+    /// A deposit or withdrawal without an amount.
+    MissingAmount,
+    /// A deposit, withdrawal, transfer or authorization of zero or less.
+    NonPositiveAmount,
+    /// A deposit or withdrawal reusing an earlier one's transaction id.
+    DuplicateTransactionId,
+    /// A deposit or withdrawal whose transaction id isn't greater than every one before it.
+    NonMonotonicTransactionId,
+    /// A dispute, resolve or chargeback of a transaction that hasn't been seen (yet).
+    UnknownTransaction,
+}
+
+impl FindingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::UnknownType => "unknown_type",
+            Self::MissingAmount => "missing_amount",
+            Self::NonPositiveAmount => "non_positive_amount",
+            Self::DuplicateTransactionId => "duplicate_transaction_id",
+            Self::NonMonotonicTransactionId => "non_monotonic_transaction_id",
+            Self::UnknownTransaction => "unknown_transaction",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    /// 1-based
+    pub line: u64,
+    pub kind: FindingKind,
+    pub detail: String,
+}
+
+/// Yields a `Finding` for each problem in the rows of `reader`, in order. Only errors that stop
+/// the input from being read any further, such as I/O errors, are returned as errors.
+pub struct Lint {
+    reader: CsvFileReader,
+    // Deposit and withdrawal ids seen so far
+    seen: HashSet<TransactionId>,
+    last_transaction_id: Option<TransactionId>,
+}
+
+impl Lint {
+    pub fn new(reader: CsvFileReader) -> Self {
+        Self {
+            reader,
+            seen: HashSet::new(),
+            last_transaction_id: None,
+        }
+    }
+}
+
+impl Iterator for Lint {
+    type Item = Result<Finding, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let txn = match self.reader.next()? {
+                Ok(txn) => txn,
+                Err(err) => {
+                    let err = match err.downcast::<RecordError>() {
+                        Ok(err) => err,
+                        Err(err) => return Some(Err(err)),
+                    };
+                    let kind = match err.source.downcast_ref::<TransactionFieldsError>() {
+                        Some(TransactionFieldsError::UnrecognisedType(_)) => {
+                            FindingKind::UnknownType
+                        }
+                        Some(TransactionFieldsError::NonPositiveAmount { .. }) => {
+                            FindingKind::NonPositiveAmount
+                        }
+                        Some(TransactionFieldsError::InvalidAmount(_)) | None => {
+                            FindingKind::Malformed
+                        }
+                        Some(_) => FindingKind::MissingAmount,
+                    };
+                    return Some(Ok(Finding {
+                        line: err.line,
+                        kind,
+                        detail: err.source.to_string(),
+                    }));
+                }
+            };
+
+            let transaction_id = txn.transaction_id;
+            let finding = |kind, detail| {
+                Some(Ok(Finding {
+                    line: self.reader.line(),
+                    kind,
+                    detail,
+                }))
+            };
+
+            match txn.inner {
+                TransactionInner::Deposit(_)
+                | TransactionInner::Withdrawal(_)
+                | TransactionInner::Transfer { .. }
+                | TransactionInner::Adjustment { .. }
+                | TransactionInner::Close
+                | TransactionInner::Authorize(_) => {
+                    if !self.seen.insert(transaction_id) {
+                        return finding(
+                            FindingKind::DuplicateTransactionId,
+                            format!("Transaction {} has already been seen", transaction_id),
+                        );
+                    }
+
+                    match self.last_transaction_id {
+                        Some(last) if transaction_id <= last => {
+                            return finding(
+                                FindingKind::NonMonotonicTransactionId,
+                                format!(
+                                    "Transaction {} follows transaction {}",
+                                    transaction_id, last
+                                ),
+                            );
+                        }
+                        _ => self.last_transaction_id = Some(transaction_id),
+                    }
+                }
+                TransactionInner::Dispute
+                | TransactionInner::PartialDispute(_)
+                | TransactionInner::Resolve
+                | TransactionInner::Chargeback
+                | TransactionInner::Unlock
+                | TransactionInner::Refund
+                | TransactionInner::Representment
+                | TransactionInner::Capture
+                | TransactionInner::Void => {
+                    if !self.seen.contains(&transaction_id) {
+                        return finding(
+                            FindingKind::UnknownTransaction,
+                            format!(
+                                "Unknown transaction {} ({})",
+                                transaction_id,
+                                txn.inner.type_name()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FindingKind, Lint};
+    use crate::io::CsvFileReader;
+
+    #[test]
+    fn test_lint() {
+        let data = b"type,client,tx,amount\n\
+            deposit,1,2,1.0\n\
+            bonus,1,3,1.0\n\
+            withdrawal,1,4,\n\
+            deposit,1,2,1.0\n\
+            deposit,1,1,1.0\n\
+            dispute,1,9,\n\
+            deposit,x,5,1.0\n\
+            resolve,1,1,\n\
+            withdrawal,1,6,-5.0\n\
+            deposit,1,7,1.00005\n";
+
+        let findings = Lint::new(CsvFileReader::from_reader(Box::new(&data[..])))
+            .map(|finding| finding.map(|finding| (finding.line, finding.kind)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(
+            findings,
+            [
+                (3, FindingKind::UnknownType),
+                (4, FindingKind::MissingAmount),
+                (5, FindingKind::DuplicateTransactionId),
+                (6, FindingKind::NonMonotonicTransactionId),
+                (7, FindingKind::UnknownTransaction),
+                (8, FindingKind::Malformed),
+                (10, FindingKind::NonPositiveAmount),
+                (11, FindingKind::Malformed),
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! systemd socket activation. When a service is started through a `.socket` unit, systemd binds
+//! the listening sockets itself and passes them in starting at file descriptor 3, describing them
+//! with the `LISTEN_PID`, `LISTEN_FDS` and (optionally) `LISTEN_FDNAMES` environment variables.
+//! Server modes should get their listeners through `bind` so they work both with and without it.
+
+use std::{
+    net::TcpListener,
+    os::unix::io::{FromRawFd, RawFd},
+    sync::{Mutex, OnceLock},
+};
+
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+// The sockets handed to us by systemd that haven't been claimed by a server yet
+static INHERITED: OnceLock<Mutex<Vec<(String, RawFd)>>> = OnceLock::new();
+
+/// Returns a listener for the server called `name`. If systemd passed in a socket named `name`
+/// (via `FileDescriptorName=`), or an unnamed one that no other server has claimed, it's used as
+/// is; otherwise a new socket is bound to `addr`.
+pub fn bind(name: &str, addr: &str) -> Result<TcpListener, std::io::Error> {
+    let inherited = INHERITED.get_or_init(|| {
+        let fds = parse_listen_env(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+            std::process::id(),
+        );
+
+        // These are meant for us only, not for any child processes
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        Mutex::new(fds)
+    });
+
+    let mut inherited = inherited.lock().unwrap();
+    let position = inherited
+        .iter()
+        .position(|(fd_name, _)| fd_name == name)
+        .or_else(|| {
+            inherited
+                .iter()
+                .position(|(fd_name, _)| fd_name == "unknown")
+        });
+
+    match position {
+        Some(position) => {
+            let (_, fd) = inherited.remove(position);
+            // Safety: systemd guarantees the descriptor is an open socket owned by this process,
+            // and removing it from `INHERITED` means it can't be wrapped twice.
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            // Fail early if it's something other than a TCP socket
+            listener.local_addr()?;
+            Ok(listener)
+        }
+        None => TcpListener::bind(addr),
+    }
+}
+
+fn parse_listen_env(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    listen_fdnames: Option<&str>,
+    our_pid: u32,
+) -> Vec<(String, RawFd)> {
+    // The variables may have been inherited from a parent that was socket activated
+    if listen_pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(our_pid) {
+        return Vec::new();
+    }
+
+    let count = match listen_fds.and_then(|count| count.parse::<RawFd>().ok()) {
+        Some(count) => count,
+        None => return Vec::new(),
+    };
+
+    let mut names = listen_fdnames
+        .into_iter()
+        .flat_map(|names| names.split(':'));
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        .map(|fd| (names.next().unwrap_or("unknown").to_string(), fd))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_listen_env;
+
+    #[test]
+    fn test_parse_listen_env() {
+        // Not activated
+        assert_eq!(parse_listen_env(None, None, None, 42), vec![]);
+
+        // Meant for another process
+        assert_eq!(parse_listen_env(Some("41"), Some("1"), None, 42), vec![]);
+
+        // Unnamed
+        assert_eq!(
+            parse_listen_env(Some("42"), Some("2"), None, 42),
+            vec![("unknown".to_string(), 3), ("unknown".to_string(), 4)]
+        );
+
+        // Named
+        assert_eq!(
+            parse_listen_env(Some("42"), Some("2"), Some("http:tcp"), 42),
+            vec![("http".to_string(), 3), ("tcp".to_string(), 4)]
+        );
+    }
+}
@@ -1,27 +1,2299 @@
-mod io;
-mod state;
-mod types;
+use clap::{builder::BoolishValueParser, Args, CommandFactory, Parser, Subcommand};
+#[cfg(unix)]
+use off_chain_transactions::daemon;
+use off_chain_transactions::{
+    checkpoint,
+    generate::{self, GenerateOptions},
+    health,
+    io::{
+        self,
+        location::{Located, SharedLocation},
+        ColumnMap, Format, RecordError,
+    },
+    lint::{Finding, Lint},
+    manifest::Manifest,
+    notify::{self, Notifier},
+    output::{self, OutputFormat, OutputOptions, Precision},
+    replay::{Paced, Timed},
+    server,
+    state::{
+        self, ClosedAccounts, DuplicateIds, IdOrdering, LockedAccounts, Outcome, WithdrawalDisputes,
+    },
+    types::FixedFloat,
+    wal::Wal,
+};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Processes a stream of client transactions (deposits, withdrawals and disputes) and works out
+/// the final state of every client account.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Log to stderr at this level or above: `off`, `error`, `warn`, `info`, `debug` or `trace`.
+    /// `debug` logs every ignored transaction and why, and `trace` every applied one
+    #[arg(long, global = true, env = "OCT_LOG_LEVEL", default_value = "off")]
+    log_level: LevelFilter,
+    /// Log as JSON lines rather than plain text
+    #[arg(long, global = true, env = "OCT_LOG_JSON", value_parser = BoolishValueParser::new())]
+    log_json: bool,
+    /// Export a trace of each input over OTLP/HTTP, configured by the standard
+    /// `OTEL_EXPORTER_OTLP_*` variables (otlp feature)
+    #[arg(long, global = true, env = "OCT_OTLP", value_parser = BoolishValueParser::new())]
+    otlp: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transactions and write the final account states (the default)
+    Process(Box<ProcessArgs>),
+    /// Check that the input parses and that every transaction is valid, without writing anything
+    Validate(ValidateArgs),
+    /// Process transactions and write an HTML summary of the run
+    Report(ReportArgs),
+    /// Check a CSV input for problems without processing it, and list every one found
+    Lint(LintArgs),
+    /// Write random (but valid) transactions as CSV, for load testing
+    Generate(GenerateArgs),
+    /// Rebuild the final account states from an event log written with `process --events`
+    Replay(ReplayArgs),
+    /// Compare two sets of accounts (CSV output or saved states) and list the clients whose
+    /// accounts differ. Exits with 1 if any do, and 2 on an error
+    Diff(DiffArgs),
+    /// Process transactions and print a hash of the final state, for checking that runs agree
+    Fingerprint(FingerprintArgs),
+    /// Serve the engine over the network, so other services can submit transactions and read
+    /// accounts directly. Runs until SIGINT or SIGTERM
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// The input file, or `-` for stdin. Optional with --manifest
+    input: Option<String>,
+    /// The input format; detected from the file extension and contents by default
+    #[arg(long, env = "OCT_FORMAT")]
+    format: Option<Format>,
+    #[command(flatten)]
+    csv: CsvArgs,
+    /// Verify the input against a manifest of checksums and row counts
+    #[arg(long, env = "OCT_MANIFEST", value_name = "PATH")]
+    manifest: Option<PathBuf>,
+    /// Pull the input from the paginated REST API described in this file, rather than reading a
+    /// file (http-pull feature)
+    #[arg(
+        long,
+        env = "OCT_HTTP_SOURCE",
+        value_name = "PATH",
+        conflicts_with_all = ["input", "manifest"]
+    )]
+    http_source: Option<PathBuf>,
+    /// Skip deposits, withdrawals, transfers and authorizations of zero or less with a warning,
+    /// rather than failing on them as malformed rows
+    #[arg(long, env = "OCT_SKIP_NON_POSITIVE", value_parser = BoolishValueParser::new())]
+    skip_non_positive: bool,
+    /// Fail on a row with an earlier `timestamp` than one before it
+    #[arg(long, env = "OCT_CHRONOLOGICAL", value_parser = BoolishValueParser::new())]
+    chronological: bool,
+    /// Print progress (rows, bytes read, rows/s and an ETA) to stderr every few seconds
+    #[arg(long, env = "OCT_PROGRESS", value_parser = BoolishValueParser::new())]
+    progress: bool,
+    // Set with --otlp, so that each batch of rows is traced
+    #[arg(skip)]
+    trace_batch_size: Option<usize>,
+}
+
+#[derive(Args)]
+struct CsvArgs {
+    /// The CSV delimiter (`\t` or `tab` for a tab); a tab for .tsv files and a comma otherwise
+    #[arg(long, env = "OCT_DELIMITER", value_parser = parse_csv_char)]
+    delimiter: Option<u8>,
+    /// The CSV quote character
+    #[arg(long, env = "OCT_QUOTE", value_parser = parse_csv_char, default_value = "\"")]
+    quote: u8,
+    /// The CSV input has no header row, so its columns are in the usual order
+    #[arg(
+        long,
+        env = "OCT_NO_HEADER",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "map"
+    )]
+    no_header: bool,
+    /// Read CSV columns with non-standard header names, e.g. `client=customer_id`
+    #[arg(long, env = "OCT_MAP", value_name = "COLUMN=NAME,...")]
+    map: Vec<ColumnMap>,
+}
+
+#[derive(Args)]
+struct EngineArgs {
+    /// Check that the ids of deposits, withdrawals, transfers, adjustments, authorizations and
+    /// closes only ever increase: `unchecked` (don't), `warn` (log a warning, see --log-level, and
+    /// carry on) or `strict` (treat one out of order as invalid)
+    #[arg(
+        long,
+        env = "OCT_MONOTONIC_IDS",
+        value_name = "MODE",
+        default_value = "unchecked"
+    )]
+    monotonic_ids: IdOrdering,
+    /// Treat a deposit or withdrawal as invalid unless its id is larger than every earlier one of
+    /// the same client's
+    #[arg(long, env = "OCT_CLIENT_MONOTONIC_IDS", value_parser = BoolishValueParser::new())]
+    client_monotonic_ids: bool,
+    /// What happens to a transaction whose id is already taken: `error` (it's invalid), `skip`
+    /// (ignore it) or `idempotent` (ignore it if it's a resend of the original, or else it's
+    /// invalid)
+    #[arg(
+        long,
+        env = "OCT_DUPLICATE_IDS",
+        value_name = "MODE",
+        default_value = "error"
+    )]
+    duplicate_ids: DuplicateIds,
+    /// Abort on an invalid transaction, rather than ignoring it
+    #[arg(long, env = "OCT_STRICT", value_parser = BoolishValueParser::new())]
+    strict: bool,
+    /// What to do with deposits and withdrawals on closed accounts: `reject`, `allow-deposits`
+    /// or `allow`
+    #[arg(
+        long,
+        env = "OCT_CLOSED_ACCOUNTS",
+        value_name = "MODE",
+        default_value = "reject"
+    )]
+    closed_accounts: ClosedAccounts,
+    /// Void an authorization by itself if it isn't captured or voided within this many more
+    /// transactions
+    #[arg(long, env = "OCT_AUTHORIZATION_EXPIRY", value_name = "TRANSACTIONS")]
+    authorization_expiry: Option<u64>,
+    /// Resolve a dispute by itself if it isn't resolved or charged back within this many more
+    /// transactions
+    #[arg(long, env = "OCT_DISPUTE_EXPIRY", value_name = "TRANSACTIONS")]
+    dispute_expiry: Option<u64>,
+    /// Void an authorization by itself if it isn't captured or voided by a transaction
+    /// timestamped this many seconds later (falling back to --authorization-expiry for one
+    /// without a timestamp)
+    #[arg(long, env = "OCT_AUTHORIZATION_EXPIRY_SECONDS", value_name = "SECONDS")]
+    authorization_expiry_seconds: Option<u64>,
+    /// Resolve a dispute by itself if it isn't resolved or charged back by a transaction
+    /// timestamped this many seconds later (falling back to --dispute-expiry for one without a
+    /// timestamp)
+    #[arg(long, env = "OCT_DISPUTE_EXPIRY_SECONDS", value_name = "SECONDS")]
+    dispute_expiry_seconds: Option<u64>,
+    /// Unlock the account when a representment restores a chargeback's funds
+    #[arg(long, env = "OCT_REPRESENTMENT_UNLOCKS", value_parser = BoolishValueParser::new())]
+    representment_unlocks: bool,
+    /// Ignore disputes of a transaction that's already been disputed this many times
+    #[arg(long, env = "OCT_MAX_DISPUTES", value_name = "COUNT")]
+    max_disputes: Option<u32>,
+    /// Ignore disputes timestamped more than this many days after the transaction they dispute
+    #[arg(long, env = "OCT_DISPUTE_WINDOW", value_name = "DAYS")]
+    dispute_window: Option<u64>,
+    /// What disputing a withdrawal does: `reject`, `treat-as-negative` (put its funds back while
+    /// held) or `hold-positive` (hold its amount, as for a deposit)
+    #[arg(
+        long,
+        env = "OCT_WITHDRAWAL_DISPUTES",
+        value_name = "MODE",
+        default_value = "treat-as-negative"
+    )]
+    withdrawal_disputes: WithdrawalDisputes,
+    /// Ignore disputes, resolves, chargebacks and other rows referring to an earlier transaction
+    /// unless they name its client
+    #[arg(long, env = "OCT_REQUIRE_CLIENT_MATCH", value_parser = BoolishValueParser::new())]
+    require_client_match: bool,
+    /// What happens to each type of transaction on a locked account, e.g.
+    /// `deposit=queue,withdrawal=reject`: `allow`, `reject` or `queue` (until it's unlocked). By
+    /// default, deposits are allowed and withdrawals, transfers and authorizations rejected
+    #[arg(long, env = "OCT_LOCKED_ACCOUNTS", value_name = "TYPE=MODE,...")]
+    locked_accounts: Option<LockedAccounts>,
+    /// Treat deposits, withdrawals, transfers and authorizations of more than this as invalid
+    #[arg(long, env = "OCT_MAX_AMOUNT", value_name = "AMOUNT")]
+    max_amount: Option<FixedFloat>,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Write to this path, atomically, rather than stdout
+    #[arg(long, env = "OCT_OUTPUT", value_name = "PATH")]
+    output: Option<PathBuf>,
+    /// How amounts are written: `minimal` or `fixed4`
+    #[arg(long, env = "OCT_PRECISION", default_value = "minimal")]
+    precision: Precision,
+    /// Give every CSV row `closed` and `pending_payout` columns
+    #[arg(long, env = "OCT_CLOSURE_COLUMNS", value_parser = BoolishValueParser::new())]
+    closure_columns: bool,
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// `csv`, `json` or `jsonl`
+    #[arg(long, env = "OCT_OUTPUT_FORMAT", default_value = "csv")]
+    output_format: OutputFormat,
+    /// Leave out accounts with nothing available or held, and which aren't locked
+    #[arg(long, env = "OCT_SKIP_EMPTY", value_parser = BoolishValueParser::new())]
+    skip_empty: bool,
+    /// Write one output file per bucket of client ids into this directory
+    #[arg(
+        long,
+        env = "OCT_SHARD_DIR",
+        value_name = "DIR",
+        conflicts_with_all = ["output", "daemon"]
+    )]
+    shard_dir: Option<PathBuf>,
+    /// The number of consecutive client ids in each --shard-dir file
+    #[arg(
+        long,
+        env = "OCT_SHARD_SIZE",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    shard_size: u16,
+    /// Write an account's row to stdout whenever it changes, rather than all of them at the end
+    #[arg(
+        long,
+        env = "OCT_DELTAS",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["output", "shard_dir"]
+    )]
+    deltas: bool,
+    /// Print end-of-run statistics to stderr
+    #[arg(long, env = "OCT_SUMMARY", value_parser = BoolishValueParser::new())]
+    summary: bool,
+    /// Write end-of-run statistics to this path as JSON
+    #[arg(long, env = "OCT_SUMMARY_JSON", value_name = "PATH")]
+    summary_json: Option<PathBuf>,
+    /// Time each transaction, for latency percentiles by type in --summary, --summary-json and
+    /// --statsd
+    #[arg(long, env = "OCT_LATENCY", value_parser = BoolishValueParser::new())]
+    latency: bool,
+    /// Send end-of-run metrics to this StatsD server over UDP
+    #[arg(long, env = "OCT_STATSD", value_name = "HOST:PORT")]
+    statsd: Option<String>,
+    /// The prefix of every metric sent to --statsd
+    #[arg(long, env = "OCT_STATSD_PREFIX", default_value = "oct")]
+    statsd_prefix: String,
+    /// Send the transaction type and outcome to --statsd as DogStatsD tags
+    #[arg(
+        long,
+        env = "OCT_STATSD_TAGS",
+        value_parser = BoolishValueParser::new(),
+        requires = "statsd"
+    )]
+    statsd_tags: bool,
+    /// Once the input is done, check that every account is consistent with its transactions, and
+    /// fail without writing the accounts if any isn't
+    #[arg(
+        long,
+        env = "OCT_VERIFY",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["daemon", "dry_run"]
+    )]
+    verify: bool,
+    /// Parse and validate everything, and report what would be accepted and rejected, without
+    /// writing any balances or other output
+    #[arg(
+        long,
+        env = "OCT_DRY_RUN",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = [
+            "output", "shard_dir", "deltas", "rejects", "daemon", "parquet", "ipc",
+            "ipc_transactions", "duckdb", "postgres", "kafka_brokers", "audit", "events",
+            "open_disputes",
+        ],
+    )]
+    dry_run: bool,
+    /// Stop with an error if the accounts and transaction cache grow beyond about this much
+    /// memory, e.g. `512M` or `4G`
+    #[arg(
+        long,
+        env = "OCT_MAX_MEMORY",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with = "daemon"
+    )]
+    max_memory: Option<usize>,
+    /// Keep the transaction cache on disk, in a database in this directory, rather than in memory
+    /// (sled feature). Whatever is in the directory is cleared, and it's deleted afterwards
+    #[arg(
+        long,
+        env = "OCT_TRANSACTION_STORE",
+        value_name = "DIR",
+        conflicts_with = "wal"
+    )]
+    transaction_store: Option<PathBuf>,
+    /// Start from the state saved in this snapshot (see --save-state), rather than from nothing
+    #[arg(long, env = "OCT_LOAD_STATE", value_name = "PATH")]
+    load_state: Option<PathBuf>,
+    /// Carry on from a previous run: its accounts as CSV output, or its whole state (see
+    /// --save-state), detected from the contents
+    #[arg(
+        long,
+        env = "OCT_PREVIOUS",
+        value_name = "PATH",
+        conflicts_with_all = ["load_state", "resume", "events"]
+    )]
+    previous: Option<PathBuf>,
+    /// Once the input is done, save the whole state (accounts and transaction cache) to this path
+    #[arg(
+        long,
+        env = "OCT_SAVE_STATE",
+        value_name = "PATH",
+        conflicts_with = "daemon"
+    )]
+    save_state: Option<PathBuf>,
+    /// Write a checkpoint (the state and how far through the input the run is) to this path
+    /// every --checkpoint-every transactions, and if interrupted by SIGINT or SIGTERM
+    #[arg(
+        long,
+        env = "OCT_CHECKPOINT",
+        value_name = "PATH",
+        conflicts_with = "daemon"
+    )]
+    checkpoint: Option<PathBuf>,
+    /// How often to write a --checkpoint, in transactions
+    #[arg(
+        long,
+        env = "OCT_CHECKPOINT_EVERY",
+        value_name = "N",
+        requires = "checkpoint",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    checkpoint_every: Option<u64>,
+    /// Carry on from a --checkpoint, skipping the transactions it had already processed (and
+    /// cutting --audit back to where it was then)
+    #[arg(
+        long,
+        env = "OCT_RESUME",
+        value_name = "PATH",
+        conflicts_with_all = ["load_state", "dry_run", "daemon"]
+    )]
+    resume: Option<PathBuf>,
+    /// On SIGUSR1, write the accounts so far to this path, and the progress to it with `.progress`
+    /// appended, then carry on
+    #[arg(long, env = "OCT_DUMP", value_name = "PATH", conflicts_with = "daemon")]
+    dump: Option<PathBuf>,
+    /// Append a row to this CSV file for every change to an account's balances or locked status,
+    /// with the transaction that caused it
+    #[arg(long, env = "OCT_AUDIT", value_name = "PATH")]
+    audit: Option<PathBuf>,
+    /// Write every change made to the state to this path as an event log (JSON Lines), for
+    /// `replay`
+    #[arg(
+        long,
+        env = "OCT_EVENTS",
+        value_name = "PATH",
+        conflicts_with_all = ["load_state", "resume", "daemon", "dry_run"]
+    )]
+    events: Option<PathBuf>,
+    /// Write malformed rows to this path and carry on, rather than aborting
+    #[arg(long, env = "OCT_REJECTS", value_name = "PATH")]
+    rejects: Option<PathBuf>,
+    /// Once the input is done, write the transactions still disputed to this CSV file, with how
+    /// long they've been disputed and where each came from, as a worklist
+    #[arg(long, env = "OCT_OPEN_DISPUTES", value_name = "PATH")]
+    open_disputes: Option<PathBuf>,
+    /// Apply at most this many transactions per second
+    #[arg(long, env = "OCT_REPLAY_RATE", value_name = "N", value_parser = parse_rate)]
+    replay_rate: Option<f64>,
+    /// Apply transactions as far apart as their timestamps, divided by this, e.g. 60 to replay an
+    /// hour in a minute
+    #[arg(
+        long,
+        env = "OCT_REPLAY_SPEED",
+        value_name = "FACTOR",
+        value_parser = parse_speed,
+        conflicts_with = "replay_rate"
+    )]
+    replay_speed: Option<f64>,
+    /// Stream transactions under a supervisor, writing snapshots on signals (unix only)
+    #[arg(
+        long,
+        env = "OCT_DAEMON", value_parser = BoolishValueParser::new(),
+        requires = "snapshot",
+        conflicts_with = "output"
+    )]
+    daemon: bool,
+    /// Under --daemon, mirror each account into Redis at this URL as it changes (redis feature)
+    #[arg(long, env = "OCT_REDIS", value_name = "URL", requires = "daemon")]
+    redis: Option<String>,
+    /// The prefix of each --redis key, which is followed by the client id
+    #[arg(
+        long,
+        env = "OCT_REDIS_PREFIX",
+        default_value = "oct:account:",
+        requires = "redis"
+    )]
+    redis_prefix: String,
+    /// Publish each account to --kafka-topic whenever it changes, through these brokers (kafka
+    /// feature)
+    #[arg(
+        long,
+        env = "OCT_KAFKA_BROKERS",
+        value_name = "HOST:PORT,...",
+        value_delimiter = ',',
+        requires = "kafka_topic"
+    )]
+    kafka_brokers: Vec<String>,
+    /// The topic for --kafka-brokers
+    #[arg(long, env = "OCT_KAFKA_TOPIC", requires = "kafka_brokers")]
+    kafka_topic: Option<String>,
+    /// POST a notification to these URLs whenever an account is locked, a dispute is opened or
+    /// charged back, or an account goes negative or reaches --notify-threshold (webhook feature)
+    #[arg(
+        long,
+        env = "OCT_WEBHOOK",
+        value_name = "URL,...",
+        value_delimiter = ','
+    )]
+    webhook: Vec<String>,
+    /// Print the same notifications as --webhook to stderr
+    #[arg(long, env = "OCT_NOTIFY_STDERR", value_parser = BoolishValueParser::new())]
+    notify_stderr: bool,
+    /// Append the same notifications as --webhook to this file as JSON Lines
+    #[arg(long, env = "OCT_NOTIFY_FILE", value_name = "PATH")]
+    notify_file: Option<PathBuf>,
+    /// Also notify when an account's total funds reach this amount
+    #[arg(long, env = "OCT_NOTIFY_THRESHOLD", value_name = "AMOUNT")]
+    notify_threshold: Option<FixedFloat>,
+    /// Where --daemon writes its snapshots
+    #[arg(long, env = "OCT_SNAPSHOT", value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+    /// Engine settings for --daemon, reloaded when the file changes or on SIGHUP
+    #[arg(long, env = "OCT_CONFIG", value_name = "PATH", requires = "daemon")]
+    config: Option<PathBuf>,
+    /// Log transactions to this file before applying them under --daemon, and recover from it on
+    /// startup
+    #[arg(
+        long,
+        env = "OCT_WAL",
+        value_name = "PATH",
+        requires = "daemon",
+        conflicts_with_all = ["load_state", "previous"]
+    )]
+    wal: Option<PathBuf>,
+    /// Under --daemon, answer health probes (`GET /healthz` and `/readyz`) on this address
+    #[arg(long, env = "OCT_HEALTH", value_name = "ADDR", requires = "daemon")]
+    health: Option<String>,
+    /// Also write the final accounts to a Parquet file (parquet feature)
+    #[arg(long, env = "OCT_PARQUET", value_name = "PATH")]
+    parquet: Option<PathBuf>,
+    /// Also write the final accounts to an Arrow IPC file (ipc feature)
+    #[arg(long, env = "OCT_IPC", value_name = "PATH")]
+    ipc: Option<PathBuf>,
+    /// Also write the transaction cache to an Arrow IPC file (ipc feature)
+    #[arg(long, env = "OCT_IPC_TRANSACTIONS", value_name = "PATH")]
+    ipc_transactions: Option<PathBuf>,
+    /// Also write the accounts, journal and rejects to a DuckDB database (duckdb feature)
+    #[arg(long, env = "OCT_DUCKDB", value_name = "PATH")]
+    duckdb: Option<PathBuf>,
+    /// Also upsert the final accounts into a Postgres table, connecting with this DSN (postgres
+    /// feature)
+    #[arg(long, env = "OCT_POSTGRES", value_name = "DSN")]
+    postgres: Option<String>,
+    /// The table for --postgres, optionally schema-qualified
+    #[arg(
+        long,
+        env = "OCT_POSTGRES_TABLE",
+        default_value = "accounts",
+        requires = "postgres"
+    )]
+    postgres_table: String,
+    /// Check recovery by injecting this many I/O errors into a second run
+    #[cfg(feature = "chaos")]
+    #[arg(
+        long,
+        value_name = "FAULTS",
+        conflicts_with_all = [
+            "deltas", "dry_run", "load_state", "previous", "events", "transaction_store",
+        ]
+    )]
+    chaos: Option<u32>,
+    /// The seed for --chaos; random by default
+    #[cfg(feature = "chaos")]
+    #[arg(long, value_name = "SEED", requires = "chaos")]
+    chaos_seed: Option<u64>,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    engine: EngineArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+#[derive(Args)]
+struct LintArgs {
+    /// The input file, or `-` for stdin
+    input: String,
+    #[command(flatten)]
+    csv: CsvArgs,
+    /// How findings are written: `csv` or `jsonl`
+    #[arg(long, env = "OCT_OUTPUT_FORMAT", default_value = "csv")]
+    output_format: OutputFormat,
+    /// Write the findings to this path, atomically, rather than stdout
+    #[arg(long, env = "OCT_OUTPUT", value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// The event log, or `-` for stdin
+    input: String,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// `csv`, `json` or `jsonl`
+    #[arg(long, env = "OCT_OUTPUT_FORMAT", default_value = "csv")]
+    output_format: OutputFormat,
+    /// Leave out accounts with nothing available or held, and which aren't locked
+    #[arg(long, env = "OCT_SKIP_EMPTY", value_parser = BoolishValueParser::new())]
+    skip_empty: bool,
+}
+
+#[derive(Args)]
+struct FingerprintArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    engine: EngineArgs,
+    /// Hash the state saved here with --save-state, rather than processing an input
+    #[arg(long, env = "OCT_STATE", value_name = "PATH", conflicts_with = "input")]
+    state: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// The accounts before: CSV output or a state saved with --save-state
+    before: PathBuf,
+    /// The accounts after, likewise
+    after: PathBuf,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// `csv` or `jsonl`
+    #[arg(long, env = "OCT_OUTPUT_FORMAT", default_value = "csv")]
+    output_format: OutputFormat,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    engine: EngineArgs,
+    /// Start from the state saved in this snapshot (see process --save-state), rather than from
+    /// nothing
+    #[arg(long, env = "OCT_LOAD_STATE", value_name = "PATH")]
+    load_state: Option<PathBuf>,
+    /// On SIGINT or SIGTERM, save the whole state to this path before exiting
+    #[arg(long, env = "OCT_SAVE_STATE", value_name = "PATH")]
+    save_state: Option<PathBuf>,
+    /// Serve the gRPC API (see proto/engine.proto) on this address, e.g. `127.0.0.1:50051` (grpc
+    /// feature)
+    #[arg(long, env = "OCT_GRPC", value_name = "ADDR")]
+    grpc: Option<String>,
+    /// Serve the REST API on this address, e.g. `127.0.0.1:8080` (rest feature)
+    #[arg(long, env = "OCT_HTTP", value_name = "ADDR")]
+    http: Option<String>,
+    /// Take transactions pushed over WebSocket connections on this address, acknowledging each
+    /// (websocket feature)
+    #[arg(long, env = "OCT_WEBSOCKET", value_name = "ADDR")]
+    websocket: Option<String>,
+    /// Take transactions as lines of CSV or JSON over plain TCP on this address, answering each
+    /// with `OK` or `ERR <reason>` (tcp feature)
+    #[arg(long, env = "OCT_TCP", value_name = "ADDR")]
+    tcp: Option<String>,
+    /// Answer health probes (`GET /healthz` and `/readyz`) on this address
+    #[arg(long, env = "OCT_HEALTH", value_name = "ADDR")]
+    health: Option<String>,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    /// The number of transactions to write
+    #[arg(long, default_value_t = 1000)]
+    transactions: u64,
+    /// The number of distinct clients
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    clients: u16,
+    /// The same seed always gives the same transactions
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Write to this path, atomically, rather than stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+// With --otlp, each batch of this many rows is traced
+const TRACE_BATCH_SIZE: usize = 10_000;
 
 fn main() {
-    if let Err(err) = run() {
+    // Running without a subcommand, as in `off_chain_transactions transactions.csv`, is the
+    // original interface, so it still means `process`
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let is_subcommand = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+        matches!(arg, "-h" | "--help" | "-V" | "--version" | "help")
+            || Cli::command().find_subcommand(arg).is_some()
+    });
+    if !is_subcommand {
+        args.insert(1, "process".into());
+    }
+
+    let mut cli = Cli::parse_from(args);
+    let tracing = match init_tracing(&cli) {
+        Ok(tracing) => tracing,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if cli.otlp {
+        let input = match &mut cli.command {
+            // Batching would hold back rows from a stream until there are enough
+            Command::Process(args) if args.daemon => None,
+            Command::Process(args) => Some(&mut args.input),
+            Command::Validate(args) => Some(&mut args.input),
+            Command::Report(args) => Some(&mut args.input),
+            Command::Fingerprint(args) => Some(&mut args.input),
+            Command::Lint(_)
+            | Command::Generate(_)
+            | Command::Replay(_)
+            | Command::Diff(_)
+            | Command::Serve(_) => None,
+        };
+        if let Some(input) = input {
+            input.trace_batch_size = Some(TRACE_BATCH_SIZE);
+        }
+    }
+
+    // Like diff(1), `diff` exits with 1 when it finds differences, so errors get 2
+    let error_code = match cli.command {
+        Command::Diff(_) => 2,
+        _ => 1,
+    };
+    let result = match cli.command {
+        Command::Process(args) => process(*args),
+        Command::Validate(args) => validate(args),
+        Command::Report(args) => report(args),
+        Command::Lint(args) => lint(args),
+        Command::Generate(args) => generate(args),
+        Command::Replay(args) => replay(args),
+        Command::Diff(args) => diff(args),
+        Command::Fingerprint(args) => fingerprint(args),
+        Command::Serve(args) => serve(args),
+    };
+    // Flushes any traces that haven't been exported yet
+    drop(tracing);
+
+    if let Err(err) = result {
+        if let Some(differ) = err.downcast_ref::<Differ>() {
+            eprintln!("{}", differ);
+            std::process::exit(1);
+        }
         eprintln!("Error: {}", err);
-        std::process::exit(1);
+        std::process::exit(error_code);
+    }
+}
+
+/// Sets up logging to stderr, and with `--otlp`, trace export.
+fn init_tracing(cli: &Cli) -> Result<Tracing, Box<dyn Error>> {
+    let registry = tracing_subscriber::registry().with(io::log::layer(
+        cli.log_level,
+        cli.log_json,
+        std::io::stderr,
+    ));
+
+    #[cfg(feature = "otlp")]
+    if cli.otlp {
+        use opentelemetry::trace::TracerProvider;
+        use tracing_subscriber::Layer;
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()?;
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                opentelemetry_sdk::Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+        // Only spans at info and above, i.e. not one for every transaction
+        registry
+            .with(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(LevelFilter::INFO),
+            )
+            .init();
+        return Ok(Tracing(Some(provider)));
+    }
+    #[cfg(not(feature = "otlp"))]
+    if cli.otlp {
+        return Err("--otlp requires the otlp feature".into());
+    }
+
+    registry.init();
+    Ok(Tracing::default())
+}
+
+/// Shuts down trace export, if any, when dropped, which exports anything still buffered.
+#[derive(Default)]
+struct Tracing(#[cfg(feature = "otlp")] Option<opentelemetry_sdk::trace::SdkTracerProvider>);
+
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.0.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Error: Failed to export traces: {}", err);
+            }
+        }
+    }
+}
+
+impl InputArgs {
+    fn options(&self) -> io::InputOptions {
+        io::InputOptions {
+            format: self.format,
+            csv: self.csv.dialect(),
+            progress: self.progress,
+            trace_batch_size: self.trace_batch_size,
+            stop: None,
+        }
+    }
+
+    /// Opens the input, or with a manifest, every input verified against it. If no input is
+    /// given, all of the files the manifest lists are read in order.
+    fn open(&self) -> Result<io::TransactionReader, Box<dyn Error>> {
+        Ok(self.open_located()?.0)
+    }
+
+    /// Like `open`, but also keeps track of which file, and which row of it, the last transaction
+    /// read came from.
+    fn open_located(&self) -> Result<(io::TransactionReader, SharedLocation), Box<dyn Error>> {
+        self.open_stoppable(None)
+    }
+
+    /// Like `open_located`, but stdin and files end early once `stop` is stopped (see
+    /// `io::stop`).
+    fn open_stoppable(
+        &self,
+        stop: Option<io::stop::Stop>,
+    ) -> Result<(io::TransactionReader, SharedLocation), Box<dyn Error>> {
+        let options = io::InputOptions {
+            stop,
+            ..self.options()
+        };
+        let readers = match (&self.manifest, &self.input) {
+            (Some(manifest_path), input) => {
+                let manifest = Manifest::load(manifest_path)?;
+                let entries = match input {
+                    Some(input) => vec![manifest.entry(Path::new(input))?.clone()],
+                    None => manifest.entries,
+                };
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let name = entry.path.display().to_string();
+                        Ok((name, io::open_verified(entry, &options)?))
+                    })
+                    .collect::<Result<Vec<_>, Box<dyn Error>>>()?
+            }
+            (None, Some(input)) => vec![(input.clone(), io::open(input, &options)?)],
+            (None, None) => match &self.http_source {
+                Some(path) => vec![(path.display().to_string(), open_http_source(path)?)],
+                None => return Err("Input filename not specified".into()),
+            },
+        };
+
+        let location = SharedLocation::default();
+        let mut reader: io::TransactionReader = Box::new(readers.into_iter().flat_map({
+            let location = location.clone();
+            move |(name, reader)| Located::new(reader, &name, location.clone())
+        }));
+        if self.skip_non_positive {
+            reader = Box::new(io::rejects::SkipNonPositive::new(reader));
+        }
+        if self.chronological {
+            reader = Box::new(io::chronological::Chronological::new(reader));
+        }
+        Ok((reader, location))
     }
 }
 
-fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let input_filename = std::env::args()
-        .nth(1)
-        .ok_or("Input filename not specified")?;
+fn open_http_source(path: &Path) -> Result<io::TransactionReader, Box<dyn Error>> {
+    #[cfg(feature = "http-pull")]
+    {
+        Ok(Box::new(io::http_pull::open(path)?))
+    }
+    #[cfg(not(feature = "http-pull"))]
+    {
+        let _ = path;
+        Err("--http-source requires the http-pull feature".into())
+    }
+}
+
+impl CsvArgs {
+    fn dialect(&self) -> io::CsvDialect {
+        let mut dialect = io::CsvDialect {
+            delimiter: self.delimiter,
+            quote: self.quote,
+            has_headers: !self.no_header,
+            ..Default::default()
+        };
+        for columns in &self.map {
+            dialect.columns.extend(columns.clone());
+        }
+        dialect
+    }
+}
+
+impl EngineArgs {
+    fn options(&self) -> state::Options {
+        state::Options {
+            id_ordering: self.monotonic_ids,
+            client_monotonic_ids: self.client_monotonic_ids,
+            duplicate_ids: self.duplicate_ids,
+            strict: self.strict,
+            closed_accounts: self.closed_accounts,
+            authorization_expiry: self.authorization_expiry,
+            dispute_expiry: self.dispute_expiry,
+            authorization_expiry_seconds: self.authorization_expiry_seconds,
+            dispute_expiry_seconds: self.dispute_expiry_seconds,
+            representment_unlocks: self.representment_unlocks,
+            max_disputes: self.max_disputes,
+            dispute_window_seconds: self
+                .dispute_window
+                .map(|days| days.saturating_mul(24 * 60 * 60)),
+            withdrawal_disputes: self.withdrawal_disputes,
+            require_client_match: self.require_client_match,
+            locked_accounts: self.locked_accounts.unwrap_or_default(),
+            max_amount: self.max_amount,
+            ..Default::default()
+        }
+    }
+}
+
+impl ProcessArgs {
+    fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            format: self.output_format,
+            skip_empty: self.skip_empty,
+            precision: self.output.precision,
+            closures: self.output.closure_columns,
+        }
+    }
+}
+
+fn process(args: ProcessArgs) -> Result<(), Box<dyn Error>> {
+    let started = std::time::Instant::now();
+    let options = state::Options {
+        track_latency: args.latency,
+        ..args.engine.options()
+    };
+
+    #[cfg(feature = "chaos")]
+    if let Some(faults) = args.chaos {
+        let input = args
+            .input
+            .input
+            .as_deref()
+            .ok_or("Input filename not specified")?;
+        let seed = match args.chaos_seed {
+            Some(seed) => seed,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_nanos() as u64,
+        };
+        let report = off_chain_transactions::chaos::run(input, &options, faults, seed)?;
+        eprintln!(
+            "Chaos run with seed {} matched after {} injected faults",
+            report.seed, report.faults_injected
+        );
+        write_output(
+            &report.state,
+            args.output_options(),
+            args.output.output.as_deref(),
+        )?;
+        return Ok(());
+    }
+
+    // Started before anything is restored, so that probes see it happening
+    let health = args.health.as_deref().map(serve_health).transpose()?;
+
+    // Lets the daemon stop reading on SIGTERM
+    let stop = args.daemon.then(io::stop::Stop::default);
+    let (reader, location) = args.input.open_stoppable(stop.clone())?;
+    if args.dry_run {
+        return dry_run(&args, reader, options);
+    }
+
+    let Restored {
+        reader,
+        mut state,
+        processed,
+        audit_len,
+    } = restore(&args, reader, options)?;
+    let mut sinks = Sinks::open(&args, audit_len)?;
+    if args.daemon {
+        return run_daemon(args, reader, stop.unwrap_or_default(), state, sinks, health);
+    }
+
+    sinks.open_batch(&args)?;
+    let unknown_references = process_transactions(
+        &args, reader, &location, &mut state, &mut sinks, processed, started,
+    )?;
+    finish(&args, &state, sinks, unknown_references, started)
+}
+
+/// Processes everything, and prints how many transactions would be accepted, queued and
+/// rejected (and why) rather than writing anything.
+fn dry_run(
+    args: &ProcessArgs,
+    reader: io::TransactionReader,
+    options: state::Options,
+) -> Result<(), Box<dyn Error>> {
+    let mut state = initial_state(args, options)?;
+    use_transaction_store(args, &mut state)?;
+    let dry_run = DryRun::run(reader, &mut state)?;
+
+    println!("Would accept {} transactions", dry_run.accepted);
+    if dry_run.queued > 0 {
+        println!(
+            "Would queue {} transactions until their accounts are unlocked",
+            dry_run.queued
+        );
+    }
+    println!("Would reject {} transactions", dry_run.rejected());
+    if dry_run.malformed > 0 {
+        println!("  malformed: {}", dry_run.malformed);
+    }
+    for (reason, count) in &dry_run.ignored {
+        println!("  {}: {}", reason, count);
+    }
+    Ok(())
+}
+
+/// Where a run starts from (see `restore`).
+struct Restored {
+    // The rest of the input, passed through --rejects and any pacing
+    reader: io::TransactionReader,
+    state: state::State,
+    // How many transactions were processed before the checkpoint being resumed from
+    processed: u64,
+    // How long the audit log was then, if that was recorded
+    audit_len: Option<u64>,
+}
+
+/// The state to start from (see `initial_state`), or with `--resume`, the checkpoint's.
+fn restore(
+    args: &ProcessArgs,
+    reader: io::TransactionReader,
+    options: state::Options,
+) -> Result<Restored, Box<dyn Error>> {
+    let reader: io::TransactionReader = match &args.rejects {
+        Some(rejects_path) => Box::new(io::rejects::SkipMalformed::new(reader, rejects_path)?),
+        None => reader,
+    };
+    let (reader, mut state, processed, audit_len): (io::TransactionReader, _, _, _) =
+        match &args.resume {
+            Some(resume) => {
+                let checkpoint = checkpoint::load(resume)
+                    .map_err(|err| format!("Failed to load {}: {}", resume.display(), err))?;
+                if checkpoint.input != args.input.input {
+                    return Err(format!(
+                        "{} is a checkpoint of a different input ({})",
+                        resume.display(),
+                        checkpoint.input.as_deref().unwrap_or("the manifest")
+                    )
+                    .into());
+                }
+                let mut state = checkpoint.state;
+                state.set_options(options);
+                (
+                    checkpoint::skip_processed(reader, checkpoint.transactions),
+                    state,
+                    checkpoint.transactions,
+                    checkpoint.audit_len,
+                )
+            }
+            None => (reader, initial_state(args, options)?, 0, None),
+        };
+    use_transaction_store(args, &mut state)?;
+    let reader: io::TransactionReader = match (args.replay_rate, args.replay_speed) {
+        (Some(replay_rate), _) => Box::new(Paced::new(reader, replay_rate)),
+        (None, Some(replay_speed)) => Box::new(Timed::new(reader, replay_speed)),
+        (None, None) => reader,
+    };
+    Ok(Restored {
+        reader,
+        state,
+        processed,
+        audit_len,
+    })
+}
+
+/// Where what each transaction did goes, as it's processed.
+struct Sinks {
+    deltas: Option<output::delta::DeltaWriter<std::io::Stdout>>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<output::kafka::KafkaSink>,
+    notifier: Option<Notifier>,
+    audit: Option<output::audit::AuditWriter>,
+    // The rest are only opened for a run that ends (see `open_batch`)
+    events: Option<state::EventWriter<io::AtomicFile>>,
+    open_disputes: Option<output::open_disputes::OpenDisputes>,
+    #[cfg(feature = "duckdb")]
+    duckdb: Option<output::duckdb::DuckDbWriter>,
+}
+
+impl Sinks {
+    /// The sinks that a daemon feeds too: `--deltas`, `--kafka-topic`, the notifier and
+    /// `--audit`, which is carried on from `resumed_audit_len` when resuming from a checkpoint
+    /// that recorded it.
+    fn open(args: &ProcessArgs, resumed_audit_len: Option<u64>) -> Result<Self, Box<dyn Error>> {
+        let deltas = args
+            .deltas
+            .then(|| output::delta::DeltaWriter::new(std::io::stdout(), args.output_options()))
+            .transpose()?;
+
+        #[cfg(feature = "kafka")]
+        let kafka = args
+            .kafka_topic
+            .as_deref()
+            .map(|topic| {
+                output::kafka::KafkaSink::connect(&args.kafka_brokers, topic, args.output.precision)
+                    .map_err(|err| format!("Failed to connect to Kafka: {}", err))
+            })
+            .transpose()?;
+        #[cfg(not(feature = "kafka"))]
+        if args.kafka_topic.is_some() {
+            return Err("--kafka-brokers requires the kafka feature".into());
+        }
+
+        let notifier = notifier(args)?;
+        let audit = args
+            .audit
+            .as_deref()
+            .map(|path| match resumed_audit_len {
+                Some(len) => output::audit::AuditWriter::open_at(path, len),
+                None => output::audit::AuditWriter::open(path),
+            })
+            .transpose()?;
+
+        Ok(Self {
+            deltas,
+            #[cfg(feature = "kafka")]
+            kafka,
+            notifier,
+            audit,
+            events: None,
+            open_disputes: None,
+            #[cfg(feature = "duckdb")]
+            duckdb: None,
+        })
+    }
+
+    /// Opens `--events`, `--open-disputes` and `--duckdb`, which a daemon doesn't have, and
+    /// checks that the features the outputs written at the end need are there.
+    fn open_batch(&mut self, args: &ProcessArgs) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "duckdb")]
+        {
+            self.duckdb = args
+                .duckdb
+                .as_deref()
+                .map(output::duckdb::DuckDbWriter::create)
+                .transpose()?;
+        }
+        #[cfg(not(feature = "duckdb"))]
+        if args.duckdb.is_some() {
+            return Err("--duckdb requires the duckdb feature".into());
+        }
+        #[cfg(not(feature = "parquet"))]
+        if args.parquet.is_some() {
+            return Err("--parquet requires the parquet feature".into());
+        }
+        #[cfg(not(feature = "ipc"))]
+        if args.ipc.is_some() || args.ipc_transactions.is_some() {
+            return Err("--ipc and --ipc-transactions require the ipc feature".into());
+        }
+        #[cfg(not(feature = "postgres"))]
+        if args.postgres.is_some() {
+            return Err("--postgres requires the postgres feature".into());
+        }
+
+        self.events = args
+            .events
+            .as_ref()
+            // Only renamed into place once the run has succeeded, like --output
+            .map(|path| io::AtomicFile::create(path).map(state::EventWriter::new))
+            .transpose()?;
+        self.open_disputes = args
+            .open_disputes
+            .is_some()
+            .then(output::open_disputes::OpenDisputes::default);
+        Ok(())
+    }
+
+    /// Sends everything recorded so far on its way, and returns how long the audit log is then,
+    /// for a checkpoint to resume from.
+    fn flush(&mut self) -> Result<Option<u64>, Box<dyn Error>> {
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = &mut self.kafka {
+            kafka.flush()?;
+        }
+        Ok(self
+            .audit
+            .as_mut()
+            .map(|audit| audit.position())
+            .transpose()?)
+    }
+}
+
+/// Runs as a daemon (see `daemon::run`), after recovering from `--wal`, if there is one.
+fn run_daemon(
+    args: ProcessArgs,
+    reader: io::TransactionReader,
+    stop: io::stop::Stop,
+    state: state::State,
+    sinks: Sinks,
+    health: Option<Arc<health::Health>>,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot_path = args
+        .snapshot
+        .as_deref()
+        .ok_or("--daemon requires --snapshot")?;
+    let (wal, state) = match &args.wal {
+        Some(path) => {
+            let (wal, state) = Wal::recover(path, state.options().clone())
+                .map_err(|err| format!("Failed to recover from {}: {}", path.display(), err))?;
+            (Some(wal), state)
+        }
+        None => (None, state),
+    };
+
+    #[cfg(feature = "redis")]
+    let redis = match &args.redis {
+        Some(url) => {
+            let mut redis = output::redis::RedisMirror::connect(url, &args.redis_prefix)
+                .map_err(|err| format!("Failed to connect to Redis: {}", err))?;
+            // Anything loaded or recovered is mirrored straight away
+            redis.record_all(&state);
+            Some(redis)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "redis"))]
+    if args.redis.is_some() {
+        return Err("--redis requires the redis feature".into());
+    }
+
+    #[cfg(unix)]
+    return daemon::run(
+        reader,
+        stop,
+        state,
+        snapshot_path,
+        args.output_options(),
+        daemon::Feeds {
+            deltas: sinks.deltas,
+            #[cfg(feature = "redis")]
+            redis,
+            #[cfg(feature = "kafka")]
+            kafka: sinks.kafka,
+            notifier: sinks.notifier,
+            audit: sinks.audit,
+            health,
+        },
+        args.config.as_deref(),
+        wal,
+    );
+    #[cfg(not(unix))]
+    return Err("--daemon is only supported on unix".into());
+}
+
+/// Processes every transaction from `reader`, recording each to `sinks`, and returns how many
+/// disputes and the like referred to unknown transactions when starting from `--previous`
+/// accounts alone, to warn about. `processed` is how many were processed before, when resuming
+/// from a checkpoint. Writes `--dump` whenever it's asked for, and `--checkpoint` every
+/// `--checkpoint-every` transactions. If the run is interrupted, the accounts so far are written
+/// (see `interrupted`), and it fails.
+fn process_transactions(
+    args: &ProcessArgs,
+    reader: io::TransactionReader,
+    location: &SharedLocation,
+    state: &mut state::State,
+    sinks: &mut Sinks,
+    mut processed: u64,
+    started: std::time::Instant,
+) -> Result<u64, Box<dyn Error>> {
+    // Starting from just the accounts, nothing from before can be disputed, so those disputes
+    // (along with any of transactions that never existed) are counted to warn about
+    let accounts_only = args.previous.is_some() && state.transactions().next().is_none();
+    let mut unknown_references = 0u64;
+
+    let interrupt = interrupt_flag()?;
+    let dump = args.dump.as_ref().map(|_| dump_flag()).transpose()?;
+
+    // Only keep a copy of each transaction around if something needs to see its outcome
+    #[cfg(feature = "duckdb")]
+    let recording = sinks.duckdb.is_some();
+
+    for result in reader {
+        let txn = result?;
+        #[cfg(feature = "duckdb")]
+        let copy = recording.then(|| txn.clone());
+        let (transaction_id, type_name, timestamp) =
+            (txn.transaction_id, txn.inner.type_name(), txn.timestamp);
+        let outcome = state.process(txn)?;
+        if let Some(events) = &mut sinks.events {
+            events.write(state.events())?;
+        }
+        if let Some(audit) = &mut sinks.audit {
+            audit.record(transaction_id, type_name, timestamp, state.events())?;
+        }
+        if let Some(open_disputes) = &mut sinks.open_disputes {
+            open_disputes.record(state, &location.borrow());
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = &mut sinks.kafka {
+            kafka.record(state)?;
+        }
+        if let Some(notifier) = &mut sinks.notifier {
+            notifier.record(state)?;
+        }
+        if let Some(deltas) = &mut sinks.deltas {
+            deltas.record(state, outcome)?;
+        }
+        if accounts_only && outcome == Outcome::Ignored(state::IgnoreReason::UnknownTransaction) {
+            unknown_references += 1;
+        }
+
+        if let Some(max_memory) = args.max_memory {
+            let memory_usage = state.memory_usage();
+            if memory_usage > max_memory {
+                let processed = state
+                    .counters()
+                    .transactions
+                    .values()
+                    .map(|counts| counts.applied + counts.ignored)
+                    .sum::<u64>();
+                return Err(format!(
+                    "Memory limit exceeded after {} transactions: the accounts and transaction \
+                    cache are using about {:.1} MiB, more than --max-memory",
+                    processed,
+                    memory_usage as f64 / 1048576.0
+                )
+                .into());
+            }
+        }
+
+        #[cfg(feature = "duckdb")]
+        if let (Some(txn), Some(duckdb)) = (copy, &mut sinks.duckdb) {
+            duckdb.record(&txn, outcome)?;
+        }
+
+        processed += 1;
+        if let (Some(checkpoint_path), Some(checkpoint_every)) =
+            (&args.checkpoint, args.checkpoint_every)
+        {
+            if processed.is_multiple_of(checkpoint_every) {
+                // Everything up to the checkpoint has to be sent first, or it would be lost if
+                // the run were resumed from it
+                let audit_len = sinks.flush()?;
+                checkpoint::save(
+                    checkpoint_path,
+                    args.input.input.as_deref(),
+                    processed,
+                    audit_len,
+                    state,
+                )?;
+            }
+        }
+        if let (Some(dump_path), Some(dump)) = (&args.dump, &dump) {
+            if dump.swap(false, Ordering::Relaxed) {
+                write_dump(state, args.output_options(), dump_path, processed, started)?;
+            }
+        }
+        if interrupt.load(Ordering::Relaxed) {
+            let audit_len = sinks.flush()?;
+            return interrupted(args, state, processed, audit_len);
+        }
+    }
+
+    Ok(unknown_references)
+}
+
+/// Once the input is done: checks the invariants with `--verify`, finishes off each sink, writes
+/// the other outputs, warnings and summaries, and finally the accounts.
+fn finish(
+    args: &ProcessArgs,
+    state: &state::State,
+    mut sinks: Sinks,
+    unknown_references: u64,
+    started: std::time::Instant,
+) -> Result<(), Box<dyn Error>> {
+    let output_options = args.output_options();
+
+    if args.verify {
+        let violations = state.verify_invariants();
+        for violation in &violations {
+            eprintln!("Invariant violated: {}", violation);
+        }
+        if !violations.is_empty() {
+            return Err(format!("{} invariant violations", violations.len()).into());
+        }
+    }
+
+    if let Some(events) = sinks.events {
+        events.into_inner().commit()?;
+    }
+    if let Some(audit) = &mut sinks.audit {
+        audit.flush()?;
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(kafka) = &mut sinks.kafka {
+        kafka.flush()?;
+    }
+    if let Some(notifier) = &mut sinks.notifier {
+        notifier.finish()?;
+    }
+    if let Some(save_state) = &args.save_state {
+        state.save_snapshot(save_state)?;
+    }
+
+    #[cfg(feature = "duckdb")]
+    if let Some(duckdb) = sinks.duckdb {
+        duckdb.finish(state)?;
+    }
+
+    #[cfg(feature = "parquet")]
+    if let Some(parquet_path) = &args.parquet {
+        output::parquet::write(state, parquet_path)?;
+    }
+
+    #[cfg(feature = "ipc")]
+    if let Some(ipc_path) = &args.ipc {
+        output::ipc::write(state, ipc_path)?;
+    }
+    #[cfg(feature = "ipc")]
+    if let Some(ipc_transactions_path) = &args.ipc_transactions {
+        output::ipc::write_transactions(state, ipc_transactions_path)?;
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some(dsn) = &args.postgres {
+        state
+            .write_to(&mut output::postgres::PostgresSink::new(
+                dsn,
+                &args.postgres_table,
+            ))
+            .map_err(|err| format!("Failed to write to Postgres: {}", err))?;
+    }
+
+    if let (Some(open_disputes), Some(path)) = (&sinks.open_disputes, &args.open_disputes) {
+        write_to(Some(path), |f| {
+            open_disputes
+                .write(state, f, output_options.precision)
+                .map_err(std::io::Error::from)
+        })?;
+    }
+
+    warn_invalid(state);
+    if unknown_references > 0 {
+        eprintln!(
+            "Warning: Ignored {} disputes, resolves and chargebacks of unknown transactions, \
+            which may be from before --previous (carry them over with --save-state instead)",
+            unknown_references
+        );
+    }
+
+    if args.summary || args.summary_json.is_some() || args.statsd.is_some() {
+        let summary = output::summary::Summary::new(state, started.elapsed());
+        if args.summary {
+            eprintln!("{}", summary);
+        }
+        if let Some(summary_json) = &args.summary_json {
+            write_to(Some(summary_json), |f| {
+                serde_json::to_writer_pretty(&mut *f, &summary)?;
+                writeln!(f)
+            })?;
+        }
+        if let Some(statsd) = &args.statsd {
+            output::statsd::StatsdSink::new(
+                statsd.as_str(),
+                &args.statsd_prefix,
+                args.statsd_tags,
+            )?
+            .send(&summary)?;
+        }
+    }
+
+    match &args.shard_dir {
+        Some(shard_dir) => {
+            output::write_shards(state, output_options, shard_dir, args.shard_size)?;
+        }
+        // The accounts have already been written as they changed
+        None if sinks.deltas.is_some() => {}
+        None => write_output(state, output_options, args.output.output.as_deref())?,
+    }
+
+    Ok(())
+}
+
+/// Reads and processes everything without writing any output, reporting every malformed row and
+/// invalid transaction on stderr rather than stopping at the first. Fails if there were any.
+fn validate(args: ValidateArgs) -> Result<(), Box<dyn Error>> {
+    let mut state = state::State::with_options(args.engine.options());
+    let dry_run = DryRun::run(args.input.open()?, &mut state)?;
+
+    let invalid = state.stats().invalid_transactions;
+    eprintln!(
+        "{} transactions read, {} malformed rows, {} invalid transactions",
+        dry_run.accepted + dry_run.queued + dry_run.ignored.values().sum::<u64>(),
+        dry_run.malformed,
+        invalid
+    );
+    if dry_run.malformed > 0 || invalid > 0 {
+        return Err("Validation failed".into());
+    }
+
+    Ok(())
+}
+
+/// What happened to each row of a run whose results are thrown away.
+#[derive(Default)]
+struct DryRun {
+    accepted: u64,
+    // Held back until their accounts are unlocked
+    queued: u64,
+    // By `IgnoreReason` name
+    ignored: BTreeMap<&'static str, u64>,
+    malformed: u64,
+}
+
+impl DryRun {
+    /// Processes everything from `reader` into `state`. Malformed rows are reported on stderr
+    /// and counted, rather than stopping the run.
+    fn run(
+        reader: io::TransactionReader,
+        state: &mut state::State,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut dry_run = Self::default();
+
+        for result in reader {
+            match result {
+                Ok(txn) => match state.process(txn)? {
+                    Outcome::Applied => dry_run.accepted += 1,
+                    Outcome::Ignored(reason) => {
+                        *dry_run.ignored.entry(reason.name()).or_default() += 1
+                    }
+                    Outcome::Queued => dry_run.queued += 1,
+                },
+                Err(err) => {
+                    let err = err.downcast::<RecordError>()?;
+                    eprintln!("{}", err);
+                    dry_run.malformed += 1;
+                }
+            }
+        }
+
+        Ok(dry_run)
+    }
+
+    fn rejected(&self) -> u64 {
+        self.malformed + self.ignored.values().sum::<u64>()
+    }
+}
+
+fn report(args: ReportArgs) -> Result<(), Box<dyn Error>> {
+    let mut state = state::State::with_options(args.engine.options());
+    let mut report = output::html::Report::default();
+
+    for result in args.input.open()? {
+        let txn = result?;
+        let outcome = state.process(txn.clone())?;
+        report.record(&txn, outcome);
+    }
+
+    warn_invalid(&state);
+
+    write_to(args.output.output.as_deref(), |f| {
+        report.write(&state, f, args.output.precision)
+    })?;
+
+    Ok(())
+}
+
+/// Writes every finding, then fails if there were any, with a count of each kind on stderr.
+fn lint(args: LintArgs) -> Result<(), Box<dyn Error>> {
+    if args.output_format == OutputFormat::Json {
+        return Err("Findings can't be written as a JSON array; use jsonl instead".into());
+    }
+
+    let input_options = io::InputOptions {
+        format: Some(Format::Csv),
+        csv: args.csv.dialect(),
+        ..Default::default()
+    };
+    let findings = Lint::new(io::open_csv(&args.input, &input_options)?);
+    let mut counts = BTreeMap::<&'static str, u64>::new();
+    // Reading errors can't be returned through `write_to`, so they're kept aside
+    let mut read_error = None;
+
+    let findings = findings.map_while(|finding| match finding {
+        Ok(finding) => {
+            *counts.entry(finding.kind.name()).or_default() += 1;
+            Some(finding)
+        }
+        Err(err) => {
+            read_error = Some(err);
+            None
+        }
+    });
+
+    #[derive(serde::Serialize)]
+    struct Row<'a> {
+        line: u64,
+        finding: &'a str,
+        detail: &'a str,
+    }
+
+    impl<'a> From<&'a Finding> for Row<'a> {
+        fn from(finding: &'a Finding) -> Self {
+            Self {
+                line: finding.line,
+                finding: finding.kind.name(),
+                detail: &finding.detail,
+            }
+        }
+    }
+
+    write_to(args.output.as_deref(), |f| {
+        if args.output_format == OutputFormat::JsonLines {
+            for finding in findings {
+                serde_json::to_writer(&mut *f, &Row::from(&finding))?;
+                writeln!(f)?;
+            }
+            return Ok(());
+        }
+
+        let mut writer = csv::Writer::from_writer(f);
+        for finding in findings {
+            writer.serialize(Row::from(&finding))?;
+        }
+        writer.flush()
+    })?;
+
+    if let Some(err) = read_error {
+        return Err(err);
+    }
+    if counts.is_empty() {
+        return Ok(());
+    }
+
+    for (kind, count) in &counts {
+        eprintln!("{}: {}", kind, count);
+    }
+    Err(format!("Found {} problems", counts.values().sum::<u64>()).into())
+}
+
+fn generate(args: GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let options = GenerateOptions {
+        clients: args.clients,
+        transactions: args.transactions,
+        seed: args.seed,
+    };
+
+    write_to(args.output.as_deref(), |f| generate::generate(&options, f))?;
+
+    Ok(())
+}
+
+/// Applies every event in the log, in order, to an empty state, and writes the accounts.
+fn replay(args: ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let f: Box<dyn std::io::BufRead> = match args.input.as_str() {
+        "-" => Box::new(std::io::stdin().lock()),
+        path => Box::new(std::io::BufReader::new(
+            std::fs::File::open(path).map_err(|err| format!("Failed to open {}: {}", path, err))?,
+        )),
+    };
 
     let mut state = state::State::default();
+    for event in state::read_events(f) {
+        state.apply_event(&event?);
+    }
+
+    let output_options = OutputOptions {
+        format: args.output_format,
+        skip_empty: args.skip_empty,
+        precision: args.output.precision,
+        closures: args.output.closure_columns,
+    };
+    write_output(&state, output_options, args.output.output.as_deref())?;
+
+    Ok(())
+}
+
+/// Serves the engine until SIGINT or SIGTERM, then saves the state with --save-state.
+fn serve(args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    let health = match args.health.as_deref() {
+        Some(addr) => serve_health(addr)?,
+        None => Arc::default(),
+    };
+    let mut state = match &args.load_state {
+        Some(path) => state::State::load_snapshot(path)
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?,
+        None => state::State::default(),
+    };
+    state.set_options(args.engine.options());
+    let engine = Arc::new(server::Engine::with_health(state, health.clone()));
+    let stop = interrupt_flag()?;
+
+    // Every listener is bound before any server starts, so that a bad address fails straight away
+    let servers = [
+        args.grpc.as_deref().map(grpc_server),
+        args.http.as_deref().map(rest_server),
+        args.websocket.as_deref().map(websocket_server),
+        args.tcp.as_deref().map(tcp_server),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Result<Vec<_>, _>>()?;
+    if servers.is_empty() {
+        return Err("serve needs --grpc, --http, --websocket or --tcp".into());
+    }
+
+    // Each server runs on a thread of its own, and if one fails, the rest are stopped too
+    let mut threads = Vec::new();
+    for (name, serve, listener) in servers {
+        tracing::info!(server = name, addr = %listener.local_addr()?, "Serving");
+        let (engine, stop) = (engine.clone(), stop.clone());
+        threads.push(std::thread::spawn(move || {
+            let result = serve(engine, listener, stop.clone())
+                .map_err(|err| format!("The {} server failed: {}", name, err));
+            if result.is_err() {
+                stop.store(true, Ordering::Relaxed);
+            }
+            result
+        }));
+    }
+    health.set_ready();
+    let mut result = Ok(());
+    for thread in threads {
+        let thread_result = thread.join().expect("Server thread panicked");
+        result = result.and(thread_result);
+    }
+    result?;
+
+    if let Some(path) = &args.save_state {
+        engine.state().save_snapshot(path)?;
+    }
+    Ok(())
+}
+
+// A server for `serve`: its name (which a socket from systemd is picked by, too), the function
+// that serves it until it's told to stop, and its listener
+type Server = (&'static str, ServeFn, std::net::TcpListener);
+type ServeFn =
+    fn(Arc<server::Engine>, std::net::TcpListener, Arc<AtomicBool>) -> Result<(), Box<dyn Error>>;
+
+fn grpc_server(addr: &str) -> Result<Server, Box<dyn Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        Ok(("grpc", server::grpc::serve, bind("grpc", addr)?))
+    }
+    #[cfg(not(feature = "grpc"))]
+    {
+        let _ = addr;
+        Err("--grpc requires the grpc feature".into())
+    }
+}
+
+fn rest_server(addr: &str) -> Result<Server, Box<dyn Error>> {
+    #[cfg(feature = "rest")]
+    {
+        Ok(("http", server::rest::serve, bind("http", addr)?))
+    }
+    #[cfg(not(feature = "rest"))]
+    {
+        let _ = addr;
+        Err("--http requires the rest feature".into())
+    }
+}
+
+fn websocket_server(addr: &str) -> Result<Server, Box<dyn Error>> {
+    #[cfg(feature = "websocket")]
+    {
+        Ok((
+            "websocket",
+            server::websocket::serve,
+            bind("websocket", addr)?,
+        ))
+    }
+    #[cfg(not(feature = "websocket"))]
+    {
+        let _ = addr;
+        Err("--websocket requires the websocket feature".into())
+    }
+}
+
+fn tcp_server(addr: &str) -> Result<Server, Box<dyn Error>> {
+    #[cfg(feature = "tcp")]
+    {
+        Ok(("tcp", server::tcp::serve, bind("tcp", addr)?))
+    }
+    #[cfg(not(feature = "tcp"))]
+    {
+        let _ = addr;
+        Err("--tcp requires the tcp feature".into())
+    }
+}
+
+/// Answers health probes on `addr` from now on, with what the returned `Health` is told.
+fn serve_health(addr: &str) -> Result<Arc<health::Health>, Box<dyn Error>> {
+    let listener = bind("health", addr)?;
+    tracing::info!(server = "health", addr = %listener.local_addr()?, "Serving");
+    let health = Arc::new(health::Health::default());
+    health::serve(health.clone(), listener);
+    Ok(health)
+}
+
+/// A listener for the server called `name`, from systemd if it passed one in (see `listen`), or
+/// bound to `addr`.
+fn bind(name: &str, addr: &str) -> Result<std::net::TcpListener, Box<dyn Error>> {
+    #[cfg(unix)]
+    let listener = off_chain_transactions::listen::bind(name, addr);
+    #[cfg(not(unix))]
+    let listener = {
+        let _ = name;
+        std::net::TcpListener::bind(addr)
+    };
+    Ok(listener.map_err(|err| format!("Failed to listen on {}: {}", addr, err))?)
+}
+
+/// Prints the fingerprint of the state after processing the input, or of a saved state.
+fn fingerprint(args: FingerprintArgs) -> Result<(), Box<dyn Error>> {
+    let state = match &args.state {
+        Some(path) => state::State::load_snapshot(path)
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?,
+        None => {
+            let mut state = state::State::with_options(args.engine.options());
+            state.process_all(args.input.open()?)?;
+            warn_invalid(&state);
+            state
+        }
+    };
+
+    println!("{}", state.fingerprint());
+    Ok(())
+}
+
+/// Writes the differences between two sets of accounts, failing with `Differ` if there are any.
+fn diff(args: DiffArgs) -> Result<(), Box<dyn Error>> {
+    let load = |path: &Path| {
+        io::accounts::load(path)
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))
+    };
+    let differences = output::diff::compare(&load(&args.before)?, &load(&args.after)?);
+
+    write_to(args.output.output.as_deref(), |f| {
+        output::diff::write(&differences, f, args.output_format, args.output.precision)
+    })?;
+
+    match differences.len() {
+        0 => Ok(()),
+        count => Err(Differ(count).into()),
+    }
+}
+
+/// The error `diff` fails with when it finds differences, which isn't really an error at all.
+#[derive(Debug)]
+struct Differ(usize);
+
+impl std::fmt::Display for Differ {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} accounts differ", self.0)
+    }
+}
 
-    for result in io::CsvFileReader::new(&input_filename)? {
-        state.process(result?)?;
+impl Error for Differ {}
+
+fn warn_invalid(state: &state::State) {
+    let invalid_transactions = state.stats().invalid_transactions;
+    if invalid_transactions > 0 {
+        eprintln!(
+            "Warning: Ignored {} invalid transactions (use --strict to fail on them instead)",
+            invalid_transactions
+        );
     }
+}
 
-    // Dump state to stdout
-    state.write(std::io::stdout())?;
+/// An empty state, or with `--load-state` or `--previous`, the one saved there, processing with
+/// `options`.
+fn initial_state(
+    args: &ProcessArgs,
+    options: state::Options,
+) -> Result<state::State, Box<dyn Error>> {
+    let mut state = match (&args.load_state, &args.previous) {
+        (Some(path), _) => state::State::load_snapshot(path)
+            .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?,
+        (None, Some(path)) => {
+            let state = io::accounts::load(path)
+                .map_err(|err| format!("Failed to load {}: {}", path.display(), err))?;
+            let held = state
+                .accounts()
+                .filter(|(_, account)| account.held() != Default::default())
+                .count();
+            if held > 0 && state.transactions().next().is_none() {
+                eprintln!(
+                    "Warning: {} accounts in {} have held funds, which can't be released without \
+                    the disputed transactions (carry them over with --save-state instead)",
+                    held,
+                    path.display()
+                );
+            }
+            state
+        }
+        (None, None) => state::State::default(),
+    };
+    state.set_options(options);
+    Ok(state)
+}
+
+/// A notifier with a sink for each of --webhook, --notify-stderr and --notify-file that was given,
+/// or `None` if none were.
+fn notifier(args: &ProcessArgs) -> Result<Option<Notifier>, Box<dyn Error>> {
+    let mut notifier = Notifier::new(args.notify_threshold);
+    let mut sinks = 0;
+    if !args.webhook.is_empty() {
+        #[cfg(feature = "webhook")]
+        {
+            notifier.add_sink(Box::new(output::webhook::WebhookSink::new(&args.webhook)));
+            sinks += 1;
+        }
+        #[cfg(not(feature = "webhook"))]
+        return Err("--webhook requires the webhook feature".into());
+    }
+    if args.notify_stderr {
+        notifier.add_sink(Box::new(notify::StderrSink));
+        sinks += 1;
+    }
+    if let Some(path) = &args.notify_file {
+        let sink = notify::FileSink::open(path)
+            .map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+        notifier.add_sink(Box::new(sink));
+        sinks += 1;
+    }
+    Ok((sinks > 0).then_some(notifier))
+}
 
+/// Moves the transaction cache to --transaction-store, if there is one.
+fn use_transaction_store(
+    args: &ProcessArgs,
+    state: &mut state::State,
+) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &args.transaction_store else {
+        return Ok(());
+    };
+    #[cfg(feature = "sled")]
+    {
+        let store = state::SledStore::open(path)
+            .map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+        state.set_transaction_store(Box::new(store));
+        Ok(())
+    }
+    #[cfg(not(feature = "sled"))]
+    {
+        let _ = (path, state);
+        Err("--transaction-store requires the sled feature".into())
+    }
+}
+
+/// Set once SIGINT or SIGTERM is received, so that a batch run can stop and write out what it has.
+/// A second signal exits straight away, as usual.
+fn interrupt_flag() -> Result<Arc<AtomicBool>, std::io::Error> {
+    let flag = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 130, flag.clone())?;
+        signal_hook::flag::register(signal, flag.clone())?;
+    }
+    Ok(flag)
+}
+
+/// Set when SIGUSR1 is received; cleared by whoever acts on it.
+fn dump_flag() -> Result<Arc<AtomicBool>, std::io::Error> {
+    let flag = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGUSR1, flag.clone())?;
+    Ok(flag)
+}
+
+/// Writes the accounts as they stand after `processed` transactions to `path`, and a line of
+/// progress and stats to `path` with `.progress` appended, each atomically.
+fn write_dump(
+    state: &state::State,
+    output_options: OutputOptions,
+    path: &Path,
+    processed: u64,
+    started: std::time::Instant,
+) -> Result<(), std::io::Error> {
+    write_output(state, output_options, Some(path))?;
+
+    let mut progress_path = path.as_os_str().to_owned();
+    progress_path.push(".progress");
+    write_to(Some(Path::new(&progress_path)), |f| {
+        writeln!(
+            f,
+            "processed={} elapsed_secs={} {}",
+            processed,
+            started.elapsed().as_secs(),
+            state.stats()
+        )
+    })?;
+
+    eprintln!(
+        "Dumped the accounts after {} transactions to {}",
+        processed,
+        path.display()
+    );
     Ok(())
 }
+
+/// Writes the accounts as they stand after `processed` transactions, marked as partial, and with
+/// `--checkpoint`, where to resume from (along with `audit_len`, the length of the flushed
+/// `--audit` log). Always returns an error, so that the run fails.
+fn interrupted<T>(
+    args: &ProcessArgs,
+    state: &state::State,
+    processed: u64,
+    audit_len: Option<u64>,
+) -> Result<T, Box<dyn Error>> {
+    let output_options = args.output_options();
+    // Never at the usual path, so nothing downstream can mistake it for a complete report
+    let partial = |path: &Path| {
+        let mut path = path.as_os_str().to_owned();
+        path.push(".partial");
+        PathBuf::from(path)
+    };
+
+    let written_to = match (&args.shard_dir, &args.output.output) {
+        (Some(shard_dir), _) => {
+            let shard_dir = partial(shard_dir);
+            output::write_shards(state, output_options, &shard_dir, args.shard_size)?;
+            shard_dir.display().to_string()
+        }
+        (None, Some(output_path)) => {
+            let output_path = partial(output_path);
+            write_output(state, output_options, Some(&output_path))?;
+            output_path.display().to_string()
+        }
+        // The accounts have already been written as they changed
+        (None, None) if args.deltas => "stdout".into(),
+        (None, None) => {
+            write_output(state, output_options, None)?;
+            "stdout".into()
+        }
+    };
+
+    if let Some(checkpoint_path) = &args.checkpoint {
+        checkpoint::save(
+            checkpoint_path,
+            args.input.input.as_deref(),
+            processed,
+            audit_len,
+            state,
+        )?;
+    }
+
+    Err(format!(
+        "Interrupted after {} transactions; partial accounts written to {}",
+        processed, written_to
+    )
+    .into())
+}
+
+/// Writes the accounts to `output_path`, or to stdout if it's `None`.
+fn write_output(
+    state: &state::State,
+    options: OutputOptions,
+    output_path: Option<&Path>,
+) -> Result<(), std::io::Error> {
+    write_to(output_path, |f| output::write_accounts(state, options, f))
+}
+
+/// Calls `write` with `output_path`, or with stdout if it's `None`. A file is written
+/// atomically, so a crash part way through can't leave a truncated report behind. So is an
+/// object, if `output_path` is an object store URL: it's uploaded once it's all been written.
+fn write_to<F>(output_path: Option<&Path>, write: F) -> Result<(), std::io::Error>
+where
+    F: FnOnce(&mut dyn std::io::Write) -> Result<(), std::io::Error>,
+{
+    match output_path {
+        Some(output_path) if output_path.to_str().is_some_and(io::is_object_url) => {
+            let url = output_path.to_str().unwrap();
+            #[cfg(feature = "object-store")]
+            {
+                let mut bytes = Vec::new();
+                write(&mut bytes)?;
+                io::object_store::put(url, bytes)
+            }
+            #[cfg(not(feature = "object-store"))]
+            {
+                let _ = write;
+                Err(std::io::Error::other(format!(
+                    "Writing {} requires the object-store feature",
+                    url
+                )))
+            }
+        }
+        Some(output_path) => io::write_file_atomically(output_path, |f| write(f)),
+        None => write(&mut std::io::stdout()),
+    }
+}
+
+/// Parses a CSV delimiter or quote character, which must be a single byte. Tabs can be given as
+/// `\t` or `tab` to save fighting the shell.
+fn parse_csv_char(arg: &str) -> Result<u8, String> {
+    match arg {
+        "\\t" | "tab" => Ok(b'\t'),
+        _ if arg.len() == 1 => Ok(arg.as_bytes()[0]),
+        _ => Err(format!("\"{}\" is not a single-byte character", arg)),
+    }
+}
+
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let arg = arg.trim_end_matches("iB").trim_end_matches('B');
+    let (number, shift) = match arg.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&arg[..index], 10),
+        Some((index, 'M' | 'm')) => (&arg[..index], 20),
+        Some((index, 'G' | 'g')) => (&arg[..index], 30),
+        Some((index, 'T' | 't')) => (&arg[..index], 40),
+        _ => (arg, 0),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .filter(|size| *size > 0)
+        .ok_or_else(|| "must be a positive size in bytes, optionally with K, M, G or T".into())
+}
+
+fn parse_rate(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(rate) if rate > 0.0 => Ok(rate),
+        _ => Err("must be a positive number of transactions per second".into()),
+    }
+}
+
+fn parse_speed(arg: &str) -> Result<f64, String> {
+    match arg.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err("must be a positive factor to speed the timing up by".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        checkpoint, interrupted, write_dump, Cli, Command, OutputFormat, OutputOptions, Precision,
+        ProcessArgs,
+    };
+    use clap::Parser;
+    use off_chain_transactions::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    // Held while the environment is changed and read, since it's shared by every test
+    static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    // Parses the arguments to `process` of in.csv, with `env` set meanwhile
+    fn process_args_with_env(
+        env: &[(&str, &str)],
+        args: &[&str],
+    ) -> Result<Box<ProcessArgs>, clap::Error> {
+        let _env = ENV.lock().unwrap();
+        for (name, value) in env {
+            std::env::set_var(name, value);
+        }
+        let cli = Cli::try_parse_from(
+            ["off_chain_transactions", "process", "in.csv"]
+                .iter()
+                .chain(args),
+        );
+        for (name, _) in env {
+            std::env::remove_var(name);
+        }
+        match cli?.command {
+            Command::Process(args) => Ok(args),
+            _ => unreachable!(),
+        }
+    }
+
+    fn process_args(args: &[&str]) -> Result<Box<ProcessArgs>, clap::Error> {
+        process_args_with_env(&[], args)
+    }
+
+    fn deposited() -> State {
+        let mut state = State::default();
+        for transaction_id in [1, 2] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    TransactionInner::Deposit(1.0.into()),
+                ))
+                .unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn test_env() {
+        let parse = process_args_with_env;
+        let strict = |value| parse(&[("OCT_STRICT", value)], &[]).map(|args| args.engine.strict);
+
+        // Any of the usual ways of saying yes or no
+        for value in ["1", "true", "yes", "on"] {
+            assert!(strict(value).unwrap());
+        }
+        for value in ["0", "false", "no", "off"] {
+            assert!(!strict(value).unwrap());
+        }
+        assert!(strict("maybe").is_err());
+        assert!(parse(&[("OCT_PRECISION", "fixed2")], &[]).is_err());
+
+        // The command line takes precedence
+        let args = parse(
+            &[("OCT_OUTPUT_FORMAT", "json"), ("OCT_PRECISION", "fixed4")],
+            &["--output-format", "csv"],
+        )
+        .unwrap();
+        assert_eq!(args.output_format, OutputFormat::Csv);
+        assert_eq!(args.output.precision, Precision::Fixed4);
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let window = |days: &str| {
+            process_args(&["--dispute-window", days])
+                .map(|args| args.engine.options().dispute_window_seconds)
+        };
+
+        // Given in days, but the engine counts seconds
+        assert_eq!(window("30").unwrap(), Some(30 * 24 * 60 * 60));
+        assert_eq!(window("0").unwrap(), Some(0));
+        assert_eq!(window(&u64::MAX.to_string()).unwrap(), Some(u64::MAX));
+        assert!(window("-1").is_err());
+        assert_eq!(
+            process_args(&[])
+                .unwrap()
+                .engine
+                .options()
+                .dispute_window_seconds,
+            None
+        );
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let dir = std::env::temp_dir().join(format!("interrupted-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (output, checkpoint_path) = (dir.join("accounts.csv"), dir.join("checkpoint"));
+        let state = deposited();
+
+        let args = process_args(&[
+            "--output",
+            output.to_str().unwrap(),
+            "--checkpoint",
+            checkpoint_path.to_str().unwrap(),
+        ])
+        .unwrap();
+        let err = interrupted::<()>(&args, &state, 2, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Interrupted after 2 transactions; partial accounts written to {}.partial",
+                output.display()
+            )
+        );
+        // Never where a complete run's accounts would be
+        assert!(!output.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("accounts.csv.partial")).unwrap(),
+            "client,available,held,total,locked\n1,2,0,2,false\n"
+        );
+        let checkpoint = checkpoint::load(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.transactions, 2);
+        assert_eq!(checkpoint.input.as_deref(), Some("in.csv"));
+        assert_eq!(checkpoint.state.sorted_accounts(), state.sorted_accounts());
+
+        // Likewise for a directory of shards, without a checkpoint to write
+        let shard_dir = dir.join("shards");
+        let args = process_args(&["--shard-dir", shard_dir.to_str().unwrap()]).unwrap();
+        assert!(interrupted::<()>(&args, &state, 2, None).is_err());
+        assert!(!shard_dir.exists());
+        assert!(dir.join("shards.partial").join("client-1.csv").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_dump() {
+        let dir = std::env::temp_dir().join(format!("dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.jsonl");
+        let options = OutputOptions {
+            format: OutputFormat::JsonLines,
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+
+        // Each dump replaces the last
+        write_dump(&State::default(), options, &path, 0, started).unwrap();
+        write_dump(&deposited(), options, &path, 2, started).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"client\":1,\"available\":2,\"held\":0,\"total\":2,\"locked\":false}\n"
+        );
+        let progress = std::fs::read_to_string(dir.join("dump.jsonl.progress")).unwrap();
+        assert!(progress.starts_with("processed=2 elapsed_secs=0 accounts=1 locked_accounts=0 "));
+
+        // A dump that can't be written fails, rather than leaving a partial one
+        let missing = dir.join("missing").join("dump.jsonl");
+        assert!(write_dump(&deposited(), options, &missing, 2, started).is_err());
+        assert!(!missing.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use sha2::{Digest, Sha256};
+use std::{
+    cell::RefCell,
+    error::Error,
+    io::Read,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// A list of the input files expected for a run, as a CSV file with the columns `file` (relative
+/// to the manifest's own directory), `sha256` (hex) and `rows` (the number of records, not counting
+/// the header). It lets us notice truncated or corrupted transfers before they turn into wrong
+/// balances.
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub rows: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct ManifestRecord {
+    file: String,
+    sha256: String,
+    rows: u64,
+}
+
+impl Manifest {
+    pub fn load(manifest_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let base_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+        let entries = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_path(manifest_path)?
+            .into_deserialize::<ManifestRecord>()
+            .map(|record| {
+                let record = record?;
+                Ok(ManifestEntry {
+                    path: base_dir.join(record.file),
+                    sha256: record.sha256.to_ascii_lowercase(),
+                    rows: record.rows,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        Ok(Self { entries })
+    }
+
+    /// Finds the entry for `input_path`, comparing canonical paths so that it doesn't matter how
+    /// either was spelled.
+    pub fn entry(&self, input_path: &Path) -> Result<&ManifestEntry, Box<dyn Error>> {
+        let input_path = input_path.canonicalize()?;
+
+        for entry in &self.entries {
+            if entry.path.canonicalize()? == input_path {
+                return Ok(entry);
+            }
+        }
+
+        Err(format!("{} is not listed in the manifest", input_path.display()).into())
+    }
+}
+
+/// Checks a file against its manifest entry as it's streamed through, so that verifying a huge
+/// file doesn't need a separate pass over it.
+pub struct Verifier {
+    expected: ManifestEntry,
+    hasher: Rc<RefCell<Sha256>>,
+    rows: u64,
+}
+
+impl Verifier {
+    /// Wraps `reader`, which must read the whole of the file described by `expected`.
+    pub fn new(expected: ManifestEntry, reader: Box<dyn Read>) -> (Self, Box<dyn Read>) {
+        let hasher = Rc::new(RefCell::new(Sha256::new()));
+        let reader = HashingReader {
+            inner: reader,
+            hasher: hasher.clone(),
+        };
+
+        (
+            Self {
+                expected,
+                hasher,
+                rows: 0,
+            },
+            Box::new(reader),
+        )
+    }
+
+    /// Counts the transactions yielded by `iter`, which must come from the reader returned by
+    /// `new`, and finishes verification once it's exhausted.
+    pub fn wrap<I>(self, iter: I) -> Verified<I> {
+        Verified {
+            iter,
+            verifier: Some(self),
+        }
+    }
+
+    fn count_row(&mut self) {
+        self.rows += 1;
+    }
+
+    /// To be called once the whole file has been read.
+    pub fn finish(&self) -> Result<(), VerificationError> {
+        if self.rows != self.expected.rows {
+            return Err(VerificationError::RowCountMismatch {
+                path: self.expected.path.clone(),
+                expected: self.expected.rows,
+                actual: self.rows,
+            });
+        }
+
+        let actual = self
+            .hasher
+            .borrow()
+            .clone()
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        if actual != self.expected.sha256 {
+            return Err(VerificationError::ChecksumMismatch {
+                path: self.expected.path.clone(),
+                expected: self.expected.sha256.clone(),
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Verified<I> {
+    iter: I,
+    verifier: Option<Verifier>,
+}
+
+impl<T, I> Iterator for Verified<I>
+where
+    I: Iterator<Item = Result<T, Box<dyn Error>>>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next() {
+            Some(result) => {
+                if let Some(verifier) = &mut self.verifier {
+                    verifier.count_row();
+                }
+                Some(result)
+            }
+            None => {
+                // Only verify once
+                let verifier = self.verifier.take()?;
+                verifier.finish().err().map(|e| Err(e.into()))
+            }
+        }
+    }
+}
+
+struct HashingReader {
+    inner: Box<dyn Read>,
+    hasher: Rc<RefCell<Sha256>>,
+}
+
+impl Read for HashingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.borrow_mut().update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    RowCountMismatch {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RowCountMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has {} rows but the manifest expects {}",
+                path.display(),
+                actual,
+                expected
+            ),
+            Self::ChecksumMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has SHA-256 {} but the manifest expects {}",
+                path.display(),
+                actual,
+                expected
+            ),
+        }
+    }
+}
+
+impl Error for VerificationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ManifestEntry, VerificationError, Verifier};
+    use std::io::Read;
+
+    fn verify(data: &'static [u8], rows: u64, sha256: &str) -> Result<(), VerificationError> {
+        let expected = ManifestEntry {
+            path: "test.csv".into(),
+            sha256: sha256.into(),
+            rows,
+        };
+        let (mut verifier, mut reader) = Verifier::new(expected, Box::new(data));
+
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        for _ in 0..2 {
+            verifier.count_row();
+        }
+
+        verifier.finish()
+    }
+
+    #[test]
+    fn test_verifier() {
+        // `printf 'abc' | sha256sum`
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+        assert!(verify(b"abc", 2, sha256).is_ok());
+        assert!(matches!(
+            verify(b"abc", 3, sha256),
+            Err(VerificationError::RowCountMismatch {
+                expected: 3,
+                actual: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            verify(b"abd", 2, sha256),
+            Err(VerificationError::ChecksumMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,360 @@
+//! Notifications of things about an account that someone may want to act on straight away, such
+//! as it being locked, sent to any number of `NotificationSink`s. The built-in sinks write to
+//! stderr (`StderrSink`), append to a file (`FileSink`) or, with the webhook feature, POST to URLs
+//! (`output::webhook::WebhookSink`); embedders can implement the trait to hook in their own
+//! alerting.
+
+use crate::{
+    state::{Event, State},
+    types::{ClientId, FixedFloat, TransactionId},
+};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Notification {
+    AccountLocked {
+        client_id: ClientId,
+    },
+    DisputeOpened {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: FixedFloat,
+    },
+    ChargedBack {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: FixedFloat,
+    },
+    /// The account's available funds went below zero, e.g. because a deposit that had already
+    /// been withdrawn was disputed. Not sent again until they've been back to zero or above.
+    NegativeBalance {
+        client_id: ClientId,
+        available: FixedFloat,
+    },
+    /// The account's total funds reached `threshold` (see `Notifier::new`). Not sent again until
+    /// they've been back below it.
+    ThresholdCrossed {
+        client_id: ClientId,
+        total: FixedFloat,
+        threshold: FixedFloat,
+    },
+}
+
+impl Notification {
+    /// The name used for this type in the `event` field of its JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AccountLocked { .. } => "account_locked",
+            Self::DisputeOpened { .. } => "dispute_opened",
+            Self::ChargedBack { .. } => "charged_back",
+            Self::NegativeBalance { .. } => "negative_balance",
+            Self::ThresholdCrossed { .. } => "threshold_crossed",
+        }
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Self::AccountLocked { client_id }
+            | Self::DisputeOpened { client_id, .. }
+            | Self::ChargedBack { client_id, .. }
+            | Self::NegativeBalance { client_id, .. }
+            | Self::ThresholdCrossed { client_id, .. } => *client_id,
+        }
+    }
+
+    /// The notification as a JSON object, with the fields of an event log's events where it has
+    /// them, e.g. `{"event":"charged_back","client":1,"tx":5,"amount":1.5}`. Amounts are written
+    /// as plain JSON numbers straight from their fixed-point representation.
+    pub fn to_json(&self) -> String {
+        let (name, client_id) = (self.name(), self.client_id());
+        match self {
+            Self::AccountLocked { .. } => {
+                format!(r#"{{"event":"{}","client":{}}}"#, name, client_id)
+            }
+            Self::DisputeOpened {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::ChargedBack {
+                transaction_id,
+                amount,
+                ..
+            } => format!(
+                r#"{{"event":"{}","client":{},"tx":{},"amount":{}}}"#,
+                name, client_id, transaction_id.0, amount
+            ),
+            Self::NegativeBalance { available, .. } => format!(
+                r#"{{"event":"{}","client":{},"available":{}}}"#,
+                name, client_id, available
+            ),
+            Self::ThresholdCrossed {
+                total, threshold, ..
+            } => format!(
+                r#"{{"event":"{}","client":{},"total":{},"threshold":{}}}"#,
+                name, client_id, total, threshold
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Notification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AccountLocked { client_id } => write!(f, "Account {} locked", client_id),
+            Self::DisputeOpened {
+                client_id,
+                transaction_id,
+                amount,
+            } => write!(
+                f,
+                "Dispute opened on transaction {} of account {} ({})",
+                transaction_id.0, client_id, amount
+            ),
+            Self::ChargedBack {
+                client_id,
+                transaction_id,
+                amount,
+            } => write!(
+                f,
+                "Transaction {} of account {} charged back ({})",
+                transaction_id.0, client_id, amount
+            ),
+            Self::NegativeBalance {
+                client_id,
+                available,
+            } => write!(
+                f,
+                "Account {} has a negative available balance ({})",
+                client_id, available
+            ),
+            Self::ThresholdCrossed {
+                client_id,
+                total,
+                threshold,
+            } => write!(
+                f,
+                "Account {} total of {} has reached {}",
+                client_id, total, threshold
+            ),
+        }
+    }
+}
+
+/// Somewhere notifications are sent.
+pub trait NotificationSink: Send {
+    fn notify(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>>;
+
+    /// Called once there will be no more notifications, to send or write whatever is pending.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Writes each notification to stderr as a line of text.
+pub struct StderrSink;
+
+impl NotificationSink for StderrSink {
+    fn notify(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+        eprintln!("Notification: {}", notification);
+        Ok(())
+    }
+}
+
+/// Appends each notification to a file as a line of JSON (see `Notification::to_json`).
+pub struct FileSink {
+    f: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            f: BufWriter::new(file),
+        })
+    }
+}
+
+impl NotificationSink for FileSink {
+    fn notify(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+        writeln!(self.f, "{}", notification.to_json())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(self.f.flush()?)
+    }
+}
+
+/// Works out the notifications for each transaction processed, and sends them to every sink.
+pub struct Notifier {
+    sinks: Vec<Box<dyn NotificationSink>>,
+    threshold: Option<FixedFloat>,
+    // The accounts that have already been notified of as negative, or over the threshold
+    negative: HashSet<ClientId>,
+    over_threshold: HashSet<ClientId>,
+}
+
+impl Notifier {
+    /// A notifier with no sinks. With `threshold`, accounts whose total funds reach it are
+    /// notified of.
+    pub fn new(threshold: Option<FixedFloat>) -> Self {
+        Self {
+            sinks: Vec::new(),
+            threshold,
+            negative: HashSet::new(),
+            over_threshold: HashSet::new(),
+        }
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn NotificationSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Sends the notifications for the last call to `State::process`.
+    pub fn record(&mut self, state: &State) -> Result<(), Box<dyn Error>> {
+        let mut last = None;
+        for event in state.events() {
+            let notification = match *event {
+                Event::AccountLocked { client_id } => {
+                    Some(Notification::AccountLocked { client_id })
+                }
+                Event::DisputeOpened {
+                    client_id,
+                    transaction_id,
+                    amount,
+                    ..
+                } => Some(Notification::DisputeOpened {
+                    client_id,
+                    transaction_id,
+                    amount,
+                }),
+                Event::ChargedBack {
+                    client_id,
+                    transaction_id,
+                    amount,
+                    ..
+                } => Some(Notification::ChargedBack {
+                    client_id,
+                    transaction_id,
+                    amount,
+                }),
+                _ => None,
+            };
+            if let Some(notification) = notification {
+                self.send(&notification)?;
+            }
+            // The several events of one transaction are all for the same account
+            last = Some(event.client_id());
+        }
+
+        let Some(client_id) = last else {
+            return Ok(());
+        };
+        let Some(account) = state.account(client_id) else {
+            return Ok(());
+        };
+        let available = account.available();
+        if available < FixedFloat::default() {
+            if self.negative.insert(client_id) {
+                self.send(&Notification::NegativeBalance {
+                    client_id,
+                    available,
+                })?;
+            }
+        } else {
+            self.negative.remove(&client_id);
+        }
+        if let Some(threshold) = self.threshold {
+            let total = account.total();
+            if total >= threshold {
+                if self.over_threshold.insert(client_id) {
+                    self.send(&Notification::ThresholdCrossed {
+                        client_id,
+                        total,
+                        threshold,
+                    })?;
+                }
+            } else {
+                self.over_threshold.remove(&client_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes every sink, once there's nothing more to process.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        for sink in &mut self.sinks {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+        for sink in &mut self.sinks {
+            sink.notify(notification)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notification, NotificationSink, Notifier};
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::{
+        error::Error,
+        sync::{Arc, Mutex},
+    };
+
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl NotificationSink for Collect {
+        fn notify(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+            self.0.lock().unwrap().push(notification.to_json());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_notifier() {
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let mut notifier = Notifier::new(Some(10.0.into()));
+        notifier.add_sink(Box::new(Collect(notified.clone())));
+
+        let mut state = State::default();
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for transaction in [
+            txn(1, TransactionInner::Deposit(12.0.into())),
+            txn(2, TransactionInner::Deposit(1.0.into())),
+            txn(3, TransactionInner::Withdrawal(5.0.into())),
+            txn(1, TransactionInner::Dispute),
+            txn(1, TransactionInner::Chargeback),
+        ] {
+            state.process(transaction).unwrap();
+            notifier.record(&state).unwrap();
+        }
+
+        assert_eq!(
+            *notified.lock().unwrap(),
+            [
+                r#"{"event":"threshold_crossed","client":1,"total":12,"threshold":10}"#,
+                r#"{"event":"dispute_opened","client":1,"tx":1,"amount":12}"#,
+                r#"{"event":"negative_balance","client":1,"available":-4}"#,
+                r#"{"event":"charged_back","client":1,"tx":1,"amount":12}"#,
+                r#"{"event":"account_locked","client":1}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{state::Event, types::TransactionId};
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+const HEADER: [&str; 6] = ["tx", "client", "field", "delta", "reason", "timestamp"];
+
+/// Appends a row to a CSV audit log for every change to an account's balances or status, so that
+/// questions like "why is this account locked?" can be answered from the log rather than by
+/// re-running the input.
+///
+/// The columns are `tx` (the transaction that caused the change), `client`, `field` (`available`,
+/// `held`, `locked` or `closed`), `delta` (the amount added, or `true` for a lock or close and
+/// `false` for an unlock), `reason` (the transaction's type, or for an adjustment, `adjustment:`
+/// and its operator reference) and `timestamp` (the transaction's, if it had one). A dispute, for
+/// example, is two rows: one taking the amount from `available` and one adding it to `held`. A
+/// hold that expired is recorded against its own transaction, with the reason `expired`.
+/// Transactions that don't change anything, such as a withdrawal declined for insufficient funds,
+/// have no rows, but an adjustment always has one, even for nothing.
+///
+/// The log is only ever appended to, with the header written when it's created, so one log can
+/// cover many runs.
+pub struct AuditWriter {
+    writer: csv::Writer<File>,
+}
+
+impl AuditWriter {
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+
+        let mut writer = csv::Writer::from_writer(file);
+        if is_new {
+            writer.write_record(HEADER)?;
+        }
+        Ok(Self { writer })
+    }
+
+    /// Opens the log after cutting it back to `len` bytes, its length when a checkpoint was
+    /// written, so that the rows of transactions after the checkpoint aren't there twice once
+    /// they're processed again.
+    pub fn open_at(path: &Path, len: u64) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new().write(true).open(path)?;
+        if file.metadata()?.len() < len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} is shorter than when the checkpoint was written",
+                    path.display()
+                ),
+            ));
+        }
+        file.set_len(len)?;
+        file.sync_all()?;
+        Self::open(path)
+    }
+
+    /// Flushes the log, and returns its length in bytes, e.g. to record in a checkpoint.
+    pub fn position(&mut self) -> Result<u64, std::io::Error> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+
+    /// Writes the changes that `events` made, all caused by the transaction `transaction_id` of
+    /// type `reason`, which happened at `timestamp`, if it's known.
+    pub fn record(
+        &mut self,
+        transaction_id: TransactionId,
+        reason: &str,
+        timestamp: Option<u64>,
+        events: &[Event],
+    ) -> Result<(), std::io::Error> {
+        let timestamp = timestamp
+            .map(|timestamp| timestamp.to_string())
+            .unwrap_or_default();
+        for event in events {
+            let client_id = event.client_id();
+            let (transaction_id, reason) = match *event {
+                Event::AdjustmentApplied { ref reference, .. } => (
+                    transaction_id,
+                    Cow::Owned(format!("{}:{}", reason, reference)),
+                ),
+                Event::AuthorizationExpired { transaction_id, .. }
+                | Event::DisputeExpired { transaction_id, .. } => {
+                    (transaction_id, Cow::Borrowed("expired"))
+                }
+                _ => (transaction_id, Cow::Borrowed(reason)),
+            };
+            let reason = reason.as_ref();
+            let mut write = |field: &str, delta: &str| {
+                self.writer.write_record([
+                    transaction_id.to_string().as_str(),
+                    client_id.to_string().as_str(),
+                    field,
+                    delta,
+                    reason,
+                    timestamp.as_str(),
+                ])
+            };
+
+            match *event {
+                Event::FundsDeposited { amount, .. } => write("available", &amount.to_string())?,
+                Event::FundsWithdrawn { amount, .. } | Event::TransferSent { amount, .. } => {
+                    write("available", &(-amount).to_string())?
+                }
+                Event::TransferReceived { amount, .. }
+                | Event::TransferReversed { amount, .. }
+                | Event::AdjustmentApplied { amount, .. }
+                | Event::ChargebackReversed { amount, .. }
+                | Event::TransactionReversed { amount, .. } => {
+                    write("available", &amount.to_string())?
+                }
+                Event::DisputeOpened { amount, .. } | Event::FundsAuthorized { amount, .. } => {
+                    write("available", &(-amount).to_string())?;
+                    write("held", &amount.to_string())?;
+                }
+                Event::DisputeResolved { amount, .. }
+                | Event::DisputeExpired { amount, .. }
+                | Event::AuthorizationVoided { amount, .. }
+                | Event::AuthorizationExpired { amount, .. } => {
+                    write("held", &(-amount).to_string())?;
+                    write("available", &amount.to_string())?;
+                }
+                Event::ChargedBack { amount, .. } | Event::AuthorizationCaptured { amount, .. } => {
+                    write("held", &(-amount).to_string())?
+                }
+                Event::AccountLocked { .. } => write("locked", "true")?,
+                Event::AccountUnlocked { .. } => write("locked", "false")?,
+                Event::AccountClosed { .. } => write("closed", "true")?,
+                Event::AccountOpened { .. }
+                | Event::ClosedAccountRefused { .. }
+                | Event::DepositBlocked { .. }
+                | Event::TransactionQueued { .. }
+                | Event::AuthorizationDeclined { .. }
+                | Event::AuthorizationBlocked { .. }
+                | Event::WithdrawalDeclined { .. }
+                | Event::WithdrawalBlocked { .. }
+                | Event::TransferDeclined { .. }
+                | Event::TransferBlocked { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditWriter;
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_record() {
+        let path = std::env::temp_dir().join(format!("audit-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut state = State::default();
+        // Appended to across runs, with the header only once
+        for txns in [
+            vec![(
+                1,
+                TransactionInner::Deposit(2.5.into()),
+                Some(1_700_000_000),
+            )],
+            vec![
+                (1, TransactionInner::Dispute, None),
+                (1, TransactionInner::Chargeback, None),
+            ],
+            // Even a correction of nothing is on record, with its reference
+            vec![(
+                2,
+                TransactionInner::Adjustment {
+                    amount: 0.0.into(),
+                    reference: "OPS-1".into(),
+                },
+                None,
+            )],
+        ] {
+            let mut audit = AuditWriter::open(&path).unwrap();
+            for (transaction_id, inner, timestamp) in txns {
+                let transaction_id = TransactionId(transaction_id);
+                let reason = inner.type_name();
+                state
+                    .process(Transaction::new(transaction_id, ClientId(7), inner))
+                    .unwrap();
+                audit
+                    .record(transaction_id, reason, timestamp, state.events())
+                    .unwrap();
+            }
+            audit.flush().unwrap();
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "tx,client,field,delta,reason,timestamp\n\
+            1,7,available,2.5,deposit,1700000000\n\
+            1,7,available,-2.5,dispute,\n\
+            1,7,held,2.5,dispute,\n\
+            1,7,held,-2.5,chargeback,\n\
+            1,7,locked,true,chargeback,\n\
+            2,7,available,0,adjustment:OPS-1,\n"
+        );
+
+        // Cut back to how it was after the deposit, as when resuming from a checkpoint then
+        let len = "tx,client,field,delta,reason,timestamp\n1,7,available,2.5,deposit,1700000000\n"
+            .len() as u64;
+        assert_eq!(
+            AuditWriter::open_at(&path, len)
+                .unwrap()
+                .position()
+                .unwrap(),
+            len
+        );
+        assert!(AuditWriter::open_at(&path, len + 1).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use super::{json, write_csv, write_csv_row, OutputFormat, OutputOptions};
+use crate::{
+    state::{AccountState, Outcome, State},
+    types::ClientId,
+};
+use std::{collections::HashMap, io::Write};
+
+/// Writes an account's row each time its balances or locked status change, rather than all of
+/// the accounts at the end, for piping into a live consumer during a long-running ingestion.
+/// Rows are CSV (with the header written once, up front) or JSON Lines, and each is flushed as
+/// soon as it's written.
+pub struct DeltaWriter<Writer: Write> {
+    f: Writer,
+    options: OutputOptions,
+    // The last row written for each client, so that transactions which don't change anything
+    // (e.g. a zero deposit) don't produce duplicate rows
+    last: HashMap<ClientId, AccountState>,
+}
+
+impl<Writer: Write> DeltaWriter<Writer> {
+    pub fn new(mut f: Writer, options: OutputOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        match options.format {
+            OutputFormat::Csv => write_csv(&[], &mut f, options.precision, options.closures)?,
+            OutputFormat::JsonLines => {}
+            OutputFormat::Json => {
+                return Err("Deltas can't be written as a JSON array; use jsonl instead".into())
+            }
+        }
+        f.flush()?;
+
+        Ok(Self {
+            f,
+            options,
+            last: HashMap::new(),
+        })
+    }
+
+    /// Writes the row for each account that the transaction `state` last processed affected, if
+    /// it was applied and changed it. `outcome` is what processing it returned.
+    pub fn record(&mut self, state: &State, outcome: Outcome) -> std::io::Result<()> {
+        if outcome != Outcome::Applied {
+            return Ok(());
+        }
+
+        // Disputes and the like affect the client of the transaction they refer to, and a
+        // transfer (or its chargeback) affects two clients, so go by the events it produced
+        let mut client_ids = Vec::new();
+        for event in state.events() {
+            if !client_ids.contains(&event.client_id()) {
+                client_ids.push(event.client_id());
+            }
+        }
+        for client_id in client_ids {
+            let Some(account) = state.account(client_id) else {
+                continue;
+            };
+            if self.last.get(&client_id) == Some(account) {
+                continue;
+            }
+            self.last.insert(client_id, account.clone());
+
+            match self.options.format {
+                OutputFormat::Csv => write_csv_row(
+                    &mut self.f,
+                    client_id,
+                    account,
+                    self.options.precision,
+                    self.options.closures,
+                )?,
+                OutputFormat::Json | OutputFormat::JsonLines => json::write(
+                    &[(client_id, account)],
+                    &mut self.f,
+                    true,
+                    self.options.precision,
+                )?,
+            }
+        }
+        self.f.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeltaWriter;
+    use crate::{
+        output::OutputOptions,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_delta_writer() {
+        let mut state = State::default();
+        let mut output = Vec::new();
+        let mut writer = DeltaWriter::new(&mut output, OutputOptions::default()).unwrap();
+
+        for (transaction_id, client_id, inner) in [
+            (1, 1, TransactionInner::Deposit(2.0.into())),
+            (2, 2, TransactionInner::Deposit(1.0.into())),
+            (3, 1, TransactionInner::Deposit(0.0.into())),
+            (4, 2, TransactionInner::Withdrawal(5.0.into())),
+            // Client 2 disputing client 1's deposit
+            (1, 2, TransactionInner::Dispute),
+        ] {
+            let txn = Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner);
+            let outcome = state.process(txn).unwrap();
+            writer.record(&state, outcome).unwrap();
+        }
+
+        // Neither the zero deposit nor the failed withdrawal produce a row
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+            1,2,0,2,false\n\
+            2,1,0,1,false\n\
+            1,0,2,2,false\n"
+        );
+    }
+}
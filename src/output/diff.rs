@@ -0,0 +1,149 @@
+use super::{OutputFormat, Precision};
+use crate::{
+    state::{AccountState, State},
+    types::ClientId,
+};
+use std::{collections::BTreeMap, io::Write};
+
+/// How one client's account differs between two sets of accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub client_id: ClientId,
+    /// `None` if the client only has an account in the second set.
+    pub before: Option<AccountState>,
+    /// `None` if the client only has an account in the first set.
+    pub after: Option<AccountState>,
+}
+
+impl Difference {
+    /// `added`, `removed` or `changed`.
+    pub fn status(&self) -> &'static str {
+        match (&self.before, &self.after) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "changed",
+        }
+    }
+}
+
+/// Every client whose account differs between `before` and `after`, in client id order. An
+/// account that's only in one of them differs, even if it's empty.
+pub fn compare(before: &State, after: &State) -> Vec<Difference> {
+    let mut accounts = BTreeMap::<ClientId, (Option<&AccountState>, Option<&AccountState>)>::new();
+    for (client_id, account) in before.accounts() {
+        accounts.entry(client_id).or_default().0 = Some(account);
+    }
+    for (client_id, account) in after.accounts() {
+        accounts.entry(client_id).or_default().1 = Some(account);
+    }
+
+    accounts
+        .into_iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(client_id, (before, after))| Difference {
+            client_id,
+            before: before.cloned(),
+            after: after.cloned(),
+        })
+        .collect()
+}
+
+/// Writes the differences as CSV or JSON Lines, with the columns `client`, `status`, the change
+/// in `available`, `held` and `total` (after minus before, with a missing account counting as
+/// empty), and `locked_before` and `locked_after`.
+pub fn write<Writer: Write>(
+    differences: &[Difference],
+    mut f: Writer,
+    format: OutputFormat,
+    precision: Precision,
+) -> std::io::Result<()> {
+    if format == OutputFormat::Csv {
+        writeln!(
+            f,
+            "client,status,available,held,total,locked_before,locked_after"
+        )?;
+    } else if format == OutputFormat::Json {
+        return Err(std::io::Error::other(
+            "Differences can't be written as a JSON array; use jsonl instead",
+        ));
+    }
+
+    for difference in differences {
+        let empty = AccountState::default();
+        let before = difference.before.as_ref().unwrap_or(&empty);
+        let after = difference.after.as_ref().unwrap_or(&empty);
+        let (available, held, total) = (
+            precision.apply(after.available() + -before.available()),
+            precision.apply(after.held() + -before.held()),
+            precision.apply(after.total() + -before.total()),
+        );
+
+        match format {
+            OutputFormat::Csv => writeln!(
+                f,
+                "{},{},{},{},{},{},{}",
+                difference.client_id,
+                difference.status(),
+                available,
+                held,
+                total,
+                before.locked(),
+                after.locked()
+            )?,
+            _ => writeln!(
+                f,
+                "{{\"client\":{},\"status\":\"{}\",\"available\":{},\"held\":{},\"total\":{},\
+                \"locked_before\":{},\"locked_after\":{}}}",
+                difference.client_id,
+                difference.status(),
+                available,
+                held,
+                total,
+                before.locked(),
+                after.locked()
+            )?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, write};
+    use crate::{
+        output::{OutputFormat, Precision},
+        state::{AccountState, State},
+        types::ClientId,
+    };
+
+    #[test]
+    fn test_compare() {
+        let account = |available: f64, held: f64, locked| {
+            AccountState::new(available.into(), held.into(), locked)
+        };
+        let before = State::from_accounts([
+            (ClientId(1), account(1.0, 0.0, false)),
+            (ClientId(2), account(2.0, 0.0, false)),
+            (ClientId(3), account(3.0, 0.0, false)),
+        ]);
+        let after = State::from_accounts([
+            (ClientId(1), account(1.0, 0.0, false)),
+            (ClientId(2), account(0.5, 1.0, true)),
+            (ClientId(4), AccountState::default()),
+        ]);
+
+        let differences = compare(&before, &after);
+        let mut f = Vec::new();
+        write(&differences, &mut f, OutputFormat::Csv, Precision::Minimal).unwrap();
+        assert_eq!(
+            String::from_utf8(f).unwrap(),
+            "client,status,available,held,total,locked_before,locked_after\n\
+            2,changed,-1.5,1,-0.5,false,true\n\
+            3,removed,-3,0,-3,false,false\n\
+            4,added,0,0,0,false,false\n"
+        );
+
+        assert!(compare(&before, &before).is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    arrow::{decimal_type, DECIMAL_PRECISION},
+    state::{Outcome, State},
+    types::{FixedFloat, Transaction},
+};
+use ::duckdb::Connection;
+use arrow_array::{
+    builder::{
+        ArrayBuilder, Decimal128Builder, StringBuilder, UInt16Builder, UInt32Builder, UInt64Builder,
+    },
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Rows are buffered and appended in batches of this size
+const BATCH_SIZE: usize = 64 * 1024;
+
+const CREATE_TABLES: &str = "
+    CREATE TABLE accounts (
+        client USMALLINT NOT NULL,
+        available DECIMAL(38, 4) NOT NULL,
+        held DECIMAL(38, 4) NOT NULL,
+        total DECIMAL(38, 4) NOT NULL,
+        locked BOOLEAN NOT NULL
+    );
+    CREATE TABLE journal (
+        seq UBIGINT NOT NULL,
+        tx UINTEGER NOT NULL,
+        client USMALLINT NOT NULL,
+        type VARCHAR NOT NULL,
+        amount DECIMAL(38, 4)
+    );
+    CREATE TABLE rejects (
+        seq UBIGINT NOT NULL,
+        tx UINTEGER NOT NULL,
+        client USMALLINT NOT NULL,
+        type VARCHAR NOT NULL,
+        amount DECIMAL(38, 4),
+        reason VARCHAR NOT NULL
+    );
+";
+
+/// Writes a single DuckDB database per run, with three tables:
+///
+/// - `accounts`: the final account states, as in the CSV output.
+/// - `journal`: every transaction that was applied or queued until its account is unlocked, in
+///   input order (`seq` being its position).
+/// - `rejects`: every transaction that was ignored, along with the reason.
+///
+/// The database is built under a temporary name and only moved to `path` by `finish`, so an
+/// aborted run never leaves a partial database behind.
+pub struct DuckDbWriter {
+    conn: Connection,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    seq: u64,
+    journal: Rows,
+    rejects: Rows,
+}
+
+impl DuckDbWriter {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut wal_path = tmp_path.clone().into_os_string();
+        wal_path.push(".wal");
+
+        // Leftovers from an aborted run
+        for stale_path in [tmp_path.as_os_str(), wal_path.as_os_str()] {
+            let stale_path = Path::new(stale_path);
+            if stale_path.exists() {
+                std::fs::remove_file(stale_path)?;
+            }
+        }
+
+        let conn = Connection::open(&tmp_path)?;
+        conn.execute_batch(CREATE_TABLES)?;
+
+        Ok(Self {
+            conn,
+            path: path.into(),
+            tmp_path,
+            seq: 0,
+            journal: Rows::default(),
+            rejects: Rows::default(),
+        })
+    }
+
+    /// Records the result of processing `txn`, which must be called for every input transaction
+    /// in order.
+    pub fn record(&mut self, txn: &Transaction, outcome: Outcome) -> Result<(), Box<dyn Error>> {
+        let seq = self.seq;
+        self.seq += 1;
+
+        match outcome {
+            // In input order, even though it's applied once its account is unlocked
+            Outcome::Applied | Outcome::Queued => {
+                self.journal.push(seq, txn, None);
+                if self.journal.len() >= BATCH_SIZE {
+                    self.flush_journal()?;
+                }
+            }
+            Outcome::Ignored(reason) => {
+                self.rejects.push(seq, txn, Some(reason.name()));
+                if self.rejects.len() >= BATCH_SIZE {
+                    self.flush_rejects()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the final account states and moves the database into place.
+    pub fn finish(mut self, state: &State) -> Result<(), Box<dyn Error>> {
+        self.flush_journal()?;
+        self.flush_rejects()?;
+        self.conn
+            .appender("accounts")?
+            .append_record_batch(state.to_record_batch()?)?;
+
+        // Closing checkpoints the write-ahead log into the database file
+        self.conn.close().map_err(|(_, err)| err)?;
+        std::fs::rename(&self.tmp_path, &self.path)?;
+
+        Ok(())
+    }
+
+    fn flush_journal(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.journal.len() > 0 {
+            let batch = std::mem::take(&mut self.journal).into_record_batch(false)?;
+            self.conn.appender("journal")?.append_record_batch(batch)?;
+        }
+        Ok(())
+    }
+
+    fn flush_rejects(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.rejects.len() > 0 {
+            let batch = std::mem::take(&mut self.rejects).into_record_batch(true)?;
+            self.conn.appender("rejects")?.append_record_batch(batch)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Rows {
+    seq: UInt64Builder,
+    tx: UInt32Builder,
+    client: UInt16Builder,
+    type_: StringBuilder,
+    amount: Decimal128Builder,
+    // Only used for rejects
+    reason: StringBuilder,
+}
+
+impl Rows {
+    fn len(&self) -> usize {
+        self.seq.len()
+    }
+
+    fn push(&mut self, seq: u64, txn: &Transaction, reason: Option<&str>) {
+        self.seq.append_value(seq);
+        self.tx.append_value(txn.transaction_id.0);
+        self.client.append_value(txn.client_id.0);
+        self.type_.append_value(txn.inner.type_name());
+        self.amount
+            .append_option(txn.inner.amount().map(|amount| amount.raw() as i128));
+        if let Some(reason) = reason {
+            self.reason.append_value(reason);
+        }
+    }
+
+    fn into_record_batch(mut self, with_reason: bool) -> Result<RecordBatch, Box<dyn Error>> {
+        let mut fields = vec![
+            Field::new("seq", DataType::UInt64, false),
+            Field::new("tx", DataType::UInt32, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("type", DataType::Utf8, false),
+            Field::new("amount", decimal_type(), true),
+        ];
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.seq.finish()),
+            Arc::new(self.tx.finish()),
+            Arc::new(self.client.finish()),
+            Arc::new(self.type_.finish()),
+            Arc::new(
+                self.amount
+                    .finish()
+                    .with_precision_and_scale(DECIMAL_PRECISION, FixedFloat::SCALE as i8)?,
+            ),
+        ];
+
+        if with_reason {
+            fields.push(Field::new("reason", DataType::Utf8, false));
+            columns.push(Arc::new(self.reason.finish()));
+        }
+
+        Ok(RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            columns,
+        )?)
+    }
+}
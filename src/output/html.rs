@@ -0,0 +1,270 @@
+use super::Precision;
+use crate::{
+    state::{Outcome, State, TypeCounts},
+    types::{FixedFloat, Transaction, TransactionState},
+};
+use std::{collections::BTreeMap, io::Write};
+
+const STYLE: &str = "
+    body { font-family: sans-serif; margin: 2em; color: #222; }
+    table { border-collapse: collapse; margin-bottom: 2em; }
+    th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; }
+    th { background: #f0f0f0; text-align: left; }
+    td.number { text-align: right; font-family: monospace; }
+    tr.locked { background: #fde8e8; }
+    tr.closed { color: #888; }
+";
+
+/// Tallies what happened to each transaction during a run, so that a standalone HTML summary of
+/// the balances, dispute activity and run statistics can be written at the end for reviewers who
+/// don't use the CLI. The page has no external resources, so it can be emailed or archived as is.
+#[derive(Debug, Default)]
+pub struct Report {
+    // Applied, ignored and queued counts, by transaction type
+    types: BTreeMap<&'static str, TypeCounts>,
+    // Ignored counts, by reason
+    reasons: BTreeMap<&'static str, u64>,
+}
+
+impl Report {
+    pub fn record(&mut self, txn: &Transaction, outcome: Outcome) {
+        let counts = self.types.entry(txn.inner.type_name()).or_default();
+        match outcome {
+            Outcome::Applied => counts.applied += 1,
+            Outcome::Ignored(reason) => {
+                counts.ignored += 1;
+                *self.reasons.entry(reason.name()).or_default() += 1;
+            }
+            Outcome::Queued => counts.queued += 1,
+        }
+    }
+
+    pub fn write<Writer: Write>(
+        &self,
+        state: &State,
+        mut f: Writer,
+        precision: Precision,
+    ) -> std::io::Result<()> {
+        let stats = state.stats();
+        let processed = self
+            .types
+            .values()
+            .map(|counts| counts.applied + counts.ignored + counts.queued)
+            .sum::<u64>();
+        // Only with `Options::locked_accounts` queueing anything
+        let queued = self.types.values().any(|counts| counts.queued > 0);
+
+        writeln!(f, "<!DOCTYPE html>")?;
+        writeln!(f, "<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(f, "<title>Transaction report</title>")?;
+        writeln!(f, "<style>{}</style>\n</head>\n<body>", STYLE)?;
+        writeln!(f, "<h1>Transaction report</h1>")?;
+
+        writeln!(f, "<h2>Run statistics</h2>\n<table>")?;
+        for (name, value) in [
+            ("Transactions processed", processed as usize),
+            ("Accounts", stats.accounts),
+            ("Locked accounts", stats.locked_accounts),
+            ("Closed accounts", stats.closed_accounts),
+            ("Cached transactions", stats.cached_transactions),
+            ("Disputed transactions", stats.disputed_transactions),
+            ("Invalid transactions", stats.invalid_transactions),
+        ] {
+            writeln!(
+                f,
+                "<tr><th>{}</th><td class=\"number\">{}</td></tr>",
+                name, value
+            )?;
+        }
+        writeln!(f, "</table>")?;
+
+        writeln!(f, "<h2>Transactions by type</h2>\n<table>")?;
+        writeln!(
+            f,
+            "<tr><th>Type</th><th>Applied</th><th>Ignored</th>{}</tr>",
+            if queued { "<th>Queued</th>" } else { "" }
+        )?;
+        for (type_name, counts) in &self.types {
+            writeln!(
+                f,
+                "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td>{}</tr>",
+                type_name,
+                counts.applied,
+                counts.ignored,
+                if queued {
+                    format!("<td class=\"number\">{}</td>", counts.queued)
+                } else {
+                    String::new()
+                }
+            )?;
+        }
+        writeln!(f, "</table>")?;
+
+        if !self.reasons.is_empty() {
+            writeln!(f, "<h2>Ignored transactions</h2>\n<table>")?;
+            writeln!(f, "<tr><th>Reason</th><th>Count</th></tr>")?;
+            for (reason, count) in &self.reasons {
+                writeln!(
+                    f,
+                    "<tr><td>{}</td><td class=\"number\">{}</td></tr>",
+                    reason, count
+                )?;
+            }
+            writeln!(f, "</table>")?;
+        }
+
+        // Transactions that are currently disputed or have been charged back
+        let mut disputes = state
+            .transactions()
+            .filter(|txn| txn.state != TransactionState::Alive)
+            .collect::<Vec<_>>();
+        disputes.sort_unstable_by_key(|txn| txn.transaction_id);
+
+        writeln!(f, "<h2>Dispute activity</h2>")?;
+        if disputes.is_empty() {
+            writeln!(f, "<p>No open disputes or chargebacks.</p>")?;
+        } else {
+            writeln!(f, "<table>")?;
+            writeln!(
+                f,
+                "<tr><th>Tx</th><th>Client</th><th>Type</th><th>Amount</th><th>State</th>\
+                <th>Reason</th></tr>"
+            )?;
+            for txn in &disputes {
+                writeln!(
+                    f,
+                    "<tr><td class=\"number\">{}</td><td class=\"number\">{}</td><td>{}</td>\
+                    <td class=\"number\">{}</td><td>{}</td><td>{}</td></tr>",
+                    txn.transaction_id,
+                    txn.client_id,
+                    txn.inner.type_name(),
+                    txn.disputed_part()
+                        .inner
+                        .amount()
+                        .map(|amount| precision.apply(amount).to_string())
+                        .unwrap_or_default(),
+                    txn.state.name(),
+                    escape(txn.reason.as_deref().unwrap_or_default())
+                )?;
+            }
+            writeln!(f, "</table>")?;
+        }
+
+        // Everything that was charged back, whatever happened after, by reason code
+        let mut chargebacks = BTreeMap::<Option<&str>, (u64, FixedFloat)>::new();
+        for txn in &disputes {
+            if let TransactionState::ChargedBack
+            | TransactionState::Unlocked
+            | TransactionState::Represented = txn.state
+            {
+                let (count, total) = chargebacks.entry(txn.reason.as_deref()).or_default();
+                *count += 1;
+                *total += txn.disputed_part().inner.amount().unwrap_or_default();
+            }
+        }
+        if !chargebacks.is_empty() {
+            writeln!(f, "<h2>Chargebacks by reason</h2>\n<table>")?;
+            writeln!(f, "<tr><th>Reason</th><th>Count</th><th>Amount</th></tr>")?;
+            for (reason, (count, total)) in chargebacks {
+                writeln!(
+                    f,
+                    "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+                    escape(reason.unwrap_or("(none)")),
+                    count,
+                    precision.apply(total)
+                )?;
+            }
+            writeln!(f, "</table>")?;
+        }
+
+        writeln!(f, "<h2>Account balances</h2>\n<table>")?;
+        writeln!(
+            f,
+            "<tr><th>Client</th><th>Available</th><th>Held</th><th>Total</th><th>Locked</th>\
+            <th>Closed</th></tr>"
+        )?;
+        for (client_id, account) in state.sorted_accounts() {
+            writeln!(
+                f,
+                "<tr{}><td class=\"number\">{}</td><td class=\"number\">{}</td>\
+                <td class=\"number\">{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td></tr>",
+                match (account.locked(), account.closed()) {
+                    (true, _) => " class=\"locked\"",
+                    (false, true) => " class=\"closed\"",
+                    (false, false) => "",
+                },
+                client_id,
+                precision.apply(account.available()),
+                precision.apply(account.held()),
+                precision.apply(account.total()),
+                if account.locked() { "yes" } else { "no" },
+                if account.closed() { "yes" } else { "no" }
+            )?;
+        }
+        writeln!(f, "</table>\n</body>\n</html>")?;
+
+        Ok(())
+    }
+}
+
+// Reason codes come from the input, so may contain anything
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::{
+        output::Precision,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_report() {
+        let mut state = State::default();
+        let mut report = Report::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(2.5.into())),
+            (2, TransactionInner::Withdrawal(5.0.into())),
+            (1, TransactionInner::Dispute),
+            (3, TransactionInner::Deposit(1.0.into())),
+            (3, TransactionInner::Dispute),
+            (3, TransactionInner::Chargeback),
+        ] {
+            let mut txn = Transaction::new(TransactionId(transaction_id), ClientId(3), inner);
+            if transaction_id == 3 && txn.inner.takes_reason() {
+                txn.reason = Some("<fraud>".into());
+            }
+            let outcome = state.process(txn.clone()).unwrap();
+            report.record(&txn, outcome);
+        }
+
+        let mut output = Vec::new();
+        report
+            .write(&state, &mut output, Precision::Fixed4)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(
+            output.contains("<tr><th>Transactions processed</th><td class=\"number\">6</td></tr>")
+        );
+        assert!(output.contains(
+            "<tr><td>withdrawal</td><td class=\"number\">0</td><td class=\"number\">1</td></tr>"
+        ));
+        assert!(output.contains("<tr><td>insufficient_funds</td><td class=\"number\">1</td></tr>"));
+        assert!(output.contains("<td class=\"number\">2.5000</td><td>disputed</td><td></td>"));
+        assert!(output.contains(
+            "<tr><td>&lt;fraud&gt;</td><td class=\"number\">1</td>\
+            <td class=\"number\">1.0000</td></tr>"
+        ));
+        assert!(output.contains(
+            "<td class=\"number\">3</td><td class=\"number\">0.0000</td>\
+            <td class=\"number\">2.5000</td>"
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{io, state::State};
+use arrow_array::RecordBatch;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::ArrowError;
+use std::{error::Error, path::Path};
+
+/// Writes the accounts table to an Arrow IPC file (aka Feather v2) at `path`, with the same
+/// columns and exact decimal typing as `State::to_record_batch`. pandas reads it with
+/// `pd.read_feather`.
+pub fn write(state: &State, path: &Path) -> Result<(), Box<dyn Error>> {
+    write_file(&state.to_record_batch()?, path)
+}
+
+/// Like `write`, but for the transaction cache (`State::transactions_record_batch`).
+pub fn write_transactions(state: &State, path: &Path) -> Result<(), Box<dyn Error>> {
+    write_file(&state.transactions_record_batch()?, path)
+}
+
+fn write_file(batch: &RecordBatch, path: &Path) -> Result<(), Box<dyn Error>> {
+    io::write_file_atomically(path, |f| {
+        write_batch(f, batch).map_err(std::io::Error::other)
+    })?;
+
+    Ok(())
+}
+
+fn write_batch<Writer: std::io::Write>(f: Writer, batch: &RecordBatch) -> Result<(), ArrowError> {
+    let mut writer = FileWriter::try_new(f, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use arrow_ipc::reader::FileReader;
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(1.2345.into())),
+            (1, TransactionInner::Dispute),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        let read = |path: &std::path::Path| {
+            let batches = FileReader::try_new(std::fs::File::open(path).unwrap(), None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            std::fs::remove_file(path).unwrap();
+            batches
+        };
+
+        let path = std::env::temp_dir().join(format!("accounts-{}.arrow", std::process::id()));
+        super::write(&state, &path).unwrap();
+        assert_eq!(read(&path), vec![state.to_record_batch().unwrap()]);
+
+        let path = std::env::temp_dir().join(format!("transactions-{}.arrow", std::process::id()));
+        super::write_transactions(&state, &path).unwrap();
+        assert_eq!(
+            read(&path),
+            vec![state.transactions_record_batch().unwrap()]
+        );
+    }
+}
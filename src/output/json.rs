@@ -0,0 +1,52 @@
+use super::Precision;
+use crate::{state::AccountState, types::ClientId};
+use std::io::Write;
+
+/// Writes the accounts as a JSON array, or as JSON Lines (one object per line) if `lines` is set.
+/// Amounts are written as plain JSON numbers straight from their fixed-point representation, so
+/// they never go through a float on the way out. A closed account also has `"closed":true` and
+/// its `pending_payout`.
+pub fn write<Writer: Write>(
+    accounts: &[(ClientId, &AccountState)],
+    mut f: Writer,
+    lines: bool,
+    precision: Precision,
+) -> std::io::Result<()> {
+    if !lines {
+        write!(f, "[")?;
+    }
+
+    for (index, (client_id, account)) in accounts.iter().enumerate() {
+        if !lines {
+            write!(f, "{}\n  ", if index == 0 { "" } else { "," })?;
+        }
+
+        write!(
+            f,
+            "{{\"client\":{},\"available\":{},\"held\":{},\"total\":{},\"locked\":{}",
+            client_id,
+            precision.apply(account.available()),
+            precision.apply(account.held()),
+            precision.apply(account.total()),
+            account.locked()
+        )?;
+        if account.closed() {
+            write!(
+                f,
+                ",\"closed\":true,\"pending_payout\":{}",
+                precision.apply(account.pending_payout())
+            )?;
+        }
+        write!(f, "}}")?;
+
+        if lines {
+            writeln!(f)?;
+        }
+    }
+
+    if !lines {
+        writeln!(f, "\n]")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use super::{json, Precision};
+use crate::{
+    state::{AccountState, State},
+    types::ClientId,
+};
+use ::kafka::producer::{Producer, Record, RequiredAcks};
+use std::{collections::HashMap, error::Error, time::Duration};
+
+// Updates are sent this many at a time, unless flushed sooner
+const BATCH_SIZE: usize = 1000;
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Publishes each account to a Kafka topic whenever its balances or locked flag change, so that
+/// downstream systems can react in near-real-time. Each message is keyed by the client id, so
+/// all of an account's updates go to the same partition, in order. Its value is the account as a
+/// JSON object, as in the `json` output, e.g.
+/// `{"client":1,"available":1.5,"held":0,"total":1.5,"locked":false}`.
+///
+/// Updates are sent in batches, and each batch waits for the partition leaders to acknowledge
+/// it. Call `flush` to send what's queued straight away.
+pub struct KafkaSink {
+    producer: Producer,
+    topic: String,
+    updates: Updates,
+}
+
+impl KafkaSink {
+    /// Connects to the Kafka cluster through `brokers` (each `host:port`), failing if it can't
+    /// or if `topic` doesn't exist.
+    pub fn connect(
+        brokers: &[String],
+        topic: &str,
+        precision: Precision,
+    ) -> Result<Self, Box<dyn Error>> {
+        let producer = Producer::from_hosts(brokers.to_vec())
+            .with_ack_timeout(ACK_TIMEOUT)
+            .with_required_acks(RequiredAcks::One)
+            .create()?;
+        if !producer.client().topics().contains(topic) {
+            return Err(format!("Kafka topic \"{}\" doesn't exist", topic).into());
+        }
+
+        Ok(Self {
+            producer,
+            topic: topic.to_owned(),
+            updates: Updates::new(precision),
+        })
+    }
+
+    /// Queues the accounts that the last call to `State::process` changed, sending them if a
+    /// batch's worth are queued.
+    pub fn record(&mut self, state: &State) -> Result<(), Box<dyn Error>> {
+        self.updates.collect(state);
+        if self.updates.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sends everything queued.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.updates.pending.is_empty() {
+            return Ok(());
+        }
+        let records = self
+            .updates
+            .pending
+            .iter()
+            .map(|(key, value)| Record::from_key_value(&self.topic, key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        for confirm in self.producer.send_all(&records)? {
+            for partition in confirm.partition_confirms {
+                if let Err(code) = partition.offset {
+                    return Err(format!(
+                        "Kafka rejected updates to partition {}: {:?}",
+                        partition.partition, code
+                    )
+                    .into());
+                }
+            }
+        }
+        self.updates.pending.clear();
+        Ok(())
+    }
+}
+
+// Which accounts have changed since they were last published, as messages ready to send
+struct Updates {
+    precision: Precision,
+    // Each account as it was last published
+    published: HashMap<ClientId, AccountState>,
+    pending: Vec<(String, String)>,
+}
+
+impl Updates {
+    fn new(precision: Precision) -> Self {
+        Self {
+            precision,
+            published: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn collect(&mut self, state: &State) {
+        for event in state.events() {
+            let client_id = event.client_id();
+            let Some(account) = state.account(client_id) else {
+                continue;
+            };
+            // A transaction that was ignored, or several events of one transaction, leave the
+            // account as it was published already
+            if self.published.get(&client_id) == Some(account) {
+                continue;
+            }
+            let mut value = Vec::new();
+            json::write(&[(client_id, account)], &mut value, true, self.precision)
+                .expect("Writing to a Vec can't fail");
+            value.pop(); // The newline
+            self.pending.push((
+                client_id.to_string(),
+                String::from_utf8(value).expect("JSON is UTF-8"),
+            ));
+            self.published.insert(client_id, account.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Updates;
+    use crate::{
+        output::Precision,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_updates() {
+        let mut updates = Updates::new(Precision::Minimal);
+        let mut state = State::default();
+        for (transaction_id, client_id, inner) in [
+            (1, 7, TransactionInner::Deposit(2.5.into())),
+            // Not enough funds, so nothing changes
+            (2, 7, TransactionInner::Withdrawal(3.0.into())),
+            (3, 8, TransactionInner::Deposit(1.0.into())),
+            (1, 7, TransactionInner::Dispute),
+            (1, 7, TransactionInner::Chargeback),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    inner,
+                ))
+                .unwrap();
+            updates.collect(&state);
+        }
+
+        assert_eq!(
+            updates.pending,
+            [
+                (
+                    "7",
+                    r#"{"client":7,"available":2.5,"held":0,"total":2.5,"locked":false}"#
+                ),
+                (
+                    "8",
+                    r#"{"client":8,"available":1,"held":0,"total":1,"locked":false}"#
+                ),
+                (
+                    "7",
+                    r#"{"client":7,"available":0,"held":2.5,"total":2.5,"locked":false}"#
+                ),
+                (
+                    "7",
+                    r#"{"client":7,"available":0,"held":0,"total":0,"locked":true}"#
+                ),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        );
+    }
+}
@@ -0,0 +1,570 @@
+pub mod audit;
+pub mod delta;
+pub mod diff;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
+pub mod html;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod open_disputes;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+pub mod statsd;
+pub mod summary;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+
+use super::{
+    state::{AccountState, State},
+    types::{ClientId, FixedFloat},
+};
+use std::{error::Error, io::Write, path::Path};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    JsonLines,
+}
+
+impl OutputFormat {
+    /// The usual file extension for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::JsonLines => "jsonl",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            other => Err(format!("Unrecognised output format \"{}\"", other)),
+        }
+    }
+}
+
+/// How amounts are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// As few decimal places as needed, e.g. `0` and `1.5`.
+    #[default]
+    Minimal,
+    /// Always exactly four decimal places, e.g. `0.0000` and `1.5000`.
+    Fixed4,
+}
+
+impl Precision {
+    /// Formats `amount` according to this precision.
+    pub fn apply(self, amount: FixedFloat) -> impl std::fmt::Display {
+        struct Formatted(FixedFloat, Precision);
+
+        impl std::fmt::Display for Formatted {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self.1 {
+                    Precision::Minimal => write!(f, "{}", self.0),
+                    Precision::Fixed4 => write!(f, "{:.4}", self.0),
+                }
+            }
+        }
+
+        Formatted(amount, self)
+    }
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(Self::Minimal),
+            "fixed4" => Ok(Self::Fixed4),
+            other => Err(format!("Unrecognised precision \"{}\"", other)),
+        }
+    }
+}
+
+/// How the final account states are written.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Leave out accounts with nothing available or held, and which aren't locked.
+    pub skip_empty: bool,
+    pub precision: Precision,
+    /// Give every CSV row the `closed` and `pending_payout` columns (see
+    /// `AccountState::pending_payout`), so that closures show up in CSV. Either way, every CSV
+    /// written with the same options has the same header. JSON always has them for a closed
+    /// account.
+    pub closures: bool,
+}
+
+/// Somewhere the final account states go, e.g. a file in one of the output formats
+/// (`WriterSink`) or a database (`postgres::PostgresSink`). See `State::write_to`.
+pub trait AccountSink {
+    /// Writes `accounts`, which are ordered by client id.
+    fn write_accounts(
+        &mut self,
+        accounts: &[(ClientId, &AccountState)],
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Writes the accounts as CSV, JSON or JSON Lines, as set by its `OutputOptions`.
+pub struct WriterSink<Writer: Write> {
+    f: Writer,
+    options: OutputOptions,
+}
+
+impl<Writer: Write> WriterSink<Writer> {
+    pub fn new(f: Writer, options: OutputOptions) -> Self {
+        Self { f, options }
+    }
+}
+
+impl<Writer: Write> AccountSink for WriterSink<Writer> {
+    fn write_accounts(
+        &mut self,
+        accounts: &[(ClientId, &AccountState)],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut accounts = accounts.to_vec();
+        if self.options.skip_empty {
+            accounts.retain(|(_, account)| !account.is_empty());
+        }
+        write_account_list(&accounts, self.options, &mut self.f)?;
+        Ok(self.f.flush()?)
+    }
+}
+
+/// Writes the final account states, ordered by client id.
+pub fn write_accounts<Writer: Write>(
+    state: &State,
+    options: OutputOptions,
+    f: Writer,
+) -> std::io::Result<()> {
+    write_account_list(&selected_accounts(state, options), options, f)
+}
+
+/// Writes the final account states into `dir` (created if need be), split into one file per
+/// bucket of `bucket_size` consecutive client ids, so that per-customer jobs only need to read
+/// their own. Files are named `client-<id>.<ext>` if `bucket_size` is 1, and
+/// `clients-<first>-<last>.<ext>` otherwise, and only written for buckets that have accounts.
+/// Each is written atomically. Returns the number of files written.
+pub fn write_shards(
+    state: &State,
+    options: OutputOptions,
+    dir: &Path,
+    bucket_size: u16,
+) -> std::io::Result<usize> {
+    assert!(bucket_size > 0, "Bucket size must be positive");
+    std::fs::create_dir_all(dir)?;
+
+    let accounts = selected_accounts(state, options);
+    let buckets = accounts.chunk_by(|(a, _), (b, _)| a.0 / bucket_size == b.0 / bucket_size);
+
+    let mut written = 0;
+    for bucket in buckets {
+        let first = bucket[0].0 .0 / bucket_size * bucket_size;
+        let name = match bucket_size {
+            1 => format!("client-{}.{}", first, options.format.extension()),
+            _ => format!(
+                "clients-{}-{}.{}",
+                first,
+                first.saturating_add(bucket_size - 1),
+                options.format.extension()
+            ),
+        };
+        crate::io::write_file_atomically(&dir.join(name), |f| {
+            write_account_list(bucket, options, f)
+        })?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+fn selected_accounts(state: &State, options: OutputOptions) -> Vec<(ClientId, &AccountState)> {
+    let mut accounts = state.sorted_accounts();
+    if options.skip_empty {
+        accounts.retain(|(_, account)| !account.is_empty());
+    }
+    accounts
+}
+
+fn write_account_list<Writer: Write>(
+    accounts: &[(ClientId, &AccountState)],
+    options: OutputOptions,
+    f: Writer,
+) -> std::io::Result<()> {
+    match options.format {
+        OutputFormat::Csv => write_csv(accounts, f, options.precision, options.closures),
+        OutputFormat::Json => json::write(accounts, f, false, options.precision),
+        OutputFormat::JsonLines => json::write(accounts, f, true, options.precision),
+    }
+}
+
+/// Writes the accounts as CSV. With `closures`, there are two more columns, `closed` and
+/// `pending_payout` (see `OutputOptions::closures`).
+pub fn write_csv<Writer: Write>(
+    accounts: &[(ClientId, &AccountState)],
+    mut f: Writer,
+    precision: Precision,
+    closures: bool,
+) -> std::io::Result<()> {
+    match closures {
+        true => writeln!(
+            f,
+            "client,available,held,total,locked,closed,pending_payout"
+        )?,
+        false => writeln!(f, "client,available,held,total,locked")?,
+    }
+
+    for (client_id, account) in accounts {
+        write_csv_row(&mut f, *client_id, account, precision, closures)?;
+    }
+
+    Ok(())
+}
+
+fn write_csv_row<Writer: Write>(
+    mut f: Writer,
+    client_id: ClientId,
+    account: &AccountState,
+    precision: Precision,
+    closures: bool,
+) -> std::io::Result<()> {
+    write!(
+        f,
+        "{},{},{},{},{}",
+        client_id,
+        precision.apply(account.available()),
+        precision.apply(account.held()),
+        precision.apply(account.total()),
+        account.locked()
+    )?;
+    if closures {
+        write!(
+            f,
+            ",{},{}",
+            account.closed(),
+            precision.apply(account.pending_payout())
+        )?;
+    }
+    writeln!(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        write_accounts, write_shards, AccountSink, OutputFormat, OutputOptions, Precision,
+        WriterSink,
+    };
+    use crate::{
+        state::{AccountState, State},
+        types::{ClientId, FixedFloat, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_output_options() {
+        let mut state = State::default();
+        for (transaction_id, client_id, inner) in [
+            (1, 1, TransactionInner::Deposit(1.0.into())),
+            (2, 2, TransactionInner::Deposit(1.0.into())),
+            (3, 2, TransactionInner::Withdrawal(1.0.into())),
+            (4, 3, TransactionInner::Deposit(1.0.into())),
+            (4, 3, TransactionInner::Dispute),
+            (4, 3, TransactionInner::Chargeback),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        let options = OutputOptions {
+            format: OutputFormat::JsonLines,
+            skip_empty: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_accounts(&state, options, &mut output).unwrap();
+
+        // Client 2 is empty, but client 3 is kept because it's locked
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":1,\"held\":0,\"total\":1,\"locked\":false}\n\
+            {\"client\":3,\"available\":0,\"held\":0,\"total\":0,\"locked\":true}\n"
+        );
+
+        let options = OutputOptions {
+            precision: Precision::Fixed4,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_accounts(&state, options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().nth(1),
+            Some("1,1.0000,0.0000,1.0000,false")
+        );
+
+        // The closure columns are there if they're asked for, whether or not anything's closed
+        let options = OutputOptions {
+            closures: true,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        write_accounts(&state, options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().nth(1),
+            Some("1,1,0,1,false,false,0")
+        );
+        state
+            .process(Transaction::new(
+                TransactionId(5),
+                ClientId(1),
+                TransactionInner::Close,
+            ))
+            .unwrap();
+        let mut output = Vec::new();
+        write_accounts(&state, options, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next(),
+            Some("client,available,held,total,locked,closed,pending_payout")
+        );
+        assert_eq!(lines.next(), Some("1,1,0,1,false,true,1"));
+        assert_eq!(lines.next(), Some("2,0,0,0,false,false,0"));
+    }
+
+    #[test]
+    fn test_json() {
+        let write = |state: &State, format| {
+            let options = OutputOptions {
+                format,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            write_accounts(state, options, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // Still valid JSON without any accounts
+        let state = State::default();
+        assert_eq!(write(&state, OutputFormat::Json), "[\n]\n");
+        assert!(
+            serde_json::from_str::<serde_json::Value>(&write(&state, OutputFormat::Json))
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(write(&state, OutputFormat::JsonLines), "");
+
+        // A negative amount is a JSON number too
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(1.0.into())),
+            (2, TransactionInner::Withdrawal(0.75.into())),
+            (1, TransactionInner::Dispute),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+        let accounts =
+            serde_json::from_str::<serde_json::Value>(&write(&state, OutputFormat::Json)).unwrap();
+        assert_eq!(accounts[0]["available"], -0.75);
+        assert_eq!(accounts[0]["held"], 1);
+
+        assert_eq!(
+            "xml".parse::<OutputFormat>(),
+            Err("Unrecognised output format \"xml\"".into())
+        );
+        assert_eq!("ndjson".parse(), Ok(OutputFormat::JsonLines));
+    }
+
+    #[test]
+    fn test_precision() {
+        for (amount, minimal, fixed4) in [
+            (FixedFloat::from_raw(-1), "-0.0001", "-0.0001"),
+            (FixedFloat::from_raw(123000), "12.3", "12.3000"),
+        ] {
+            assert_eq!(Precision::Minimal.apply(amount).to_string(), minimal);
+            assert_eq!(Precision::Fixed4.apply(amount).to_string(), fixed4);
+        }
+        // Exact all the way to the ends of the range, where going through a float wouldn't be
+        assert_eq!(
+            Precision::Fixed4
+                .apply(FixedFloat::from_raw(i64::MAX))
+                .to_string(),
+            "922337203685477.5807"
+        );
+        assert_eq!(
+            Precision::Fixed4
+                .apply(FixedFloat::from_raw(i64::MIN))
+                .to_string(),
+            "-922337203685477.5808"
+        );
+
+        assert_eq!("fixed4".parse(), Ok(Precision::Fixed4));
+        assert_eq!(
+            "fixed2".parse::<Precision>(),
+            Err("Unrecognised precision \"fixed2\"".into())
+        );
+    }
+
+    #[test]
+    fn test_account_sink() {
+        // Keeps the client ids it's given, or fails
+        struct Recording(Option<Vec<u16>>);
+
+        impl AccountSink for Recording {
+            fn write_accounts(
+                &mut self,
+                accounts: &[(ClientId, &AccountState)],
+            ) -> Result<(), Box<dyn std::error::Error>> {
+                let ids = self.0.as_mut().ok_or("Connection refused")?;
+                ids.extend(accounts.iter().map(|(client_id, _)| client_id.0));
+                Ok(())
+            }
+        }
+
+        let mut state = State::default();
+        for (transaction_id, client_id) in [(1, 3), (2, 1), (3, 2)] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    TransactionInner::Deposit(1.0.into()),
+                ))
+                .unwrap();
+        }
+
+        let mut sink = Recording(Some(Vec::new()));
+        state.write_to(&mut sink).unwrap();
+        assert_eq!(sink.0.unwrap(), [1, 2, 3]);
+
+        // A sink's failure is the caller's
+        let err = state.write_to(&mut Recording(None)).unwrap_err();
+        assert_eq!(err.to_string(), "Connection refused");
+        let mut full = [0; 16];
+        let mut sink = WriterSink::new(&mut full[..], OutputOptions::default());
+        assert!(state.write_to(&mut sink).is_err());
+
+        // Nothing to write is still a header
+        let mut output = Vec::new();
+        State::default()
+            .write_to(&mut WriterSink::new(&mut output, OutputOptions::default()))
+            .unwrap();
+        assert_eq!(output, b"client,available,held,total,locked\n");
+    }
+
+    #[test]
+    fn test_same_header() {
+        let mut state = State::default();
+        for (transaction_id, client_id, inner) in [
+            (1, 1, TransactionInner::Deposit(1.0.into())),
+            (2, 12, TransactionInner::Deposit(1.0.into())),
+            (3, 12, TransactionInner::Close),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        // Only one shard has a closed account, and the state before the close had none
+        let mut before = State::default();
+        before
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ))
+            .unwrap();
+        for closures in [false, true] {
+            let options = OutputOptions {
+                closures,
+                ..Default::default()
+            };
+            let dir = std::env::temp_dir().join(format!(
+                "same-header-{}-{}",
+                std::process::id(),
+                closures
+            ));
+            write_shards(&state, options, &dir, 10).unwrap();
+            let mut outputs = ["clients-0-9.csv", "clients-10-19.csv"]
+                .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+                .to_vec();
+            std::fs::remove_dir_all(&dir).unwrap();
+            for state in [&state, &before] {
+                let mut output = Vec::new();
+                write_accounts(state, options, &mut output).unwrap();
+                outputs.push(String::from_utf8(output).unwrap());
+            }
+
+            let header = match closures {
+                false => "client,available,held,total,locked",
+                true => "client,available,held,total,locked,closed,pending_payout",
+            };
+            for output in outputs {
+                assert_eq!(output.lines().next(), Some(header));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_shards() {
+        let mut state = State::default();
+        for (transaction_id, client_id) in [(1, 1), (2, 2), (3, 12)] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(client_id),
+                    TransactionInner::Deposit(1.0.into()),
+                ))
+                .unwrap();
+        }
+
+        let dir = std::env::temp_dir().join(format!("shards-{}", std::process::id()));
+        let written = write_shards(&state, OutputOptions::default(), &dir, 10).unwrap();
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        let first = std::fs::read_to_string(dir.join("clients-0-9.csv")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written, 2);
+        assert_eq!(files, ["clients-0-9.csv", "clients-10-19.csv"]);
+        assert_eq!(
+            first,
+            "client,available,held,total,locked\n1,1,0,1,false\n2,1,0,1,false\n"
+        );
+    }
+}
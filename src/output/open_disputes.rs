@@ -0,0 +1,159 @@
+use super::Precision;
+use crate::{
+    io::location::Location,
+    state::{Event, State},
+    types::{TransactionId, TransactionState},
+};
+use std::{collections::HashMap, io::Write, rc::Rc};
+
+/// Keeps track of where each transaction came from and when each dispute was opened, so that the
+/// transactions still disputed at the end of a run can be written out as a worklist for whoever
+/// resolves them, rather than found by searching the logs.
+///
+/// The worklist is a CSV file, sorted by transaction, with the columns `tx`, `client` (whose
+/// funds are held), `amount` (how much is held), `age` (how many transactions have been processed
+/// since the dispute was opened), and `file` and `row` (where the disputed transaction came from;
+/// see `Location`). What isn't known, such as the age of a dispute opened before the state was
+/// loaded, is left blank.
+#[derive(Debug, Default)]
+pub struct OpenDisputes {
+    // Each input's name, once, for `origins` to refer to
+    files: Vec<Rc<str>>,
+    // Where each cached transaction came from, as an index into `files` and a row
+    origins: HashMap<TransactionId, (usize, u64)>,
+    // `State::processed` just after each dispute was opened
+    opened: HashMap<TransactionId, u64>,
+}
+
+impl OpenDisputes {
+    /// Records what the last transaction processed, read from `location`, did to `state`.
+    pub fn record(&mut self, state: &State, location: &Location) {
+        for event in state.events() {
+            match *event {
+                Event::FundsDeposited { transaction_id, .. }
+                | Event::FundsWithdrawn { transaction_id, .. }
+                | Event::WithdrawalDeclined { transaction_id, .. }
+                | Event::TransferSent { transaction_id, .. }
+                | Event::FundsAuthorized { transaction_id, .. }
+                // A queued transaction came from where it was queued, not from wherever the
+                // transaction that unlocked its account came from
+                | Event::TransactionQueued { transaction_id, .. } => {
+                    if self.origins.contains_key(&transaction_id) {
+                        continue;
+                    }
+                    if !self
+                        .files
+                        .last()
+                        .is_some_and(|file| Rc::ptr_eq(file, &location.file))
+                    {
+                        self.files.push(location.file.clone());
+                    }
+                    self.origins
+                        .insert(transaction_id, (self.files.len() - 1, location.row));
+                }
+                Event::DisputeOpened { transaction_id, .. } => {
+                    self.opened.insert(transaction_id, state.processed());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn write<Writer: Write>(
+        &self,
+        state: &State,
+        f: Writer,
+        precision: Precision,
+    ) -> Result<(), csv::Error> {
+        let mut disputes = state
+            .transactions()
+            .filter(|txn| txn.state == TransactionState::Disputed)
+            .collect::<Vec<_>>();
+        disputes.sort_unstable_by_key(|txn| txn.transaction_id);
+
+        let mut writer = csv::Writer::from_writer(f);
+        writer.write_record(["tx", "client", "amount", "age", "file", "row"])?;
+        for txn in disputes {
+            let origin = self.origins.get(&txn.transaction_id);
+            writer.write_record([
+                txn.transaction_id.to_string(),
+                txn.disputed_client_id().to_string(),
+                txn.disputed_part()
+                    .inner
+                    .amount()
+                    .map(|amount| precision.apply(amount).to_string())
+                    .unwrap_or_default(),
+                self.opened
+                    .get(&txn.transaction_id)
+                    .map(|opened| (state.processed() - opened).to_string())
+                    .unwrap_or_default(),
+                origin
+                    .map(|&(file, _)| self.files[file].to_string())
+                    .unwrap_or_default(),
+                origin.map(|(_, row)| row.to_string()).unwrap_or_default(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpenDisputes;
+    use crate::{
+        io::location::Location,
+        output::Precision,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_write() {
+        let mut state = State::default();
+        let mut open_disputes = OpenDisputes::default();
+        let mut location = Location::default();
+        for (file, txns) in [
+            (
+                "a.csv",
+                vec![
+                    (1, TransactionInner::Deposit(2.5.into())),
+                    (2, TransactionInner::Deposit(1.0.into())),
+                ],
+            ),
+            (
+                "b.csv",
+                vec![
+                    (3, TransactionInner::Deposit(4.0.into())),
+                    (1, TransactionInner::Dispute),
+                    (3, TransactionInner::PartialDispute(1.5.into())),
+                    (2, TransactionInner::Dispute),
+                    (2, TransactionInner::Resolve),
+                    (4, TransactionInner::Deposit(1.0.into())),
+                ],
+            ),
+        ] {
+            location.file = file.into();
+            for (row, (transaction_id, inner)) in txns.into_iter().enumerate() {
+                location.row = row as u64 + 1;
+                state
+                    .process(Transaction::new(
+                        TransactionId(transaction_id),
+                        ClientId(7),
+                        inner,
+                    ))
+                    .unwrap();
+                open_disputes.record(&state, &location);
+            }
+        }
+
+        let mut output = Vec::new();
+        open_disputes
+            .write(&state, &mut output, Precision::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,amount,age,file,row\n1,7,2.5,4,a.csv,1\n3,7,1.5,3,b.csv,1\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{io, state::State};
+use arrow_array::RecordBatch;
+use parquet::{
+    arrow::ArrowWriter, basic::Compression, errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use std::{error::Error, path::Path};
+
+/// Writes the accounts table to a Parquet file at `path`, with the same columns (and exact
+/// decimal typing) as `State::to_record_batch`.
+pub fn write(state: &State, path: &Path) -> Result<(), Box<dyn Error>> {
+    let batch = state.to_record_batch()?;
+
+    io::write_file_atomically(path, |f| {
+        write_batch(f, &batch).map_err(std::io::Error::other)
+    })?;
+
+    Ok(())
+}
+
+fn write_batch<Writer: std::io::Write + Send>(
+    f: Writer,
+    batch: &RecordBatch,
+) -> Result<(), ParquetError> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let mut writer = ArrowWriter::try_new(f, batch.schema(), Some(props))?;
+    writer.write(batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        arrow::decimal_type,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = State::default();
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.2345.into()),
+            ))
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("accounts-{}.parquet", std::process::id()));
+        super::write(&state, &path).unwrap();
+
+        let batches = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(batches, vec![state.to_record_batch().unwrap()]);
+        assert_eq!(
+            batches[0]
+                .schema()
+                .field_with_name("total")
+                .unwrap()
+                .data_type(),
+            &decimal_type()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use super::AccountSink;
+use crate::{state::AccountState, types::ClientId};
+use ::postgres::{types::ToSql, Client, NoTls, Transaction};
+use std::error::Error;
+
+// Accounts are upserted this many to a statement, well under Postgres' limit on parameters
+const BATCH_SIZE: usize = 1000;
+
+/// Upserts the accounts into `table` in the Postgres database at `dsn` (e.g.
+/// `host=localhost user=oct dbname=reporting`, or a `postgresql://` URL), creating the table if
+/// it doesn't exist. `table` may be qualified with a schema, as in `reporting.accounts`.
+///
+/// The table has the columns `client` (`INTEGER PRIMARY KEY`), `available`, `held` and `total`
+/// (`NUMERIC(24, 4)`, so amounts are exact) and `locked` (`BOOLEAN`). Accounts already in it are
+/// updated, and any others are left alone. Everything happens in one transaction, so readers see
+/// either all of this run's accounts or none of them.
+pub struct PostgresSink {
+    dsn: String,
+    table: String,
+}
+
+impl PostgresSink {
+    /// Connects when the accounts are written, not before.
+    pub fn new(dsn: &str, table: &str) -> Self {
+        Self {
+            dsn: dsn.to_owned(),
+            table: table.to_owned(),
+        }
+    }
+}
+
+impl AccountSink for PostgresSink {
+    fn write_accounts(
+        &mut self,
+        accounts: &[(ClientId, &AccountState)],
+    ) -> Result<(), Box<dyn Error>> {
+        let result = Client::connect(&self.dsn, NoTls).and_then(|mut client| {
+            let mut transaction = client.transaction()?;
+            upsert(accounts, &mut transaction, &quote_table(&self.table))?;
+            transaction.commit()
+        });
+
+        // A postgres::Error's message leaves out its cause, e.g. why the connection failed
+        result.map_err(|err| match err.source() {
+            Some(source) => format!("{}: {}", err, source).into(),
+            None => err.into(),
+        })
+    }
+}
+
+fn upsert(
+    accounts: &[(ClientId, &AccountState)],
+    transaction: &mut Transaction,
+    table: &str,
+) -> Result<(), ::postgres::Error> {
+    transaction.batch_execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {} (\
+            client INTEGER PRIMARY KEY, \
+            available NUMERIC(24, 4) NOT NULL, \
+            held NUMERIC(24, 4) NOT NULL, \
+            total NUMERIC(24, 4) NOT NULL, \
+            locked BOOLEAN NOT NULL\
+        )",
+        table
+    ))?;
+
+    for batch in accounts.chunks(BATCH_SIZE) {
+        // Amounts go over as their exact decimal text
+        let rows = batch
+            .iter()
+            .map(|(client_id, account)| {
+                (
+                    client_id.0 as i32,
+                    account.available().to_string(),
+                    account.held().to_string(),
+                    account.total().to_string(),
+                    account.locked(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let params = rows
+            .iter()
+            .flat_map(|(client_id, available, held, total, locked)| {
+                [
+                    client_id as &(dyn ToSql + Sync),
+                    available,
+                    held,
+                    total,
+                    locked,
+                ]
+            })
+            .collect::<Vec<_>>();
+        transaction.execute(&upsert_statement(table, rows.len()), &params)?;
+    }
+
+    Ok(())
+}
+
+// Quotes each part of a possibly schema-qualified table name, so any name can be used safely
+fn quote_table(table: &str) -> String {
+    table
+        .split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn upsert_statement(table: &str, rows: usize) -> String {
+    let values = (0..rows)
+        .map(|row| {
+            let n = row * 5;
+            format!(
+                "(${}::int4, ${}::text::numeric, ${}::text::numeric, ${}::text::numeric, ${}::bool)",
+                n + 1,
+                n + 2,
+                n + 3,
+                n + 4,
+                n + 5
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "INSERT INTO {} (client, available, held, total, locked) VALUES {} \
+        ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, \
+        held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked",
+        table, values
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote_table, upsert_statement};
+
+    #[test]
+    fn test_upsert_statement() {
+        assert_eq!(
+            quote_table("reporting.accounts"),
+            "\"reporting\".\"accounts\""
+        );
+        assert_eq!(quote_table("a\"; DROP"), "\"a\"\"; DROP\"");
+
+        assert_eq!(
+            upsert_statement("\"accounts\"", 2),
+            "INSERT INTO \"accounts\" (client, available, held, total, locked) VALUES \
+            ($1::int4, $2::text::numeric, $3::text::numeric, $4::text::numeric, $5::bool), \
+            ($6::int4, $7::text::numeric, $8::text::numeric, $9::text::numeric, $10::bool) \
+            ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available, \
+            held = EXCLUDED.held, total = EXCLUDED.total, locked = EXCLUDED.locked"
+        );
+    }
+}
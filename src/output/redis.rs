@@ -0,0 +1,241 @@
+use crate::{
+    state::{AccountState, State},
+    types::ClientId,
+};
+use ::redis::{Client, Connection};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+// How long to wait before trying again after Redis fails
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Mirrors accounts into Redis as they change, so that other services can read near-real-time
+/// balances without asking the engine. Each account is a hash at `<prefix><client id>`, with the
+/// fields `available`, `held`, `total` and `locked`, formatted as in the CSV output.
+///
+/// Writes happen behind the engine's back, on a thread of their own, so a slow or unavailable
+/// Redis never holds up processing. Changes queue up while a write is in flight, and only each
+/// account's latest state is written. If Redis fails, the failure is reported on stderr and the
+/// write is retried every second, with anything newer merged in.
+pub struct RedisMirror {
+    sender: Sender<(ClientId, AccountState)>,
+    worker: JoinHandle<()>,
+    // The client whose account was sent last, so that the several events of one transaction
+    // don't send it several times
+    last: Option<ClientId>,
+}
+
+impl RedisMirror {
+    /// Connects to the Redis server at `url` (e.g. `redis://localhost:6379`), failing if it
+    /// can't, so that a misconfiguration shows up straight away.
+    pub fn connect(url: &str, prefix: &str) -> Result<Self, Box<dyn Error>> {
+        let client = Client::open(url)?;
+        let connection = connect(&client)?;
+        let (sender, receiver) = mpsc::channel();
+        let prefix = prefix.to_owned();
+        let worker =
+            std::thread::spawn(move || write_behind(receiver, client, Some(connection), &prefix));
+
+        Ok(Self {
+            sender,
+            worker,
+            last: None,
+        })
+    }
+
+    /// Queues the accounts that the last call to `State::process` changed.
+    pub fn record(&mut self, state: &State) {
+        self.last = None;
+        for event in state.events() {
+            let client_id = event.client_id();
+            if self.last == Some(client_id) {
+                continue;
+            }
+            if let Some(account) = state.account(client_id) {
+                // The worker only goes away if it panicked, which has been reported already
+                let _ = self.sender.send((client_id, account.clone()));
+            }
+            self.last = Some(client_id);
+        }
+    }
+
+    /// Queues every account, e.g. to bring Redis up to date with a state that was loaded.
+    pub fn record_all(&mut self, state: &State) {
+        for (client_id, account) in state.accounts() {
+            let _ = self.sender.send((client_id, account.clone()));
+        }
+    }
+
+    /// Waits for everything queued to be written, trying once more if Redis has failed.
+    pub fn finish(self) {
+        drop(self.sender);
+        let _ = self.worker.join();
+    }
+}
+
+fn connect(client: &Client) -> Result<Connection, ::redis::RedisError> {
+    let connection = client.get_connection_with_timeout(TIMEOUT)?;
+    connection.set_write_timeout(Some(TIMEOUT))?;
+    connection.set_read_timeout(Some(TIMEOUT))?;
+    Ok(connection)
+}
+
+fn write_behind(
+    receiver: Receiver<(ClientId, AccountState)>,
+    client: Client,
+    mut connection: Option<Connection>,
+    prefix: &str,
+) {
+    let mut pending = HashMap::new();
+    let mut open = true;
+    while open || !pending.is_empty() {
+        // Wait for a change, unless there's a failed write to retry, then take every other
+        // change that's queued
+        if pending.is_empty() {
+            match receiver.recv() {
+                Ok((client_id, account)) => pending.insert(client_id, account),
+                Err(_) => return,
+            };
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok((client_id, account)) => {
+                    pending.insert(client_id, account);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+
+        match write(&client, &mut connection, prefix, &pending) {
+            Ok(()) => pending.clear(),
+            Err(err) if open => {
+                eprintln!("Error: Failed to update Redis, retrying: {}", err);
+                connection = None;
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            Err(err) => {
+                eprintln!(
+                    "Error: Failed to update Redis, so {} accounts weren't mirrored: {}",
+                    pending.len(),
+                    err
+                );
+                return;
+            }
+        }
+    }
+}
+
+fn write(
+    client: &Client,
+    connection: &mut Option<Connection>,
+    prefix: &str,
+    accounts: &HashMap<ClientId, AccountState>,
+) -> Result<(), ::redis::RedisError> {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => connection.insert(connect(client)?),
+    };
+
+    let mut pipe = ::redis::pipe();
+    for (client_id, account) in accounts {
+        pipe.cmd("HSET")
+            .arg(format!("{}{}", prefix, client_id))
+            .arg("available")
+            .arg(account.available().to_string())
+            .arg("held")
+            .arg(account.held().to_string())
+            .arg("total")
+            .arg(account.total().to_string())
+            .arg("locked")
+            .arg(account.locked().to_string())
+            .ignore();
+    }
+    pipe.query::<()>(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RedisMirror;
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    // Reads one command from a RESP client, as its arguments
+    fn read_command(f: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        f.read_line(&mut line).ok().filter(|read| *read > 0)?;
+        let arguments = line.trim_end().strip_prefix('*')?.parse::<usize>().ok()?;
+        (0..arguments)
+            .map(|_| {
+                line.clear();
+                f.read_line(&mut line).ok()?;
+                let len = line.trim_end().strip_prefix('$')?.parse::<usize>().ok()?;
+                let mut argument = vec![0; len + 2];
+                f.read_exact(&mut argument).ok()?;
+                argument.truncate(len);
+                String::from_utf8(argument).ok()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mirror() {
+        // A fake Redis server that acknowledges everything and keeps the HSETs
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut hsets = Vec::new();
+            while let Some(command) = read_command(&mut reader) {
+                writer.write_all(b"+OK\r\n").unwrap();
+                if command[0] == "HSET" {
+                    hsets.push(command[1..].join(" "));
+                }
+            }
+            hsets
+        });
+
+        let mut mirror = RedisMirror::connect(&url, "oct:account:").unwrap();
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(2.5.into())),
+            (1, TransactionInner::Dispute),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(7),
+                    inner,
+                ))
+                .unwrap();
+            mirror.record(&state);
+        }
+        mirror.finish();
+
+        // The deposit may or may not have been written before the dispute came in, but the
+        // latest state always is
+        let hsets = server.join().unwrap();
+        assert!((1..=2).contains(&hsets.len()));
+        assert_eq!(
+            hsets.last().unwrap(),
+            "oct:account:7 available 0 held 2.5 total 2.5 locked false"
+        );
+    }
+}
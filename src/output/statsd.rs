@@ -0,0 +1,181 @@
+use super::summary::Summary;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+// Keeps each datagram within a typical MTU, so nothing gets fragmented or truncated on the way
+const MAX_PACKET: usize = 1432;
+
+/// Sends a run's `Summary` to a StatsD server over UDP, for shops that collect metrics that way
+/// rather than by scraping. Counters are sent as counters (`|c`), the state of the accounts as
+/// gauges (`|g`) and the run time as a timing (`|ms`). Latency percentiles, if they were tracked,
+/// are gauges in microseconds, e.g. `<prefix>.latency.deposit.p99`.
+///
+/// Plain StatsD has no tags, so the transaction type and outcome go in the metric name
+/// (`<prefix>.transactions.deposit.applied`). With `dogstatsd`, they're sent as tags instead
+/// (`<prefix>.transactions` with `#type:deposit,outcome:applied`). The `queued` outcome is only
+/// sent for a type with transactions waiting for their accounts to be unlocked.
+///
+/// Being UDP, sending is best effort: nothing is lost if the server isn't there.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+}
+
+impl StatsdSink {
+    pub fn new(
+        address: impl ToSocketAddrs,
+        prefix: &str,
+        dogstatsd: bool,
+    ) -> std::io::Result<Self> {
+        let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "No StatsD address")
+        })?;
+        let local: SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.into(),
+            dogstatsd,
+        })
+    }
+
+    pub fn send(&self, summary: &Summary) -> std::io::Result<()> {
+        let mut packet = String::new();
+        for line in self.lines(summary) {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+                self.socket.send(packet.as_bytes())?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet += &line;
+        }
+        if !packet.is_empty() {
+            self.socket.send(packet.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn lines(&self, summary: &Summary) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for (type_name, counts) in &summary.transactions {
+            let queued = (counts.queued > 0).then_some(("queued", counts.queued));
+            for (outcome, count) in [("applied", counts.applied), ("ignored", counts.ignored)]
+                .into_iter()
+                .chain(queued)
+            {
+                lines.push(if self.dogstatsd {
+                    format!(
+                        "{}.transactions:{}|c|#type:{},outcome:{}",
+                        self.prefix, count, type_name, outcome
+                    )
+                } else {
+                    format!(
+                        "{}.transactions.{}.{}:{}|c",
+                        self.prefix, type_name, outcome, count
+                    )
+                });
+            }
+        }
+
+        for (name, value, kind) in [
+            ("disputes.opened", summary.disputes_opened, "c"),
+            ("disputes.resolved", summary.disputes_resolved, "c"),
+            ("disputes.charged_back", summary.charged_back, "c"),
+            ("rejected", summary.rejected, "c"),
+            ("invalid", summary.invalid, "c"),
+            ("accounts.created", summary.accounts_created, "c"),
+            ("accounts.locked", summary.accounts_locked, "g"),
+            ("accounts.closed", summary.accounts_closed, "g"),
+            ("memory_bytes", summary.memory_bytes, "g"),
+        ] {
+            lines.push(format!("{}.{}:{}|{}", self.prefix, name, value, kind));
+        }
+
+        for (type_name, latency) in &summary.latency {
+            for (name, value) in latency.figures() {
+                lines.push(if self.dogstatsd {
+                    format!(
+                        "{}.latency.{}:{:.3}|g|#type:{}",
+                        self.prefix, name, value, type_name
+                    )
+                } else {
+                    format!(
+                        "{}.latency.{}.{}:{:.3}|g",
+                        self.prefix, type_name, name, value
+                    )
+                });
+            }
+        }
+
+        lines.push(format!(
+            "{}.run.duration:{}|ms",
+            self.prefix,
+            (summary.elapsed_secs * 1000.0).round() as u64
+        ));
+        lines.push(format!(
+            "{}.run.transactions_per_sec:{:.0}|g",
+            self.prefix, summary.transactions_per_sec
+        ));
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StatsdSink;
+    use crate::{
+        output::summary::Summary,
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::{net::UdpSocket, time::Duration};
+
+    #[test]
+    fn test_send() {
+        let mut state = State::default();
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ))
+            .unwrap();
+        let summary = Summary::new(&state, Duration::from_millis(1500));
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let receive = || {
+            let mut buf = [0; 2048];
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        StatsdSink::new(server.local_addr().unwrap(), "oct", false)
+            .unwrap()
+            .send(&summary)
+            .unwrap();
+        let packet = receive();
+        let lines = packet.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "oct.transactions.deposit.applied:1|c");
+        assert_eq!(lines[1], "oct.transactions.deposit.ignored:0|c");
+        assert!(lines.contains(&"oct.accounts.created:1|c"));
+        assert!(lines.contains(&"oct.run.duration:1500|ms"));
+
+        StatsdSink::new(server.local_addr().unwrap(), "oct", true)
+            .unwrap()
+            .send(&summary)
+            .unwrap();
+        assert!(receive().starts_with("oct.transactions:1|c|#type:deposit,outcome:applied\n"));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    state::{State, TypeCounts},
+    types::FixedFloat,
+};
+use std::{collections::BTreeMap, time::Duration};
+
+/// End-of-run figures: what happened to each type of transaction, dispute activity, accounts,
+/// and throughput. `Display` gives a human-readable version for stderr, and it serialises to JSON
+/// for anything that wants to collect them.
+#[derive(Debug, serde::Serialize)]
+pub struct Summary {
+    pub transactions: BTreeMap<&'static str, TypeCounts>,
+    pub disputes_opened: u64,
+    pub disputes_resolved: u64,
+    pub charged_back: u64,
+    /// Transactions that were ignored, including invalid ones.
+    pub rejected: u64,
+    pub invalid: u64,
+    pub accounts_created: u64,
+    pub accounts_locked: u64,
+    pub accounts_closed: u64,
+    /// What's owed to the clients of closed accounts, in total; see `AccountState::pending_payout`.
+    pub pending_payouts: FixedFloat,
+    /// See `State::memory_usage`.
+    pub memory_bytes: u64,
+    pub elapsed_secs: f64,
+    pub transactions_per_sec: f64,
+    /// By transaction type; only if `Options::track_latency` was set.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub latency: BTreeMap<&'static str, LatencySummary>,
+}
+
+/// Percentiles of the time taken to process one type of transaction, in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean_us: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+impl LatencySummary {
+    /// The figures, with their names as used in metrics.
+    pub fn figures(&self) -> [(&'static str, f64); 5] {
+        [
+            ("mean", self.mean_us),
+            ("p50", self.p50_us),
+            ("p90", self.p90_us),
+            ("p99", self.p99_us),
+            ("max", self.max_us),
+        ]
+    }
+}
+
+impl Summary {
+    /// Summarises everything `state` has processed, which took `elapsed`.
+    pub fn new(state: &State, elapsed: Duration) -> Self {
+        let counters = state.counters();
+        let stats = state.stats();
+        let applied = |type_name| {
+            counters
+                .transactions
+                .get(type_name)
+                .map_or(0, |counts| counts.applied)
+        };
+        let total = counters
+            .transactions
+            .values()
+            .map(|counts| counts.applied + counts.ignored + counts.queued)
+            .sum::<u64>();
+
+        Self {
+            transactions: counters.transactions.clone(),
+            disputes_opened: applied("dispute"),
+            disputes_resolved: applied("resolve"),
+            charged_back: applied("chargeback"),
+            rejected: counters
+                .transactions
+                .values()
+                .map(|counts| counts.ignored)
+                .sum(),
+            invalid: stats.invalid_transactions as u64,
+            accounts_created: counters.accounts_created,
+            accounts_locked: stats.locked_accounts as u64,
+            accounts_closed: stats.closed_accounts as u64,
+            pending_payouts: state
+                .accounts()
+                .map(|(_, account)| account.pending_payout())
+                .fold(FixedFloat::default(), |total, payout| total + payout),
+            memory_bytes: stats.memory_bytes as u64,
+            elapsed_secs: elapsed.as_secs_f64(),
+            transactions_per_sec: total as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            latency: state
+                .latencies()
+                .iter()
+                .map(|(type_name, histogram)| {
+                    let micros = |duration: Duration| duration.as_secs_f64() * 1e6;
+                    (
+                        *type_name,
+                        LatencySummary {
+                            count: histogram.count(),
+                            mean_us: micros(histogram.mean()),
+                            p50_us: micros(histogram.quantile(0.5)),
+                            p90_us: micros(histogram.quantile(0.9)),
+                            p99_us: micros(histogram.quantile(0.99)),
+                            max_us: micros(histogram.max()),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Transactions:")?;
+        for (type_name, counts) in &self.transactions {
+            write!(
+                f,
+                "  {}: {} applied, {} ignored",
+                type_name, counts.applied, counts.ignored
+            )?;
+            if counts.queued > 0 {
+                write!(f, ", {} queued", counts.queued)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "Disputes: {} opened, {} resolved, {} charged back",
+            self.disputes_opened, self.disputes_resolved, self.charged_back
+        )?;
+        writeln!(f, "Rejected: {} ({} invalid)", self.rejected, self.invalid)?;
+        write!(
+            f,
+            "Accounts: {} created, {} locked",
+            self.accounts_created, self.accounts_locked
+        )?;
+        if self.accounts_closed > 0 {
+            write!(
+                f,
+                ", {} closed ({} pending payout)",
+                self.accounts_closed, self.pending_payouts
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Memory: about {:.1} MiB",
+            self.memory_bytes as f64 / 1048576.0
+        )?;
+        if !self.latency.is_empty() {
+            writeln!(f, "Latency (µs):")?;
+            for (type_name, latency) in &self.latency {
+                write!(f, "  {}: {} timed", type_name, latency.count)?;
+                for (name, value) in latency.figures() {
+                    write!(f, ", {} {:.3}", name, value)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        write!(
+            f,
+            "Took {:.3}s ({:.0} transactions/s)",
+            self.elapsed_secs, self.transactions_per_sec
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_summary() {
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(1.0.into())),
+            (2, TransactionInner::Withdrawal(2.0.into())),
+            (1, TransactionInner::Dispute),
+            (1, TransactionInner::Chargeback),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        assert_eq!(
+            Summary::new(&state, Duration::from_secs(2)).to_string(),
+            "Transactions:\n  \
+            chargeback: 1 applied, 0 ignored\n  \
+            deposit: 1 applied, 0 ignored\n  \
+            dispute: 1 applied, 0 ignored\n  \
+            withdrawal: 0 applied, 1 ignored\n\
+            Disputes: 1 opened, 0 resolved, 1 charged back\n\
+            Rejected: 1 (0 invalid)\n\
+            Accounts: 1 created, 1 locked\n\
+            Memory: about 0.0 MiB\n\
+            Took 2.000s (2 transactions/s)"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::notify::{Notification, NotificationSink};
+use std::{
+    error::Error,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+// Each notification is tried this many times at most at each URL, waiting twice as long after
+// each failure, starting from `FIRST_BACKOFF`
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sends one body to one URL, failing with whether it's worth trying again
+type Post = Box<dyn FnMut(&str, &str) -> Result<(), (String, bool)> + Send>;
+
+/// POSTs each notification to each of a set of webhook URLs, so that they can be acted on straight
+/// away rather than found in the next report. The body is `Notification::to_json`, e.g.
+/// `{"event":"charged_back","client":1,"tx":5,"amount":1.5}` or
+/// `{"event":"account_locked","client":1}`.
+///
+/// Notifications are sent in order on a thread of their own, so processing isn't held up by a
+/// slow endpoint. A failed request is retried with exponential backoff, unless the endpoint
+/// refused it with a 4xx status (other than 429); once it's given up on, the error is logged and
+/// the next notification is sent.
+pub struct WebhookSink {
+    // Both taken by `finish`
+    sender: Option<Sender<String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WebhookSink {
+    pub fn new(urls: &[String]) -> Self {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(TIMEOUT))
+            .build()
+            .into();
+        Self::with_post(
+            urls,
+            FIRST_BACKOFF,
+            Box::new(move |url, body| {
+                agent
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .send(body)
+                    .map(|_| ())
+                    .map_err(|err| {
+                        let retry = match err {
+                            ureq::Error::StatusCode(status) => {
+                                status == 429 || !(400..500).contains(&status)
+                            }
+                            _ => true,
+                        };
+                        (format!("POST {} failed: {}", url, err), retry)
+                    })
+            }),
+        )
+    }
+
+    fn with_post(urls: &[String], first_backoff: Duration, post: Post) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let urls = urls.to_vec();
+        let thread = std::thread::spawn(move || send_all(receiver, &urls, first_backoff, post));
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    /// Queues the notification to be sent.
+    fn notify(&mut self, notification: &Notification) -> Result<(), Box<dyn Error>> {
+        if let Some(sender) = &self.sender {
+            // Only fails once the thread has gone, which it never does before `finish`
+            let _ = sender.send(notification.to_json());
+        }
+        Ok(())
+    }
+
+    /// Waits for every queued notification to be sent, or given up on.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Webhook thread panicked");
+        }
+        Ok(())
+    }
+}
+
+fn send_all(receiver: Receiver<String>, urls: &[String], first_backoff: Duration, mut post: Post) {
+    for body in receiver {
+        for url in urls {
+            let mut backoff = first_backoff;
+            for attempt in 1..=MAX_ATTEMPTS {
+                match post(url, &body) {
+                    Ok(()) => break,
+                    Err((err, retry)) if retry && attempt < MAX_ATTEMPTS => {
+                        tracing::debug!(error = %err, attempt, "Webhook failed; will retry");
+                        std::thread::sleep(backoff);
+                        backoff *= 2;
+                    }
+                    Err((err, _)) => {
+                        tracing::warn!(error = %err, body, "Webhook notification dropped");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebhookSink, MAX_ATTEMPTS};
+    use crate::{
+        notify::{Notification, NotificationSink},
+        types::{ClientId, TransactionId},
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    #[test]
+    fn test_sink() {
+        let posted = Arc::new(Mutex::new(Vec::new()));
+        let mut sink = {
+            let posted = posted.clone();
+            let urls = ["http://a".to_owned(), "http://b".to_owned()];
+            WebhookSink::with_post(
+                &urls,
+                Duration::ZERO,
+                Box::new(move |url, body| {
+                    let mut posted = posted.lock().unwrap();
+                    posted.push((url.to_owned(), body.to_owned()));
+                    match url {
+                        // Always down, so given up on after every attempt
+                        "http://a" => Err(("Connection refused".into(), true)),
+                        _ => Ok(()),
+                    }
+                }),
+            )
+        };
+
+        for notification in [
+            Notification::ChargedBack {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: 2.0.into(),
+            },
+            Notification::AccountLocked {
+                client_id: ClientId(1),
+            },
+        ] {
+            sink.notify(&notification).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let posted = posted.lock().unwrap();
+        let to_b = posted
+            .iter()
+            .filter(|(url, _)| url == "http://b")
+            .map(|(_, body)| body.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            to_b,
+            [
+                r#"{"event":"charged_back","client":1,"tx":1,"amount":2}"#,
+                r#"{"event":"account_locked","client":1}"#,
+            ]
+        );
+        assert_eq!(posted.len(), 2 * MAX_ATTEMPTS as usize + 2);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::io::{TransactionResult, TransactionSource};
+use std::time::{Duration, Instant};
+
+/// Wraps an iterator so that items are yielded at (at most) a fixed rate, for rehearsing
+/// scenarios with realistic timing rather than as fast as the input can be read. Each item is
+/// scheduled relative to the start, so a slow consumer catches up instead of drifting.
+pub struct Paced<I> {
+    inner: I,
+    interval: Duration,
+    started: Option<Instant>,
+    count: u32,
+}
+
+impl<I> Paced<I> {
+    pub fn new(inner: I, per_second: f64) -> Self {
+        Self {
+            inner,
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            started: None,
+            count: 0,
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for Paced<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let due = started + self.interval * self.count;
+
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+
+        self.count = self.count.saturating_add(1);
+        self.inner.next()
+    }
+}
+
+/// Wraps a source so that transactions are yielded as far apart as their timestamps say, divided
+/// by `speed`, e.g. 60 to replay an hour in a minute. As with `Paced`, each is scheduled relative
+/// to the first timestamped one, so a slow consumer catches up instead of drifting. Rows without a
+/// timestamp, rows timestamped before the first, and errors aren't held back.
+pub struct Timed<I> {
+    inner: I,
+    speed: f64,
+    // When the first timestamped transaction was yielded, and its timestamp
+    started: Option<(Instant, u64)>,
+}
+
+impl<I> Timed<I> {
+    pub fn new(inner: I, speed: f64) -> Self {
+        Self {
+            inner,
+            speed,
+            started: None,
+        }
+    }
+}
+
+impl<I: TransactionSource> Iterator for Timed<I> {
+    type Item = TransactionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.inner.next()?;
+        let Some(timestamp) = result.as_ref().ok().and_then(|txn| txn.timestamp) else {
+            return Some(result);
+        };
+
+        let (started, first) = *self
+            .started
+            .get_or_insert_with(|| (Instant::now(), timestamp));
+        let due =
+            started + Duration::from_secs_f64(timestamp.saturating_sub(first) as f64 / self.speed);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Paced, Timed};
+    use crate::io::CsvFileReader;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_paced() {
+        let started = Instant::now();
+        assert_eq!(Paced::new(0..5, 100.0).sum::<i32>(), 10);
+
+        // The first item is immediate, so it's four intervals (and the final None is another)
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_timed() {
+        // Three seconds from the first timestamp to the last, at 50 times the speed
+        let data = b"type,client,tx,amount,timestamp\ndeposit,1,1,1.0,100\ndeposit,1,2,1.0,\n\
+            deposit,1,3,1.0,102\ndeposit,1,4,1.0,103\n";
+        let started = Instant::now();
+        let ids = Timed::new(CsvFileReader::from_reader(Box::new(&data[..])), 50.0)
+            .map(|result| result.unwrap().transaction_id.0)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, [1, 2, 3, 4]);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(60));
+        assert!(elapsed < Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,173 @@
+//! What the server modes (`serve`) share: one `State` that every connection submits transactions
+//! to and reads accounts from, whichever protocol it came in on. Each protocol lives in a module of
+//! its own, behind a feature.
+
+use crate::{
+    health::Health,
+    state::{AccountState, Event, Outcome, ProcessError, State},
+    types::{ClientId, Transaction},
+};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "tcp")]
+pub mod tcp;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+/// Called with each account whenever it changes. Returns false once it no longer wants updates,
+/// e.g. because its connection has gone.
+pub type Subscriber = Box<dyn FnMut(ClientId, &AccountState) -> bool + Send>;
+
+/// The state behind a server, safe to share between connections.
+pub struct Engine {
+    state: Mutex<State>,
+    // Always locked after `state`, so that updates reach subscribers in the order they happened
+    subscribers: Mutex<Vec<Subscriber>>,
+    health: Arc<Health>,
+}
+
+impl Engine {
+    pub fn new(state: State) -> Self {
+        Self::with_health(state, Arc::default())
+    }
+
+    /// An engine that counts each transaction it processes in `health`, for the health probes.
+    pub fn with_health(state: State, health: Arc<Health>) -> Self {
+        Self {
+            state: Mutex::new(state),
+            subscribers: Mutex::new(Vec::new()),
+            health,
+        }
+    }
+
+    /// Processes `txn`, then passes each account it changed to the subscribers.
+    pub fn submit(&self, txn: Transaction) -> Result<Outcome, ProcessError> {
+        let mut state = self.state.lock().unwrap();
+        let outcome = state.process(txn)?;
+        self.health.processed();
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if !subscribers.is_empty() {
+            let mut last = None;
+            for event in state.events() {
+                if let Event::WithdrawalDeclined { .. } | Event::WithdrawalBlocked { .. } = event {
+                    continue;
+                }
+                // The several events of one transaction are all for the same account
+                let client_id = event.client_id();
+                if last == Some(client_id) {
+                    continue;
+                }
+                last = Some(client_id);
+                if let Some(account) = state.account(client_id) {
+                    subscribers.retain_mut(|subscriber| subscriber(client_id, account));
+                }
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<AccountState> {
+        self.state.lock().unwrap().account(client_id).cloned()
+    }
+
+    /// Every account, ordered by client id.
+    pub fn accounts(&self) -> Vec<(ClientId, AccountState)> {
+        let state = self.state.lock().unwrap();
+        state
+            .sorted_accounts()
+            .into_iter()
+            .map(|(client_id, account)| (client_id, account.clone()))
+            .collect()
+    }
+
+    /// Passes every change from now on to `subscriber`, until it returns false.
+    pub fn subscribe(&self, subscriber: Subscriber) {
+        let _state = self.state.lock().unwrap();
+        self.subscribers.lock().unwrap().push(subscriber);
+    }
+
+    /// The state itself, e.g. to save it. Nothing can be submitted while it's held.
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Completes once `stop` is set, for a server to shut down on.
+#[cfg(any(
+    feature = "grpc",
+    feature = "rest",
+    feature = "tcp",
+    feature = "websocket"
+))]
+async fn stopped(stop: &std::sync::atomic::AtomicBool) {
+    // How often to check
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+    use crate::{
+        state::{IgnoreReason, Outcome, State},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_engine() {
+        let engine = Engine::new(State::default());
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        {
+            let updates = updates.clone();
+            engine.subscribe(Box::new(move |client_id, account| {
+                let mut updates = updates.lock().unwrap();
+                updates.push((client_id, account.available()));
+                // Only wants the first two
+                updates.len() < 2
+            }));
+        }
+
+        for (transaction, outcome) in [
+            (
+                txn(1, 1, TransactionInner::Deposit(2.0.into())),
+                Outcome::Applied,
+            ),
+            (
+                txn(2, 1, TransactionInner::Withdrawal(3.0.into())),
+                Outcome::Ignored(IgnoreReason::InsufficientFunds),
+            ),
+            (
+                txn(3, 2, TransactionInner::Deposit(1.0.into())),
+                Outcome::Applied,
+            ),
+            (
+                txn(4, 2, TransactionInner::Deposit(1.0.into())),
+                Outcome::Applied,
+            ),
+        ] {
+            assert_eq!(engine.submit(transaction).unwrap(), outcome);
+        }
+
+        assert_eq!(
+            *updates.lock().unwrap(),
+            [(ClientId(1), 2.0.into()), (ClientId(2), 1.0.into())]
+        );
+        assert_eq!(engine.account(ClientId(2)).unwrap().available(), 2.0.into());
+        assert!(engine.account(ClientId(3)).is_none());
+        assert_eq!(engine.accounts().len(), 2);
+    }
+}
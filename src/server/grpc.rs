@@ -0,0 +1,324 @@
+//! The gRPC API (see proto/engine.proto). There's no protoc in the build, so the messages and the
+//! service are written out by hand, the way tonic-build would generate them.
+
+use super::Engine;
+use crate::{
+    io::protobuf::TransactionMessage,
+    state::{AccountState, Outcome, ProcessError},
+    types::{ClientId, Transaction},
+};
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    net::TcpListener,
+    pin::Pin,
+    sync::{atomic::AtomicBool, Arc},
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{
+    body::Body,
+    codegen::{http, Body as HttpBody, BoxFuture, Service, StdError},
+    server::{Grpc, NamedService},
+    Request, Response, Status,
+};
+use tonic_prost::ProstCodec;
+
+// How many updates a StreamAccountUpdates client can fall behind before it's cut off
+const UPDATES_BUFFER: usize = 1024;
+
+// These mirror proto/engine.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubmitTransactionResponse {
+    #[prost(bool, tag = "1")]
+    pub applied: bool,
+    #[prost(string, tag = "2")]
+    pub ignored_reason: String,
+    #[prost(bool, tag = "3")]
+    pub queued: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetAccountRequest {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamAccountUpdatesRequest {
+    #[prost(uint32, optional, tag = "1")]
+    pub client: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Account {
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(int64, tag = "2")]
+    pub available: i64,
+    #[prost(int64, tag = "3")]
+    pub held: i64,
+    #[prost(int64, tag = "4")]
+    pub total: i64,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+}
+
+impl Account {
+    fn new(client_id: ClientId, account: &AccountState) -> Self {
+        Self {
+            client: client_id.0.into(),
+            available: account.available().raw(),
+            held: account.held().raw(),
+            total: account.total().raw(),
+            locked: account.locked(),
+        }
+    }
+}
+
+/// Serves the API on `listener` until `stop` is set, then waits for the calls in progress to
+/// finish.
+pub fn serve(
+    engine: Arc<Engine>,
+    listener: TcpListener,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let incoming = tokio_stream::wrappers::TcpListenerStream::new(
+            tokio::net::TcpListener::from_std(listener)?,
+        );
+        tonic::transport::Server::builder()
+            .serve_with_incoming_shutdown(EngineServer(engine), incoming, super::stopped(&stop))
+            .await?;
+        Ok(())
+    })
+}
+
+#[derive(Clone)]
+struct EngineServer(Arc<Engine>);
+
+impl NamedService for EngineServer {
+    const NAME: &'static str = "off_chain_transactions.Engine";
+}
+
+impl<B> Service<http::Request<B>> for EngineServer
+where
+    B: HttpBody + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let engine = self.0.clone();
+        match request.uri().path() {
+            "/off_chain_transactions.Engine/SubmitTransaction" => Box::pin(async move {
+                let method = Unary(engine, submit_transaction);
+                Ok(Grpc::new(ProstCodec::default())
+                    .unary(method, request)
+                    .await)
+            }),
+            "/off_chain_transactions.Engine/GetAccount" => Box::pin(async move {
+                let method = Unary(engine, get_account);
+                Ok(Grpc::new(ProstCodec::default())
+                    .unary(method, request)
+                    .await)
+            }),
+            "/off_chain_transactions.Engine/StreamAccountUpdates" => Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default())
+                    .server_streaming(StreamAccountUpdates(engine), request)
+                    .await)
+            }),
+            _ => Box::pin(async { Ok(Status::unimplemented("").into_http()) }),
+        }
+    }
+}
+
+fn submit_transaction(
+    engine: &Engine,
+    message: TransactionMessage,
+) -> Result<SubmitTransactionResponse, Status> {
+    let txn =
+        Transaction::try_from(message).map_err(|err| Status::invalid_argument(err.to_string()))?;
+    match engine.submit(txn) {
+        Ok(Outcome::Applied) => Ok(SubmitTransactionResponse {
+            applied: true,
+            ignored_reason: String::new(),
+            queued: false,
+        }),
+        Ok(Outcome::Ignored(reason)) => Ok(SubmitTransactionResponse {
+            applied: false,
+            ignored_reason: reason.name().into(),
+            queued: false,
+        }),
+        Ok(Outcome::Queued) => Ok(SubmitTransactionResponse {
+            applied: false,
+            ignored_reason: String::new(),
+            queued: true,
+        }),
+        Err(err @ ProcessError::DuplicateTransactionId(_)) => {
+            Err(Status::already_exists(err.to_string()))
+        }
+        Err(err) => Err(Status::failed_precondition(err.to_string())),
+    }
+}
+
+fn get_account(engine: &Engine, request: GetAccountRequest) -> Result<Account, Status> {
+    let client_id = u16::try_from(request.client)
+        .map(ClientId)
+        .map_err(|_| Status::invalid_argument("Client id is out of range"))?;
+    match engine.account(client_id) {
+        Some(account) => Ok(Account::new(client_id, &account)),
+        None => Err(Status::not_found(format!(
+            "Client {} has no account",
+            client_id
+        ))),
+    }
+}
+
+// A unary method, as a function of the engine and the request
+struct Unary<Req, Res>(Arc<Engine>, fn(&Engine, Req) -> Result<Res, Status>);
+
+impl<Req, Res> Service<Request<Req>> for Unary<Req, Res> {
+    type Response = Response<Res>;
+    type Error = Status;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        ready((self.1)(&self.0, request.into_inner()).map(Response::new))
+    }
+}
+
+type AccountStream = Pin<Box<dyn Stream<Item = Result<Account, Status>> + Send>>;
+
+struct StreamAccountUpdates(Arc<Engine>);
+
+impl Service<Request<StreamAccountUpdatesRequest>> for StreamAccountUpdates {
+    type Response = Response<AccountStream>;
+    type Error = Status;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<StreamAccountUpdatesRequest>) -> Self::Future {
+        let only = request.into_inner().client;
+        let (sender, receiver) = mpsc::channel(UPDATES_BUFFER);
+        self.0.subscribe(Box::new(move |client_id, account| {
+            if only.is_some_and(|only| only != u32::from(client_id.0)) {
+                return !sender.is_closed();
+            }
+            // A client that's fallen behind is dropped, rather than holding up the engine or
+            // buffering without limit
+            sender
+                .try_send(Ok(Account::new(client_id, account)))
+                .is_ok()
+        }));
+
+        // The channel only closes once the client has been dropped for falling behind
+        let updates = ReceiverStream::new(receiver).chain(tokio_stream::once(Err(
+            Status::resource_exhausted("Fell too far behind the updates"),
+        )));
+        ready(Ok(Response::new(Box::pin(updates))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_account, submit_transaction, GetAccountRequest, StreamAccountUpdates,
+        StreamAccountUpdatesRequest,
+    };
+    use crate::{
+        io::protobuf::{TransactionMessage, TransactionType},
+        server::Engine,
+        state::State,
+    };
+    use std::sync::Arc;
+    use tokio_stream::StreamExt;
+    use tonic::{codegen::Service, Code, Request};
+
+    #[test]
+    fn test_methods() {
+        let engine = Arc::new(Engine::new(State::default()));
+        let mut updates = StreamAccountUpdates(engine.clone())
+            .call(Request::new(StreamAccountUpdatesRequest {
+                client: Some(7),
+            }))
+            .into_inner()
+            .unwrap()
+            .into_inner();
+
+        let deposit = |client, tx, amount| TransactionMessage {
+            r#type: TransactionType::Deposit as i32,
+            client,
+            tx,
+            amount: Some(amount),
+            to: None,
+            reference: None,
+            reason: None,
+            timestamp: None,
+        };
+        assert!(
+            submit_transaction(&engine, deposit(7, 1, 25000))
+                .unwrap()
+                .applied
+        );
+        assert!(
+            submit_transaction(&engine, deposit(8, 2, 10000))
+                .unwrap()
+                .applied
+        );
+        let withdrawal = TransactionMessage {
+            r#type: TransactionType::Withdrawal as i32,
+            ..deposit(7, 3, 30000)
+        };
+        let response = submit_transaction(&engine, withdrawal).unwrap();
+        assert!(!response.applied);
+        assert_eq!(response.ignored_reason, "insufficient_funds");
+        assert_eq!(
+            submit_transaction(&engine, deposit(70000, 4, 1))
+                .unwrap_err()
+                .code(),
+            Code::InvalidArgument
+        );
+        assert!(
+            submit_transaction(&engine, deposit(7, 5, 10000))
+                .unwrap()
+                .applied
+        );
+
+        let account = get_account(&engine, GetAccountRequest { client: 7 }).unwrap();
+        assert_eq!((account.available, account.total), (35000, 35000));
+        assert_eq!(
+            get_account(&engine, GetAccountRequest { client: 9 })
+                .unwrap_err()
+                .code(),
+            Code::NotFound
+        );
+
+        // Only client 7's changes
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for available in [25000, 35000] {
+            let update = runtime.block_on(updates.next()).unwrap().unwrap();
+            assert_eq!((update.client, update.available), (7, available));
+        }
+    }
+}
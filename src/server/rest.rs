@@ -0,0 +1,268 @@
+//! The REST API: transactions are submitted with `POST /transactions`, and accounts read with
+//! `GET /accounts` and `GET /accounts/{client}`. Bodies are JSON, with transactions and accounts
+//! as in the JSON Lines input and the `json` output.
+
+use super::Engine;
+use crate::{
+    output::{json, Precision},
+    state::{IgnoreReason, Outcome},
+    types::{ClientId, Transaction, TransactionFields},
+};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::Incoming, header, server::conn::http1, service::service_fn, Method, Request, Response,
+    StatusCode,
+};
+use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown};
+use std::{
+    convert::Infallible,
+    net::TcpListener,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+// A transaction is well under this; anything bigger is refused
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Serves the API on `listener` until `stop` is set, then waits for the requests in progress to
+/// finish.
+pub fn serve(
+    engine: Arc<Engine>,
+    listener: TcpListener,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let graceful = GracefulShutdown::new();
+
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "Failed to accept a connection");
+                        continue;
+                    }
+                },
+                () = super::stopped(&stop) => break,
+            };
+
+            let engine = engine.clone();
+            let connection = http1::Builder::new().serve_connection(
+                TokioIo::new(stream),
+                service_fn(move |request| handle(engine.clone(), request)),
+            );
+            let connection = graceful.watch(connection);
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    tracing::debug!(error = %err, "HTTP connection failed");
+                }
+            });
+        }
+
+        graceful.shutdown().await;
+        Ok(())
+    })
+}
+
+async fn handle(
+    engine: Arc<Engine>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let (status, body) = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+        Ok(body) => route(&engine, &parts.method, parts.uri.path(), &body.to_bytes()),
+        Err(_) => error(StatusCode::PAYLOAD_TOO_LARGE, "body_too_large", None),
+    };
+
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    Ok(response)
+}
+
+// The status and JSON body of the response to a request
+fn route(engine: &Engine, method: &Method, path: &str, body: &[u8]) -> (StatusCode, String) {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match (method, segments.as_slice()) {
+        (&Method::POST, ["transactions"]) => submit(engine, body),
+        (&Method::GET, ["accounts"]) => {
+            let accounts = engine.accounts();
+            let accounts = accounts
+                .iter()
+                .map(|(client_id, account)| (*client_id, account))
+                .collect::<Vec<_>>();
+            let mut body = Vec::new();
+            json::write(&accounts, &mut body, false, Precision::Minimal)
+                .expect("Writing to a Vec can't fail");
+            (
+                StatusCode::OK,
+                String::from_utf8(body).expect("JSON is UTF-8"),
+            )
+        }
+        (&Method::GET, ["accounts", client]) => match client.parse::<u16>() {
+            Ok(client_id) => account(engine, ClientId(client_id)),
+            Err(_) => error(
+                StatusCode::BAD_REQUEST,
+                "invalid_client",
+                Some("Client ids are whole numbers from 0 to 65535"),
+            ),
+        },
+        (_, ["transactions"] | ["accounts"] | ["accounts", _]) => {
+            error(StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", None)
+        }
+        _ => error(StatusCode::NOT_FOUND, "not_found", None),
+    }
+}
+
+fn submit(engine: &Engine, body: &[u8]) -> (StatusCode, String) {
+    let txn = match serde_json::from_slice::<TransactionFields>(body)
+        .map_err(|err| err.to_string())
+        .and_then(|fields| Transaction::try_from(fields).map_err(|err| err.to_string()))
+    {
+        Ok(txn) => txn,
+        Err(err) => return error(StatusCode::BAD_REQUEST, "malformed_transaction", Some(&err)),
+    };
+
+    let client_id = txn.client_id;
+    match engine.submit(txn) {
+        Ok(Outcome::Applied) => account(engine, client_id),
+        // Accepted, but not applied until the account is unlocked
+        Ok(Outcome::Queued) => match account(engine, client_id) {
+            (StatusCode::OK, body) => (StatusCode::ACCEPTED, body),
+            response => response,
+        },
+        Ok(Outcome::Ignored(reason)) => error(status_for(reason), reason.name(), None),
+        Err(err) => {
+            let reason = IgnoreReason::from(&err);
+            error(status_for(reason), reason.name(), Some(&err.to_string()))
+        }
+    }
+}
+
+fn account(engine: &Engine, client_id: ClientId) -> (StatusCode, String) {
+    let Some(account) = engine.account(client_id) else {
+        return error(StatusCode::NOT_FOUND, "unknown_client", None);
+    };
+    let mut body = Vec::new();
+    json::write(
+        &[(client_id, &account)],
+        &mut body,
+        true,
+        Precision::Minimal,
+    )
+    .expect("Writing to a Vec can't fail");
+    (
+        StatusCode::OK,
+        String::from_utf8(body).expect("JSON is UTF-8"),
+    )
+}
+
+// The status for a transaction the engine didn't apply
+fn status_for(reason: IgnoreReason) -> StatusCode {
+    match reason {
+        IgnoreReason::InsufficientFunds
+        | IgnoreReason::DisputeTargetInvalid
+        | IgnoreReason::DisputedTransactionClientMissing
+        | IgnoreReason::NotDisputable
+        | IgnoreReason::ClientMismatch
+        | IgnoreReason::NotRefundable
+        | IgnoreReason::NotRepresentable
+        | IgnoreReason::InvalidDisputeAmount
+        | IgnoreReason::AmountTooLarge => StatusCode::UNPROCESSABLE_ENTITY,
+        IgnoreReason::AccountLocked | IgnoreReason::AccountClosed => StatusCode::FORBIDDEN,
+        IgnoreReason::UnknownTransaction => StatusCode::NOT_FOUND,
+        IgnoreReason::DuplicateTransactionId
+        | IgnoreReason::NonMonotonicTransactionId
+        | IgnoreReason::AlreadyDisputed
+        | IgnoreReason::NotDisputed
+        | IgnoreReason::ChargedBack
+        | IgnoreReason::NotChargedBack
+        | IgnoreReason::AlreadyReversed
+        | IgnoreReason::NotAuthorized
+        | IgnoreReason::TooManyDisputes
+        | IgnoreReason::DisputeWindowExpired => StatusCode::CONFLICT,
+    }
+}
+
+fn error(status: StatusCode, error: &str, message: Option<&str>) -> (StatusCode, String) {
+    let body = match message {
+        Some(message) => serde_json::json!({ "error": error, "message": message }),
+        None => serde_json::json!({ "error": error }),
+    };
+    (status, format!("{}\n", body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::route;
+    use crate::{server::Engine, state::State};
+    use hyper::{Method, StatusCode};
+
+    #[test]
+    fn test_route() {
+        let engine = Engine::new(State::default());
+        let post = |body: &str| route(&engine, &Method::POST, "/transactions", body.as_bytes());
+
+        assert_eq!(
+            post(r#"{"type":"deposit","client":1,"tx":1,"amount":2.5}"#),
+            (
+                StatusCode::OK,
+                "{\"client\":1,\"available\":2.5,\"held\":0,\"total\":2.5,\"locked\":false}\n"
+                    .into()
+            )
+        );
+        for (body, status, error) in [
+            (
+                r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "insufficient_funds",
+            ),
+            (
+                r#"{"type":"deposit","client":1,"tx":1,"amount":1}"#,
+                StatusCode::CONFLICT,
+                "duplicate_transaction_id",
+            ),
+            (
+                r#"{"type":"dispute","client":1,"tx":9}"#,
+                StatusCode::NOT_FOUND,
+                "unknown_transaction",
+            ),
+            (
+                r#"{"type":"deposit","client":1}"#,
+                StatusCode::BAD_REQUEST,
+                "malformed_transaction",
+            ),
+        ] {
+            let (actual_status, actual_body) = post(body);
+            assert_eq!(actual_status, status, "{}", body);
+            assert!(actual_body.contains(error), "{}", actual_body);
+        }
+
+        // Locked by a chargeback
+        post(r#"{"type":"dispute","client":1,"tx":1}"#);
+        post(r#"{"type":"chargeback","client":1,"tx":1}"#);
+        assert_eq!(
+            post(r#"{"type":"withdrawal","client":1,"tx":3,"amount":1}"#).0,
+            StatusCode::FORBIDDEN
+        );
+
+        let get = |path: &str| route(&engine, &Method::GET, path, b"");
+        assert_eq!(
+            get("/accounts/1").1,
+            "{\"client\":1,\"available\":0,\"held\":0,\"total\":0,\"locked\":true}\n"
+        );
+        assert_eq!(get("/accounts/2").0, StatusCode::NOT_FOUND);
+        assert_eq!(get("/accounts/x").0, StatusCode::BAD_REQUEST);
+        assert!(get("/accounts").1.starts_with("[\n  {\"client\":1,"));
+        assert_eq!(get("/transactions").0, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(get("/nowhere").0, StatusCode::NOT_FOUND);
+    }
+}
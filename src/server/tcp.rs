@@ -0,0 +1,176 @@
+//! A plain TCP line protocol, for systems that can't speak HTTP. Each line a client sends is one
+//! transaction, either as a CSV record without a header (`deposit,1,1,2.5`) or as a JSON object
+//! as in the JSON Lines input, and each is answered with a line of its own, in order: `OK` if it
+//! was applied, `QUEUED` if it's held back until its client's account is unlocked, or
+//! `ERR <reason>` if not, e.g. `ERR insufficient_funds`. A line that couldn't be read gets
+//! `ERR malformed_transaction` followed by what was wrong with it.
+
+use super::Engine;
+use crate::{
+    state::{IgnoreReason, Outcome},
+    types::{Transaction, TransactionFields},
+};
+use std::{
+    net::TcpListener,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::TcpStream,
+    sync::watch,
+    task::JoinSet,
+};
+
+// A transaction is well under this; a connection that sends a longer line is closed
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Serves connections on `listener` until `stop` is set, then closes them once each has answered
+/// the line it's on.
+pub fn serve(
+    engine: Arc<Engine>,
+    listener: TcpListener,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let (shutdown, shutting_down) = watch::channel(());
+        let mut connections = JoinSet::new();
+
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "Failed to accept a connection");
+                        continue;
+                    }
+                },
+                () = super::stopped(&stop) => break,
+            };
+            let (engine, shutting_down) = (engine.clone(), shutting_down.clone());
+            connections.spawn(async move {
+                if let Err(err) = connection(engine, stream, shutting_down).await {
+                    tracing::debug!(error = %err, "TCP connection failed");
+                }
+            });
+            while connections.try_join_next().is_some() {}
+        }
+
+        shutdown.send_replace(());
+        while connections.join_next().await.is_some() {}
+        Ok(())
+    })
+}
+
+async fn connection(
+    engine: Arc<Engine>,
+    stream: TcpStream,
+    mut shutting_down: watch::Receiver<()>,
+) -> std::io::Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let mut limited = (&mut reader).take(MAX_LINE_BYTES as u64 + 1);
+        let read = tokio::select! {
+            read = limited.read_until(b'\n', &mut line) => read?,
+            _ = shutting_down.changed() => break,
+        };
+        if read == 0 {
+            break;
+        }
+        if line.len() > MAX_LINE_BYTES {
+            writer.write_all(b"ERR line_too_long\n").await?;
+            break;
+        }
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+
+        writer.write_all(reply(&engine, &line).as_bytes()).await?;
+        // Replies to lines that came in together go out together
+        if reader.buffer().is_empty() {
+            writer.flush().await?;
+        }
+    }
+
+    writer.flush().await?;
+    writer.shutdown().await
+}
+
+// Applies the transaction on `line`, returning the reply
+fn reply(engine: &Engine, line: &[u8]) -> String {
+    let txn = match parse(line.trim_ascii()) {
+        Ok(txn) => txn,
+        // On one line, whatever the error says
+        Err(err) => return format!("ERR malformed_transaction {}\n", err.replace('\n', " ")),
+    };
+    match engine.submit(txn) {
+        Ok(Outcome::Applied) => "OK\n".into(),
+        Ok(Outcome::Queued) => "QUEUED\n".into(),
+        Ok(Outcome::Ignored(reason)) => format!("ERR {}\n", reason.name()),
+        Err(err) => format!("ERR {}\n", IgnoreReason::from(&err).name()),
+    }
+}
+
+fn parse(line: &[u8]) -> Result<Transaction, String> {
+    let fields = if line.starts_with(b"{") {
+        serde_json::from_slice::<TransactionFields>(line).map_err(|err| err.to_string())?
+    } else {
+        let mut record = csv::ByteRecord::new();
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(line)
+            .read_byte_record(&mut record)
+            .map_err(|err| err.to_string())?;
+        // A dispute, resolve or chargeback can leave out the trailing comma
+        if record.len() == 3 {
+            record.push_field(b"");
+        }
+        let mut fields = record
+            .deserialize::<TransactionFields>(None)
+            .map_err(|err| err.to_string())?;
+        // As for a CSV file, the amount is taken from its text rather than as a float
+        fields.amount = std::str::from_utf8(&record[3])
+            .ok()
+            .filter(|amount| !amount.is_empty())
+            .map(String::from);
+        fields
+    };
+    Transaction::try_from(fields).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reply;
+    use crate::{server::Engine, state::State, types::ClientId};
+
+    #[test]
+    fn test_reply() {
+        let engine = Engine::new(State::default());
+        for (line, expected) in [
+            ("deposit,1,1,2.5\n", "OK\n"),
+            (r#"{"type":"deposit","client":1,"tx":2,"amount":1}"#, "OK\n"),
+            (" withdrawal, 1, 3, 5 \r\n", "ERR insufficient_funds\n"),
+            ("deposit,1,1,1\n", "ERR duplicate_transaction_id\n"),
+            ("dispute,1,2\n", "OK\n"),
+            ("resolve,1,2,\n", "OK\n"),
+            ("dispute,1,9\n", "ERR unknown_transaction\n"),
+        ] {
+            assert_eq!(reply(&engine, line.as_bytes()), expected, "{}", line);
+        }
+        for line in ["deposit,1,4\n", "type,client,tx,amount\n", "{\"type\":\n"] {
+            assert!(reply(&engine, line.as_bytes()).starts_with("ERR malformed_transaction "));
+        }
+        assert_eq!(engine.account(ClientId(1)).unwrap().available(), 3.5.into());
+    }
+}
@@ -0,0 +1,209 @@
+//! WebSocket ingestion, for partners that push transactions continuously rather than submitting
+//! them one request at a time. Each message on a connection is one transaction, a JSON object as
+//! in the JSON Lines input, and each is answered with an acknowledgement, in the order they came
+//! in:
+//!
+//! ```text
+//! {"seq":1,"tx":1,"status":"applied"}
+//! {"seq":2,"tx":2,"status":"ignored","reason":"insufficient_funds"}
+//! {"seq":3,"tx":1,"status":"rejected","reason":"duplicate_transaction_id","message":"..."}
+//! {"seq":4,"status":"malformed","message":"..."}
+//! ```
+//!
+//! `seq` counts the messages on the connection from 1, so that a partner can match each
+//! acknowledgement to what it sent, even one that couldn't be read. A transaction is `rejected`
+//! only in strict mode, and `queued` only if it's held back until its client's account is unlocked
+//! (see `LockedAccounts`).
+
+use super::Engine;
+use crate::{
+    state::{IgnoreReason, Outcome},
+    types::{Transaction, TransactionFields},
+};
+use futures::{SinkExt, StreamExt};
+use std::{
+    net::TcpListener,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::{net::TcpStream, sync::watch, task::JoinSet};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+    Message,
+};
+
+// A transaction is well under this; a connection that sends anything bigger is closed
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Serves WebSocket connections on `listener` until `stop` is set, then closes them once each
+/// has acknowledged the message it's on.
+pub fn serve(
+    engine: Arc<Engine>,
+    listener: TcpListener,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let (shutdown, shutting_down) = watch::channel(());
+        let mut connections = JoinSet::new();
+
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        tracing::warn!(error = %err, "Failed to accept a connection");
+                        continue;
+                    }
+                },
+                () = super::stopped(&stop) => break,
+            };
+            connections.spawn(connection(engine.clone(), stream, shutting_down.clone()));
+            while connections.try_join_next().is_some() {}
+        }
+
+        shutdown.send_replace(());
+        while connections.join_next().await.is_some() {}
+        Ok(())
+    })
+}
+
+async fn connection(
+    engine: Arc<Engine>,
+    stream: TcpStream,
+    mut shutting_down: watch::Receiver<()>,
+) {
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(MAX_MESSAGE_BYTES))
+        .max_frame_size(Some(MAX_MESSAGE_BYTES));
+    let mut socket = match tokio_tungstenite::accept_async_with_config(stream, Some(config)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            tracing::debug!(error = %err, "WebSocket handshake failed");
+            return;
+        }
+    };
+
+    let mut seq = 0;
+    loop {
+        let message = tokio::select! {
+            message = socket.next() => message,
+            _ = shutting_down.changed() => {
+                let frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Shutting down".into(),
+                };
+                let _ = socket.close(Some(frame)).await;
+                return;
+            }
+        };
+        let ack = match message {
+            Some(Ok(Message::Text(text))) => {
+                seq += 1;
+                acknowledge(&engine, seq, text.as_bytes())
+            }
+            Some(Ok(Message::Binary(data))) => {
+                seq += 1;
+                acknowledge(&engine, seq, &data)
+            }
+            // Pings are answered, and a close returned, by the next read
+            Some(Ok(_)) => continue,
+            Some(Err(err)) => {
+                tracing::debug!(error = %err, "WebSocket connection failed");
+                return;
+            }
+            None => return,
+        };
+        if let Err(err) = socket.send(Message::text(ack)).await {
+            tracing::debug!(error = %err, "WebSocket connection failed");
+            return;
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Ack {
+    seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx: Option<u32>,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+// Applies the transaction in the connection's `seq`th message, returning the acknowledgement
+fn acknowledge(engine: &Engine, seq: u64, message: &[u8]) -> String {
+    let mut ack = Ack {
+        seq,
+        tx: None,
+        status: "malformed",
+        reason: None,
+        message: None,
+    };
+    match serde_json::from_slice::<TransactionFields>(message)
+        .map_err(|err| err.to_string())
+        .and_then(|fields| Transaction::try_from(fields).map_err(|err| err.to_string()))
+    {
+        Ok(txn) => {
+            ack.tx = Some(txn.transaction_id.0);
+            match engine.submit(txn) {
+                Ok(Outcome::Applied) => ack.status = "applied",
+                Ok(Outcome::Queued) => ack.status = "queued",
+                Ok(Outcome::Ignored(reason)) => {
+                    ack.status = "ignored";
+                    ack.reason = Some(reason.name());
+                }
+                Err(err) => {
+                    ack.status = "rejected";
+                    ack.reason = Some(IgnoreReason::from(&err).name());
+                    ack.message = Some(err.to_string());
+                }
+            }
+        }
+        Err(err) => ack.message = Some(err),
+    }
+    serde_json::to_string(&ack).expect("An Ack always serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::acknowledge;
+    use crate::{
+        server::Engine,
+        state::{Options, State},
+        types::ClientId,
+    };
+
+    #[test]
+    fn test_acknowledge() {
+        let engine = Engine::new(State::with_options(Options {
+            strict: true,
+            ..Options::default()
+        }));
+        let mut seq = 0;
+        let mut ack = |message: &str| {
+            seq += 1;
+            acknowledge(&engine, seq, message.as_bytes())
+        };
+
+        assert_eq!(
+            ack(r#"{"type":"deposit","client":1,"tx":1,"amount":2.5}"#),
+            r#"{"seq":1,"tx":1,"status":"applied"}"#
+        );
+        assert_eq!(
+            ack(r#"{"type":"withdrawal","client":1,"tx":2,"amount":3}"#),
+            r#"{"seq":2,"tx":2,"status":"ignored","reason":"insufficient_funds"}"#
+        );
+        assert_eq!(
+            ack(r#"{"type":"deposit","client":1,"tx":1,"amount":1}"#),
+            r#"{"seq":3,"tx":1,"status":"rejected","reason":"duplicate_transaction_id","message":"Duplicate transaction id: 1"}"#
+        );
+        assert!(ack("deposit,1,4,1").starts_with(r#"{"seq":4,"status":"malformed","message":"#));
+        assert_eq!(engine.account(ClientId(1)).unwrap().available(), 2.5.into());
+    }
+}
@@ -1,538 +1,4340 @@
-use super::types::{
-    ClientId, FixedFloat, Transaction, TransactionId, TransactionInner, TransactionState,
-};
-use std::{
-    collections::{hash_map::Entry, HashMap},
-    error::Error,
+use self::expiry::Expiries;
+#[cfg(feature = "io")]
+use super::{io::TransactionSource, output::AccountSink};
+use super::{
+    latency::Latencies,
+    types::{
+        Amount, ClientId, FixedFloat, Transaction, TransactionId, TransactionInner,
+        TransactionState,
+    },
 };
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use core::error::Error;
+#[cfg(not(feature = "std"))]
+use hashbrown::HashMap;
+#[cfg(feature = "std")]
+use std::{collections::HashMap, time::Instant};
+
+mod event;
+mod expiry;
+mod fingerprint;
+mod invariants;
+mod observer;
+mod policy;
+mod serialize;
+#[cfg(feature = "std")]
+mod snapshot;
+mod storage;
+mod store;
 
-#[derive(Default)]
-pub struct State {
-    transactions: HashMap<TransactionId, Transaction>,
-    accounts: HashMap<ClientId, AccountState>,
+pub use event::Event;
+#[cfg(feature = "io")]
+pub use event::{read_events, EventWriter};
+pub use invariants::Violation;
+pub use observer::Observer;
+pub use policy::{DefaultDisputePolicy, DisputePolicy};
+pub use storage::{MemoryStorage, Storage};
+#[cfg(feature = "sled")]
+pub use store::SledStore;
+pub use store::TransactionStore;
+
+/// How to react when a deposit, withdrawal, transfer, adjustment, authorization or close arrives
+/// with a transaction id that isn't greater than every one of those seen before it. Disputes,
+/// resolutions and chargebacks refer back to old ids, so they're never checked. Most of the
+/// processing logic assumes that transactions happen in chronological order, and an id going
+/// backwards is the only cheap symptom we get of an input file that breaks that assumption.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IdOrdering {
+    /// Ids aren't checked.
+    #[default]
+    Unchecked,
+    /// One out of order is logged as a warning (through `tracing`), and processed as usual.
+    Warn,
+    /// One out of order is invalid (see `ProcessError::NonMonotonicTransactionId`).
+    Strict,
 }
 
-impl State {
-    pub fn process(&mut self, txn: Transaction) -> Result<(), ProcessError> {
-        match txn.inner {
-            TransactionInner::Deposit(amount) => {
-                let account = self.get_or_create_account(txn.client_id);
+impl core::str::FromStr for IdOrdering {
+    type Err = String;
 
-                // Assume we can deposit into a frozen account
-                account.available += amount;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unchecked" => Ok(Self::Unchecked),
+            "warn" => Ok(Self::Warn),
+            "strict" => Ok(Self::Strict),
+            other => Err(format!("Unrecognised id ordering mode \"{}\"", other)),
+        }
+    }
+}
 
-                self.cache_transaction(txn)?;
-            }
-            TransactionInner::Withdrawal(amount) => {
-                let account = self.get_or_create_account(txn.client_id);
+/// What happens to a deposit, withdrawal, transfer, adjustment, authorization or close whose id is
+/// already taken by an earlier one, whether that was applied, queued or ignored. Partner feeds
+/// resend transactions they aren't sure arrived, so a duplicate isn't necessarily a sign of a
+/// broken file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateIds {
+    /// It's invalid (see `ProcessError::DuplicateTransactionId`).
+    #[default]
+    Error,
+    /// It's ignored as `IgnoreReason::DuplicateTransactionId`, even in strict mode, and isn't
+    /// counted as invalid.
+    Skip,
+    /// If it has the same client, type and amount as the transaction it duplicates, it's taken
+    /// as a resend of it, and has the same outcome (e.g. `Outcome::Ignored` with the same reason
+    /// for a resent withdrawal that was declined, or `Outcome::Queued` if the original is still
+    /// waiting) without doing anything again. Otherwise, it's invalid, as for `Error`.
+    Idempotent,
+}
 
-                // Assume we can't withdraw from a frozen account
+impl core::str::FromStr for DuplicateIds {
+    type Err = String;
 
-                if !account.locked {
-                    if account.available >= amount {
-                        account.available -= amount;
-                    }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "skip" => Ok(Self::Skip),
+            "idempotent" => Ok(Self::Idempotent),
+            other => Err(format!("Unrecognised duplicate id mode \"{}\"", other)),
+        }
+    }
+}
 
-                    // Only cache if the account isn't locked. If this withdrawal were to be
-                    // disputed (is that even possible?), we wouldn't want to negate it, so
-                    // just don't cache it, and the dispute code will think it's an "error on
-                    // the partner side" - that's probably good enough.
-                    self.cache_transaction(txn)?;
-                }
-            }
-            TransactionInner::Dispute => {
-                // Grab the disputed transaction. If it doesn't exist, just ignore and return
-                let disputed_txn = match self.transactions.get_mut(&txn.transaction_id) {
-                    Some(disputed_txn) => disputed_txn,
-                    None => {
-                        // Error on partner side
-                        return Ok(());
-                    }
-                };
+/// What happens to deposits and withdrawals on an account once it's closed. A transfer counts as
+/// a withdrawal for its sender and a deposit for its recipient, and an authorization as a
+/// withdrawal. Adjustments, and disputes, refunds, representments, captures and voids of earlier
+/// transactions, always go through.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClosedAccounts {
+    /// Refuse both.
+    #[default]
+    Reject,
+    /// Refuse withdrawals, but accept deposits, which add to what's paid out.
+    AllowDeposits,
+    /// Accept both, so closing only marks the account.
+    Allow,
+}
 
-                if !matches!(disputed_txn.state, TransactionState::Alive) {
-                    // Cannot dispute if already disputed or charged back
-                    return Ok(());
-                }
+impl core::str::FromStr for ClosedAccounts {
+    type Err = String;
 
-                // Fetch the disputed amount. The problem description implies this is for
-                // deposits only, but presumably each deposit may have a corresponding
-                // withdrawal. To handle that we just neg the amount
-                let amount = match disputed_txn.inner {
-                    TransactionInner::Deposit(amount) => amount,
-                    TransactionInner::Withdrawal(amount) => -amount,
-                    _ => return Err(ProcessError::DisputeTargetInvalid(txn.transaction_id)),
-                };
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "allow-deposits" => Ok(Self::AllowDeposits),
+            "allow" => Ok(Self::Allow),
+            other => Err(format!("Unrecognised closed account mode \"{}\"", other)),
+        }
+    }
+}
 
-                // Does the client_id on the disputed_txn need to match the one on the txn,
-                // or is txn.client_id the client doing the disputing? Not clear. Either way,
-                // we'll want to negate the amount on the disputed_txn's client.
+/// What a dispute of a withdrawal does. Some partners expect one to put the withdrawn funds back
+/// while they're held, and others to hold the same amount as for a deposit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WithdrawalDisputes {
+    /// Withdrawals can't be disputed (`IgnoreReason::NotDisputable`).
+    Reject,
+    /// Held funds go down by the amount, and available funds up, so the withdrawal is undone
+    /// while it's disputed and a chargeback refunds it for good. Exactly how much is up to the
+    /// `DisputePolicy`.
+    #[default]
+    TreatAsNegative,
+    /// The amount moves from available to held, as for a deposit, and a chargeback takes it out.
+    HoldPositive,
+}
 
-                // Fetch the client. We know that the transactions happen in chronological order,
-                // so the client should exist already.
-                let account = match self.accounts.get_mut(&disputed_txn.client_id) {
-                    Some(account) => account,
-                    None => {
-                        return Err(ProcessError::DisputedTransactionClientMissing(
-                            disputed_txn.client_id,
-                        ));
-                    }
-                };
+impl core::str::FromStr for WithdrawalDisputes {
+    type Err = String;
 
-                // Everything seems fine, so do all mutations
-                disputed_txn.state = TransactionState::Disputed;
-                account.available -= amount;
-                account.held += amount;
-            }
-            TransactionInner::Resolve => {
-                // Grab the disputed transaction. If it doesn't exist, just ignore and return
-                let disputed_txn = match self.transactions.get_mut(&txn.transaction_id) {
-                    Some(disputed_txn) => disputed_txn,
-                    None => {
-                        // Error on partner side
-                        return Ok(());
-                    }
-                };
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "treat-as-negative" => Ok(Self::TreatAsNegative),
+            "hold-positive" => Ok(Self::HoldPositive),
+            other => Err(format!(
+                "Unrecognised withdrawal dispute mode \"{}\"",
+                other
+            )),
+        }
+    }
+}
 
-                if !matches!(disputed_txn.state, TransactionState::Disputed) {
-                    // Not disputed; do nothing
-                    return Ok(());
-                }
+/// What happens to one type of transaction on a locked account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockedAccountMode {
+    /// It goes through as if the account weren't locked.
+    Allow,
+    /// It's ignored as `IgnoreReason::AccountLocked`, with an event saying it was blocked.
+    Reject,
+    /// It's held back, as `Outcome::Queued`, until the account is unlocked, and then processed
+    /// as if it had just arrived. It isn't cached in the meantime, so it can't be disputed
+    /// (though its id is taken), and it's lost if the account is never unlocked.
+    Queue,
+}
 
-                // Fetch the disputed amount. The problem description implies this is for
-                // deposits only, but presumably each deposit may have a corresponding
-                // withdrawal. To handle that we just neg the amount.
-                let amount = match disputed_txn.inner {
-                    TransactionInner::Deposit(amount) => amount,
-                    TransactionInner::Withdrawal(amount) => -amount,
-                    _ => return Err(ProcessError::DisputeTargetInvalid(txn.transaction_id)),
-                };
+impl core::str::FromStr for LockedAccountMode {
+    type Err = String;
 
-                // Does the client_id on the disputed_txn need to match the one on the txn,
-                // or is txn.client_id the client doing the disputing? Not clear. Either way,
-                // we'll want to negate the amount on the disputed_txn's client.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "reject" => Ok(Self::Reject),
+            "queue" => Ok(Self::Queue),
+            other => Err(format!("Unrecognised locked account mode \"{}\"", other)),
+        }
+    }
+}
 
-                // Fetch the client. We know that the transactions happen in chronological order,
-                // so the client should exist already.
-                let account = match self.accounts.get_mut(&disputed_txn.client_id) {
-                    Some(account) => account,
-                    None => {
-                        return Err(ProcessError::DisputedTransactionClientMissing(
-                            disputed_txn.client_id,
-                        ));
-                    }
-                };
+/// What happens to deposits, withdrawals, transfers and authorizations on a locked account, each
+/// by its own `LockedAccountMode`. A transfer goes by its sender's account; one to a locked
+/// account is always received. Adjustments, and disputes, resolves, chargebacks, refunds,
+/// representments, captures, voids and closes of earlier transactions, always go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockedAccounts {
+    pub deposits: LockedAccountMode,
+    pub withdrawals: LockedAccountMode,
+    pub transfers: LockedAccountMode,
+    pub authorizations: LockedAccountMode,
+}
 
-                // Everything seems fine, so do all mutations
-                disputed_txn.state = TransactionState::Alive;
-                account.available += amount;
-                account.held -= amount;
-            }
-            TransactionInner::Chargeback => {
-                // Grab the disputed transaction. If it doesn't exist, just ignore and return
-                let disputed_txn = match self.transactions.get_mut(&txn.transaction_id) {
-                    Some(disputed_txn) => disputed_txn,
-                    None => {
-                        // Error on partner side
-                        return Ok(());
-                    }
-                };
+/// Deposits are allowed, and everything else rejected.
+impl Default for LockedAccounts {
+    fn default() -> Self {
+        Self {
+            deposits: LockedAccountMode::Allow,
+            withdrawals: LockedAccountMode::Reject,
+            transfers: LockedAccountMode::Reject,
+            authorizations: LockedAccountMode::Reject,
+        }
+    }
+}
 
-                if !matches!(disputed_txn.state, TransactionState::Disputed) {
-                    // Not disputed; do nothing
-                    return Ok(());
+/// Parsed from e.g. `deposit=queue,withdrawal=allow`, by `TransactionInner::type_name`. Types
+/// that aren't listed keep their defaults.
+impl core::str::FromStr for LockedAccounts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut locked_accounts = Self::default();
+        for setting in s
+            .split(',')
+            .map(str::trim)
+            .filter(|setting| !setting.is_empty())
+        {
+            let (type_name, mode) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Expected \"type=mode\", not \"{}\"", setting))?;
+            let mode = mode.trim().parse()?;
+            match type_name.trim() {
+                "deposit" => locked_accounts.deposits = mode,
+                "withdrawal" => locked_accounts.withdrawals = mode,
+                "transfer" => locked_accounts.transfers = mode,
+                "authorize" => locked_accounts.authorizations = mode,
+                other => {
+                    return Err(format!(
+                        "Unrecognised type \"{}\" (only deposit, withdrawal, transfer and \
+                        authorize have a locked account mode)",
+                        other
+                    ))
                 }
+            }
+        }
+        Ok(locked_accounts)
+    }
+}
 
-                // Fetch the disputed amount. The problem description implies this is for
-                // deposits only, but presumably each deposit may have a corresponding
-                // withdrawal. To handle that we just neg the amount
-                let amount = match disputed_txn.inner {
-                    TransactionInner::Deposit(amount) => amount,
-                    TransactionInner::Withdrawal(amount) => -amount,
-                    _ => return Err(ProcessError::DisputeTargetInvalid(txn.transaction_id)),
-                };
+#[derive(Debug, Default, Clone)]
+pub struct Options<A: Amount = FixedFloat> {
+    pub id_ordering: IdOrdering,
+    /// Whether each client's deposits and withdrawals have to have strictly increasing
+    /// transaction ids, whatever `id_ordering` says about everything else. If one doesn't, it's
+    /// invalid (see `ProcessError::NonMonotonicClientTransactionId`). Off by default, since only
+    /// some upstreams guarantee it; for those, an id going backwards usually means a corrupted or
+    /// mis-merged file.
+    pub client_monotonic_ids: bool,
+    pub duplicate_ids: DuplicateIds,
+    /// Whether `State::process` fails with a `ProcessError`, rather than ignoring the transaction
+    /// (with the equivalent `IgnoreReason`) and counting it in `Stats::invalid_transactions`.
+    /// Either way, a transaction causing one has no effect.
+    pub strict: bool,
+    /// Time each call to `State::process`, for `State::latencies`. Off by default, since reading
+    /// the clock twice costs about as much as processing a deposit. Does nothing without the std
+    /// feature, which the clock needs.
+    pub track_latency: bool,
+    pub closed_accounts: ClosedAccounts,
+    /// Void a pending authorization by itself once this many more transactions have been
+    /// processed without it being captured or voided. By default, it's held until one is.
+    pub authorization_expiry: Option<u64>,
+    /// Likewise, resolve a dispute by itself once this many more transactions have been processed
+    /// without it being resolved or charged back.
+    pub dispute_expiry: Option<u64>,
+    /// Void a pending authorization by itself once a transaction comes with a timestamp more
+    /// than this many seconds after the authorization's, if it hasn't been captured or voided.
+    /// One authorized by a transaction without a timestamp falls back to `authorization_expiry`,
+    /// counting transactions processed, if that's set.
+    pub authorization_expiry_seconds: Option<u64>,
+    /// Likewise, resolve a dispute by itself once a transaction comes with a timestamp more than
+    /// this many seconds after the dispute's, falling back to `dispute_expiry` for a dispute
+    /// without a timestamp.
+    pub dispute_expiry_seconds: Option<u64>,
+    /// Whether a representment also unlocks the account that its chargeback locked. By default,
+    /// it only restores the funds, and the lock is left for an unlock to lift.
+    pub representment_unlocks: bool,
+    /// How many times a transaction may be disputed, counting those that were resolved. By
+    /// default, there's no limit, so it can go back and forth between disputed and resolved
+    /// forever.
+    pub max_disputes: Option<u32>,
+    /// How many seconds after a transaction it may be disputed. A dispute with a timestamp more
+    /// than this after the disputed transaction's is ignored as
+    /// `IgnoreReason::DisputeWindowExpired`; one exactly this long after is still in time. If
+    /// either has no timestamp, the window can't be checked, so the dispute goes ahead. By
+    /// default, there's no window.
+    pub dispute_window_seconds: Option<u64>,
+    /// Resolves and chargebacks go by this as well, so changing it while withdrawals are
+    /// disputed leaves their held funds inconsistent.
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// Whether a row referring back to an earlier transaction (a dispute, resolve, chargeback,
+    /// unlock, representment, refund, capture or void) has to name that transaction's client, or
+    /// for a transfer, either of its clients. If it doesn't, it's ignored as
+    /// `IgnoreReason::ClientMismatch`. By default, any client id may refer to any transaction,
+    /// subject to the `DisputePolicy`, and the account that changes is the transaction's client's
+    /// regardless.
+    pub require_client_match: bool,
+    pub locked_accounts: LockedAccounts,
+    /// Treat deposits, withdrawals, transfers and authorizations of more than this as invalid
+    /// (see `ProcessError::AmountTooLarge`), as a sanity check against typos and amounts in the
+    /// wrong units. By default, there's no limit.
+    pub max_amount: Option<A>,
+}
 
-                // Does the client_id on the disputed_txn need to match the one on the txn,
-                // or is txn.client_id the client doing the disputing? Not clear. Either way,
-                // we'll want to negate the amount on the disputed_txn's client.
+/// What `State::process` did with a transaction that didn't cause an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Applied,
+    Ignored(IgnoreReason),
+    /// Held back until the account is unlocked; see `LockedAccountMode::Queue`.
+    Queued,
+}
 
-                // Fetch the client. We know that the transactions happen in chronological order,
-                // so the client should exist already.
-                let account = self.accounts.get_mut(&disputed_txn.client_id).ok_or(
-                    ProcessError::DisputedTransactionClientMissing(disputed_txn.client_id),
-                )?;
+impl Outcome {
+    /// `applied`, `queued`, or the `IgnoreReason::name` of why it was ignored.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Applied => "applied",
+            Self::Ignored(reason) => reason.name(),
+            Self::Queued => "queued",
+        }
+    }
+}
 
-                // Everything seems fine, so do all mutations
-                disputed_txn.state = TransactionState::ChargedBack;
-                account.held -= amount;
-                account.locked = true;
-            }
+impl core::str::FromStr for Outcome {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "applied" => Ok(Self::Applied),
+            "queued" => Ok(Self::Queued),
+            other => IgnoreReason::ALL
+                .into_iter()
+                .find(|reason| reason.name() == other)
+                .map(Self::Ignored)
+                .ok_or_else(|| format!("Unrecognised outcome \"{}\"", other)),
         }
+    }
+}
 
-        Ok(())
+/// Why a transaction was skipped without changing any balances. These are all problems that we
+/// assume to be errors on the partner side rather than fatal ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    InsufficientFunds,
+    AccountLocked,
+    /// A deposit or withdrawal refused by `Options::closed_accounts`, or a close of an account
+    /// that already is.
+    AccountClosed,
+    UnknownTransaction,
+    AlreadyDisputed,
+    NotDisputed,
+    ChargedBack,
+    /// An unlock or representment of a transaction that isn't charged back (or has already been
+    /// unlocked or represented).
+    NotChargedBack,
+    /// A dispute or refund of a transaction that's already been refunded.
+    AlreadyReversed,
+    /// A refund of something other than a deposit or withdrawal.
+    NotRefundable,
+    /// A representment of a transfer, whose chargeback already sent the funds back to the sender.
+    NotRepresentable,
+    /// A partial dispute of more than the transaction's amount, or of nothing.
+    InvalidDisputeAmount,
+    /// A dispute of a transaction that's already been disputed `Options::max_disputes` times.
+    TooManyDisputes,
+    /// A dispute from after `Options::dispute_window_seconds` had closed.
+    DisputeWindowExpired,
+    /// A capture or void of something other than a pending authorization.
+    NotAuthorized,
+    /// The `DisputePolicy` doesn't allow disputing the transaction.
+    NotDisputable,
+    /// `Options::require_client_match` or the `DisputePolicy` doesn't allow the row's client to
+    /// refer to the transaction.
+    ClientMismatch,
+    /// Returned for a duplicate even in strict mode if `Options::duplicate_ids` skips it.
+    DuplicateTransactionId,
+    // The rest are only returned when not in strict mode; see `ProcessError`
+    NonMonotonicTransactionId,
+    DisputeTargetInvalid,
+    DisputedTransactionClientMissing,
+    AmountTooLarge,
+}
+
+impl IgnoreReason {
+    const ALL: [Self; 22] = [
+        Self::InsufficientFunds,
+        Self::AccountLocked,
+        Self::AccountClosed,
+        Self::UnknownTransaction,
+        Self::AlreadyDisputed,
+        Self::NotDisputed,
+        Self::ChargedBack,
+        Self::NotChargedBack,
+        Self::AlreadyReversed,
+        Self::NotRefundable,
+        Self::NotRepresentable,
+        Self::InvalidDisputeAmount,
+        Self::TooManyDisputes,
+        Self::DisputeWindowExpired,
+        Self::NotAuthorized,
+        Self::NotDisputable,
+        Self::ClientMismatch,
+        Self::DuplicateTransactionId,
+        Self::NonMonotonicTransactionId,
+        Self::DisputeTargetInvalid,
+        Self::DisputedTransactionClientMissing,
+        Self::AmountTooLarge,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::AccountLocked => "account_locked",
+            Self::AccountClosed => "account_closed",
+            Self::UnknownTransaction => "unknown_transaction",
+            Self::AlreadyDisputed => "already_disputed",
+            Self::NotDisputed => "not_disputed",
+            Self::ChargedBack => "charged_back",
+            Self::NotChargedBack => "not_charged_back",
+            Self::AlreadyReversed => "already_reversed",
+            Self::NotRefundable => "not_refundable",
+            Self::NotRepresentable => "not_representable",
+            Self::InvalidDisputeAmount => "invalid_dispute_amount",
+            Self::TooManyDisputes => "too_many_disputes",
+            Self::DisputeWindowExpired => "dispute_window_expired",
+            Self::NotAuthorized => "not_authorized",
+            Self::NotDisputable => "not_disputable",
+            Self::ClientMismatch => "client_mismatch",
+            Self::DuplicateTransactionId => "duplicate_transaction_id",
+            Self::NonMonotonicTransactionId => "non_monotonic_transaction_id",
+            Self::DisputeTargetInvalid => "dispute_target_invalid",
+            Self::DisputedTransactionClientMissing => "disputed_transaction_client_missing",
+            Self::AmountTooLarge => "amount_too_large",
+        }
     }
+}
 
-    fn cache_transaction(&mut self, txn: Transaction) -> Result<(), ProcessError> {
-        match self.transactions.entry(txn.transaction_id) {
-            Entry::Occupied(_) => Err(ProcessError::DuplicateTransactionId(txn.transaction_id)),
-            Entry::Vacant(entry) => {
-                entry.insert(txn);
-                Ok(())
+impl From<&ProcessError> for IgnoreReason {
+    fn from(err: &ProcessError) -> Self {
+        match err {
+            ProcessError::DisputedTransactionClientMissing(_) => {
+                Self::DisputedTransactionClientMissing
             }
+            ProcessError::DisputeTargetInvalid(_) => Self::DisputeTargetInvalid,
+            ProcessError::DuplicateTransactionId(_) => Self::DuplicateTransactionId,
+            ProcessError::NonMonotonicTransactionId { .. }
+            | ProcessError::NonMonotonicClientTransactionId { .. } => {
+                Self::NonMonotonicTransactionId
+            }
+            ProcessError::AmountTooLarge(_) => Self::AmountTooLarge,
         }
     }
+}
 
-    fn get_or_create_account(&mut self, client_id: ClientId) -> &mut AccountState {
-        self.accounts
-            .entry(client_id)
-            .or_insert_with(AccountState::default)
+/// The accounts, and the transactions cached so they can be disputed, with amounts in `A`; see
+/// `Amount`.
+pub struct State<A: Amount = FixedFloat> {
+    options: Options<A>,
+    storage: Box<dyn Storage<A>>,
+    dispute_policy: Box<dyn DisputePolicy<A>>,
+    // The largest transaction id seen so far that wasn't referring back to an earlier one
+    last_transaction_id: Option<TransactionId>,
+    // The largest deposit or withdrawal id seen so far for each client
+    client_transaction_ids: BTreeMap<ClientId, TransactionId>,
+    // Transactions ignored because of a `ProcessError`, when not in strict mode
+    invalid_transactions: usize,
+    // How many transactions `process` has been given, which is the clock that holds expire by
+    processed: u64,
+    expiries: Expiries,
+    // The latest timestamp `process` has been given, which is the clock that holds with a
+    // timestamp expire by
+    clock: Option<u64>,
+    timed_expiries: Expiries,
+    // Transactions held back until their account is unlocked, in the order they arrived
+    queued: BTreeMap<ClientId, Vec<Transaction<A>>>,
+    // Every deposit, withdrawal, transfer, adjustment, authorization and close id that's been
+    // used, whatever happened to it, since most of those are never cached
+    taken_ids: BTreeMap<TransactionId, TakenId<A>>,
+    counters: Counters,
+    latencies: Latencies,
+    // What the last call to `process` did
+    events: Vec<Event<A>>,
+    observers: Vec<Box<dyn Observer<A>>>,
+}
+
+// What's kept of a transaction once its id is taken, for `Options::duplicate_ids`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct TakenId<A: Amount = FixedFloat> {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "type")]
+    inner: TransactionInner<A>,
+    outcome: Outcome,
+}
+
+// Only for `FixedFloat`, so that `State::default()` doesn't need the amount type spelling out
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn write<Writer: std::io::Write>(self, mut f: Writer) -> Result<(), std::io::Error> {
-        writeln!(f, "client,available,held,total,locked")?;
+impl<A: Amount> State<A> {
+    /// An empty state. For `FixedFloat` amounts, this is `State::default()`.
+    pub fn new() -> Self {
+        Self {
+            options: Options::default(),
+            storage: Box::default(),
+            dispute_policy: Box::default(),
+            last_transaction_id: None,
+            client_transaction_ids: BTreeMap::new(),
+            invalid_transactions: 0,
+            processed: 0,
+            expiries: Expiries::default(),
+            clock: None,
+            timed_expiries: Expiries::default(),
+            queued: BTreeMap::new(),
+            taken_ids: BTreeMap::new(),
+            counters: Counters::default(),
+            latencies: Latencies::default(),
+            events: Vec::new(),
+            observers: Vec::new(),
+        }
+    }
 
-        for (client_id, account_state) in self.accounts {
-            writeln!(
-                f,
-                "{},{},{},{},{}",
-                client_id,
-                account_state.available,
-                account_state.held,
-                account_state.available + account_state.held,
-                account_state.locked
-            )?;
+    pub fn with_options(options: Options<A>) -> Self {
+        Self {
+            options,
+            ..Self::new()
         }
+    }
 
-        Ok(())
+    /// A state starting from `accounts`, e.g. a previous run's output, with nothing cached. So
+    /// transactions from before then can't be disputed, and anything held can't be released.
+    pub fn from_accounts(accounts: impl IntoIterator<Item = (ClientId, AccountState<A>)>) -> Self {
+        let mut state = Self::new();
+        for (client_id, account) in accounts {
+            *state.storage.account_mut(client_id) = account;
+        }
+        state
     }
-}
 
-#[derive(Debug, Default, PartialEq)]
-pub struct AccountState {
-    available: FixedFloat,
-    held: FixedFloat,
-    locked: bool,
-}
+    pub fn options(&self) -> &Options<A> {
+        &self.options
+    }
 
-#[derive(Debug)]
-pub enum ProcessError {
-    DisputedTransactionClientMissing(ClientId),
-    DisputeTargetInvalid(TransactionId),
-    DuplicateTransactionId(TransactionId),
-}
+    /// Changes how transactions are processed from now on. What's already been processed is
+    /// unaffected.
+    pub fn set_options(&mut self, options: Options<A>) {
+        self.options = options;
+    }
 
-impl std::fmt::Display for ProcessError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::DisputedTransactionClientMissing(client_id) => {
-                write!(
-                    f,
-                    "Disputed transaction refers to non-existent client id: {}",
-                    client_id
-                )
+    pub fn process(&mut self, txn: Transaction<A>) -> Result<Outcome, ProcessError> {
+        let type_name = txn.inner.type_name();
+        let (transaction_id, client_id) = (txn.transaction_id.0, txn.client_id.0);
+        let accounts = self.storage.account_count();
+        #[cfg(feature = "std")]
+        let started = self.options.track_latency.then(Instant::now);
+
+        // Reuse the buffer from last time, to save allocating for every transaction
+        let mut events = core::mem::take(&mut self.events);
+        events.clear();
+        self.processed += 1;
+        self.clock = self.clock.max(txn.timestamp);
+        // Before the transaction is decided on, so that it sees the funds the holds release
+        self.expire_holds(&mut events);
+        let expired = events.len();
+        let outcome = match self.decide(&txn, &mut events, false) {
+            Err(err) if !self.options.strict => {
+                tracing::warn!(tx = transaction_id, client = client_id, error = %err, "Invalid transaction ignored");
+                self.invalid_transactions += 1;
+                events.truncate(expired);
+                Outcome::Ignored(IgnoreReason::from(&err))
             }
-            Self::DisputeTargetInvalid(transaction_id) => {
-                write!(
-                    f,
-                    "Dispute of non-disputable transaction id: {}",
-                    transaction_id
+            Err(err) => {
+                events.truncate(expired);
+                self.notify_observers(&events);
+                self.events = events;
+                return Err(err);
+            }
+            Ok(outcome) => {
+                // Only a valid transaction takes its id, and a duplicate leaves the original's
+                if txn.inner.introduces_id() {
+                    self.taken_ids
+                        .entry(txn.transaction_id)
+                        .or_insert_with(|| TakenId {
+                            client_id: txn.client_id,
+                            inner: txn.inner.clone(),
+                            outcome,
+                        });
+                }
+                outcome
+            }
+        };
+        // Taken before the events are applied, since unlocking an account empties its queue
+        let released = events[expired..]
+            .iter()
+            .filter_map(|event| match *event {
+                Event::AccountUnlocked { client_id, .. } => self.queued.get(&client_id).cloned(),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        for event in &events[expired..] {
+            self.apply_event(event);
+        }
+        self.keep_timestamp(&txn, &events[expired..]);
+        for txn in released {
+            self.release(txn, &mut events);
+        }
+        self.time_holds(txn.timestamp, &events[expired..]);
+        self.notify_observers(&events);
+        self.events = events;
+
+        #[cfg(feature = "std")]
+        if let Some(started) = started {
+            self.latencies
+                .entry(type_name)
+                .or_default()
+                .record(started.elapsed());
+        }
+
+        match outcome {
+            Outcome::Applied => {
+                tracing::trace!(
+                    tx = transaction_id,
+                    client = client_id,
+                    r#type = type_name,
+                    "Applied"
                 )
             }
-            Self::DuplicateTransactionId(transaction_id) => {
-                write!(f, "Duplicate transaction id: {}", transaction_id)
+            Outcome::Queued => tracing::debug!(
+                tx = transaction_id,
+                client = client_id,
+                r#type = type_name,
+                "Queued until the account is unlocked"
+            ),
+            Outcome::Ignored(reason) => tracing::debug!(
+                tx = transaction_id,
+                client = client_id,
+                r#type = type_name,
+                reason = reason.name(),
+                "Ignored"
+            ),
+        }
+
+        self.counters.count(type_name, outcome);
+        self.counters.accounts_created += (self.storage.account_count() - accounts) as u64;
+
+        Ok(outcome)
+    }
+
+    // Processes `txn`, queued until its account was unlocked, as part of the transaction that
+    // unlocked it, adding what it does to `events`. Its id was checked when it was queued, so it
+    // isn't checked again, and nothing it does can fail the unlock, so in strict mode an invalid
+    // transaction is ignored all the same.
+    fn release(&mut self, txn: Transaction<A>, events: &mut Vec<Event<A>>) {
+        let start = events.len();
+        let outcome = match self.decide(&txn, events, true) {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                tracing::warn!(tx = txn.transaction_id.0, client = txn.client_id.0, error = %err, "Invalid queued transaction ignored");
+                self.invalid_transactions += 1;
+                events.truncate(start);
+                Outcome::Ignored(IgnoreReason::from(&err))
             }
+        };
+        for event in &events[start..] {
+            self.apply_event(event);
         }
+        self.keep_timestamp(&txn, &events[start..]);
+        if let Some(taken) = self.taken_ids.get_mut(&txn.transaction_id) {
+            taken.outcome = outcome;
+        }
+
+        // It was counted as queued when it arrived
+        let type_name = txn.inner.type_name();
+        let counts = self.counters.transactions.entry(type_name).or_default();
+        counts.queued = counts.queued.saturating_sub(1);
+        self.counters.count(type_name, outcome);
     }
-}
 
-impl Error for ProcessError {}
+    /// The events that the last call to `process` produced, i.e. every change it made, in the
+    /// order it made them. Empty if the transaction was ignored without changing anything, or if
+    /// it failed, except for any holds that expired before it (see
+    /// `Options::authorization_expiry`).
+    pub fn events(&self) -> &[Event<A>] {
+        &self.events
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::{ClientId, ProcessError, State, TransactionId, TransactionInner};
-    use crate::{
-        state::AccountState,
-        types::{Transaction, TransactionState},
-    };
-    use std::collections::HashMap;
+    /// Makes the change that `event` describes, as `process` does with the events it produces.
+    /// Replaying an event log into an empty state this way rebuilds the state that wrote it, but
+    /// nothing is checked, so the events must be applied in the order they were produced.
+    pub fn apply_event(&mut self, event: &Event<A>) {
+        match *event {
+            Event::AccountOpened { client_id } => {
+                self.storage.account_mut(client_id);
+            }
+            Event::FundsDeposited {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.saw_client_transaction_id(client_id, transaction_id);
+                self.storage.account_mut(client_id).available += amount;
+                self.storage.transactions_mut().insert(Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Deposit(amount),
+                ));
+            }
+            Event::FundsWithdrawn {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.saw_client_transaction_id(client_id, transaction_id);
+                self.storage.account_mut(client_id).available -= amount;
+                self.storage.transactions_mut().insert(Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Withdrawal(amount),
+                ));
+            }
+            Event::WithdrawalDeclined {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.saw_client_transaction_id(client_id, transaction_id);
+                let mut declined = Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Withdrawal(amount),
+                );
+                declined.state = TransactionState::Declined;
+                self.storage.transactions_mut().insert(declined);
+            }
+            Event::DepositBlocked {
+                client_id,
+                transaction_id,
+                ..
+            }
+            | Event::WithdrawalBlocked {
+                client_id,
+                transaction_id,
+                ..
+            } => {
+                self.saw_client_transaction_id(client_id, transaction_id);
+            }
+            Event::TransferSent {
+                client_id,
+                transaction_id,
+                to,
+                amount,
+            } => {
+                self.saw_transaction_id(transaction_id);
+                self.storage.account_mut(client_id).available -= amount;
+                self.storage.transactions_mut().insert(Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Transfer { to, amount },
+                ));
+            }
+            Event::TransferReceived {
+                client_id, amount, ..
+            }
+            | Event::TransferReversed {
+                client_id, amount, ..
+            } => {
+                self.storage.account_mut(client_id).available += amount;
+            }
+            Event::TransferDeclined { transaction_id, .. }
+            | Event::TransferBlocked { transaction_id, .. } => {
+                self.saw_transaction_id(transaction_id);
+            }
+            Event::AdjustmentApplied {
+                client_id,
+                transaction_id,
+                amount,
+                ..
+            } => {
+                self.saw_transaction_id(transaction_id);
+                self.storage.account_mut(client_id).available += amount;
+            }
+            Event::DisputeOpened {
+                client_id,
+                transaction_id,
+                amount,
+                portion,
+                ref reason,
+            } => {
+                if let Some(mut txn) = self.storage.transactions().get(transaction_id) {
+                    txn.state = TransactionState::Disputed;
+                    txn.disputed = portion;
+                    txn.reason = reason.clone();
+                    txn.disputes += 1;
+                    self.storage.transactions_mut().insert(txn);
+                }
+                let account = self.storage.account_mut(client_id);
+                account.available -= amount;
+                account.held += amount;
+                if let Some(expiry) = self.options.dispute_expiry {
+                    self.expiries
+                        .insert(transaction_id, self.processed.saturating_add(expiry));
+                }
+            }
+            Event::DisputeResolved {
+                client_id,
+                transaction_id,
+                amount,
+            }
+            | Event::DisputeExpired {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.forget_expiry(transaction_id);
+                self.set_dispute(transaction_id, TransactionState::Alive, None);
+                let account = self.storage.account_mut(client_id);
+                account.available += amount;
+                account.held -= amount;
+            }
+            Event::ChargedBack {
+                client_id,
+                transaction_id,
+                amount,
+                ref reason,
+            } => {
+                self.forget_expiry(transaction_id);
+                self.set_transaction_state(transaction_id, TransactionState::ChargedBack);
+                if reason.is_some() {
+                    self.set_reason(transaction_id, reason.clone());
+                }
+                self.storage.account_mut(client_id).held -= amount;
+            }
+            Event::AccountLocked { client_id } => {
+                self.storage.account_mut(client_id).locked = true;
+            }
+            Event::AccountUnlocked {
+                client_id,
+                transaction_id,
+            } => {
+                // A represented transaction stays that way, whatever happens to the lock
+                if self
+                    .storage
+                    .transactions()
+                    .get(transaction_id)
+                    .is_some_and(|txn| txn.state == TransactionState::ChargedBack)
+                {
+                    self.set_transaction_state(transaction_id, TransactionState::Unlocked);
+                }
+                self.storage.account_mut(client_id).locked = false;
+                // Its queue is released, with the events of what each transaction then did
+                // following this one
+                self.queued.remove(&client_id);
+            }
+            Event::ChargebackReversed {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.set_transaction_state(transaction_id, TransactionState::Represented);
+                self.storage.account_mut(client_id).available += amount;
+            }
+            Event::FundsAuthorized {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.saw_transaction_id(transaction_id);
+                let account = self.storage.account_mut(client_id);
+                account.available -= amount;
+                account.held += amount;
+                self.storage.transactions_mut().insert(Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Authorize(amount),
+                ));
+                if let Some(expiry) = self.options.authorization_expiry {
+                    self.expiries
+                        .insert(transaction_id, self.processed.saturating_add(expiry));
+                }
+            }
+            Event::AuthorizationDeclined { transaction_id, .. }
+            | Event::AuthorizationBlocked { transaction_id, .. } => {
+                self.saw_transaction_id(transaction_id);
+            }
+            Event::AuthorizationCaptured {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.forget_expiry(transaction_id);
+                self.storage.account_mut(client_id).held -= amount;
+                self.storage.transactions_mut().insert(Transaction::new(
+                    transaction_id,
+                    client_id,
+                    TransactionInner::Withdrawal(amount),
+                ));
+            }
+            Event::AuthorizationVoided {
+                client_id,
+                transaction_id,
+                amount,
+            }
+            | Event::AuthorizationExpired {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.forget_expiry(transaction_id);
+                self.set_transaction_state(transaction_id, TransactionState::Reversed);
+                let account = self.storage.account_mut(client_id);
+                account.held -= amount;
+                account.available += amount;
+            }
+            Event::AccountClosed {
+                client_id,
+                transaction_id,
+                ..
+            } => {
+                self.saw_transaction_id(transaction_id);
+                self.storage.account_mut(client_id).closed = true;
+            }
+            Event::ClosedAccountRefused { transaction_id, .. } => {
+                self.saw_transaction_id(transaction_id);
+            }
+            Event::TransactionQueued {
+                client_id,
+                transaction_id,
+                ref inner,
+            } => {
+                match inner {
+                    TransactionInner::Deposit(_) | TransactionInner::Withdrawal(_) => {
+                        self.saw_client_transaction_id(client_id, transaction_id)
+                    }
+                    _ => self.saw_transaction_id(transaction_id),
+                }
+                self.queued
+                    .entry(client_id)
+                    .or_default()
+                    .push(Transaction::new(transaction_id, client_id, inner.clone()));
+            }
+            Event::TransactionReversed {
+                client_id,
+                transaction_id,
+                amount,
+            } => {
+                self.set_transaction_state(transaction_id, TransactionState::Reversed);
+                self.storage.account_mut(client_id).available += amount;
+            }
+        }
+    }
 
-    fn build_state(txns: &[Transaction]) -> Result<State, ProcessError> {
-        let mut state = State::default();
+    // Works out what `txn` does, as events, without changing anything. Every error must be
+    // returned before any event is pushed, so that a transaction that fails has no effect.
+    //
+    // A `released` transaction is one that was queued until its account was unlocked, and whose
+    // id was checked then.
+    fn decide(
+        &self,
+        txn: &Transaction<A>,
+        events: &mut Vec<Event<A>>,
+        released: bool,
+    ) -> Result<Outcome, ProcessError> {
+        let (client_id, transaction_id) = (txn.client_id, txn.transaction_id);
+        let locked_accounts = self.options.locked_accounts;
 
-        for txn in txns {
-            state.process(txn.clone())?;
+        // Before anything else that could refuse it, so that a duplicate is treated the same
+        // whatever its type, and a resend is recognised before it's found to be out of order or
+        // too large
+        if txn.inner.introduces_id() && !released {
+            if let Some(outcome) = self.check_duplicate(txn)? {
+                return Ok(outcome);
+            }
         }
 
-        Ok(state)
-    }
+        if let TransactionInner::Deposit(amount)
+        | TransactionInner::Withdrawal(amount)
+        | TransactionInner::Transfer { amount, .. }
+        | TransactionInner::Authorize(amount) = txn.inner
+        {
+            if self.options.max_amount.is_some_and(|max| amount > max) {
+                return Err(ProcessError::AmountTooLarge(transaction_id));
+            }
+        }
 
-    #[test]
-    fn test_basic_example() {
-        let state = build_state(&[
-            Transaction::new(
-                TransactionId(1),
-                ClientId(1),
-                TransactionInner::Deposit(1.0.into()),
-            ),
-            Transaction::new(
-                TransactionId(2),
-                ClientId(2),
-                TransactionInner::Deposit(2.0.into()),
-            ),
-            Transaction::new(
-                TransactionId(3),
+        let outcome = match txn.inner {
+            TransactionInner::Deposit(amount) => {
+                if !released {
+                    self.check_id_ordering(transaction_id)?;
+                    self.check_client_id_ordering(client_id, transaction_id)?;
+                }
+                if self.closed_refuses(client_id, true) {
+                    events.push(Event::ClosedAccountRefused {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+                }
+
+                match self.locked_mode(client_id, locked_accounts.deposits) {
+                    LockedAccountMode::Allow => {}
+                    LockedAccountMode::Reject => {
+                        events.push(Event::DepositBlocked {
+                            client_id,
+                            transaction_id,
+                            amount,
+                        });
+                        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
+                    }
+                    LockedAccountMode::Queue => return self.queue(txn, events),
+                }
+                self.open_account(client_id, events);
+                events.push(Event::FundsDeposited {
+                    client_id,
+                    transaction_id,
+                    amount,
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Withdrawal(amount) => {
+                if !released {
+                    self.check_id_ordering(transaction_id)?;
+                    self.check_client_id_ordering(client_id, transaction_id)?;
+                }
+                if self.closed_refuses(client_id, false) {
+                    events.push(Event::ClosedAccountRefused {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+                }
+
+                // By default, we can't withdraw from a frozen account
+                match self.locked_mode(client_id, locked_accounts.withdrawals) {
+                    LockedAccountMode::Allow => {}
+                    LockedAccountMode::Reject => {
+                        // Only cache if the account isn't locked. If this withdrawal were to be
+                        // disputed (is that even possible?), we wouldn't want to negate it, so
+                        // just don't cache it, and the dispute code will think it's an "error
+                        // on the partner side" - that's probably good enough.
+                        events.push(Event::WithdrawalBlocked {
+                            client_id,
+                            transaction_id,
+                            amount,
+                        });
+                        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
+                    }
+                    LockedAccountMode::Queue => return self.queue(txn, events),
+                }
+
+                let available = self
+                    .storage
+                    .account(client_id)
+                    .map(|account| account.available)
+                    .unwrap_or_default();
+                self.open_account(client_id, events);
+
+                if available >= amount {
+                    events.push(Event::FundsWithdrawn {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    Outcome::Applied
+                } else {
+                    events.push(Event::WithdrawalDeclined {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    Outcome::Ignored(IgnoreReason::InsufficientFunds)
+                }
+            }
+            TransactionInner::Transfer { to, amount } => {
+                if !released {
+                    self.check_id_ordering(transaction_id)?;
+                }
+                if self.closed_refuses(client_id, false) || self.closed_refuses(to, true) {
+                    events.push(Event::ClosedAccountRefused {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+                }
+
+                // As with a withdrawal, a locked account can't send by default, but it can always
+                // receive
+                match self.locked_mode(client_id, locked_accounts.transfers) {
+                    LockedAccountMode::Allow => {}
+                    LockedAccountMode::Reject => {
+                        events.push(Event::TransferBlocked {
+                            client_id,
+                            transaction_id,
+                            to,
+                            amount,
+                        });
+                        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
+                    }
+                    LockedAccountMode::Queue => return self.queue(txn, events),
+                }
+
+                let available = self
+                    .storage
+                    .account(client_id)
+                    .map(|account| account.available)
+                    .unwrap_or_default();
+                self.open_account(client_id, events);
+
+                if available >= amount {
+                    if to != client_id {
+                        self.open_account(to, events);
+                    }
+                    events.push(Event::TransferSent {
+                        client_id,
+                        transaction_id,
+                        to,
+                        amount,
+                    });
+                    events.push(Event::TransferReceived {
+                        client_id: to,
+                        transaction_id,
+                        amount,
+                    });
+                    Outcome::Applied
+                } else {
+                    events.push(Event::TransferDeclined {
+                        client_id,
+                        transaction_id,
+                        to,
+                        amount,
+                    });
+                    Outcome::Ignored(IgnoreReason::InsufficientFunds)
+                }
+            }
+            TransactionInner::Adjustment {
+                amount,
+                ref reference,
+            } => {
+                self.check_id_ordering(transaction_id)?;
+
+                // Corrections are exactly what a locked account may need, so they go through
+                // regardless
+                self.open_account(client_id, events);
+                events.push(Event::AdjustmentApplied {
+                    client_id,
+                    transaction_id,
+                    amount,
+                    reference: reference.clone(),
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Authorize(amount) => {
+                if !released {
+                    self.check_id_ordering(transaction_id)?;
+                }
+                if self.closed_refuses(client_id, false) {
+                    events.push(Event::ClosedAccountRefused {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+                }
+
+                // An authorization is the first half of a withdrawal, so by default it's refused
+                // in the same cases
+                match self.locked_mode(client_id, locked_accounts.authorizations) {
+                    LockedAccountMode::Allow => {}
+                    LockedAccountMode::Reject => {
+                        events.push(Event::AuthorizationBlocked {
+                            client_id,
+                            transaction_id,
+                            amount,
+                        });
+                        return Ok(Outcome::Ignored(IgnoreReason::AccountLocked));
+                    }
+                    LockedAccountMode::Queue => return self.queue(txn, events),
+                }
+
+                let available = self
+                    .storage
+                    .account(client_id)
+                    .map(|account| account.available)
+                    .unwrap_or_default();
+                self.open_account(client_id, events);
+
+                if available >= amount {
+                    events.push(Event::FundsAuthorized {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    Outcome::Applied
+                } else {
+                    events.push(Event::AuthorizationDeclined {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    });
+                    Outcome::Ignored(IgnoreReason::InsufficientFunds)
+                }
+            }
+            TransactionInner::Capture | TransactionInner::Void => {
+                // Grab the authorization. If it doesn't exist, just ignore and return
+                let authorization = match self.storage.transactions().get(transaction_id) {
+                    Some(authorization) => authorization,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &authorization) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                // Once captured, it's a withdrawal, and once voided, it's reversed, so either
+                // can only happen once. The funds are already held, so neither is affected by the
+                // account being locked or closed.
+                let TransactionInner::Authorize(amount) = authorization.inner else {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized));
+                };
+                if authorization.state != TransactionState::Alive {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotAuthorized));
+                }
+
+                let client_id = authorization.client_id;
+                events.push(match txn.inner {
+                    TransactionInner::Capture => Event::AuthorizationCaptured {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    },
+                    _ => Event::AuthorizationVoided {
+                        client_id,
+                        transaction_id,
+                        amount,
+                    },
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Close => {
+                self.check_id_ordering(transaction_id)?;
+                if self
+                    .storage
+                    .account(client_id)
+                    .is_some_and(|account| account.closed)
+                {
+                    return Ok(Outcome::Ignored(IgnoreReason::AccountClosed));
+                }
+
+                // Anything held stays held, and is only owed to the client if it's released
+                let payout = self
+                    .storage
+                    .account(client_id)
+                    .map(|account| account.available)
+                    .unwrap_or_default();
+                self.open_account(client_id, events);
+                events.push(Event::AccountClosed {
+                    client_id,
+                    transaction_id,
+                    payout,
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Dispute | TransactionInner::PartialDispute(_) => {
+                // Grab the disputed transaction. If it doesn't exist, just ignore and return
+                let mut disputed_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(disputed_txn) => disputed_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &disputed_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                match disputed_txn.state {
+                    TransactionState::Alive => {}
+                    // Cannot dispute if already disputed or charged back
+                    TransactionState::Disputed => {
+                        return Ok(Outcome::Ignored(IgnoreReason::AlreadyDisputed));
+                    }
+                    TransactionState::ChargedBack
+                    | TransactionState::Unlocked
+                    | TransactionState::Represented => {
+                        return Ok(Outcome::Ignored(IgnoreReason::ChargedBack));
+                    }
+                    TransactionState::Reversed => {
+                        return Ok(Outcome::Ignored(IgnoreReason::AlreadyReversed));
+                    }
+                    // Putting back what it never took would create money
+                    TransactionState::Declined => {
+                        return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+                    }
+                }
+
+                if self
+                    .options
+                    .max_disputes
+                    .is_some_and(|max| disputed_txn.disputes >= max)
+                {
+                    return Ok(Outcome::Ignored(IgnoreReason::TooManyDisputes));
+                }
+
+                if let (Some(window), Some(disputed_at), Some(at)) = (
+                    self.options.dispute_window_seconds,
+                    disputed_txn.timestamp,
+                    txn.timestamp,
+                ) {
+                    if at > disputed_at.saturating_add(window) {
+                        return Ok(Outcome::Ignored(IgnoreReason::DisputeWindowExpired));
+                    }
+                }
+
+                // Only part of it may be disputed, leaving the rest available. A partial dispute
+                // of all of it is just a dispute.
+                disputed_txn.disputed = match txn.inner {
+                    TransactionInner::PartialDispute(portion) => {
+                        let whole = disputed_txn.inner.amount().unwrap_or_default();
+                        if portion <= A::default() || portion > whole {
+                            return Ok(Outcome::Ignored(IgnoreReason::InvalidDisputeAmount));
+                        }
+                        (portion < whole).then_some(portion)
+                    }
+                    _ => None,
+                };
+
+                let Some(amount) = self.disputed_amount(&disputed_txn)? else {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+                };
+                let mut available = self
+                    .storage
+                    .account(disputed_txn.disputed_client_id())
+                    .unwrap()
+                    .available;
+                available -= amount;
+                if available < A::default()
+                    && !self.dispute_policy.allows_negative_available(available)
+                {
+                    return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+                }
+                events.push(Event::DisputeOpened {
+                    client_id: disputed_txn.disputed_client_id(),
+                    transaction_id,
+                    amount,
+                    portion: disputed_txn.disputed,
+                    reason: txn.reason.clone(),
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Resolve => {
+                // Grab the disputed transaction. If it doesn't exist, just ignore and return
+                let disputed_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(disputed_txn) => disputed_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &disputed_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                if !matches!(disputed_txn.state, TransactionState::Disputed) {
+                    // Not disputed; do nothing
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputed));
+                }
+
+                let Some(amount) = self.disputed_amount(&disputed_txn)? else {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+                };
+                events.push(Event::DisputeResolved {
+                    client_id: disputed_txn.disputed_client_id(),
+                    transaction_id,
+                    amount,
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Chargeback => {
+                // Grab the disputed transaction. If it doesn't exist, just ignore and return
+                let disputed_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(disputed_txn) => disputed_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &disputed_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                if !matches!(disputed_txn.state, TransactionState::Disputed) {
+                    // Not disputed; do nothing
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputed));
+                }
+
+                let Some(amount) = self.disputed_amount(&disputed_txn)? else {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+                };
+                let client_id = disputed_txn.disputed_client_id();
+                events.push(Event::ChargedBack {
+                    client_id,
+                    transaction_id,
+                    amount,
+                    reason: txn.reason.clone(),
+                });
+                // A transfer's funds go back to where they came from
+                if let TransactionInner::Transfer { .. } = disputed_txn.inner {
+                    events.push(Event::TransferReversed {
+                        client_id: disputed_txn.client_id,
+                        transaction_id,
+                        amount,
+                    });
+                }
+                if !self.storage.account(client_id).unwrap().locked {
+                    events.push(Event::AccountLocked { client_id });
+                }
+                Outcome::Applied
+            }
+            TransactionInner::Unlock => {
+                // Grab the charged back transaction. If it doesn't exist, just ignore and return
+                let charged_back_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(charged_back_txn) => charged_back_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &charged_back_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                // A represented transaction's chargeback may have left the account locked (see
+                // `Options::representment_unlocks`)
+                if !matches!(
+                    charged_back_txn.state,
+                    TransactionState::ChargedBack | TransactionState::Represented
+                ) {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotChargedBack));
+                }
+                if charged_back_txn.state == TransactionState::Represented
+                    && !self
+                        .storage
+                        .account(charged_back_txn.disputed_client_id())
+                        .is_some_and(|account| account.locked)
+                {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotChargedBack));
+                }
+
+                // The account the chargeback locked. It may have been locked by another
+                // chargeback too, but an unlock is an operator's decision to lift the freeze
+                // altogether; the other chargeback can still be unlocked later, for the record.
+                events.push(Event::AccountUnlocked {
+                    client_id: charged_back_txn.disputed_client_id(),
+                    transaction_id,
+                });
+                Outcome::Applied
+            }
+            TransactionInner::Representment => {
+                // Grab the charged back transaction. If it doesn't exist, just ignore and return
+                let charged_back_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(charged_back_txn) => charged_back_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &charged_back_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                if !matches!(
+                    charged_back_txn.state,
+                    TransactionState::ChargedBack | TransactionState::Unlocked
+                ) {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotChargedBack));
+                }
+                if let TransactionInner::Transfer { .. } = charged_back_txn.inner {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotRepresentable));
+                }
+
+                // What the chargeback took out of held goes back into available, as if the
+                // dispute had been resolved instead
+                let Some(amount) = self.disputed_amount(&charged_back_txn)? else {
+                    return Ok(Outcome::Ignored(IgnoreReason::NotDisputable));
+                };
+                let client_id = charged_back_txn.disputed_client_id();
+                let account = self.storage.account(client_id).unwrap();
+                let mut available = account.available;
+                available += amount;
+                if available < A::default()
+                    && !self.dispute_policy.allows_negative_available(available)
+                {
+                    return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+                }
+                let unlock = self.options.representment_unlocks && account.locked;
+
+                events.push(Event::ChargebackReversed {
+                    client_id,
+                    transaction_id,
+                    amount,
+                });
+                if unlock {
+                    events.push(Event::AccountUnlocked {
+                        client_id,
+                        transaction_id,
+                    });
+                }
+                Outcome::Applied
+            }
+            TransactionInner::Refund => {
+                // Grab the refunded transaction. If it doesn't exist, just ignore and return
+                let refunded_txn = match self.storage.transactions().get(transaction_id) {
+                    Some(refunded_txn) => refunded_txn,
+                    None => {
+                        // Error on partner side
+                        return Ok(Outcome::Ignored(IgnoreReason::UnknownTransaction));
+                    }
+                };
+
+                if !self.may_refer_to(client_id, &refunded_txn) {
+                    return Ok(Outcome::Ignored(IgnoreReason::ClientMismatch));
+                }
+
+                // Once a dispute is under way, it has to run its course
+                match refunded_txn.state {
+                    TransactionState::Alive => {}
+                    TransactionState::Disputed => {
+                        return Ok(Outcome::Ignored(IgnoreReason::AlreadyDisputed));
+                    }
+                    TransactionState::ChargedBack
+                    | TransactionState::Unlocked
+                    | TransactionState::Represented => {
+                        return Ok(Outcome::Ignored(IgnoreReason::ChargedBack));
+                    }
+                    TransactionState::Reversed => {
+                        return Ok(Outcome::Ignored(IgnoreReason::AlreadyReversed));
+                    }
+                    TransactionState::Declined => {
+                        return Ok(Outcome::Ignored(IgnoreReason::NotRefundable));
+                    }
+                }
+
+                // What undoing it does to available funds
+                let amount = match refunded_txn.inner {
+                    TransactionInner::Deposit(amount) => -amount,
+                    TransactionInner::Withdrawal(amount) => amount,
+                    _ => return Ok(Outcome::Ignored(IgnoreReason::NotRefundable)),
+                };
+                let client_id = refunded_txn.client_id;
+                let Some(account) = self.storage.account(client_id) else {
+                    return Err(ProcessError::DisputedTransactionClientMissing(client_id));
+                };
+
+                // Refunding a deposit takes the funds back out, so they have to be there still.
+                // Like an adjustment, it goes through whether or not the account is locked or
+                // closed, since it isn't the client moving money.
+                let mut available = account.available;
+                available += amount;
+                if available < A::default() {
+                    return Ok(Outcome::Ignored(IgnoreReason::InsufficientFunds));
+                }
+                events.push(Event::TransactionReversed {
+                    client_id,
+                    transaction_id,
+                    amount,
+                });
+                Outcome::Applied
+            }
+        };
+
+        Ok(outcome)
+    }
+
+    // What to do with a transaction on `client_id`'s account whose type has `mode` for locked
+    // accounts: `mode` if the account is locked, and otherwise to let it through.
+    fn locked_mode(&self, client_id: ClientId, mode: LockedAccountMode) -> LockedAccountMode {
+        if self
+            .storage
+            .account(client_id)
+            .is_some_and(|account| account.locked)
+        {
+            mode
+        } else {
+            LockedAccountMode::Allow
+        }
+    }
+
+    // Holds `txn` back until its account is unlocked.
+    fn queue(
+        &self,
+        txn: &Transaction<A>,
+        events: &mut Vec<Event<A>>,
+    ) -> Result<Outcome, ProcessError> {
+        events.push(Event::TransactionQueued {
+            client_id: txn.client_id,
+            transaction_id: txn.transaction_id,
+            inner: txn.inner.clone(),
+        });
+        Ok(Outcome::Queued)
+    }
+
+    // How much a dispute of `disputed_txn` moves between available and held, as the dispute
+    // policy has it, checking that its client's account exists to move it in.
+    fn disputed_amount(&self, disputed_txn: &Transaction<A>) -> Result<Option<A>, ProcessError> {
+        // A pending authorization hasn't moved any funds yet, so there's nothing to dispute
+        if let TransactionInner::Authorize(_) = disputed_txn.inner {
+            return Ok(None);
+        }
+        if !matches!(
+            disputed_txn.inner,
+            TransactionInner::Deposit(_)
+                | TransactionInner::Withdrawal(_)
+                | TransactionInner::Transfer { .. }
+        ) {
+            return Err(ProcessError::DisputeTargetInvalid(
+                disputed_txn.transaction_id,
+            ));
+        }
+
+        // Whatever client the row names (see `Options::require_client_match`), the disputed
+        // transaction's client (or a transfer's recipient) is the one whose account changes.
+
+        // Check the client. We know that the transactions happen in chronological order, so the
+        // client should exist already.
+        let client_id = disputed_txn.disputed_client_id();
+        if self.storage.account(client_id).is_none() {
+            return Err(ProcessError::DisputedTransactionClientMissing(client_id));
+        }
+
+        Ok(self.held_amount(&disputed_txn.disputed_part()))
+    }
+
+    // Whether a row naming `client_id` may refer to `txn`, as `Options::require_client_match` and
+    // the dispute policy have it.
+    fn may_refer_to(&self, client_id: ClientId, txn: &Transaction<A>) -> bool {
+        (!self.options.require_client_match
+            || client_id == txn.client_id
+            || client_id == txn.disputed_client_id())
+            && self.dispute_policy.may_refer_to(client_id, txn)
+    }
+
+    // How much a dispute of `disputed`, already cut down to the part disputed, moves from
+    // available to held: as `Options::withdrawal_disputes` has it for a withdrawal, unless that
+    // leaves it to the dispute policy, which decides everything else.
+    fn held_amount(&self, disputed: &Transaction<A>) -> Option<A> {
+        match (&disputed.inner, self.options.withdrawal_disputes) {
+            (TransactionInner::Withdrawal(_), WithdrawalDisputes::Reject) => None,
+            (TransactionInner::Withdrawal(amount), WithdrawalDisputes::HoldPositive) => {
+                Some(*amount)
+            }
+            _ => self.dispute_policy.disputed_amount(disputed),
+        }
+    }
+
+    /// Only deposits, withdrawals, transfers, adjustments, authorizations and closes introduce new
+    /// transaction ids; disputes, resolutions and chargebacks refer back to old ones, so they're
+    /// not checked.
+    fn check_id_ordering(&self, transaction_id: TransactionId) -> Result<(), ProcessError> {
+        let previous = match self.last_transaction_id {
+            Some(previous) if transaction_id <= previous => previous,
+            _ => return Ok(()),
+        };
+
+        let err = ProcessError::NonMonotonicTransactionId {
+            previous,
+            current: transaction_id,
+        };
+
+        match self.options.id_ordering {
+            IdOrdering::Unchecked => Ok(()),
+            IdOrdering::Warn => {
+                tracing::warn!(tx = transaction_id.0, previous = previous.0, error = %err, "Transaction id out of order");
+                Ok(())
+            }
+            IdOrdering::Strict => Err(err),
+        }
+    }
+
+    /// Only checked if `Options::client_monotonic_ids` is on.
+    fn check_client_id_ordering(
+        &self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    ) -> Result<(), ProcessError> {
+        if !self.options.client_monotonic_ids {
+            return Ok(());
+        }
+        match self.client_transaction_ids.get(&client_id) {
+            Some(&previous) if transaction_id <= previous => {
+                Err(ProcessError::NonMonotonicClientTransactionId {
+                    client_id,
+                    previous,
+                    current: transaction_id,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The transaction that `transaction_id` is already taken by, if any, and the outcome that it
+    // had. A state rebuilt from events (see `apply_event`) only knows the ids it cached, which
+    // were applied.
+    fn taken_by(&self, transaction_id: TransactionId) -> Option<TakenId<A>> {
+        if let Some(taken) = self.taken_ids.get(&transaction_id) {
+            return Some(taken.clone());
+        }
+        self.storage
+            .transactions()
+            .get(transaction_id)
+            .map(|cached| TakenId {
+                client_id: cached.client_id,
+                inner: cached.inner,
+                outcome: Outcome::Applied,
+            })
+    }
+
+    // Fails if `txn`'s id is already taken, unless `Options::duplicate_ids` says it goes no
+    // further, with the returned outcome.
+    fn check_duplicate(&self, txn: &Transaction<A>) -> Result<Option<Outcome>, ProcessError> {
+        let Some(original) = self.taken_by(txn.transaction_id) else {
+            return Ok(None);
+        };
+        match self.options.duplicate_ids {
+            DuplicateIds::Skip => Ok(Some(Outcome::Ignored(IgnoreReason::DuplicateTransactionId))),
+            DuplicateIds::Idempotent
+                if original.client_id == txn.client_id && original.inner == txn.inner =>
+            {
+                Ok(Some(original.outcome))
+            }
+            _ => Err(ProcessError::DuplicateTransactionId(txn.transaction_id)),
+        }
+    }
+
+    /// Whether `client_id`'s account is closed, and `Options::closed_accounts` refuses money going
+    /// into it (if `deposit`) or out of it.
+    fn closed_refuses(&self, client_id: ClientId, deposit: bool) -> bool {
+        self.storage
+            .account(client_id)
+            .is_some_and(|account| account.closed)
+            && match self.options.closed_accounts {
+                ClosedAccounts::Reject => true,
+                ClosedAccounts::AllowDeposits => !deposit,
+                ClosedAccounts::Allow => false,
+            }
+    }
+
+    fn open_account(&self, client_id: ClientId, events: &mut Vec<Event<A>>) {
+        if self.storage.account(client_id).is_none() {
+            events.push(Event::AccountOpened { client_id });
+        }
+    }
+
+    // Releases the holds that are due to expire before the transaction being processed, pushing
+    // the events that do so, already applied.
+    fn expire_holds(&mut self, events: &mut Vec<Event<A>>) {
+        while let Some(transaction_id) = self
+            .expiries
+            .due(self.processed)
+            .or_else(|| self.clock.and_then(|clock| self.timed_expiries.due(clock)))
+        {
+            let event = match self.storage.transactions().get(transaction_id) {
+                Some(txn) => match txn.inner {
+                    TransactionInner::Authorize(amount) if txn.state == TransactionState::Alive => {
+                        Some(Event::AuthorizationExpired {
+                            client_id: txn.client_id,
+                            transaction_id,
+                            amount,
+                        })
+                    }
+                    _ if txn.state == TransactionState::Disputed => self
+                        .held_amount(&txn.disputed_part())
+                        .map(|amount| Event::DisputeExpired {
+                            client_id: txn.disputed_client_id(),
+                            transaction_id,
+                            amount,
+                        }),
+                    _ => None,
+                },
+                None => None,
+            };
+            match event {
+                Some(event) => {
+                    self.apply_event(&event);
+                    events.push(event);
+                }
+                // Gone from the cache some other way, so there's nothing left to release
+                None => self.forget_expiry(transaction_id),
+            }
+        }
+    }
+
+    // Holds opened by `events`, just applied for a transaction with `timestamp`, expire by that
+    // rather than by the number of transactions processed, if the options give a time to.
+    fn time_holds(&mut self, timestamp: Option<u64>, events: &[Event<A>]) {
+        let Some(timestamp) = timestamp else {
+            return;
+        };
+        for event in events {
+            let (transaction_id, expiry) = match *event {
+                Event::FundsAuthorized { transaction_id, .. } => {
+                    (transaction_id, self.options.authorization_expiry_seconds)
+                }
+                Event::DisputeOpened { transaction_id, .. } => {
+                    (transaction_id, self.options.dispute_expiry_seconds)
+                }
+                _ => continue,
+            };
+            if let Some(expiry) = expiry {
+                self.expiries.remove(transaction_id);
+                self.timed_expiries
+                    .insert(transaction_id, timestamp.saturating_add(expiry));
+            }
+        }
+    }
+
+    fn forget_expiry(&mut self, transaction_id: TransactionId) {
+        self.expiries.remove(transaction_id);
+        self.timed_expiries.remove(transaction_id);
+    }
+
+    // Events don't carry timestamps, so if `events`, just applied, cached or queued `txn`, the
+    // copy they made is given its timestamp here.
+    fn keep_timestamp(&mut self, txn: &Transaction<A>, events: &[Event<A>]) {
+        let Some(timestamp) = txn.timestamp else {
+            return;
+        };
+        for event in events {
+            match *event {
+                Event::FundsDeposited { transaction_id, .. }
+                | Event::FundsWithdrawn { transaction_id, .. }
+                | Event::WithdrawalDeclined { transaction_id, .. }
+                | Event::TransferSent { transaction_id, .. }
+                | Event::FundsAuthorized { transaction_id, .. }
+                    if transaction_id == txn.transaction_id =>
+                {
+                    if let Some(mut cached) = self.storage.transactions().get(transaction_id) {
+                        cached.timestamp = Some(timestamp);
+                        self.storage.transactions_mut().insert(cached);
+                    }
+                }
+                Event::TransactionQueued {
+                    client_id,
+                    transaction_id,
+                    ..
+                } if transaction_id == txn.transaction_id => {
+                    if let Some(queued) = self
+                        .queued
+                        .get_mut(&client_id)
+                        .and_then(|queued| queued.last_mut())
+                    {
+                        queued.timestamp = Some(timestamp);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn saw_transaction_id(&mut self, transaction_id: TransactionId) {
+        self.last_transaction_id = self.last_transaction_id.max(Some(transaction_id));
+    }
+
+    // Like `saw_transaction_id`, for a deposit or withdrawal by `client_id`
+    fn saw_client_transaction_id(&mut self, client_id: ClientId, transaction_id: TransactionId) {
+        self.saw_transaction_id(transaction_id);
+        let last = self
+            .client_transaction_ids
+            .entry(client_id)
+            .or_insert(transaction_id);
+        *last = (*last).max(transaction_id);
+    }
+
+    // Like `set_transaction_state`, also recording how much of the transaction is disputed
+    fn set_dispute(
+        &mut self,
+        transaction_id: TransactionId,
+        state: TransactionState,
+        disputed: Option<A>,
+    ) {
+        match self.storage.transactions().get(transaction_id) {
+            Some(mut txn) if txn.disputed != disputed => {
+                txn.state = state;
+                txn.disputed = disputed;
+                self.storage.transactions_mut().insert(txn);
+            }
+            _ => self.set_transaction_state(transaction_id, state),
+        }
+    }
+
+    // Records the reason code a dispute or chargeback of the transaction gave
+    fn set_reason(&mut self, transaction_id: TransactionId, reason: Option<String>) {
+        match self.storage.transactions().get(transaction_id) {
+            Some(mut txn) if txn.reason != reason => {
+                txn.reason = reason;
+                self.storage.transactions_mut().insert(txn);
+            }
+            _ => {}
+        }
+    }
+
+    fn set_transaction_state(&mut self, transaction_id: TransactionId, state: TransactionState) {
+        self.storage
+            .transactions_mut()
+            .set_state(transaction_id, state);
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (ClientId, &AccountState<A>)> {
+        self.storage.accounts()
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&AccountState<A>> {
+        self.storage.account(client_id)
+    }
+
+    /// Like `accounts`, but ordered by client id so that output is the same from run to run.
+    /// Client ids are only 16 bits, so there are never enough accounts for this to be slow.
+    pub fn sorted_accounts(&self) -> Vec<(ClientId, &AccountState<A>)> {
+        let mut accounts = self.accounts().collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|(client_id, _)| *client_id);
+        accounts
+    }
+
+    /// The cached (i.e. disputable) transactions, in no particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = Transaction<A>> + '_ {
+        self.storage.transactions().iter()
+    }
+
+    /// A cached transaction, by id.
+    pub fn transaction(&self, transaction_id: TransactionId) -> Option<Transaction<A>> {
+        self.storage.transactions().get(transaction_id)
+    }
+
+    /// The transactions queued until their accounts are unlocked (see `LockedAccountMode::Queue`),
+    /// by client id and then in the order they arrived.
+    pub fn queued(&self) -> impl Iterator<Item = &Transaction<A>> {
+        self.queued.values().flatten()
+    }
+
+    /// Decides disputes by `policy` from now on, rather than by `DefaultDisputePolicy`.
+    pub fn set_dispute_policy(&mut self, policy: Box<dyn DisputePolicy<A>>) {
+        self.dispute_policy = policy;
+    }
+
+    /// Moves the accounts and the transaction cache into `storage`, which should be empty, and
+    /// keeps them there from now on.
+    pub fn set_storage(&mut self, mut storage: Box<dyn Storage<A>>) {
+        for (client_id, account) in self.storage.accounts() {
+            *storage.account_mut(client_id) = account.clone();
+        }
+        for txn in self.storage.transactions().iter() {
+            storage.transactions_mut().insert(txn);
+        }
+        self.storage = storage;
+    }
+
+    /// Moves the transaction cache into `store`, which should be empty, and keeps it there from
+    /// now on, e.g. to keep it on disk rather than in memory. The accounts are kept in a
+    /// `MemoryStorage`, in place of any `Storage` set before.
+    pub fn set_transaction_store(&mut self, store: Box<dyn TransactionStore<A>>) {
+        self.set_storage(Box::new(MemoryStorage::with_transaction_store(store)));
+    }
+
+    /// How many transactions `process` has been given, whether or not they were applied. Holds
+    /// expire by this (see `Options::authorization_expiry`).
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            accounts: self.storage.account_count(),
+            locked_accounts: self.storage.accounts().filter(|(_, a)| a.locked).count(),
+            closed_accounts: self.storage.accounts().filter(|(_, a)| a.closed).count(),
+            cached_transactions: self.storage.transactions().len(),
+            disputed_transactions: self
+                .storage
+                .transactions()
+                .iter()
+                .filter(|t| t.state == TransactionState::Disputed)
+                .count(),
+            invalid_transactions: self.invalid_transactions,
+            memory_bytes: self.memory_usage(),
+        }
+    }
+
+    /// Roughly how much memory the accounts, the transaction cache and the record of the ids
+    /// taken are using, in bytes. For the accounts, and the transaction cache if it's in memory,
+    /// this is what their maps have allocated, which is up to twice what's in them, as they grow
+    /// by doubling.
+    pub fn memory_usage(&self) -> usize {
+        self.storage.memory_usage()
+            + self.taken_ids.len() * core::mem::size_of::<(TransactionId, TakenId<A>)>()
+    }
+
+    /// Combines `other` into this state, as if this state had also processed everything that
+    /// `other` did, e.g. to put back together states built from disjoint partitions of the
+    /// clients. The two can't share any clients or transaction ids, since there's no way
+    /// to tell what processing them together would have done; if they do, nothing is changed.
+    /// Counters, latencies and the numbers of transactions processed are added together, each
+    /// hold due to expire keeps however many more transactions (or until the same time) it had
+    /// left, and this state's options are kept.
+    pub fn merge(&mut self, other: Self) -> Result<(), MergeError> {
+        if let Some((client_id, _)) = other
+            .storage
+            .accounts()
+            .find(|(client_id, _)| self.storage.account(*client_id).is_some())
+        {
+            return Err(MergeError::ClientInBoth(client_id));
+        }
+        if let Some(txn) = other
+            .storage
+            .transactions()
+            .iter()
+            .find(|txn| self.storage.transactions().contains(txn.transaction_id))
+        {
+            return Err(MergeError::TransactionInBoth(txn.transaction_id));
+        }
+        if let Some(&transaction_id) = other
+            .taken_ids
+            .keys()
+            .find(|transaction_id| self.taken_ids.contains_key(transaction_id))
+        {
+            return Err(MergeError::TransactionInBoth(transaction_id));
+        }
+
+        for (client_id, account) in other.storage.accounts() {
+            *self.storage.account_mut(client_id) = account.clone();
+        }
+        for txn in other.storage.transactions().iter() {
+            self.storage.transactions_mut().insert(txn);
+        }
+        self.last_transaction_id = self.last_transaction_id.max(other.last_transaction_id);
+        self.client_transaction_ids
+            .extend(other.client_transaction_ids);
+        self.invalid_transactions += other.invalid_transactions;
+        let processed = self.processed + other.processed;
+        for (state_processed, expiries) in [
+            (self.processed, self.expiries.iter().collect::<Vec<_>>()),
+            (other.processed, other.expiries.iter().collect()),
+        ] {
+            for (transaction_id, deadline) in expiries {
+                self.expiries.insert(
+                    transaction_id,
+                    processed + deadline.saturating_sub(state_processed),
+                );
+            }
+        }
+        self.processed = processed;
+        for (transaction_id, deadline) in other.timed_expiries.iter() {
+            self.timed_expiries.insert(transaction_id, deadline);
+        }
+        self.clock = self.clock.max(other.clock);
+        for (type_name, counts) in other.counters.transactions {
+            let total = self.counters.transactions.entry(type_name).or_default();
+            total.applied += counts.applied;
+            total.ignored += counts.ignored;
+            total.queued += counts.queued;
+        }
+        self.counters.accounts_created += other.counters.accounts_created;
+        self.queued.extend(other.queued);
+        self.taken_ids.extend(other.taken_ids);
+        for (type_name, histogram) in other.latencies {
+            self.latencies
+                .entry(type_name)
+                .or_default()
+                .merge(&histogram);
+        }
+
+        Ok(())
+    }
+
+    /// Running totals of what `process` has done so far.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// How long `process` has taken, by `TransactionInner::type_name`. Empty unless
+    /// `Options::track_latency` is set.
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+}
+
+// The parts that go through the input and output formats, which are all `FixedFloat`
+#[cfg(feature = "io")]
+impl State {
+    /// Processes every transaction from `source`, in order, stopping at the first that can't be
+    /// read or fails with a `ProcessError`.
+    pub fn process_all(&mut self, source: impl TransactionSource) -> Result<(), Box<dyn Error>> {
+        for result in source {
+            self.process(result?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the accounts to `sink`, ordered by client id.
+    pub fn write_to(&self, sink: &mut dyn AccountSink) -> Result<(), Box<dyn Error>> {
+        sink.write_accounts(&self.sorted_accounts())
+    }
+}
+
+/// A cheap overview of the state's size, for operators poking at a long-running process.
+#[derive(Debug)]
+pub struct Stats {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    pub closed_accounts: usize,
+    pub cached_transactions: usize,
+    pub disputed_transactions: usize,
+    pub invalid_transactions: usize,
+    /// See `State::memory_usage`.
+    pub memory_bytes: usize,
+}
+
+impl core::fmt::Display for Stats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "accounts={} locked_accounts={} closed_accounts={} cached_transactions={} \
+            disputed_transactions={} invalid_transactions={} memory_bytes={}",
+            self.accounts,
+            self.locked_accounts,
+            self.closed_accounts,
+            self.cached_transactions,
+            self.disputed_transactions,
+            self.invalid_transactions,
+            self.memory_bytes
+        )
+    }
+}
+
+// `HashMap` capacity is 7/8 of its buckets, each of which has a slot and a control byte
+fn map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * 8 / 7 * (core::mem::size_of::<(K, V)>() + 1)
+}
+
+/// Running totals of the transactions `State::process` has handled, for end-of-run reporting.
+/// Transactions that failed with a `ProcessError` in strict mode aren't counted.
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize)]
+pub struct Counters {
+    /// By `TransactionInner::type_name`. An applied dispute, resolve or chargeback is one that
+    /// opened, resolved or charged back a dispute.
+    pub transactions: BTreeMap<&'static str, TypeCounts>,
+    pub accounts_created: u64,
+}
+
+impl Counters {
+    fn count(&mut self, type_name: &'static str, outcome: Outcome) {
+        let counts = self.transactions.entry(type_name).or_default();
+        match outcome {
+            Outcome::Applied => counts.applied += 1,
+            Outcome::Ignored(_) => counts.ignored += 1,
+            Outcome::Queued => counts.queued += 1,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TypeCounts {
+    pub applied: u64,
+    pub ignored: u64,
+    /// Queued until their account is unlocked, and not yet processed. Once one is, it's counted
+    /// as applied or ignored instead.
+    pub queued: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AccountState<A: Amount = FixedFloat> {
+    available: A,
+    held: A,
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+}
+
+impl<A: Amount> AccountState<A> {
+    pub fn new(available: A, held: A, locked: bool) -> Self {
+        Self {
+            available,
+            held,
+            locked,
+            closed: false,
+        }
+    }
+
+    /// The same account, closed (or not).
+    pub fn with_closed(self, closed: bool) -> Self {
+        Self { closed, ..self }
+    }
+
+    /// Whether the account has nothing available or held, and isn't locked or closed.
+    pub fn is_empty(&self) -> bool {
+        self.available == A::default() && self.held == A::default() && !self.locked && !self.closed
+    }
+
+    pub fn available(&self) -> A {
+        self.available
+    }
+
+    pub fn held(&self) -> A {
+        self.held
+    }
+
+    pub fn total(&self) -> A {
+        self.available + self.held
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// What's owed to the client of a closed account: everything available, which includes
+    /// anything held when it closed whose dispute has since been resolved. Zero if it's open.
+    pub fn pending_payout(&self) -> A {
+        match self.closed {
+            true => self.available,
+            false => A::default(),
+        }
+    }
+}
+
+/// Why `State::merge` couldn't combine two states.
+#[derive(Debug)]
+pub enum MergeError {
+    ClientInBoth(ClientId),
+    TransactionInBoth(TransactionId),
+}
+
+impl core::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ClientInBoth(client_id) => {
+                write!(f, "Client id {} has an account in both states", client_id)
+            }
+            Self::TransactionInBoth(transaction_id) => {
+                write!(
+                    f,
+                    "Transaction id {} is taken in both states",
+                    transaction_id
+                )
+            }
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+#[derive(Debug)]
+pub enum ProcessError {
+    DisputedTransactionClientMissing(ClientId),
+    DisputeTargetInvalid(TransactionId),
+    DuplicateTransactionId(TransactionId),
+    NonMonotonicTransactionId {
+        previous: TransactionId,
+        current: TransactionId,
+    },
+    /// A deposit or withdrawal whose id isn't larger than the client's previous one, with
+    /// `Options::client_monotonic_ids`.
+    NonMonotonicClientTransactionId {
+        client_id: ClientId,
+        previous: TransactionId,
+        current: TransactionId,
+    },
+    /// A deposit, withdrawal, transfer or authorization of more than `Options::max_amount`.
+    AmountTooLarge(TransactionId),
+}
+
+impl core::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::DisputedTransactionClientMissing(client_id) => {
+                write!(
+                    f,
+                    "Disputed transaction refers to non-existent client id: {}",
+                    client_id
+                )
+            }
+            Self::DisputeTargetInvalid(transaction_id) => {
+                write!(
+                    f,
+                    "Dispute of non-disputable transaction id: {}",
+                    transaction_id
+                )
+            }
+            Self::DuplicateTransactionId(transaction_id) => {
+                write!(f, "Duplicate transaction id: {}", transaction_id)
+            }
+            Self::NonMonotonicTransactionId { previous, current } => {
+                write!(
+                    f,
+                    "Transaction id {} does not follow previous transaction id {}",
+                    current, previous
+                )
+            }
+            Self::NonMonotonicClientTransactionId {
+                client_id,
+                previous,
+                current,
+            } => {
+                write!(
+                    f,
+                    "Transaction id {} does not follow client {}'s previous deposit or withdrawal \
+                    id {}",
+                    current, client_id, previous
+                )
+            }
+            Self::AmountTooLarge(transaction_id) => {
+                write!(
+                    f,
+                    "Transaction id {} is for more than the maximum amount",
+                    transaction_id
+                )
+            }
+        }
+    }
+}
+
+impl Error for ProcessError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ClientId, ClosedAccounts, DuplicateIds, Event, IdOrdering, IgnoreReason, LockedAccountMode,
+        LockedAccounts, MergeError, Options, Outcome, ProcessError, State, TransactionId,
+        TransactionInner, TypeCounts, WithdrawalDisputes,
+    };
+    #[cfg(feature = "io")]
+    use crate::output::WriterSink;
+    use crate::{
+        state::AccountState,
+        types::{Transaction, TransactionState},
+    };
+    use std::collections::HashMap;
+
+    fn build_state(txns: &[Transaction]) -> Result<State, ProcessError> {
+        let mut state = State::default();
+
+        for txn in txns {
+            state.process(txn.clone())?;
+        }
+
+        Ok(state)
+    }
+
+    #[test]
+    fn test_basic_example() {
+        let state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(2),
+                ClientId(2),
+                TransactionInner::Deposit(2.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(3),
+                ClientId(1),
+                TransactionInner::Deposit(2.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(4),
+                ClientId(1),
+                TransactionInner::Withdrawal(1.5.into()),
+            ),
+            Transaction::new(
+                TransactionId(5),
+                ClientId(2),
+                TransactionInner::Withdrawal(3.0.into()),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([
+                (
+                    ClientId(1),
+                    AccountState {
+                        available: 1.5.into(),
+                        held: 0.0.into(),
+                        locked: false,
+                        closed: false,
+                    }
+                ),
+                (
+                    ClientId(2),
+                    AccountState {
+                        available: 2.0.into(),
+                        held: 0.0.into(),
+                        locked: false,
+                        closed: false,
+                    }
+                )
+            ],)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_write_sorted() {
+        let state = build_state(&[300, 1, 20].map(|client_id| {
+            Transaction::new(
+                TransactionId(client_id.into()),
+                ClientId(client_id),
+                TransactionInner::Deposit(1.0.into()),
+            )
+        }))
+        .unwrap();
+
+        let mut output = Vec::new();
+        state
+            .write_to(&mut WriterSink::new(&mut output, Default::default()))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+            1,1,0,1,false\n\
+            20,1,0,1,false\n\
+            300,1,0,1,false\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_withdrawal() {
+        let state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(2),
+                ClientId(1),
+                TransactionInner::Withdrawal(2.0.into()),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 1.0.into(),
+                    held: 0.0.into(),
+                    locked: false,
+                    closed: false,
+                }
+            )])
+        );
+    }
+
+    #[test]
+    fn test_dispute_deposit() {
+        let state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ),
+            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 0.0.into(),
+                    held: 1.0.into(),
+                    locked: false,
+                    closed: false,
+                }
+            )])
+        );
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(5.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(2),
+                ClientId(1),
+                TransactionInner::Withdrawal(3.0.into()),
+            ),
+            Transaction::new(TransactionId(2), ClientId(1), TransactionInner::Dispute),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 5.0.into(),
+                    held: (-3.0).into(),
+                    locked: false,
+                    closed: false,
+                }
+            )])
+        );
+
+        assert_eq!(
+            state.transaction(TransactionId(2)).unwrap().state,
+            TransactionState::Disputed
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            ),
+            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
+            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Resolve),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 1.0.into(),
+                    held: 0.0.into(),
+                    locked: false,
+                    closed: false,
+                }
+            )])
+        );
+
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::Alive
+        );
+
+        // Try disputing/resolving again
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Dispute,
+            ))
+            .unwrap();
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Resolve,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 1.0.into(),
+                    held: 0.0.into(),
+                    locked: false,
+                    closed: false,
+                }
+            )])
+        );
+
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::Alive
+        );
+    }
+
+    #[test]
+    fn test_chargeback() {
+        let mut state = build_state(&[
+            Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Deposit(123.0.into()),
+            ),
+            Transaction::new(
+                TransactionId(2),
+                ClientId(1),
+                TransactionInner::Deposit(456.0.into()),
+            ),
+            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
+            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Chargeback),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
+                ClientId(1),
+                AccountState {
+                    available: 456.0.into(),
+                    held: 0.0.into(),
+                    locked: true,
+                    closed: false,
+                }
+            )])
+        );
+
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::ChargedBack
+        );
+
+        // Try disputing again; this shouldn't return an error, but the transaction
+        // will stay in the ChargedBack state with no further changes.
+        state
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Dispute,
+            ))
+            .unwrap();
+
+        assert_eq!(
+            state
+                .accounts()
+                .map(|(client_id, account)| (client_id, account.clone()))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from_iter([(
                 ClientId(1),
-                TransactionInner::Deposit(2.0.into()),
-            ),
+                AccountState {
+                    available: 456.0.into(),
+                    held: 0.0.into(),
+                    locked: true,
+                    closed: false,
+                }
+            )])
+        );
+
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::ChargedBack
+        );
+    }
+
+    #[test]
+    fn test_transfer() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let transfer = |amount: f64| TransactionInner::Transfer {
+            to: ClientId(2),
+            amount: amount.into(),
+        };
+        let balances = |state: &State, client_id| {
+            let account = state.account(ClientId(client_id)).unwrap();
+            (account.available(), account.held(), account.locked())
+        };
+        let mut state = State::default();
+        state
+            .process(txn(1, 1, TransactionInner::Deposit(5.0.into())))
+            .unwrap();
+
+        // Opens the recipient's account, and moves the funds in one go
+        assert_eq!(
+            state.process(txn(2, 1, transfer(3.0))).unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(state.events().len(), 3);
+        assert_eq!(balances(&state, 1), (2.0.into(), 0.0.into(), false));
+        assert_eq!(balances(&state, 2), (3.0.into(), 0.0.into(), false));
+
+        // Too much is neither debited nor credited
+        assert_eq!(
+            state.process(txn(3, 1, transfer(2.5))).unwrap(),
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert!(state.transaction(TransactionId(3)).is_none());
+        assert_eq!(balances(&state, 2), (3.0.into(), 0.0.into(), false));
+
+        // A dispute holds the funds at the recipient's end, and a chargeback sends them back
+        for inner in [TransactionInner::Dispute, TransactionInner::Chargeback] {
+            assert_eq!(state.process(txn(2, 1, inner)).unwrap(), Outcome::Applied);
+        }
+        assert_eq!(balances(&state, 1), (5.0.into(), 0.0.into(), false));
+        assert_eq!(balances(&state, 2), (0.0.into(), 0.0.into(), true));
+        assert!(state.verify_invariants().is_empty());
+
+        // The locked recipient can't send
+        let back = TransactionInner::Transfer {
+            to: ClientId(1),
+            amount: 0.0.into(),
+        };
+        assert_eq!(
+            state.process(txn(4, 2, back)).unwrap(),
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn test_adjustment() {
+        let adjustment = |transaction_id, amount: f64| {
             Transaction::new(
-                TransactionId(4),
+                TransactionId(transaction_id),
                 ClientId(1),
-                TransactionInner::Withdrawal(1.5.into()),
+                TransactionInner::Adjustment {
+                    amount: amount.into(),
+                    reference: "OPS-1".into(),
+                },
+            )
+        };
+        let mut state: State =
+            State::from_accounts([(ClientId(1), AccountState::new(1.0.into(), 0.0.into(), true))]);
+
+        // Both ways, despite the lock, and into the negative
+        for (transaction_id, amount) in [(1, 2.5), (2, -4.0)] {
+            assert_eq!(
+                state.process(adjustment(transaction_id, amount)).unwrap(),
+                Outcome::Applied
+            );
+        }
+        assert_eq!(
+            state.events(),
+            [Event::AdjustmentApplied {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: (-4.0).into(),
+                reference: "OPS-1".into(),
+            }]
+        );
+        assert_eq!(
+            state.account(ClientId(1)).unwrap().available(),
+            (-0.5).into()
+        );
+
+        // Not cached, so there's nothing to dispute
+        assert!(state.transaction(TransactionId(1)).is_none());
+    }
+
+    #[test]
+    fn test_unlock() {
+        let txn = |inner| Transaction::new(TransactionId(1), ClientId(1), inner);
+        let mut state = State::default();
+        for inner in [
+            TransactionInner::Deposit(2.0.into()),
+            TransactionInner::Dispute,
+            TransactionInner::Chargeback,
+        ] {
+            state.process(txn(inner)).unwrap();
+        }
+        assert!(state.account(ClientId(1)).unwrap().locked());
+
+        assert_eq!(
+            state.process(txn(TransactionInner::Unlock)).unwrap(),
+            Outcome::Applied
+        );
+        assert!(!state.account(ClientId(1)).unwrap().locked());
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::Unlocked
+        );
+        assert!(state.verify_invariants().is_empty());
+
+        // Only once, and it's still not disputable
+        assert_eq!(
+            state.process(txn(TransactionInner::Unlock)).unwrap(),
+            Outcome::Ignored(IgnoreReason::NotChargedBack)
+        );
+        assert_eq!(
+            state.process(txn(TransactionInner::Dispute)).unwrap(),
+            Outcome::Ignored(IgnoreReason::ChargedBack)
+        );
+    }
+
+    #[test]
+    fn test_refund() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state = State::default();
+        for transaction in [
+            txn(1, TransactionInner::Deposit(5.0.into())),
+            txn(2, TransactionInner::Withdrawal(2.0.into())),
+            txn(3, TransactionInner::Deposit(1.0.into())),
+            txn(3, TransactionInner::Dispute),
+        ] {
+            state.process(transaction).unwrap();
+        }
+
+        // Refunding the withdrawal puts its funds back, and refunding the deposit takes them out
+        for (transaction_id, amount) in [(2, 2.0), (1, -5.0)] {
+            assert_eq!(
+                state
+                    .process(txn(transaction_id, TransactionInner::Refund))
+                    .unwrap(),
+                Outcome::Applied
+            );
+            assert_eq!(
+                state.events(),
+                [Event::TransactionReversed {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(transaction_id),
+                    amount: amount.into(),
+                }]
+            );
+        }
+        let account = state.account(ClientId(1)).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (0.0.into(), 1.0.into())
+        );
+        assert!(!account.locked());
+        assert!(state.verify_invariants().is_empty());
+
+        // Neither can be refunded or disputed again, and a disputed transaction can't be refunded
+        for (transaction_id, inner, reason) in [
+            (1, TransactionInner::Refund, IgnoreReason::AlreadyReversed),
+            (2, TransactionInner::Dispute, IgnoreReason::AlreadyReversed),
+            (3, TransactionInner::Refund, IgnoreReason::AlreadyDisputed),
+        ] {
+            assert_eq!(
+                state.process(txn(transaction_id, inner)).unwrap(),
+                Outcome::Ignored(reason)
+            );
+        }
+
+        // A deposit whose funds have gone can't be refunded
+        for transaction in [
+            txn(3, TransactionInner::Resolve),
+            txn(4, TransactionInner::Deposit(1.0.into())),
+            txn(5, TransactionInner::Withdrawal(2.0.into())),
+        ] {
+            state.process(transaction).unwrap();
+        }
+        assert_eq!(
+            state.process(txn(4, TransactionInner::Refund)).unwrap(),
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            state.transaction(TransactionId(4)).unwrap().state,
+            TransactionState::Alive
+        );
+    }
+
+    #[test]
+    fn test_representment() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let charged_back = [
+            txn(1, 1, TransactionInner::Deposit(5.0.into())),
+            txn(
+                2,
+                1,
+                TransactionInner::Transfer {
+                    to: ClientId(2),
+                    amount: 1.0.into(),
+                },
             ),
-            Transaction::new(
-                TransactionId(5),
-                ClientId(2),
-                TransactionInner::Withdrawal(3.0.into()),
+            txn(1, 1, TransactionInner::Dispute),
+            txn(1, 1, TransactionInner::Chargeback),
+            txn(2, 1, TransactionInner::Dispute),
+            txn(2, 1, TransactionInner::Chargeback),
+        ];
+
+        for representment_unlocks in [false, true] {
+            let mut state = State::with_options(Options {
+                representment_unlocks,
+                ..Default::default()
+            });
+            for transaction in charged_back.clone() {
+                state.process(transaction).unwrap();
+            }
+
+            // The chargeback's funds come back, and the lock only goes if asked to
+            assert_eq!(
+                state
+                    .process(txn(1, 1, TransactionInner::Representment))
+                    .unwrap(),
+                Outcome::Applied
+            );
+            let mut expected = vec![Event::ChargebackReversed {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: 5.0.into(),
+            }];
+            if representment_unlocks {
+                expected.push(Event::AccountUnlocked {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(1),
+                });
+            }
+            assert_eq!(state.events(), expected);
+            let account = state.account(ClientId(1)).unwrap();
+            assert_eq!(account.available(), 5.0.into());
+            assert_eq!(account.locked(), !representment_unlocks);
+            assert!(state.verify_invariants().is_empty());
+
+            // It's final, though a lock it left can still be lifted
+            for (transaction_id, inner, outcome) in [
+                (
+                    1,
+                    TransactionInner::Representment,
+                    Outcome::Ignored(IgnoreReason::NotChargedBack),
+                ),
+                (
+                    1,
+                    TransactionInner::Dispute,
+                    Outcome::Ignored(IgnoreReason::ChargedBack),
+                ),
+                (
+                    2,
+                    TransactionInner::Representment,
+                    Outcome::Ignored(IgnoreReason::NotRepresentable),
+                ),
+            ] {
+                assert_eq!(
+                    state.process(txn(transaction_id, 1, inner)).unwrap(),
+                    outcome
+                );
+            }
+            assert_eq!(
+                state.process(txn(1, 1, TransactionInner::Unlock)).unwrap(),
+                if representment_unlocks {
+                    Outcome::Ignored(IgnoreReason::NotChargedBack)
+                } else {
+                    Outcome::Applied
+                }
+            );
+            assert_eq!(
+                state.transaction(TransactionId(1)).unwrap().state,
+                TransactionState::Represented
+            );
+            assert!(!state.account(ClientId(1)).unwrap().locked());
+        }
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let txn = |inner| Transaction::new(TransactionId(1), ClientId(1), inner);
+        let balances = |state: &State| {
+            let account = state.account(ClientId(1)).unwrap();
+            (account.available(), account.held())
+        };
+        let mut state = State::default();
+        state
+            .process(txn(TransactionInner::Deposit(5.0.into())))
+            .unwrap();
+
+        // More than the transaction, or nothing, can't be disputed
+        for portion in [6.0, 0.0, -1.0] {
+            assert_eq!(
+                state
+                    .process(txn(TransactionInner::PartialDispute(portion.into())))
+                    .unwrap(),
+                Outcome::Ignored(IgnoreReason::InvalidDisputeAmount)
+            );
+        }
+
+        // Only the disputed part is held, and a resolve releases just that
+        state
+            .process(txn(TransactionInner::PartialDispute(2.0.into())))
+            .unwrap();
+        assert_eq!(
+            state.events(),
+            [Event::DisputeOpened {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: 2.0.into(),
+                portion: Some(2.0.into()),
+                reason: None,
+            }]
+        );
+        assert_eq!(balances(&state), (3.0.into(), 2.0.into()));
+        assert!(state.verify_invariants().is_empty());
+        state.process(txn(TransactionInner::Resolve)).unwrap();
+        assert_eq!(balances(&state), (5.0.into(), 0.0.into()));
+        assert_eq!(state.transaction(TransactionId(1)).unwrap().disputed, None);
+
+        // A chargeback takes just the disputed part, and a representment brings just that back
+        for inner in [
+            TransactionInner::PartialDispute(2.0.into()),
+            TransactionInner::Chargeback,
+        ] {
+            state.process(txn(inner)).unwrap();
+        }
+        assert_eq!(balances(&state), (3.0.into(), 0.0.into()));
+        assert!(state.verify_invariants().is_empty());
+        state.process(txn(TransactionInner::Representment)).unwrap();
+        assert_eq!(balances(&state), (5.0.into(), 0.0.into()));
+    }
+
+    #[test]
+    fn test_max_disputes() {
+        let txn = |inner| Transaction::new(TransactionId(1), ClientId(1), inner);
+        let mut state: State = State::with_options(Options {
+            max_disputes: Some(2),
+            ..Default::default()
+        });
+        state
+            .process(txn(TransactionInner::Deposit(5.0.into())))
+            .unwrap();
+
+        // Resolved disputes count, partial or not
+        for inner in [
+            TransactionInner::Dispute,
+            TransactionInner::Resolve,
+            TransactionInner::PartialDispute(1.0.into()),
+            TransactionInner::Resolve,
+        ] {
+            assert_eq!(state.process(txn(inner)).unwrap(), Outcome::Applied);
+        }
+        assert_eq!(state.transaction(TransactionId(1)).unwrap().disputes, 2);
+        assert_eq!(
+            state.process(txn(TransactionInner::Dispute)).unwrap(),
+            Outcome::Ignored(IgnoreReason::TooManyDisputes)
+        );
+        assert_eq!(state.account(ClientId(1)).unwrap().available(), 5.0.into());
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let txn = |transaction_id, inner, timestamp| Transaction {
+            timestamp,
+            ..Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state: State = State::with_options(Options {
+            dispute_window_seconds: Some(100),
+            ..Default::default()
+        });
+        for transaction_id in 1..=4 {
+            state
+                .process(txn(
+                    transaction_id,
+                    TransactionInner::Deposit(1.0.into()),
+                    Some(1_000),
+                ))
+                .unwrap();
+        }
+
+        for (transaction_id, timestamp, outcome) in [
+            // Inside the window, and at its very end
+            (1, Some(1_050), Outcome::Applied),
+            (2, Some(1_100), Outcome::Applied),
+            // Past it
+            (
+                3,
+                Some(1_101),
+                Outcome::Ignored(IgnoreReason::DisputeWindowExpired),
             ),
-        ])
-        .unwrap();
+            // Without a timestamp, there's nothing to check
+            (4, None, Outcome::Applied),
+        ] {
+            assert_eq!(
+                state
+                    .process(txn(transaction_id, TransactionInner::Dispute, timestamp))
+                    .unwrap(),
+                outcome
+            );
+        }
+        assert_eq!(state.account(ClientId(1)).unwrap().held(), 3.0.into());
+        // A partial dispute is just as late
+        assert_eq!(
+            state
+                .process(txn(
+                    3,
+                    TransactionInner::PartialDispute(0.5.into()),
+                    Some(2_000)
+                ))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::DisputeWindowExpired)
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_disputes() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for (withdrawal_disputes, disputed, charged_back) in [
+            (WithdrawalDisputes::Reject, None, None),
+            (
+                WithdrawalDisputes::TreatAsNegative,
+                Some((10.0, -4.0)),
+                Some((10.0, 0.0)),
+            ),
+            (
+                WithdrawalDisputes::HoldPositive,
+                Some((2.0, 4.0)),
+                Some((2.0, 0.0)),
+            ),
+        ] {
+            let mut state: State = State::with_options(Options {
+                withdrawal_disputes,
+                ..Default::default()
+            });
+            for transaction in [
+                txn(1, TransactionInner::Deposit(10.0.into())),
+                txn(2, TransactionInner::Withdrawal(4.0.into())),
+            ] {
+                state.process(transaction).unwrap();
+            }
+            let balances = |state: &State| {
+                let account = state.account(ClientId(1)).unwrap();
+                (account.available(), account.held())
+            };
+
+            let outcome = state.process(txn(2, TransactionInner::Dispute)).unwrap();
+            match disputed {
+                Some((available, held)) => {
+                    assert_eq!(outcome, Outcome::Applied);
+                    assert_eq!(balances(&state), (available.into(), held.into()));
+                    assert!(state.verify_invariants().is_empty());
+                }
+                None => {
+                    assert_eq!(outcome, Outcome::Ignored(IgnoreReason::NotDisputable));
+                    assert_eq!(balances(&state), (6.0.into(), 0.0.into()));
+                }
+            }
+
+            state.process(txn(2, TransactionInner::Chargeback)).unwrap();
+            if let Some((available, held)) = charged_back {
+                assert_eq!(balances(&state), (available.into(), held.into()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_require_client_match() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        for require_client_match in [false, true] {
+            let mut state: State = State::with_options(Options {
+                require_client_match,
+                ..Default::default()
+            });
+            for transaction in [
+                txn(1, 1, TransactionInner::Deposit(5.0.into())),
+                txn(
+                    2,
+                    1,
+                    TransactionInner::Transfer {
+                        to: ClientId(2),
+                        amount: 1.0.into(),
+                    },
+                ),
+            ] {
+                state.process(transaction).unwrap();
+            }
+
+            // Another client's deposit only without the option, but either end of a transfer
+            let mismatch = if require_client_match {
+                Outcome::Ignored(IgnoreReason::ClientMismatch)
+            } else {
+                Outcome::Applied
+            };
+            assert_eq!(
+                state.process(txn(1, 2, TransactionInner::Dispute)).unwrap(),
+                mismatch
+            );
+            assert_eq!(
+                state.process(txn(2, 2, TransactionInner::Dispute)).unwrap(),
+                Outcome::Applied
+            );
+            assert_eq!(
+                state.process(txn(2, 1, TransactionInner::Resolve)).unwrap(),
+                Outcome::Applied
+            );
+            assert_eq!(
+                state.process(txn(2, 3, TransactionInner::Dispute)).unwrap(),
+                mismatch
+            );
+            assert_eq!(
+                state.account(ClientId(1)).unwrap().held(),
+                if require_client_match { 0.0 } else { 5.0 }.into()
+            );
+        }
+    }
+
+    #[test]
+    fn test_locked_accounts() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state: State = State::with_options(Options {
+            locked_accounts: "deposit=reject,withdrawal=queue,transfer=allow"
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        });
+        assert_eq!(
+            state.options().locked_accounts,
+            LockedAccounts {
+                deposits: LockedAccountMode::Reject,
+                withdrawals: LockedAccountMode::Queue,
+                transfers: LockedAccountMode::Allow,
+                authorizations: LockedAccountMode::Reject,
+            }
+        );
+        for transaction in [
+            txn(1, TransactionInner::Deposit(10.0.into())),
+            txn(2, TransactionInner::Deposit(3.0.into())),
+            txn(2, TransactionInner::Dispute),
+            txn(2, TransactionInner::Chargeback),
+        ] {
+            state.process(transaction).unwrap();
+        }
+
+        assert_eq!(
+            state
+                .process(txn(3, TransactionInner::Deposit(5.0.into())))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert!(matches!(state.events(), [Event::DepositBlocked { .. }]));
+        for (transaction_id, amount) in [(4, 4.0), (5, 9.0)] {
+            assert_eq!(
+                state
+                    .process(txn(
+                        transaction_id,
+                        TransactionInner::Withdrawal(amount.into())
+                    ))
+                    .unwrap(),
+                Outcome::Queued
+            );
+        }
+        let transfer = TransactionInner::Transfer {
+            to: ClientId(2),
+            amount: 1.0.into(),
+        };
+        assert_eq!(state.process(txn(6, transfer)).unwrap(), Outcome::Applied);
+        assert_eq!(
+            state
+                .process(txn(7, TransactionInner::Authorize(1.0.into())))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+        assert_eq!(state.queued().count(), 2);
+        assert_eq!(state.account(ClientId(1)).unwrap().available(), 9.0.into());
+        // Its id is taken while it waits
+        assert_eq!(
+            state
+                .process(txn(4, TransactionInner::Deposit(1.0.into())))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::DuplicateTransactionId)
+        );
+
+        // Unlocking processes the queue in order, as part of the unlock
+        assert_eq!(
+            state.process(txn(2, TransactionInner::Unlock)).unwrap(),
+            Outcome::Applied
+        );
+        assert!(matches!(
+            state.events(),
+            [
+                Event::AccountUnlocked { .. },
+                Event::FundsWithdrawn { .. },
+                Event::WithdrawalDeclined { .. },
+            ]
+        ));
+        assert_eq!(state.queued().count(), 0);
+        assert_eq!(state.account(ClientId(1)).unwrap().available(), 5.0.into());
+        assert_eq!(
+            state.counters().transactions["withdrawal"],
+            TypeCounts {
+                applied: 1,
+                ignored: 1,
+                queued: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_max_amount() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for strict in [false, true] {
+            let mut state: State = State::with_options(Options {
+                strict,
+                max_amount: Some(100.0.into()),
+                ..Default::default()
+            });
+            assert_eq!(
+                state
+                    .process(txn(1, TransactionInner::Deposit(100.0.into())))
+                    .unwrap(),
+                Outcome::Applied
+            );
+            let result = state.process(txn(2, TransactionInner::Withdrawal(100.0001.into())));
+            if strict {
+                assert!(matches!(
+                    result,
+                    Err(ProcessError::AmountTooLarge(TransactionId(2)))
+                ));
+            } else {
+                assert_eq!(
+                    result.unwrap(),
+                    Outcome::Ignored(IgnoreReason::AmountTooLarge)
+                );
+                assert_eq!(state.stats().invalid_transactions, 1);
+            }
+            // Adjustments aren't limited
+            let adjustment = TransactionInner::Adjustment {
+                amount: 1000.0.into(),
+                reference: "T-1".into(),
+            };
+            assert_eq!(state.process(txn(3, adjustment)).unwrap(), Outcome::Applied);
+            assert_eq!(
+                state.account(ClientId(1)).unwrap().available(),
+                1100.0.into()
+            );
+        }
+    }
+
+    #[test]
+    fn test_declined_withdrawal() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for withdrawal_disputes in [
+            WithdrawalDisputes::TreatAsNegative,
+            WithdrawalDisputes::HoldPositive,
+        ] {
+            let mut state: State = State::with_options(Options {
+                withdrawal_disputes,
+                ..Default::default()
+            });
+            for transaction in [
+                txn(1, TransactionInner::Deposit(1.0.into())),
+                txn(2, TransactionInner::Withdrawal(5.0.into())),
+            ] {
+                state.process(transaction).unwrap();
+            }
+            assert_eq!(
+                state.transaction(TransactionId(2)).unwrap().state,
+                TransactionState::Declined
+            );
+
+            // It took nothing, so there's nothing to put back
+            for (inner, reason) in [
+                (TransactionInner::Dispute, IgnoreReason::NotDisputable),
+                (TransactionInner::Refund, IgnoreReason::NotRefundable),
+                (TransactionInner::Chargeback, IgnoreReason::NotDisputed),
+            ] {
+                assert_eq!(
+                    state.process(txn(2, inner)).unwrap(),
+                    Outcome::Ignored(reason)
+                );
+            }
+            let account = state.account(ClientId(1)).unwrap();
+            assert_eq!((account.available, account.held), (1.0.into(), 0.0.into()));
+            assert!(state.verify_invariants().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_dispute_reasons() {
+        let txn = |transaction_id, inner, reason: Option<&str>| Transaction {
+            reason: reason.map(Into::into),
+            ..Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state = State::default();
+        let mut reason_after = |transaction: Transaction| {
+            let transaction_id = transaction.transaction_id;
+            state.process(transaction).unwrap();
+            state.transaction(transaction_id).unwrap().reason.clone()
+        };
+
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Deposit(5.0.into()), None)),
+            None
+        );
+        reason_after(txn(2, TransactionInner::Deposit(1.0.into()), None));
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Dispute, Some("10.4"))),
+            Some("10.4".into())
+        );
+        // Kept once the dispute's over, and not changed by one that's ignored
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Dispute, Some("99"))),
+            Some("10.4".into())
+        );
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Resolve, None)),
+            Some("10.4".into())
+        );
+        // The latest dispute's, even if it gave none
+        assert_eq!(reason_after(txn(1, TransactionInner::Dispute, None)), None);
+        assert_eq!(reason_after(txn(1, TransactionInner::Resolve, None)), None);
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Dispute, Some("13.1"))),
+            Some("13.1".into())
+        );
+        // A chargeback only replaces it with one of its own
+        assert_eq!(
+            reason_after(txn(1, TransactionInner::Chargeback, None)),
+            Some("13.1".into())
+        );
+
+        reason_after(txn(2, TransactionInner::Dispute, None));
+        assert_eq!(
+            reason_after(txn(2, TransactionInner::Chargeback, Some("4837"))),
+            Some("4837".into())
+        );
+    }
+
+    #[test]
+    fn test_authorize() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state = State::default();
+        for transaction in [
+            txn(1, TransactionInner::Deposit(5.0.into())),
+            txn(2, TransactionInner::Authorize(2.0.into())),
+            txn(3, TransactionInner::Authorize(1.0.into())),
+        ] {
+            assert_eq!(state.process(transaction).unwrap(), Outcome::Applied);
+        }
+        let balances = |state: &State| {
+            let account = state.account(ClientId(1)).unwrap();
+            (account.available(), account.held())
+        };
+        assert_eq!(balances(&state), (2.0.into(), 3.0.into()));
+        assert!(state.verify_invariants().is_empty());
+
+        // More than is available is declined
+        assert_eq!(
+            state
+                .process(txn(4, TransactionInner::Authorize(3.0.into())))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+
+        // Capturing one takes its funds for good, and voiding the other releases them
+        for (transaction_id, inner) in [(2, TransactionInner::Capture), (3, TransactionInner::Void)]
+        {
+            assert_eq!(
+                state.process(txn(transaction_id, inner)).unwrap(),
+                Outcome::Applied
+            );
+        }
+        assert_eq!(balances(&state), (3.0.into(), 0.0.into()));
+        assert!(matches!(
+            state.transaction(TransactionId(2)).unwrap().inner,
+            TransactionInner::Withdrawal(amount) if amount == 2.0.into()
+        ));
+        assert!(state.verify_invariants().is_empty());
+
+        // Neither can happen twice, nor to something that isn't an authorization
+        for (transaction_id, inner) in [
+            (2, TransactionInner::Void),
+            (3, TransactionInner::Capture),
+            (1, TransactionInner::Capture),
+        ] {
+            assert_eq!(
+                state.process(txn(transaction_id, inner)).unwrap(),
+                Outcome::Ignored(IgnoreReason::NotAuthorized)
+            );
+        }
+
+        // A pending authorization can't be disputed, but once captured, it's a withdrawal, which
+        // can
+        state
+            .process(txn(5, TransactionInner::Authorize(1.0.into())))
+            .unwrap();
+        for (transaction_id, outcome) in [
+            (5, Outcome::Ignored(IgnoreReason::NotDisputable)),
+            (2, Outcome::Applied),
+        ] {
+            assert_eq!(
+                state
+                    .process(txn(transaction_id, TransactionInner::Dispute))
+                    .unwrap(),
+                outcome
+            );
+        }
+        assert_eq!(
+            state.transaction(TransactionId(2)).unwrap().state,
+            TransactionState::Disputed
+        );
+    }
+
+    #[test]
+    fn test_hold_expiry() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state = State::with_options(Options {
+            authorization_expiry: Some(2),
+            dispute_expiry: Some(1),
+            ..Default::default()
+        });
+        for transaction in [
+            txn(1, TransactionInner::Deposit(5.0.into())),
+            txn(2, TransactionInner::Authorize(2.0.into())),
+            txn(3, TransactionInner::Authorize(1.0.into())),
+            txn(3, TransactionInner::Capture),
+        ] {
+            state.process(transaction).unwrap();
+            assert!(state
+                .events()
+                .iter()
+                .all(|event| !matches!(event, Event::AuthorizationExpired { .. })));
+        }
+
+        // Two transactions later, the authorization that wasn't captured is voided before the
+        // next one is processed, so that it can use the funds; the captured one is left alone
+        assert_eq!(
+            state
+                .process(txn(4, TransactionInner::Withdrawal(4.0.into())))
+                .unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(
+            state.events()[0],
+            Event::AuthorizationExpired {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: 2.0.into(),
+            }
+        );
+        assert_eq!(
+            state.process(txn(2, TransactionInner::Capture)).unwrap(),
+            Outcome::Ignored(IgnoreReason::NotAuthorized)
+        );
+
+        // A dispute is resolved likewise, one transaction later
+        for transaction in [
+            txn(5, TransactionInner::Deposit(2.0.into())),
+            txn(5, TransactionInner::Dispute),
+            txn(6, TransactionInner::Deposit(1.0.into())),
+            txn(7, TransactionInner::Deposit(1.0.into())),
+        ] {
+            state.process(transaction).unwrap();
+        }
+        assert_eq!(
+            state.events()[0],
+            Event::DisputeExpired {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(5),
+                amount: 2.0.into(),
+            }
+        );
+        let account = state.account(ClientId(1)).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (4.0.into(), 0.0.into())
+        );
+        assert!(state.verify_invariants().is_empty());
+    }
+
+    #[test]
+    fn test_timed_hold_expiry() {
+        let txn = |transaction_id, inner, timestamp| Transaction {
+            timestamp,
+            ..Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let expired = |state: &State| {
+            state
+                .events()
+                .iter()
+                .filter_map(|event| match event {
+                    Event::AuthorizationExpired { transaction_id, .. }
+                    | Event::DisputeExpired { transaction_id, .. } => Some(transaction_id.0),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut state = State::with_options(Options {
+            authorization_expiry: Some(3),
+            authorization_expiry_seconds: Some(60),
+            dispute_expiry_seconds: Some(10),
+            ..Default::default()
+        });
+        for transaction in [
+            txn(1, TransactionInner::Deposit(5.0.into()), Some(1_000)),
+            txn(2, TransactionInner::Authorize(1.0.into()), Some(1_000)),
+            // Without a timestamp, so it falls back to expiring after 3 more transactions
+            txn(3, TransactionInner::Authorize(1.0.into()), None),
+            // Exactly the window later, so it's still in time
+            txn(4, TransactionInner::Deposit(1.0.into()), Some(1_060)),
+        ] {
+            state.process(transaction).unwrap();
+            assert!(expired(&state).is_empty());
+        }
+
+        // Rows without a timestamp don't move the time on, however many there are, but do count
+        for (transaction_id, timestamp, ids) in [
+            (5, None, &[][..]),
+            (6, None, &[]),
+            (7, None, &[3]),
+            (8, Some(1_061), &[2]),
+        ] {
+            state
+                .process(txn(
+                    transaction_id,
+                    TransactionInner::Deposit(1.0.into()),
+                    timestamp,
+                ))
+                .unwrap();
+            assert_eq!(expired(&state), ids);
+        }
+
+        // A dispute goes by its own timestamp, not the disputed transaction's
+        for transaction in [
+            txn(1, TransactionInner::Dispute, Some(1_100)),
+            txn(9, TransactionInner::Deposit(1.0.into()), Some(1_110)),
+        ] {
+            state.process(transaction).unwrap();
+            assert!(expired(&state).is_empty());
+        }
+        state
+            .process(txn(10, TransactionInner::Deposit(1.0.into()), Some(1_111)))
+            .unwrap();
+        assert_eq!(expired(&state), [1]);
+
+        let account = state.account(ClientId(1)).unwrap();
+        assert_eq!(
+            (account.available(), account.held()),
+            (12.0.into(), 0.0.into())
+        );
+        assert!(state.verify_invariants().is_empty());
+    }
 
+    #[test]
+    fn test_close() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let closed = Outcome::Ignored(IgnoreReason::AccountClosed);
+
+        for (closed_accounts, deposit, withdrawal) in [
+            (ClosedAccounts::Reject, closed, closed),
+            (ClosedAccounts::AllowDeposits, Outcome::Applied, closed),
+            (ClosedAccounts::Allow, Outcome::Applied, Outcome::Applied),
+        ] {
+            let mut state = State::with_options(Options {
+                closed_accounts,
+                ..Default::default()
+            });
+            for transaction in [
+                txn(1, 1, TransactionInner::Deposit(3.0.into())),
+                txn(2, 1, TransactionInner::Deposit(1.0.into())),
+                txn(2, 1, TransactionInner::Dispute),
+                txn(3, 1, TransactionInner::Close),
+            ] {
+                state.process(transaction).unwrap();
+            }
+            // What's held isn't owed yet
+            assert_eq!(
+                state.events(),
+                [Event::AccountClosed {
+                    client_id: ClientId(1),
+                    transaction_id: TransactionId(3),
+                    payout: 3.0.into(),
+                }]
+            );
+
+            assert_eq!(
+                state
+                    .process(txn(4, 1, TransactionInner::Deposit(1.0.into())))
+                    .unwrap(),
+                deposit
+            );
+            assert_eq!(
+                state
+                    .process(txn(5, 1, TransactionInner::Withdrawal(1.0.into())))
+                    .unwrap(),
+                withdrawal
+            );
+            // Transfers in count as deposits
+            state
+                .process(txn(6, 2, TransactionInner::Deposit(1.0.into())))
+                .unwrap();
+            assert_eq!(
+                state
+                    .process(txn(
+                        7,
+                        2,
+                        TransactionInner::Transfer {
+                            to: ClientId(1),
+                            amount: 1.0.into()
+                        }
+                    ))
+                    .unwrap(),
+                deposit
+            );
+        }
+
+        let mut state = State::default();
+        for transaction in [
+            txn(1, 1, TransactionInner::Deposit(3.0.into())),
+            txn(2, 1, TransactionInner::Deposit(1.0.into())),
+            txn(2, 1, TransactionInner::Dispute),
+            txn(3, 1, TransactionInner::Close),
+            // Disputes go on, and releasing held funds adds them to the payout
+            txn(2, 1, TransactionInner::Resolve),
+        ] {
+            state.process(transaction).unwrap();
+        }
+        let account = state.account(ClientId(1)).unwrap();
+        assert!(account.closed() && !account.is_empty());
+        assert_eq!(account.pending_payout(), 4.0.into());
+        assert_eq!(state.stats().closed_accounts, 1);
         assert_eq!(
-            state.accounts,
-            HashMap::from_iter([
-                (
-                    ClientId(1),
-                    AccountState {
-                        available: 1.5.into(),
-                        held: 0.0.into(),
-                        locked: false
-                    }
-                ),
-                (
-                    ClientId(2),
-                    AccountState {
-                        available: 2.0.into(),
-                        held: 0.0.into(),
-                        locked: false
-                    }
-                )
-            ],)
+            state.process(txn(4, 1, TransactionInner::Close)).unwrap(),
+            Outcome::Ignored(IgnoreReason::AccountClosed)
         );
     }
 
     #[test]
-    fn test_failed_withdrawal() {
-        let state = build_state(&[
+    fn test_id_ordering() {
+        let txns: [Transaction; 3] = [
             Transaction::new(
-                TransactionId(1),
+                TransactionId(2),
                 ClientId(1),
                 TransactionInner::Deposit(1.0.into()),
             ),
+            Transaction::new(TransactionId(2), ClientId(1), TransactionInner::Dispute),
             Transaction::new(
-                TransactionId(2),
+                TransactionId(1),
                 ClientId(1),
-                TransactionInner::Withdrawal(2.0.into()),
+                TransactionInner::Deposit(1.0.into()),
             ),
-        ])
-        .unwrap();
+        ];
+
+        // Unchecked and warning modes process everything
+        for id_ordering in [IdOrdering::Unchecked, IdOrdering::Warn] {
+            let mut state = State::with_options(Options {
+                id_ordering,
+                strict: true,
+                ..Default::default()
+            });
+            for txn in &txns {
+                state.process(txn.clone()).unwrap();
+            }
+            assert_eq!(state.storage.transactions().len(), 2);
+        }
+
+        // Strict mode accepts the dispute (which refers back to an old id), but not the
+        // regressing deposit
+        let mut state = State::with_options(Options {
+            id_ordering: IdOrdering::Strict,
+            strict: true,
+            ..Default::default()
+        });
+        state.process(txns[0].clone()).unwrap();
+        state.process(txns[1].clone()).unwrap();
+        assert!(matches!(
+            state.process(txns[2].clone()),
+            Err(ProcessError::NonMonotonicTransactionId {
+                previous: TransactionId(2),
+                current: TransactionId(1),
+            })
+        ));
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let mut state = State::default();
+        let mut process = |transaction_id, inner| {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap()
+        };
 
         assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 1.0.into(),
-                    held: 0.0.into(),
-                    locked: false
-                }
-            )])
+            process(1, TransactionInner::Deposit(1.0.into())),
+            Outcome::Applied
+        );
+        assert_eq!(
+            process(2, TransactionInner::Withdrawal(2.0.into())),
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            process(3, TransactionInner::Dispute),
+            Outcome::Ignored(IgnoreReason::UnknownTransaction)
+        );
+        assert_eq!(
+            process(1, TransactionInner::Resolve),
+            Outcome::Ignored(IgnoreReason::NotDisputed)
+        );
+        assert_eq!(process(1, TransactionInner::Dispute), Outcome::Applied);
+        assert_eq!(
+            process(1, TransactionInner::Dispute),
+            Outcome::Ignored(IgnoreReason::AlreadyDisputed)
+        );
+        assert_eq!(process(1, TransactionInner::Chargeback), Outcome::Applied);
+        assert_eq!(
+            process(1, TransactionInner::Dispute),
+            Outcome::Ignored(IgnoreReason::ChargedBack)
+        );
+        assert_eq!(
+            process(4, TransactionInner::Withdrawal(1.0.into())),
+            Outcome::Ignored(IgnoreReason::AccountLocked)
+        );
+
+        let counters = state.counters();
+        assert_eq!(
+            counters.transactions["dispute"],
+            TypeCounts {
+                applied: 1,
+                ignored: 3,
+                queued: 0,
+            }
+        );
+        assert_eq!(
+            counters.transactions["withdrawal"],
+            TypeCounts {
+                applied: 0,
+                ignored: 2,
+                queued: 0,
+            }
         );
+        assert_eq!(counters.accounts_created, 1);
     }
 
     #[test]
-    fn test_dispute_deposit() {
-        let state = build_state(&[
+    fn test_strict() {
+        let deposit = |transaction_id, client_id| {
             Transaction::new(
-                TransactionId(1),
-                ClientId(1),
+                TransactionId(transaction_id),
+                ClientId(client_id),
                 TransactionInner::Deposit(1.0.into()),
-            ),
-            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
-        ])
-        .unwrap();
+            )
+        };
 
+        // By default, a duplicate is ignored without touching either account
+        let mut state = State::default();
+        state.process(deposit(1, 1)).unwrap();
         assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 0.0.into(),
-                    held: 1.0.into(),
-                    locked: false
-                }
-            )])
+            state.process(deposit(1, 2)).unwrap(),
+            Outcome::Ignored(IgnoreReason::DuplicateTransactionId)
         );
+        assert_eq!(state.storage.account_count(), 1);
+        assert_eq!(state.account(ClientId(1)).unwrap().available, 1.0.into());
+        assert_eq!(state.stats().invalid_transactions, 1);
+
+        let mut state = State::with_options(Options {
+            strict: true,
+            ..Default::default()
+        });
+        state.process(deposit(1, 1)).unwrap();
+        assert!(matches!(
+            state.process(deposit(1, 1)),
+            Err(ProcessError::DuplicateTransactionId(TransactionId(1)))
+        ));
+        assert_eq!(state.account(ClientId(1)).unwrap().available, 1.0.into());
     }
 
     #[test]
-    fn test_dispute_withdrawal() {
-        let state = build_state(&[
+    fn test_duplicate_ids() {
+        let deposit = |transaction_id, client_id, amount: f64| -> Transaction {
             Transaction::new(
-                TransactionId(1),
-                ClientId(1),
-                TransactionInner::Deposit(5.0.into()),
-            ),
+                TransactionId(transaction_id),
+                ClientId(client_id),
+                TransactionInner::Deposit(amount.into()),
+            )
+        };
+
+        // Skipped duplicates aren't invalid, even in strict mode
+        let mut state: State = State::with_options(Options {
+            strict: true,
+            duplicate_ids: DuplicateIds::Skip,
+            ..Default::default()
+        });
+        state.process(deposit(1, 1, 1.0)).unwrap();
+        assert_eq!(
+            state.process(deposit(1, 2, 5.0)).unwrap(),
+            Outcome::Ignored(IgnoreReason::DuplicateTransactionId)
+        );
+        assert_eq!(state.storage.account_count(), 1);
+        assert_eq!(state.stats().invalid_transactions, 0);
+
+        // A resend has the original's outcome, and is out of order without being invalid
+        let mut state: State = State::with_options(Options {
+            strict: true,
+            id_ordering: IdOrdering::Strict,
+            duplicate_ids: DuplicateIds::Idempotent,
+            ..Default::default()
+        });
+        state.process(deposit(1, 1, 1.0)).unwrap();
+        state.process(deposit(2, 1, 2.0)).unwrap();
+        assert_eq!(state.process(deposit(1, 1, 1.0)).unwrap(), Outcome::Applied);
+        assert!(state.events().is_empty());
+        assert_eq!(state.account(ClientId(1)).unwrap().available, 3.0.into());
+        assert!(matches!(
+            state.process(deposit(1, 1, 1.5)),
+            Err(ProcessError::DuplicateTransactionId(TransactionId(1)))
+        ));
+        assert!(matches!(
+            state.process(deposit(2, 2, 2.0)),
+            Err(ProcessError::DuplicateTransactionId(TransactionId(2)))
+        ));
+
+        // A withdrawal's duplicate is found before its order is checked, in every mode
+        let withdrawal = || {
             Transaction::new(
                 TransactionId(2),
                 ClientId(1),
-                TransactionInner::Withdrawal(3.0.into()),
+                TransactionInner::Withdrawal(1.0.into()),
+            )
+        };
+        for duplicate_ids in [
+            DuplicateIds::Error,
+            DuplicateIds::Skip,
+            DuplicateIds::Idempotent,
+        ] {
+            let mut state: State = State::with_options(Options {
+                strict: true,
+                id_ordering: IdOrdering::Strict,
+                client_monotonic_ids: true,
+                duplicate_ids,
+                ..Default::default()
+            });
+            state.process(deposit(1, 1, 5.0)).unwrap();
+            state.process(withdrawal()).unwrap();
+            let result = state.process(withdrawal());
+            match duplicate_ids {
+                DuplicateIds::Error => assert!(matches!(
+                    result,
+                    Err(ProcessError::DuplicateTransactionId(TransactionId(2)))
+                )),
+                DuplicateIds::Skip => assert_eq!(
+                    result.unwrap(),
+                    Outcome::Ignored(IgnoreReason::DuplicateTransactionId)
+                ),
+                DuplicateIds::Idempotent => assert_eq!(result.unwrap(), Outcome::Applied),
+            }
+            assert_eq!(state.account(ClientId(1)).unwrap().available, 4.0.into());
+        }
+    }
+
+    #[test]
+    fn test_duplicate_uncached_ids() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let mut state: State = State::with_options(Options {
+            strict: true,
+            ..Default::default()
+        });
+        state
+            .process(txn(1, 1, TransactionInner::Deposit(1.0.into())))
+            .unwrap();
+        state
+            .process(txn(2, 2, TransactionInner::Deposit(1.0.into())))
+            .unwrap();
+        state.process(txn(2, 2, TransactionInner::Dispute)).unwrap();
+        state
+            .process(txn(2, 2, TransactionInner::Chargeback))
+            .unwrap();
+
+        // None of these are cached, but their ids are taken all the same
+        for (transaction_id, client_id, inner, outcome) in [
+            (
+                5,
+                1,
+                TransactionInner::Adjustment {
+                    amount: 10.0.into(),
+                    reference: "T1".into(),
+                },
+                Outcome::Applied,
             ),
-            Transaction::new(TransactionId(2), ClientId(1), TransactionInner::Dispute),
-        ])
-        .unwrap();
+            (
+                7,
+                3,
+                TransactionInner::Transfer {
+                    to: ClientId(1),
+                    amount: 1.0.into(),
+                },
+                Outcome::Ignored(IgnoreReason::InsufficientFunds),
+            ),
+            (
+                8,
+                3,
+                TransactionInner::Authorize(1.0.into()),
+                Outcome::Ignored(IgnoreReason::InsufficientFunds),
+            ),
+            (
+                9,
+                2,
+                TransactionInner::Withdrawal(1.0.into()),
+                Outcome::Ignored(IgnoreReason::AccountLocked),
+            ),
+            (6, 1, TransactionInner::Close, Outcome::Applied),
+        ] {
+            assert_eq!(
+                state
+                    .process(txn(transaction_id, client_id, inner))
+                    .unwrap(),
+                outcome
+            );
+            assert!(matches!(
+                state.process(txn(transaction_id, 4, TransactionInner::Deposit(3.0.into()))),
+                Err(ProcessError::DuplicateTransactionId(id)) if id.0 == transaction_id
+            ));
+        }
+        assert!(matches!(
+            state.process(txn(6, 2, TransactionInner::Close)),
+            Err(ProcessError::DuplicateTransactionId(TransactionId(6)))
+        ));
+        assert!(state.account(ClientId(4)).is_none());
+    }
 
-        assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 5.0.into(),
-                    held: (-3.0).into(),
-                    locked: false
-                }
-            )])
-        );
+    #[test]
+    fn test_duplicate_ids_keep_outcome() {
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        let mut state: State = State::with_options(Options {
+            duplicate_ids: DuplicateIds::Idempotent,
+            locked_accounts: "withdrawal=queue".parse().unwrap(),
+            ..Default::default()
+        });
+        state
+            .process(txn(1, TransactionInner::Deposit(1.0.into())))
+            .unwrap();
+        let declined = txn(2, TransactionInner::Withdrawal(5.0.into()));
+        for _ in 0..2 {
+            assert_eq!(
+                state.process(declined.clone()).unwrap(),
+                Outcome::Ignored(IgnoreReason::InsufficientFunds)
+            );
+        }
 
-        assert_eq!(
-            state.transactions.get(&TransactionId(2)).unwrap().state,
-            TransactionState::Disputed
-        );
+        // Once a queued transaction is released, a resend has the outcome it had then
+        state
+            .process(txn(3, TransactionInner::Deposit(2.0.into())))
+            .unwrap();
+        state.process(txn(3, TransactionInner::Dispute)).unwrap();
+        state.process(txn(3, TransactionInner::Chargeback)).unwrap();
+        let queued = txn(4, TransactionInner::Withdrawal(0.5.into()));
+        assert_eq!(state.process(queued.clone()).unwrap(), Outcome::Queued);
+        assert_eq!(state.process(queued.clone()).unwrap(), Outcome::Queued);
+        state.process(txn(3, TransactionInner::Unlock)).unwrap();
+        assert_eq!(state.process(queued).unwrap(), Outcome::Applied);
+        assert_eq!(state.account(ClientId(1)).unwrap().available, 0.5.into());
     }
 
     #[test]
-    fn test_resolve() {
-        let mut state = build_state(&[
+    fn test_duplicate_ids_before_max_amount() {
+        let deposit = |amount: f64| {
             Transaction::new(
                 TransactionId(1),
                 ClientId(1),
-                TransactionInner::Deposit(1.0.into()),
-            ),
-            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
-            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Resolve),
-        ])
-        .unwrap();
+                TransactionInner::Deposit(amount.into()),
+            )
+        };
+        for duplicate_ids in [
+            DuplicateIds::Error,
+            DuplicateIds::Skip,
+            DuplicateIds::Idempotent,
+        ] {
+            let mut state: State = State::with_options(Options {
+                strict: true,
+                duplicate_ids,
+                max_amount: Some(100.0.into()),
+                ..Default::default()
+            });
+            state.process(deposit(50.0)).unwrap();
+            let result = state.process(deposit(500.0));
+            match duplicate_ids {
+                DuplicateIds::Skip => assert_eq!(
+                    result.unwrap(),
+                    Outcome::Ignored(IgnoreReason::DuplicateTransactionId)
+                ),
+                _ => assert!(matches!(
+                    result,
+                    Err(ProcessError::DuplicateTransactionId(TransactionId(1)))
+                )),
+            }
+        }
+    }
+
+    #[test]
+    fn test_client_monotonic_ids() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
 
+        let mut state: State = State::with_options(Options {
+            client_monotonic_ids: true,
+            ..Default::default()
+        });
+        state
+            .process(txn(5, 1, TransactionInner::Deposit(2.0.into())))
+            .unwrap();
+        // Other clients, and other types, go by their own ids
+        state
+            .process(txn(3, 2, TransactionInner::Deposit(1.0.into())))
+            .unwrap();
         assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 1.0.into(),
-                    held: 0.0.into(),
-                    locked: false
-                }
-            )])
+            state
+                .process(txn(
+                    4,
+                    1,
+                    TransactionInner::Adjustment {
+                        amount: 1.0.into(),
+                        reference: "T-1".into(),
+                    }
+                ))
+                .unwrap(),
+            Outcome::Applied
         );
-
         assert_eq!(
-            state.transactions.get(&TransactionId(1)).unwrap().state,
-            TransactionState::Alive
+            state
+                .process(txn(2, 1, TransactionInner::Withdrawal(1.0.into())))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::NonMonotonicTransactionId)
         );
+        assert_eq!(state.account(ClientId(1)).unwrap().available, 3.0.into());
+        assert_eq!(state.stats().invalid_transactions, 1);
 
-        // Try disputing/resolving again
-        state
-            .process(Transaction::new(
-                TransactionId(1),
-                ClientId(1),
-                TransactionInner::Dispute,
-            ))
-            .unwrap();
+        state.set_options(Options {
+            strict: true,
+            client_monotonic_ids: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            state.process(txn(2, 1, TransactionInner::Deposit(1.0.into()))),
+            Err(ProcessError::NonMonotonicClientTransactionId {
+                client_id: ClientId(1),
+                previous: TransactionId(5),
+                current: TransactionId(2),
+            })
+        ));
         state
-            .process(Transaction::new(
-                TransactionId(1),
-                ClientId(1),
-                TransactionInner::Resolve,
-            ))
+            .process(txn(6, 1, TransactionInner::Withdrawal(1.0.into())))
             .unwrap();
+    }
 
-        assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 1.0.into(),
-                    held: 0.0.into(),
-                    locked: false
-                }
-            )])
-        );
+    #[test]
+    fn test_memory_usage() {
+        let mut state = State::default();
+        assert_eq!(state.memory_usage(), 0);
+
+        for transaction_id in 1..=1000 {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(transaction_id as u16 % 10),
+                    TransactionInner::Deposit(1.0.into()),
+                ))
+                .unwrap();
+        }
+
+        // At least what's in the maps, but not more than a few times that
+        let contents = 1000 * std::mem::size_of::<(TransactionId, Transaction)>()
+            + 1000 * std::mem::size_of::<(TransactionId, super::TakenId)>()
+            + 10 * std::mem::size_of::<(ClientId, AccountState)>();
+        assert!(state.memory_usage() >= contents);
+        assert!(state.memory_usage() < contents * 4);
+    }
+
+    #[test]
+    fn test_replay_events() {
+        let deposit = |transaction_id, client_id, amount: f64| {
+            Transaction::new(
+                TransactionId(transaction_id),
+                ClientId(client_id),
+                TransactionInner::Deposit(amount.into()),
+            )
+        };
+        let other = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+
+        let mut state = State::default();
+        let mut events = Vec::new();
+        for txn in [
+            deposit(1, 1, 3.0),
+            deposit(2, 2, 1.0),
+            other(3, 2, TransactionInner::Withdrawal(5.0.into())),
+            other(2, 2, TransactionInner::Dispute),
+            other(1, 1, TransactionInner::Dispute),
+            other(1, 1, TransactionInner::Chargeback),
+            other(4, 1, TransactionInner::Withdrawal(1.0.into())),
+            deposit(1, 3, 1.0),
+        ] {
+            state.process(txn).unwrap();
+            events.extend_from_slice(state.events());
+        }
 
+        let names = events.iter().map(|event| event.name()).collect::<Vec<_>>();
         assert_eq!(
-            state.transactions.get(&TransactionId(1)).unwrap().state,
-            TransactionState::Alive
+            names,
+            [
+                "account_opened",
+                "funds_deposited",
+                "account_opened",
+                "funds_deposited",
+                "withdrawal_declined",
+                "dispute_opened",
+                "dispute_opened",
+                "charged_back",
+                "account_locked",
+                "withdrawal_blocked",
+            ]
         );
+
+        let mut replayed = State::default();
+        for event in &events {
+            replayed.apply_event(event);
+        }
+        assert_eq!(replayed.sorted_accounts(), state.sorted_accounts());
+        assert_eq!(replayed.last_transaction_id, state.last_transaction_id);
+        let states = |state: &State| {
+            let mut states = state
+                .transactions()
+                .map(|txn| (txn.transaction_id, txn.state.clone()))
+                .collect::<Vec<_>>();
+            states.sort_by_key(|(transaction_id, _)| *transaction_id);
+            states
+        };
+        assert_eq!(states(&replayed), states(&state));
     }
 
     #[test]
-    fn test_chargeback() {
-        let mut state = build_state(&[
+    fn test_merge() {
+        let txns = [
             Transaction::new(
                 TransactionId(1),
                 ClientId(1),
-                TransactionInner::Deposit(123.0.into()),
+                TransactionInner::Deposit(1.0.into()),
             ),
             Transaction::new(
                 TransactionId(2),
+                ClientId(2),
+                TransactionInner::Deposit(2.0.into()),
+            ),
+            Transaction::new(TransactionId(2), ClientId(2), TransactionInner::Dispute),
+            Transaction::new(
+                TransactionId(3),
                 ClientId(1),
-                TransactionInner::Deposit(456.0.into()),
+                TransactionInner::Withdrawal(0.5.into()),
             ),
-            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Dispute),
-            Transaction::new(TransactionId(1), ClientId(1), TransactionInner::Chargeback),
-        ])
-        .unwrap();
+        ];
+        let whole = build_state(&txns).unwrap();
 
-        assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 456.0.into(),
-                    held: 0.0.into(),
-                    locked: true
-                }
-            )])
-        );
+        // Partitioned by client
+        let (ones, twos): (Vec<_>, Vec<_>) = txns
+            .iter()
+            .cloned()
+            .partition(|txn| txn.client_id == ClientId(1));
+        let mut merged = build_state(&ones).unwrap();
+        merged.merge(build_state(&twos).unwrap()).unwrap();
 
-        assert_eq!(
-            state.transactions.get(&TransactionId(1)).unwrap().state,
-            TransactionState::ChargedBack
-        );
+        assert_eq!(merged.sorted_accounts(), whole.sorted_accounts());
+        assert_eq!(merged.last_transaction_id, whole.last_transaction_id);
+        assert_eq!(merged.counters(), whole.counters());
 
-        // Try disputing again; this shouldn't return an error, but the transaction
-        // will stay in the ChargedBack state with no further changes.
-        state
-            .process(Transaction::new(
-                TransactionId(1),
+        assert!(matches!(
+            merged.merge(build_state(&twos[..1]).unwrap()),
+            Err(MergeError::ClientInBoth(ClientId(2)))
+        ));
+        assert_eq!(merged.sorted_accounts(), whole.sorted_accounts());
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_process_all() {
+        let deposit = |transaction_id| -> crate::io::TransactionResult {
+            Ok(Transaction::new(
+                TransactionId(transaction_id),
                 ClientId(1),
-                TransactionInner::Dispute,
+                TransactionInner::Deposit(1.0.into()),
             ))
-            .unwrap();
+        };
 
-        assert_eq!(
-            state.accounts,
-            HashMap::from_iter([(
-                ClientId(1),
-                AccountState {
-                    available: 456.0.into(),
-                    held: 0.0.into(),
-                    locked: true
-                }
-            )])
-        );
+        let mut state = State::default();
+        state
+            .process_all([deposit(1), deposit(2)].into_iter())
+            .unwrap();
+        assert_eq!(state.account(ClientId(1)).unwrap().available(), 2.0.into());
 
-        assert_eq!(
-            state.transactions.get(&TransactionId(1)).unwrap().state,
-            TransactionState::ChargedBack
-        );
+        // Stops at the first error, without going on to the rest
+        let source = [deposit(3), Err("Unreadable".into()), deposit(4)];
+        let err = state.process_all(source.into_iter()).unwrap_err();
+        assert_eq!(err.to_string(), "Unreadable");
+        assert_eq!(state.account(ClientId(1)).unwrap().available(), 3.0.into());
     }
 }
@@ -0,0 +1,292 @@
+//! The changes that `State::process` makes, as explicit domain events. Processing a transaction
+//! decides which events it produces, without changing anything, and then applies them, so the
+//! events are a complete record of how the state got to where it is: applying them in order to an
+//! empty state (see `State::apply_event`) rebuilds it exactly, apart from the counters, which are
+//! about transactions rather than the state.
+//!
+//! An event log is JSON Lines, one event per line, e.g.
+//!
+//! ```text
+//! {"event":"account_opened","client":1}
+//! {"event":"funds_deposited","client":1,"tx":1,"amount":1.5}
+//! ```
+
+use crate::types::{Amount, ClientId, FixedFloat, TransactionId, TransactionInner};
+use alloc::string::String;
+
+#[cfg(feature = "io")]
+mod log;
+
+#[cfg(feature = "io")]
+pub use log::{read_events, EventWriter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<A: Amount = FixedFloat> {
+    AccountOpened {
+        client_id: ClientId,
+    },
+    FundsDeposited {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A deposit into a locked account, refused by `Options::locked_accounts`. It isn't cached,
+    /// but its id still counts towards the id ordering checks.
+    DepositBlocked {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    FundsWithdrawn {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A withdrawal of more than was available. Nothing moves, but it's still cached, so it can
+    /// be disputed like any other.
+    WithdrawalDeclined {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A withdrawal from a locked account. It isn't cached, but its id still counts towards the
+    /// id ordering checks.
+    WithdrawalBlocked {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// `amount` leaves the sender's available funds. Always directly followed by
+    /// `TransferReceived`, which credits them to `to`; the two are produced together, so a
+    /// transfer never half happens.
+    TransferSent {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        to: ClientId,
+        amount: A,
+    },
+    /// `amount` arrives in the recipient's available funds.
+    TransferReceived {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A transfer of more than the sender had available. Nothing moves, and unlike a declined
+    /// withdrawal it isn't cached, as there's nothing at the recipient's end to dispute.
+    TransferDeclined {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        to: ClientId,
+        amount: A,
+    },
+    /// A transfer from a locked account. It isn't cached either.
+    TransferBlocked {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        to: ClientId,
+        amount: A,
+    },
+    /// A charged back transfer's `amount` goes back into the sender's available funds, rather
+    /// than leaving the system. Always directly follows the `ChargedBack` at the recipient's end.
+    TransferReversed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// An operator's correction: `amount` (negative for a debit) is added to available funds,
+    /// whether or not the account is locked. It isn't cached, so it can't be disputed.
+    AdjustmentApplied {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+        reference: String,
+    },
+    /// `amount` moves from available to held. It's negative for a disputed withdrawal. For a
+    /// partial dispute, `portion` is how much of the transaction is disputed. `reason` is the
+    /// dispute's reason code, if it gave one.
+    DisputeOpened {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+        portion: Option<A>,
+        reason: Option<String>,
+    },
+    /// `amount` moves from held back to available.
+    DisputeResolved {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// `amount` leaves held for good. Always followed by `AccountLocked`, unless the account
+    /// already was. `reason` is the chargeback's reason code, if it gave one; otherwise the
+    /// dispute's stands.
+    ChargedBack {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+        reason: Option<String>,
+    },
+    /// The dispute wasn't resolved or charged back in time (see `Options::dispute_expiry`), so
+    /// it's resolved: `amount` moves from held back to available.
+    DisputeExpired {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    AccountLocked {
+        client_id: ClientId,
+    },
+    /// An administrative unlock of the account, recorded against `transaction_id`, the charged
+    /// back transaction that locked it. Followed by the events of any transactions queued for the
+    /// account (see `TransactionQueued`), which are processed then, in the order they arrived.
+    AccountUnlocked {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+    },
+    /// A representment won back the charged back deposit or withdrawal `transaction_id`: `amount`,
+    /// what the chargeback took out of held, is added to available funds, so it's negative for a
+    /// withdrawal. Followed by `AccountUnlocked` if `Options::representment_unlocks` is set and
+    /// the account is locked.
+    ChargebackReversed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A refund undid the deposit or withdrawal `transaction_id`: `amount` is added to available
+    /// funds, so it's negative for a refunded deposit.
+    TransactionReversed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// `amount` moves from available to held, pending a capture or void.
+    FundsAuthorized {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// An authorization refused for insufficient funds.
+    AuthorizationDeclined {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// An authorization refused because the account is locked.
+    AuthorizationBlocked {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// The authorization's `amount` leaves held, and it's cached as a withdrawal from then on.
+    AuthorizationCaptured {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// The authorization's `amount` moves from held back to available.
+    AuthorizationVoided {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// The authorization wasn't captured or voided in time (see `Options::authorization_expiry`),
+    /// so its `amount` moves from held back to available, as for a void.
+    AuthorizationExpired {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// The account is closed, with `payout` available to be paid out to the client.
+    AccountClosed {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        payout: A,
+    },
+    /// A deposit, withdrawal or transfer of `amount` by `client_id` was refused because it, or
+    /// for a transfer the recipient, has a closed account (see `Options::closed_accounts`).
+    ClosedAccountRefused {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        amount: A,
+    },
+    /// A deposit, withdrawal, transfer or authorization by `client_id`, whose account is locked,
+    /// held back by `Options::locked_accounts` until it's unlocked. Nothing moves, and it isn't
+    /// cached, but its id counts towards the id ordering checks.
+    TransactionQueued {
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        inner: TransactionInner<A>,
+    },
+}
+
+impl<A: Amount> Event<A> {
+    /// The name used for this type in the `event` field of an event log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AccountOpened { .. } => "account_opened",
+            Self::FundsDeposited { .. } => "funds_deposited",
+            Self::DepositBlocked { .. } => "deposit_blocked",
+            Self::FundsWithdrawn { .. } => "funds_withdrawn",
+            Self::WithdrawalDeclined { .. } => "withdrawal_declined",
+            Self::WithdrawalBlocked { .. } => "withdrawal_blocked",
+            Self::TransferSent { .. } => "transfer_sent",
+            Self::TransferReceived { .. } => "transfer_received",
+            Self::TransferDeclined { .. } => "transfer_declined",
+            Self::TransferBlocked { .. } => "transfer_blocked",
+            Self::TransferReversed { .. } => "transfer_reversed",
+            Self::AdjustmentApplied { .. } => "adjustment_applied",
+            Self::DisputeOpened { .. } => "dispute_opened",
+            Self::DisputeResolved { .. } => "dispute_resolved",
+            Self::ChargedBack { .. } => "charged_back",
+            Self::DisputeExpired { .. } => "dispute_expired",
+            Self::AccountLocked { .. } => "account_locked",
+            Self::AccountUnlocked { .. } => "account_unlocked",
+            Self::ChargebackReversed { .. } => "chargeback_reversed",
+            Self::TransactionReversed { .. } => "transaction_reversed",
+            Self::FundsAuthorized { .. } => "funds_authorized",
+            Self::AuthorizationDeclined { .. } => "authorization_declined",
+            Self::AuthorizationBlocked { .. } => "authorization_blocked",
+            Self::AuthorizationCaptured { .. } => "authorization_captured",
+            Self::AuthorizationVoided { .. } => "authorization_voided",
+            Self::AuthorizationExpired { .. } => "authorization_expired",
+            Self::AccountClosed { .. } => "account_closed",
+            Self::ClosedAccountRefused { .. } => "closed_account_refused",
+            Self::TransactionQueued { .. } => "transaction_queued",
+        }
+    }
+
+    /// The client whose account the event changes.
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Self::AccountOpened { client_id }
+            | Self::FundsDeposited { client_id, .. }
+            | Self::DepositBlocked { client_id, .. }
+            | Self::FundsWithdrawn { client_id, .. }
+            | Self::WithdrawalDeclined { client_id, .. }
+            | Self::WithdrawalBlocked { client_id, .. }
+            | Self::TransferSent { client_id, .. }
+            | Self::TransferReceived { client_id, .. }
+            | Self::TransferDeclined { client_id, .. }
+            | Self::TransferBlocked { client_id, .. }
+            | Self::TransferReversed { client_id, .. }
+            | Self::AdjustmentApplied { client_id, .. }
+            | Self::DisputeOpened { client_id, .. }
+            | Self::DisputeResolved { client_id, .. }
+            | Self::ChargedBack { client_id, .. }
+            | Self::DisputeExpired { client_id, .. }
+            | Self::AccountLocked { client_id }
+            | Self::AccountUnlocked { client_id, .. }
+            | Self::ChargebackReversed { client_id, .. }
+            | Self::TransactionReversed { client_id, .. }
+            | Self::FundsAuthorized { client_id, .. }
+            | Self::AuthorizationDeclined { client_id, .. }
+            | Self::AuthorizationBlocked { client_id, .. }
+            | Self::AuthorizationCaptured { client_id, .. }
+            | Self::AuthorizationVoided { client_id, .. }
+            | Self::AuthorizationExpired { client_id, .. }
+            | Self::AccountClosed { client_id, .. }
+            | Self::ClosedAccountRefused { client_id, .. }
+            | Self::TransactionQueued { client_id, .. } => *client_id,
+        }
+    }
+}
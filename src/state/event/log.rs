@@ -0,0 +1,504 @@
+//! Reading and writing event logs, in the format described in `super`.
+
+use super::Event;
+use crate::types::{ClientId, FixedFloat, TransactionId, TransactionInner};
+use std::{
+    error::Error,
+    io::{BufRead, Write},
+};
+
+impl Event {
+    fn fields(&self) -> EventFields {
+        let to = match self {
+            Self::TransferSent { to, .. }
+            | Self::TransferDeclined { to, .. }
+            | Self::TransferBlocked { to, .. }
+            | Self::TransactionQueued {
+                inner: TransactionInner::Transfer { to, .. },
+                ..
+            } => Some(to.0),
+            _ => None,
+        };
+        let (transaction_id, amount) = match self {
+            Self::AccountOpened { .. } | Self::AccountLocked { .. } => (None, None),
+            Self::AccountUnlocked { transaction_id, .. } => (Some(transaction_id.0), None),
+            Self::FundsDeposited {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::DepositBlocked {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::FundsWithdrawn {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::WithdrawalDeclined {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::WithdrawalBlocked {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransferSent {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransferReceived {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransferDeclined {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransferBlocked {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransferReversed {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AdjustmentApplied {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::DisputeOpened {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::DisputeResolved {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::ChargedBack {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::DisputeExpired {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::ChargebackReversed {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::TransactionReversed {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::FundsAuthorized {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AuthorizationDeclined {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AuthorizationBlocked {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AuthorizationCaptured {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AuthorizationVoided {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AuthorizationExpired {
+                transaction_id,
+                amount,
+                ..
+            }
+            | Self::AccountClosed {
+                transaction_id,
+                payout: amount,
+                ..
+            }
+            | Self::ClosedAccountRefused {
+                transaction_id,
+                amount,
+                ..
+            } => (Some(transaction_id.0), Some(amount.raw() as f64 / 10000.0)),
+            Self::TransactionQueued {
+                transaction_id,
+                inner,
+                ..
+            } => (
+                Some(transaction_id.0),
+                inner.amount().map(|amount| amount.raw() as f64 / 10000.0),
+            ),
+        };
+
+        EventFields {
+            event: self.name().into(),
+            client_id: self.client_id().0,
+            transaction_id,
+            to,
+            amount,
+            reference: match self {
+                Self::AdjustmentApplied { reference, .. } => Some(reference.clone()),
+                _ => None,
+            },
+            portion: match self {
+                Self::DisputeOpened {
+                    portion: Some(portion),
+                    ..
+                } => Some(portion.raw() as f64 / 10000.0),
+                _ => None,
+            },
+            reason: match self {
+                Self::DisputeOpened { reason, .. } | Self::ChargedBack { reason, .. } => {
+                    reason.clone()
+                }
+                _ => None,
+            },
+            type_name: match self {
+                Self::TransactionQueued { inner, .. } => Some(inner.type_name().into()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl TryFrom<EventFields> for Event {
+    type Error = String;
+
+    fn try_from(fields: EventFields) -> Result<Self, Self::Error> {
+        let client_id = ClientId(fields.client_id);
+        let transaction_id = || {
+            fields
+                .transaction_id
+                .map(TransactionId)
+                .ok_or_else(|| format!("\"{}\" event is missing \"tx\"", fields.event))
+        };
+        let to = || {
+            fields
+                .to
+                .map(ClientId)
+                .ok_or_else(|| format!("\"{}\" event is missing \"to\"", fields.event))
+        };
+        let amount = || {
+            fields
+                .amount
+                .map(FixedFloat::from)
+                .ok_or_else(|| format!("\"{}\" event is missing \"amount\"", fields.event))
+        };
+
+        Ok(match fields.event.as_str() {
+            "account_opened" => Self::AccountOpened { client_id },
+            "funds_deposited" => Self::FundsDeposited {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "deposit_blocked" => Self::DepositBlocked {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "funds_withdrawn" => Self::FundsWithdrawn {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "withdrawal_declined" => Self::WithdrawalDeclined {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "withdrawal_blocked" => Self::WithdrawalBlocked {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "transfer_sent" => Self::TransferSent {
+                client_id,
+                transaction_id: transaction_id()?,
+                to: to()?,
+                amount: amount()?,
+            },
+            "transfer_received" => Self::TransferReceived {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "transfer_declined" => Self::TransferDeclined {
+                client_id,
+                transaction_id: transaction_id()?,
+                to: to()?,
+                amount: amount()?,
+            },
+            "transfer_blocked" => Self::TransferBlocked {
+                client_id,
+                transaction_id: transaction_id()?,
+                to: to()?,
+                amount: amount()?,
+            },
+            "transfer_reversed" => Self::TransferReversed {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "adjustment_applied" => Self::AdjustmentApplied {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+                reference: fields.reference.clone().ok_or_else(|| {
+                    format!("\"{}\" event is missing \"reference\"", fields.event)
+                })?,
+            },
+            "dispute_opened" => Self::DisputeOpened {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+                portion: fields.portion.map(FixedFloat::from),
+                reason: fields.reason.clone(),
+            },
+            "dispute_resolved" => Self::DisputeResolved {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "charged_back" => Self::ChargedBack {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+                reason: fields.reason.clone(),
+            },
+            "dispute_expired" => Self::DisputeExpired {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "account_locked" => Self::AccountLocked { client_id },
+            "account_unlocked" => Self::AccountUnlocked {
+                client_id,
+                transaction_id: transaction_id()?,
+            },
+            "chargeback_reversed" => Self::ChargebackReversed {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "transaction_reversed" => Self::TransactionReversed {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "funds_authorized" => Self::FundsAuthorized {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "authorization_declined" => Self::AuthorizationDeclined {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "authorization_blocked" => Self::AuthorizationBlocked {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "authorization_captured" => Self::AuthorizationCaptured {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "authorization_voided" => Self::AuthorizationVoided {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "authorization_expired" => Self::AuthorizationExpired {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "account_closed" => Self::AccountClosed {
+                client_id,
+                transaction_id: transaction_id()?,
+                payout: amount()?,
+            },
+            "closed_account_refused" => Self::ClosedAccountRefused {
+                client_id,
+                transaction_id: transaction_id()?,
+                amount: amount()?,
+            },
+            "transaction_queued" => Self::TransactionQueued {
+                client_id,
+                transaction_id: transaction_id()?,
+                inner: match fields.type_name.as_deref() {
+                    Some("deposit") => TransactionInner::Deposit(amount()?),
+                    Some("withdrawal") => TransactionInner::Withdrawal(amount()?),
+                    Some("transfer") => TransactionInner::Transfer {
+                        to: to()?,
+                        amount: amount()?,
+                    },
+                    Some("authorize") => TransactionInner::Authorize(amount()?),
+                    Some(other) => return Err(format!("Can't queue a \"{}\" transaction", other)),
+                    None => return Err(format!("\"{}\" event is missing \"type\"", fields.event)),
+                },
+            },
+            other => return Err(format!("Unrecognised event \"{}\"", other)),
+        })
+    }
+}
+
+// The shape of an event in an event log
+#[derive(serde::Deserialize, serde::Serialize)]
+struct EventFields {
+    event: String,
+    #[serde(rename = "client")]
+    client_id: u16,
+    #[serde(rename = "tx", default, skip_serializing_if = "Option::is_none")]
+    transaction_id: Option<u32>,
+    // Only for the transfer events that name the recipient
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<f64>,
+    // Only for adjustments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reference: Option<String>,
+    // Only for partial disputes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    portion: Option<f64>,
+    // Only for disputes and chargebacks that gave a reason code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    // Only for queued transactions
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    type_name: Option<String>,
+}
+
+/// Writes events to an event log.
+pub struct EventWriter<Writer: Write> {
+    f: Writer,
+}
+
+impl<Writer: Write> EventWriter<Writer> {
+    pub fn new(f: Writer) -> Self {
+        Self { f }
+    }
+
+    pub fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        for event in events {
+            serde_json::to_writer(&mut self.f, &event.fields())?;
+            self.f.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.f.flush()
+    }
+
+    pub fn into_inner(self) -> Writer {
+        self.f
+    }
+}
+
+/// Reads the events in an event log, in order. Blank lines are skipped.
+pub fn read_events(f: impl BufRead) -> impl Iterator<Item = Result<Event, Box<dyn Error>>> {
+    f.lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            let fields = serde_json::from_str::<EventFields>(&line?)
+                .map_err(|err| format!("Line {}: {}", index + 1, err))?;
+            Event::try_from(fields).map_err(|err| format!("Line {}: {}", index + 1, err).into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_events, Event, EventWriter};
+    use crate::types::{ClientId, TransactionId, TransactionInner};
+
+    #[test]
+    fn test_round_trip() {
+        let events = vec![
+            Event::AccountOpened {
+                client_id: ClientId(1),
+            },
+            Event::FundsDeposited {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: 1.2345.into(),
+            },
+            Event::DisputeOpened {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(2),
+                amount: (-0.5).into(),
+                portion: None,
+                reason: None,
+            },
+            Event::DisputeOpened {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(1),
+                amount: 0.25.into(),
+                portion: Some(0.25.into()),
+                reason: Some("fraud".into()),
+            },
+            Event::TransactionQueued {
+                client_id: ClientId(1),
+                transaction_id: TransactionId(3),
+                inner: TransactionInner::Transfer {
+                    to: ClientId(2),
+                    amount: 0.5.into(),
+                },
+            },
+        ];
+
+        let mut writer = EventWriter::new(Vec::new());
+        writer.write(&events).unwrap();
+        let text = String::from_utf8(writer.f).unwrap();
+        assert!(text.starts_with(
+            "{\"event\":\"account_opened\",\"client\":1}\n\
+            {\"event\":\"funds_deposited\",\"client\":1,\"tx\":1,\"amount\":1.2345}\n"
+        ));
+
+        let read = read_events(text.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read, events);
+
+        let err = read_events("\n{\"event\":\"funds_deposited\",\"client\":1}\n".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line 2: \"funds_deposited\" event is missing \"tx\""
+        );
+    }
+}
@@ -0,0 +1,45 @@
+//! The holds that `State` releases by itself if nothing else does first (see
+//! `Options::authorization_expiry`, `Options::dispute_expiry` and their `_seconds` versions).
+
+use crate::types::TransactionId;
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// When each pending hold expires, as a number of transactions processed (see
+/// `State::processed`) or, for holds that expire by time, a timestamp. A transaction has at most
+/// one deadline, for the hold it's under now, so one disputed again after being resolved gets a
+/// new one.
+#[derive(Debug, Default, Clone)]
+pub(super) struct Expiries {
+    deadlines: BTreeMap<TransactionId, u64>,
+    by_deadline: BTreeSet<(u64, TransactionId)>,
+}
+
+impl Expiries {
+    pub fn insert(&mut self, transaction_id: TransactionId, deadline: u64) {
+        self.remove(transaction_id);
+        self.deadlines.insert(transaction_id, deadline);
+        self.by_deadline.insert((deadline, transaction_id));
+    }
+
+    pub fn remove(&mut self, transaction_id: TransactionId) {
+        if let Some(deadline) = self.deadlines.remove(&transaction_id) {
+            self.by_deadline.remove(&(deadline, transaction_id));
+        }
+    }
+
+    /// A hold whose deadline is before `now`, if there is one, earliest first. It's left in place
+    /// until it's removed.
+    pub fn due(&self, now: u64) -> Option<TransactionId> {
+        self.by_deadline
+            .first()
+            .filter(|(deadline, _)| *deadline < now)
+            .map(|(_, transaction_id)| *transaction_id)
+    }
+
+    /// Every hold and its deadline, by transaction id.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (TransactionId, u64)> + '_ {
+        self.deadlines
+            .iter()
+            .map(|(transaction_id, deadline)| (*transaction_id, *deadline))
+    }
+}
@@ -0,0 +1,115 @@
+//! A hash of a state's contents, for checking that two runs (e.g. on different versions or
+//! machines) ended up in exactly the same state without comparing the whole output.
+//!
+//! The hash is SHA-256, over the bytes `OCTFP1` followed by, in client id order, each account's
+//! client id (`u16`), available and held amounts in ten-thousandths (`i64` each) and flags (`u8`: 1
+//! if it's locked, plus 2 if it's closed), then, in transaction id order, each cached transaction's
+//! id (`u32`), client id (`u16`), type (`u8`: 0 for a deposit, 1 for a withdrawal, 2 for a
+//! transfer, 3 for an authorization), amount in ten-thousandths (`i64`), for a transfer the client
+//! id it's to (`u16`), and state (`u8`: 0 for alive, 1 for disputed, 2 for charged back, 3 for
+//! unlocked, 4 for reversed, 5 for represented), followed, only if just part of it is disputed, by
+//! that part in ten-thousandths (`i64`). Integers are little-endian. Nothing else (options,
+//! counters, and so on) goes into it, so it only changes if the balances, the account statuses or
+//! the dispute statuses do.
+
+use super::State;
+use crate::types::{TransactionInner, TransactionState};
+use alloc::{format, string::String, vec::Vec};
+use sha2::{Digest, Sha256};
+
+impl State {
+    /// The state's fingerprint, as 64 hex digits.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"OCTFP1");
+
+        for (client_id, account) in self.sorted_accounts() {
+            hasher.update(client_id.0.to_le_bytes());
+            hasher.update(account.available.raw().to_le_bytes());
+            hasher.update(account.held.raw().to_le_bytes());
+            hasher.update([account.locked as u8 | (account.closed as u8) << 1]);
+        }
+
+        let mut transactions = self.storage.transactions().iter().collect::<Vec<_>>();
+        transactions.sort_unstable_by_key(|txn| txn.transaction_id);
+        for txn in transactions {
+            let (type_, amount, to) = match txn.inner {
+                TransactionInner::Deposit(amount) => (0, amount, None),
+                TransactionInner::Withdrawal(amount) => (1, amount, None),
+                TransactionInner::Transfer { to, amount } => (2, amount, Some(to)),
+                TransactionInner::Authorize(amount) => (3, amount, None),
+                // Only deposits, withdrawals, transfers and authorizations are cached
+                _ => unreachable!(),
+            };
+            let state = match txn.state {
+                TransactionState::Alive => 0,
+                TransactionState::Disputed => 1,
+                TransactionState::ChargedBack => 2,
+                TransactionState::Unlocked => 3,
+                TransactionState::Reversed => 4,
+                TransactionState::Represented => 5,
+                TransactionState::Declined => 6,
+            };
+            hasher.update(txn.transaction_id.0.to_le_bytes());
+            hasher.update(txn.client_id.0.to_le_bytes());
+            hasher.update([type_]);
+            hasher.update(amount.raw().to_le_bytes());
+            if let Some(to) = to {
+                hasher.update(to.0.to_le_bytes());
+            }
+            hasher.update([state]);
+            if let Some(disputed) = txn.disputed {
+                hasher.update(disputed.raw().to_le_bytes());
+            }
+        }
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_fingerprint() {
+        let empty = State::default().fingerprint();
+        assert_eq!(empty.len(), 64);
+
+        let deposit = |transaction_id| {
+            Transaction::new(
+                TransactionId(transaction_id),
+                ClientId(1),
+                TransactionInner::Deposit(1.0.into()),
+            )
+        };
+        let mut a = State::default();
+        let mut b = State::default();
+        for transaction_id in 1..=100 {
+            a.process(deposit(transaction_id)).unwrap();
+        }
+        // The same contents, but with the maps filled in a different order
+        for transaction_id in (1..=100).rev() {
+            b.process(deposit(transaction_id)).unwrap();
+        }
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), empty);
+
+        // A dispute changes nothing but a transaction's state, which still counts
+        let before = a.fingerprint();
+        a.process(Transaction::new(
+            TransactionId(1),
+            ClientId(1),
+            TransactionInner::Dispute,
+        ))
+        .unwrap();
+        assert_ne!(a.fingerprint(), before);
+    }
+}
@@ -0,0 +1,331 @@
+//! Consistency checks over a whole state, for catching engine bugs (or a bad starting state) that
+//! the balances alone wouldn't show.
+
+use super::{Outcome, State};
+use crate::types::{ClientId, FixedFloat, Transaction, TransactionInner, TransactionState};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+/// A way in which an account doesn't add up.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// The account's held funds aren't what its open disputes and pending authorizations add up
+    /// to.
+    HeldMismatch {
+        client_id: ClientId,
+        held: FixedFloat,
+        disputed: FixedFloat,
+        open_disputes: usize,
+        authorized: FixedFloat,
+    },
+    /// The account is locked, but none of its transactions were charged back (and not since
+    /// unlocked).
+    LockedWithoutChargeback { client_id: ClientId },
+    /// The account has negative held funds, which only a dispute of a withdrawal that was applied
+    /// can cause, and only with `WithdrawalDisputes::TreatAsNegative` (or a dispute policy that
+    /// holds a negative amount), but it has none.
+    NegativeHeld {
+        client_id: ClientId,
+        held: FixedFloat,
+    },
+    /// The account's total is more than the deposits, incoming transfers and adjustments applied
+    /// to it, less the withdrawals and outgoing transfers that still stand, so money has come from
+    /// nowhere.
+    TotalExceedsNet {
+        client_id: ClientId,
+        total: FixedFloat,
+        net: FixedFloat,
+    },
+}
+
+impl Violation {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Self::HeldMismatch { client_id, .. }
+            | Self::LockedWithoutChargeback { client_id }
+            | Self::NegativeHeld { client_id, .. }
+            | Self::TotalExceedsNet { client_id, .. } => *client_id,
+        }
+    }
+}
+
+impl core::fmt::Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::HeldMismatch {
+                client_id,
+                held,
+                disputed,
+                open_disputes,
+                authorized,
+            } => write!(
+                f,
+                "Client {} has {} held, but its {} open disputes total {} and its pending \
+                authorizations {}",
+                client_id, held, open_disputes, disputed, authorized
+            ),
+            Self::LockedWithoutChargeback { client_id } => write!(
+                f,
+                "Client {} is locked, but none of its transactions were charged back",
+                client_id
+            ),
+            Self::NegativeHeld { client_id, held } => write!(
+                f,
+                "Client {} has {} held, but no disputed withdrawals to account for it",
+                client_id, held
+            ),
+            Self::TotalExceedsNet {
+                client_id,
+                total,
+                net,
+            } => write!(
+                f,
+                "Client {} has {} in total, but only {} came in net of what went out",
+                client_id, total, net
+            ),
+        }
+    }
+}
+
+// What a client's cached transactions say about its account
+#[derive(Default)]
+struct History {
+    disputed: FixedFloat,
+    open_disputes: usize,
+    // Disputed withdrawals that were applied, and hold a negative amount
+    negative_disputes: usize,
+    authorized: FixedFloat,
+    charged_back: bool,
+    // What's come in, less what's gone out and not come back
+    net: FixedFloat,
+}
+
+impl State {
+    /// Checks that every account is consistent with the transaction cache and the ids taken: its
+    /// held funds are what its open disputes add up to (as `Options::withdrawal_disputes` and the
+    /// dispute policy have them, so negative for a withdrawal by default), it's only locked if one
+    /// of its transactions was charged back and not since unlocked, it only has negative held
+    /// funds if a withdrawal that was applied is disputed for a negative amount, and its total is
+    /// no more than the deposits, incoming transfers and adjustments applied to it, less the
+    /// withdrawals and outgoing transfers that haven't since been charged back or refunded.
+    /// Returns every violation, ordered by client id.
+    ///
+    /// A state started from accounts alone (see `State::from_accounts`) has no history to check
+    /// against, so held funds, locks or balances it started with are reported too.
+    pub fn verify_invariants(&self) -> Vec<Violation> {
+        let mut histories = BTreeMap::<ClientId, History>::new();
+        for txn in self.storage.transactions().iter() {
+            let history = histories.entry(txn.disputed_client_id()).or_default();
+            match txn.state {
+                TransactionState::Alive => {
+                    if let TransactionInner::Authorize(amount) = txn.inner {
+                        history.authorized += amount;
+                    }
+                }
+                TransactionState::Reversed | TransactionState::Declined => {}
+                TransactionState::Disputed => {
+                    let held = self.held_amount(&txn.disputed_part()).unwrap_or_default();
+                    history.open_disputes += 1;
+                    history.disputed += held;
+                    if let TransactionInner::Withdrawal(_) = txn.inner {
+                        if self.applied(&txn) && held < FixedFloat::default() {
+                            history.negative_disputes += 1;
+                        }
+                    }
+                }
+                // A representment may have left the lock in place
+                TransactionState::ChargedBack | TransactionState::Represented => {
+                    history.charged_back = true
+                }
+                // The lock it caused has been lifted
+                TransactionState::Unlocked => {}
+            }
+
+            // Deposits and transfers in count even if they've since been taken back, and
+            // authorizations not even once captured, which only loosens the bound
+            let stands = matches!(
+                txn.state,
+                TransactionState::Alive
+                    | TransactionState::Disputed
+                    | TransactionState::Represented
+            ) && self.applied(&txn);
+            match txn.inner {
+                TransactionInner::Deposit(amount) => history.net += amount,
+                TransactionInner::Withdrawal(amount) if stands => history.net -= amount,
+                TransactionInner::Transfer { amount, .. } => {
+                    history.net += amount;
+                    if stands {
+                        histories.entry(txn.client_id).or_default().net -= amount;
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Adjustments aren't cached
+        for taken in self.taken_ids.values() {
+            if let (TransactionInner::Adjustment { amount, .. }, Outcome::Applied) =
+                (&taken.inner, taken.outcome)
+            {
+                histories.entry(taken.client_id).or_default().net += *amount;
+            }
+        }
+
+        let mut violations = Vec::new();
+        let none = History::default();
+        for (client_id, account) in self.sorted_accounts() {
+            let history = histories.get(&client_id).unwrap_or(&none);
+
+            if account.held != history.disputed + history.authorized {
+                violations.push(Violation::HeldMismatch {
+                    client_id,
+                    held: account.held,
+                    disputed: history.disputed,
+                    open_disputes: history.open_disputes,
+                    authorized: history.authorized,
+                });
+            }
+            if account.locked && !history.charged_back {
+                violations.push(Violation::LockedWithoutChargeback { client_id });
+            }
+            if account.held < FixedFloat::default() && history.negative_disputes == 0 {
+                violations.push(Violation::NegativeHeld {
+                    client_id,
+                    held: account.held,
+                });
+            }
+            if account.total() > history.net {
+                violations.push(Violation::TotalExceedsNet {
+                    client_id,
+                    total: account.total(),
+                    net: history.net,
+                });
+            }
+        }
+
+        violations
+    }
+
+    // Whether `txn`, which is cached, was applied when it arrived. A state rebuilt from events
+    // (see `apply_event`) has no record of the ids taken, so there it's taken on trust.
+    fn applied(&self, txn: &Transaction) -> bool {
+        self.taken_ids
+            .get(&txn.transaction_id)
+            .is_none_or(|taken| taken.outcome == Outcome::Applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Violation;
+    use crate::{
+        state::{AccountState, Event, State},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_verify_invariants() {
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(5.0.into())),
+            (2, TransactionInner::Withdrawal(2.0.into())),
+            (1, TransactionInner::Dispute),
+            (2, TransactionInner::Dispute),
+            (1, TransactionInner::Chargeback),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+        assert_eq!(state.verify_invariants(), []);
+
+        let state = State::from_accounts([(
+            ClientId(2),
+            AccountState::new(0.0.into(), (-1.0).into(), true),
+        )]);
+        assert_eq!(
+            state.verify_invariants(),
+            [
+                Violation::HeldMismatch {
+                    client_id: ClientId(2),
+                    held: (-1.0).into(),
+                    disputed: 0.0.into(),
+                    open_disputes: 0,
+                    authorized: 0.0.into(),
+                },
+                Violation::LockedWithoutChargeback {
+                    client_id: ClientId(2)
+                },
+                Violation::NegativeHeld {
+                    client_id: ClientId(2),
+                    held: (-1.0).into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_money_from_nowhere() {
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(1.0.into())),
+            (2, TransactionInner::Withdrawal(5.0.into())),
+            (
+                3,
+                TransactionInner::Transfer {
+                    to: ClientId(2),
+                    amount: 0.5.into(),
+                },
+            ),
+            (
+                4,
+                TransactionInner::Adjustment {
+                    amount: 2.0.into(),
+                    reference: "T-4".into(),
+                },
+            ),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(1),
+                    inner,
+                ))
+                .unwrap();
+        }
+        assert_eq!(state.verify_invariants(), []);
+
+        // What disputing and charging back the declined withdrawal used to do
+        let (client_id, transaction_id) = (ClientId(1), TransactionId(2));
+        state.apply_event(&Event::DisputeOpened {
+            client_id,
+            transaction_id,
+            amount: (-5.0).into(),
+            portion: None,
+            reason: None,
+        });
+        assert_eq!(
+            state.verify_invariants(),
+            [Violation::NegativeHeld {
+                client_id,
+                held: (-5.0).into(),
+            }]
+        );
+        state.apply_event(&Event::ChargedBack {
+            client_id,
+            transaction_id,
+            amount: (-5.0).into(),
+            reason: None,
+        });
+        assert_eq!(
+            state.verify_invariants(),
+            [Violation::TotalExceedsNet {
+                client_id,
+                total: 7.5.into(),
+                net: 2.5.into(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,474 @@
+//! Hooks for library users to follow what `State::process` does as it happens, e.g. to keep
+//! metrics or raise alerts, without wrapping every call to it.
+
+use super::{Event, IgnoreReason, State};
+use crate::types::{Amount, ClientId, FixedFloat, TransactionId, TransactionInner};
+use alloc::boxed::Box;
+
+/// Told of each change `State::process` makes, once it's been made. Every method does nothing by
+/// default, so an observer only implements the ones it cares about. Replaying an event log with
+/// `State::apply_event` doesn't call them.
+pub trait Observer<A: Amount = FixedFloat>: Send {
+    fn deposit_applied(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// A deposit that was ignored, because of `reason`: `AccountLocked`.
+    fn deposit_rejected(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+        _reason: IgnoreReason,
+    ) {
+    }
+
+    fn withdrawal_applied(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// A withdrawal that was ignored, because of `reason`: `InsufficientFunds` or
+    /// `AccountLocked`.
+    fn withdrawal_rejected(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+        _reason: IgnoreReason,
+    ) {
+    }
+
+    /// `amount` moved from `client_id` to `to`.
+    fn transfer_applied(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _to: ClientId,
+        _amount: A,
+    ) {
+    }
+
+    /// A transfer that was ignored, because of `reason`: `InsufficientFunds` or `AccountLocked`.
+    fn transfer_rejected(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _to: ClientId,
+        _amount: A,
+        _reason: IgnoreReason,
+    ) {
+    }
+
+    /// A charged back transfer's `amount` went back to its sender, `client_id`.
+    fn transfer_reversed(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// An operator's correction of `amount` (negative for a debit), made for `reference`.
+    fn adjustment_applied(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+        _reference: &str,
+    ) {
+    }
+
+    /// `amount` moved from available to held; it's negative for a disputed withdrawal.
+    fn dispute_opened(&mut self, _client_id: ClientId, _transaction_id: TransactionId, _amount: A) {
+    }
+
+    fn dispute_resolved(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    fn charged_back(&mut self, _client_id: ClientId, _transaction_id: TransactionId, _amount: A) {}
+
+    /// The dispute wasn't resolved or charged back in time, so it was resolved by itself.
+    fn dispute_expired(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    fn account_locked(&mut self, _client_id: ClientId) {}
+
+    /// The account was unlocked, recorded against `transaction_id`, the chargeback that locked it.
+    fn account_unlocked(&mut self, _client_id: ClientId, _transaction_id: TransactionId) {}
+
+    /// A representment won back the charged back `transaction_id`, adding `amount` (negative for a
+    /// withdrawal) to available funds.
+    fn chargeback_reversed(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// A refund undid the deposit or withdrawal `transaction_id`, adding `amount` (negative for a
+    /// deposit) to available funds.
+    fn transaction_reversed(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// `amount` moved from available to held, pending a capture or void.
+    fn authorization_applied(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// An authorization that was ignored, because of `reason`: `InsufficientFunds` or
+    /// `AccountLocked`.
+    fn authorization_rejected(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+        _reason: IgnoreReason,
+    ) {
+    }
+
+    /// The authorization's held `amount` was debited, as a withdrawal.
+    fn authorization_captured(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// The authorization's held `amount` was released back to available.
+    fn authorization_voided(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// The authorization wasn't captured or voided in time, so its held `amount` was released
+    /// back to available by itself.
+    fn authorization_expired(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+
+    /// The account was closed, with `payout` available to be paid out.
+    fn account_closed(&mut self, _client_id: ClientId, _transaction_id: TransactionId, _payout: A) {
+    }
+
+    /// A deposit, withdrawal or transfer that was refused because of a closed account.
+    fn closed_account_refused(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _amount: A,
+    ) {
+    }
+    /// A transaction that was held back until `client_id`'s account is unlocked. Once it is,
+    /// whatever the transaction then does is observed as usual.
+    fn transaction_queued(
+        &mut self,
+        _client_id: ClientId,
+        _transaction_id: TransactionId,
+        _inner: &TransactionInner<A>,
+    ) {
+    }
+}
+
+impl<A: Amount> State<A> {
+    /// Calls `observer` for every change made by `process` from now on, after any observers
+    /// added before it.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer<A>>) {
+        self.observers.push(observer);
+    }
+
+    pub(super) fn notify_observers(&mut self, events: &[Event<A>]) {
+        for observer in &mut self.observers {
+            for event in events {
+                notify(observer.as_mut(), event);
+            }
+        }
+    }
+}
+
+fn notify<A: Amount>(observer: &mut dyn Observer<A>, event: &Event<A>) {
+    match *event {
+        Event::AccountOpened { .. } => {}
+        Event::FundsDeposited {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.deposit_applied(client_id, transaction_id, amount),
+        Event::DepositBlocked {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.deposit_rejected(
+            client_id,
+            transaction_id,
+            amount,
+            IgnoreReason::AccountLocked,
+        ),
+        Event::FundsWithdrawn {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.withdrawal_applied(client_id, transaction_id, amount),
+        Event::WithdrawalDeclined {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.withdrawal_rejected(
+            client_id,
+            transaction_id,
+            amount,
+            IgnoreReason::InsufficientFunds,
+        ),
+        Event::WithdrawalBlocked {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.withdrawal_rejected(
+            client_id,
+            transaction_id,
+            amount,
+            IgnoreReason::AccountLocked,
+        ),
+        Event::TransferSent {
+            client_id,
+            transaction_id,
+            to,
+            amount,
+        } => observer.transfer_applied(client_id, transaction_id, to, amount),
+        // Covered by `transfer_applied`
+        Event::TransferReceived { .. } => {}
+        Event::TransferDeclined {
+            client_id,
+            transaction_id,
+            to,
+            amount,
+        } => observer.transfer_rejected(
+            client_id,
+            transaction_id,
+            to,
+            amount,
+            IgnoreReason::InsufficientFunds,
+        ),
+        Event::TransferBlocked {
+            client_id,
+            transaction_id,
+            to,
+            amount,
+        } => observer.transfer_rejected(
+            client_id,
+            transaction_id,
+            to,
+            amount,
+            IgnoreReason::AccountLocked,
+        ),
+        Event::TransferReversed {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.transfer_reversed(client_id, transaction_id, amount),
+        Event::AdjustmentApplied {
+            client_id,
+            transaction_id,
+            amount,
+            ref reference,
+        } => observer.adjustment_applied(client_id, transaction_id, amount, reference),
+        Event::DisputeOpened {
+            client_id,
+            transaction_id,
+            amount,
+            ..
+        } => observer.dispute_opened(client_id, transaction_id, amount),
+        Event::DisputeResolved {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.dispute_resolved(client_id, transaction_id, amount),
+        Event::ChargedBack {
+            client_id,
+            transaction_id,
+            amount,
+            ..
+        } => observer.charged_back(client_id, transaction_id, amount),
+        Event::DisputeExpired {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.dispute_expired(client_id, transaction_id, amount),
+        Event::AccountLocked { client_id } => observer.account_locked(client_id),
+        Event::AccountUnlocked {
+            client_id,
+            transaction_id,
+        } => observer.account_unlocked(client_id, transaction_id),
+        Event::ChargebackReversed {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.chargeback_reversed(client_id, transaction_id, amount),
+        Event::TransactionReversed {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.transaction_reversed(client_id, transaction_id, amount),
+        Event::FundsAuthorized {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_applied(client_id, transaction_id, amount),
+        Event::AuthorizationDeclined {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_rejected(
+            client_id,
+            transaction_id,
+            amount,
+            IgnoreReason::InsufficientFunds,
+        ),
+        Event::AuthorizationBlocked {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_rejected(
+            client_id,
+            transaction_id,
+            amount,
+            IgnoreReason::AccountLocked,
+        ),
+        Event::AuthorizationCaptured {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_captured(client_id, transaction_id, amount),
+        Event::AuthorizationVoided {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_voided(client_id, transaction_id, amount),
+        Event::AuthorizationExpired {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.authorization_expired(client_id, transaction_id, amount),
+        Event::AccountClosed {
+            client_id,
+            transaction_id,
+            payout,
+        } => observer.account_closed(client_id, transaction_id, payout),
+        Event::ClosedAccountRefused {
+            client_id,
+            transaction_id,
+            amount,
+        } => observer.closed_account_refused(client_id, transaction_id, amount),
+        Event::TransactionQueued {
+            client_id,
+            transaction_id,
+            ref inner,
+        } => observer.transaction_queued(client_id, transaction_id, inner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Observer;
+    use crate::{
+        state::{IgnoreReason, State},
+        types::{ClientId, FixedFloat, Transaction, TransactionId, TransactionInner},
+    };
+    use std::sync::{Arc, Mutex};
+
+    // Records the calls it cares about as strings
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Observer for Recorder {
+        fn deposit_applied(&mut self, client_id: ClientId, _: TransactionId, amount: FixedFloat) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("deposit {} {}", client_id, amount));
+        }
+
+        fn withdrawal_rejected(
+            &mut self,
+            client_id: ClientId,
+            _: TransactionId,
+            _: FixedFloat,
+            reason: IgnoreReason,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("rejected {} {}", client_id, reason.name()));
+        }
+
+        fn account_locked(&mut self, client_id: ClientId) {
+            self.0.lock().unwrap().push(format!("locked {}", client_id));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut state = State::default();
+        state.add_observer(Box::new(Recorder(calls.clone())));
+
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for transaction in [
+            txn(1, TransactionInner::Deposit(2.0.into())),
+            txn(2, TransactionInner::Withdrawal(3.0.into())),
+            txn(1, TransactionInner::Dispute),
+            txn(1, TransactionInner::Chargeback),
+            txn(3, TransactionInner::Withdrawal(1.0.into())),
+            // A duplicate, which changes nothing
+            txn(1, TransactionInner::Deposit(2.0.into())),
+        ] {
+            state.process(transaction).unwrap();
+        }
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "deposit 1 2",
+                "rejected 1 insufficient_funds",
+                "locked 1",
+                "rejected 1 account_locked",
+            ]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! The rules for disputes, resolves and chargebacks that partners or jurisdictions may disagree
+//! on. `State` asks its `DisputePolicy` at each point where they do, and one other than the
+//! default can be swapped in with `State::set_dispute_policy`.
+
+use crate::types::{Amount, ClientId, FixedFloat, Transaction, TransactionInner};
+use alloc::boxed::Box;
+
+/// Decides the contentious parts of a dispute. Only deposits, withdrawals and transfers are ever
+/// asked about, and withdrawals' amounts only with the default `Options::withdrawal_disputes`;
+/// disputes of anything else are always invalid.
+pub trait DisputePolicy<A: Amount = FixedFloat>: Send {
+    /// How much a dispute of `disputed` moves from available to held (and a resolve or
+    /// chargeback of it, back or out), or `None` if it can't be disputed at all. For a partial
+    /// dispute, `disputed` is cut down to the part disputed (see `Transaction::disputed_part`).
+    fn disputed_amount(&self, disputed: &Transaction<A>) -> Option<A>;
+
+    /// Whether `client_id`, on a dispute, resolve or chargeback row, may refer to `disputed`,
+    /// which may be another client's. Either way, it's `disputed`'s client's account that
+    /// changes, or for a transfer, its recipient's (see `Transaction::disputed_client_id`). Not
+    /// asked if `Options::require_client_match` has already ruled it out.
+    fn may_refer_to(&self, client_id: ClientId, disputed: &Transaction<A>) -> bool;
+
+    /// Whether a dispute may leave its account with `available` funds, which are negative.
+    fn allows_negative_available(&self, available: A) -> bool;
+}
+
+/// The rules as they've always been: a deposit is disputed for its amount, a withdrawal for its
+/// amount negated (putting it back in available while it's held), and a transfer for its amount,
+/// held at the recipient's end; any client id may refer to any transaction; and available funds
+/// may go negative, e.g. when a deposit that's already been withdrawn is disputed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultDisputePolicy;
+
+impl<A: Amount> DisputePolicy<A> for DefaultDisputePolicy {
+    fn disputed_amount(&self, disputed: &Transaction<A>) -> Option<A> {
+        match disputed.inner {
+            TransactionInner::Deposit(amount) | TransactionInner::Transfer { amount, .. } => {
+                Some(amount)
+            }
+            TransactionInner::Withdrawal(amount) => Some(-amount),
+            _ => None,
+        }
+    }
+
+    fn may_refer_to(&self, _client_id: ClientId, _disputed: &Transaction<A>) -> bool {
+        true
+    }
+
+    fn allows_negative_available(&self, _available: A) -> bool {
+        true
+    }
+}
+
+impl<A: Amount> Default for Box<dyn DisputePolicy<A>> {
+    fn default() -> Self {
+        Box::new(DefaultDisputePolicy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisputePolicy;
+    use crate::{
+        state::{IgnoreReason, Outcome, State},
+        types::{ClientId, FixedFloat, Transaction, TransactionId, TransactionInner},
+    };
+
+    // Only deposits are disputable, only by their own client, and never into the negative
+    struct Strict;
+
+    impl DisputePolicy for Strict {
+        fn disputed_amount(&self, disputed: &Transaction) -> Option<FixedFloat> {
+            match disputed.inner {
+                TransactionInner::Deposit(amount) => Some(amount),
+                _ => None,
+            }
+        }
+
+        fn may_refer_to(&self, client_id: ClientId, disputed: &Transaction) -> bool {
+            client_id == disputed.client_id
+        }
+
+        fn allows_negative_available(&self, _available: FixedFloat) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_policy() {
+        let mut state = State::default();
+        state.set_dispute_policy(Box::new(Strict));
+
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let outcomes = [
+            txn(1, 1, TransactionInner::Deposit(5.0.into())),
+            txn(2, 1, TransactionInner::Deposit(3.0.into())),
+            txn(3, 1, TransactionInner::Withdrawal(4.0.into())),
+            txn(3, 1, TransactionInner::Dispute),
+            txn(2, 2, TransactionInner::Dispute),
+            txn(1, 1, TransactionInner::Dispute),
+            txn(2, 1, TransactionInner::Dispute),
+        ]
+        .into_iter()
+        .map(|transaction| state.process(transaction).unwrap())
+        .collect::<Vec<_>>();
+
+        assert!(matches!(
+            outcomes[3..],
+            [
+                Outcome::Ignored(IgnoreReason::NotDisputable),
+                Outcome::Ignored(IgnoreReason::ClientMismatch),
+                Outcome::Ignored(IgnoreReason::InsufficientFunds),
+                Outcome::Applied,
+            ]
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+//! Serde support for `State`, so that embedders can persist or ship one through whatever
+//! serialization layer they already use. (For a compact format of our own, see `snapshot`.)
+//!
+//! A state serializes as its `accounts` (a map from client id to account, in client id order),
+//! its cached `transactions` (in transaction id order), `last_transaction_id`,
+//! `invalid_transactions`, the number of transactions `processed` and the `expiries` of holds due
+//! to expire (a map from transaction id to the number processed they expire after) and the
+//! transactions `queued` until their accounts are unlocked (by client id, then in the order they
+//! arrived), `client_transaction_ids`, each client's largest deposit or withdrawal id (a map
+//! from client id to transaction id) and the `timed_expiries` of holds due to expire at a time (a
+//! map from transaction id to the timestamp they expire after) and the `taken_ids` of every
+//! deposit, withdrawal, transfer, adjustment, authorization and close (a map from transaction id
+//! to its `client`, `type` and `outcome`, which is `applied`, `queued` or the reason it was
+//! ignored), so the same state always serializes the same way. The last six may be missing, for
+//! a state serialized before holds could expire, transactions be queued, ids be checked per
+//! client, holds expire by time or ids be recorded whatever happened to them. As with snapshots,
+//! the options, counters and latencies aren't included: a deserialized state has the default
+//! options, and counts from zero.
+
+use super::{AccountState, Outcome, State, TakenId};
+use crate::types::{ClientId, Transaction, TransactionId};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+#[derive(serde::Serialize)]
+struct StateRef<'a> {
+    accounts: BTreeMap<ClientId, &'a AccountState>,
+    transactions: Vec<Transaction>,
+    last_transaction_id: Option<TransactionId>,
+    invalid_transactions: usize,
+    processed: u64,
+    expiries: BTreeMap<TransactionId, u64>,
+    queued: Vec<&'a Transaction>,
+    client_transaction_ids: &'a BTreeMap<ClientId, TransactionId>,
+    timed_expiries: BTreeMap<TransactionId, u64>,
+    taken_ids: &'a BTreeMap<TransactionId, TakenId>,
+}
+
+#[derive(serde::Deserialize)]
+struct StateFields {
+    accounts: BTreeMap<ClientId, AccountState>,
+    transactions: Vec<Transaction>,
+    last_transaction_id: Option<TransactionId>,
+    invalid_transactions: usize,
+    #[serde(default)]
+    processed: u64,
+    #[serde(default)]
+    expiries: BTreeMap<TransactionId, u64>,
+    #[serde(default)]
+    queued: Vec<Transaction>,
+    #[serde(default)]
+    client_transaction_ids: BTreeMap<ClientId, TransactionId>,
+    #[serde(default)]
+    timed_expiries: BTreeMap<TransactionId, u64>,
+    #[serde(default)]
+    taken_ids: BTreeMap<TransactionId, TakenId>,
+}
+
+// As its name, e.g. `"insufficient_funds"` for a withdrawal declined for that reason
+impl serde::Serialize for Outcome {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Outcome {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl serde::Serialize for State {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut transactions = self.storage.transactions().iter().collect::<Vec<_>>();
+        transactions.sort_unstable_by_key(|txn| txn.transaction_id);
+
+        StateRef {
+            accounts: self.accounts().collect(),
+            transactions,
+            last_transaction_id: self.last_transaction_id,
+            invalid_transactions: self.invalid_transactions,
+            processed: self.processed,
+            expiries: self.expiries.iter().collect(),
+            queued: self.queued().collect(),
+            client_transaction_ids: &self.client_transaction_ids,
+            timed_expiries: self.timed_expiries.iter().collect(),
+            taken_ids: &self.taken_ids,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for State {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = StateFields::deserialize(deserializer)?;
+        let mut state = State {
+            last_transaction_id: fields.last_transaction_id,
+            invalid_transactions: fields.invalid_transactions,
+            processed: fields.processed,
+            client_transaction_ids: fields.client_transaction_ids,
+            taken_ids: fields.taken_ids,
+            ..State::from_accounts(fields.accounts)
+        };
+        for txn in fields.transactions {
+            state.storage.transactions_mut().insert(txn);
+        }
+        for (transaction_id, deadline) in fields.expiries {
+            state.expiries.insert(transaction_id, deadline);
+        }
+        for (transaction_id, deadline) in fields.timed_expiries {
+            state.timed_expiries.insert(transaction_id, deadline);
+        }
+        for txn in fields.queued {
+            state.queued.entry(txn.client_id).or_default().push(txn);
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = State::default();
+        for (transaction_id, inner) in [
+            (1, TransactionInner::Deposit(1.5.into())),
+            (2, TransactionInner::Withdrawal(0.5.into())),
+            (1, TransactionInner::Dispute),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(3),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            json,
+            "{\"accounts\":{\"3\":{\"available\":\"-0.5000\",\"held\":\"1.5000\",\
+            \"locked\":false,\"closed\":false}},\
+            \"transactions\":[\
+            {\"tx\":1,\"client\":3,\"type\":{\"deposit\":\"1.5000\"},\"state\":\"disputed\",\
+            \"disputes\":1},\
+            {\"tx\":2,\"client\":3,\"type\":{\"withdrawal\":\"0.5000\"},\"state\":\"alive\"}],\
+            \"last_transaction_id\":2,\"invalid_transactions\":0,\"processed\":3,\
+            \"expiries\":{},\"queued\":[],\"client_transaction_ids\":{\"3\":2},\"timed_expiries\":{},\
+            \"taken_ids\":{\"1\":{\"client\":3,\"type\":{\"deposit\":\"1.5000\"},\
+            \"outcome\":\"applied\"},\
+            \"2\":{\"client\":3,\"type\":{\"withdrawal\":\"0.5000\"},\"outcome\":\"applied\"}}}"
+        );
+
+        let read: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.fingerprint(), state.fingerprint());
+        assert_eq!(read.last_transaction_id, state.last_transaction_id);
+        assert_eq!(read.client_transaction_ids, state.client_transaction_ids);
+        assert_eq!(read.taken_ids, state.taken_ids);
+    }
+}
@@ -0,0 +1,496 @@
+//! A compact binary encoding of a `State`, so that a run can be saved and carried on with (or
+//! inspected) later. All integers are little-endian:
+//!
+//! - the magic bytes `OCTSTATE` and a `u32` format version (1)
+//! - the last deposit/withdrawal/transfer/adjustment/authorization/close id: a `u8` of 1 then a
+//!   `u32`, or a `u8` of 0
+//! - the number of invalid transactions: `u64`
+//! - the number of transactions processed: `u64`
+//! - the number of accounts (`u64`), then for each, in client id order: the client id (`u16`),
+//!   the available and held amounts in ten-thousandths (`i64` each), and its flags (`u8`: 1 if
+//!   it's locked, plus 2 if it's closed)
+//! - the number of cached transactions (`u64`), then for each, in transaction id order: the
+//!   transaction id (`u32`), the client id (`u16`), the type (`u8`: 0 for a deposit, 1 for a
+//!   withdrawal, 2 for a transfer, 3 for an authorization, 4 for an adjustment, 5 for a close),
+//!   the amount in ten-thousandths (`i64`, 0 for a close), for a transfer the client id it's to
+//!   (`u16`), for an adjustment its reference's length in bytes (`u16`) and UTF-8, and its
+//!   state (`u8`: 0 for alive, 1 for disputed, 2 for charged back, 3 for unlocked, 4 for
+//!   reversed, 5 for represented, 6 for declined, plus 128 if only part of it is disputed, in
+//!   which case that part follows in ten-thousandths as an `i64`, plus 64 if its dispute or
+//!   chargeback gave a reason code, which then follows as its length in bytes (`u16`) and UTF-8,
+//!   plus 32 if it's ever been disputed, in which case the number of times follows (`u32`), and
+//!   plus 16 if it has a timestamp, which follows last in seconds since the Unix epoch (`u64`))
+//! - the number of holds due to expire (`u64`), then for each, in transaction id order: the
+//!   transaction id (`u32`) and the number of transactions processed it expires after (`u64`)
+//! - the number of transactions queued until their accounts are unlocked (`u64`), then for each,
+//!   in client id order and then the order they arrived in: the transaction id (`u32`), the client
+//!   id (`u16`), the type, amount and any recipient or reference, as for a cached transaction,
+//!   and its timestamp: a `u8` of 1 then the seconds since the Unix epoch (`u64`), or a `u8` of 0
+//! - the number of clients who've made a deposit or withdrawal (`u64`), then for each, in client
+//!   id order: the client id (`u16`) and the largest id of those deposits and withdrawals (`u32`)
+//! - the number of holds due to expire at a time (`u64`), then for each, in transaction id order:
+//!   the transaction id (`u32`) and the timestamp it expires after, in seconds since the Unix
+//!   epoch (`u64`)
+//! - the number of deposit, withdrawal, transfer, adjustment, authorization and close ids taken
+//!   (`u64`), then for each, in transaction id order: the transaction id, client id, type, amount
+//!   and any recipient or reference, as for a cached transaction, and its outcome's name (as in
+//!   `Outcome::name`), as its length in bytes (`u8`) and UTF-8
+//!
+//! The same state always encodes to the same bytes. The options aren't saved, since they're
+//! decided by whoever carries on with the state, and neither are the counters.
+
+use super::{AccountState, State, TakenId};
+#[cfg(feature = "io")]
+use crate::io::write_file_atomically;
+use crate::types::{
+    ClientId, FixedFloat, Transaction, TransactionId, TransactionInner, TransactionState,
+};
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"OCTSTATE";
+const VERSION: u32 = 1;
+// Set in a transaction's state if only part of it is disputed
+const PARTIAL: u8 = 0x80;
+// Set in a transaction's state if it has a reason code
+const REASON: u8 = 0x40;
+// Set in a transaction's state if it's ever been disputed
+const DISPUTES: u8 = 0x20;
+// Set in a transaction's state if it has a timestamp
+const TIMESTAMP: u8 = 0x10;
+
+impl State {
+    /// Writes the state to `path` in the snapshot format, atomically.
+    #[cfg(feature = "io")]
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Error> {
+        write_file_atomically(path, |f| self.write_snapshot(f))
+    }
+
+    /// Reads a state written by `save_snapshot`, with the default options.
+    pub fn load_snapshot(path: &Path) -> Result<Self, Error> {
+        Self::read_snapshot(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_snapshot<Writer: Write>(&self, mut f: Writer) -> Result<(), Error> {
+        f.write_all(MAGIC)?;
+        f.write_all(&VERSION.to_le_bytes())?;
+
+        match self.last_transaction_id {
+            Some(transaction_id) => {
+                f.write_all(&[1])?;
+                f.write_all(&transaction_id.0.to_le_bytes())?;
+            }
+            None => f.write_all(&[0])?,
+        }
+        f.write_all(&(self.invalid_transactions as u64).to_le_bytes())?;
+        f.write_all(&self.processed.to_le_bytes())?;
+
+        let accounts = self.sorted_accounts();
+        f.write_all(&(accounts.len() as u64).to_le_bytes())?;
+        for (client_id, account) in accounts {
+            f.write_all(&client_id.0.to_le_bytes())?;
+            f.write_all(&account.available.raw().to_le_bytes())?;
+            f.write_all(&account.held.raw().to_le_bytes())?;
+            f.write_all(&[account.locked as u8 | (account.closed as u8) << 1])?;
+        }
+
+        let mut transactions = self.storage.transactions().iter().collect::<Vec<_>>();
+        transactions.sort_unstable_by_key(|txn| txn.transaction_id);
+        f.write_all(&(transactions.len() as u64).to_le_bytes())?;
+        for txn in transactions {
+            write_transaction(&mut f, &txn)?;
+            let state = match txn.state {
+                TransactionState::Alive => 0,
+                TransactionState::Disputed => 1,
+                TransactionState::ChargedBack => 2,
+                TransactionState::Unlocked => 3,
+                TransactionState::Reversed => 4,
+                TransactionState::Represented => 5,
+                TransactionState::Declined => 6,
+            };
+            let partial = if txn.disputed.is_some() { PARTIAL } else { 0 };
+            let reason = if txn.reason.is_some() { REASON } else { 0 };
+            let disputes = if txn.disputes > 0 { DISPUTES } else { 0 };
+            let timestamp = if txn.timestamp.is_some() {
+                TIMESTAMP
+            } else {
+                0
+            };
+            f.write_all(&[state | partial | reason | disputes | timestamp])?;
+            if let Some(disputed) = txn.disputed {
+                f.write_all(&disputed.raw().to_le_bytes())?;
+            }
+            if let Some(reason) = &txn.reason {
+                let len = u16::try_from(reason.len())
+                    .map_err(|_| Error::new(ErrorKind::InvalidInput, "Reason code is too long"))?;
+                f.write_all(&len.to_le_bytes())?;
+                f.write_all(reason.as_bytes())?;
+            }
+            if txn.disputes > 0 {
+                f.write_all(&txn.disputes.to_le_bytes())?;
+            }
+            if let Some(timestamp) = txn.timestamp {
+                f.write_all(&timestamp.to_le_bytes())?;
+            }
+        }
+
+        f.write_all(&(self.expiries.iter().len() as u64).to_le_bytes())?;
+        for (transaction_id, deadline) in self.expiries.iter() {
+            f.write_all(&transaction_id.0.to_le_bytes())?;
+            f.write_all(&deadline.to_le_bytes())?;
+        }
+
+        f.write_all(&(self.queued.values().map(Vec::len).sum::<usize>() as u64).to_le_bytes())?;
+        for txn in self.queued.values().flatten() {
+            write_transaction(&mut f, txn)?;
+            match txn.timestamp {
+                Some(timestamp) => {
+                    f.write_all(&[1])?;
+                    f.write_all(&timestamp.to_le_bytes())?;
+                }
+                None => f.write_all(&[0])?,
+            }
+        }
+
+        f.write_all(&(self.client_transaction_ids.len() as u64).to_le_bytes())?;
+        for (client_id, transaction_id) in &self.client_transaction_ids {
+            f.write_all(&client_id.0.to_le_bytes())?;
+            f.write_all(&transaction_id.0.to_le_bytes())?;
+        }
+
+        f.write_all(&(self.timed_expiries.iter().len() as u64).to_le_bytes())?;
+        for (transaction_id, deadline) in self.timed_expiries.iter() {
+            f.write_all(&transaction_id.0.to_le_bytes())?;
+            f.write_all(&deadline.to_le_bytes())?;
+        }
+
+        f.write_all(&(self.taken_ids.len() as u64).to_le_bytes())?;
+        for (&transaction_id, taken) in &self.taken_ids {
+            let txn = Transaction::new(transaction_id, taken.client_id, taken.inner.clone());
+            write_transaction(&mut f, &txn)?;
+            let outcome = taken.outcome.name();
+            f.write_all(&[outcome.len() as u8])?;
+            f.write_all(outcome.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_snapshot<Reader: Read>(mut f: Reader) -> Result<Self, Error> {
+        let mut magic = [0; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("Not a state snapshot".into()));
+        }
+        let version = u32::from_le_bytes(read(&mut f)?);
+        if version != VERSION {
+            return Err(invalid(format!(
+                "Unsupported state snapshot version {}",
+                version
+            )));
+        }
+
+        let last_transaction_id = match read::<1>(&mut f)? {
+            [0] => None,
+            [1] => Some(TransactionId(u32::from_le_bytes(read(&mut f)?))),
+            [other] => return Err(invalid(format!("Invalid flag {}", other))),
+        };
+        let mut state = State {
+            last_transaction_id,
+            invalid_transactions: u64::from_le_bytes(read(&mut f)?) as usize,
+            processed: u64::from_le_bytes(read(&mut f)?),
+            ..Default::default()
+        };
+
+        let accounts = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..accounts {
+            let client_id = ClientId(u16::from_le_bytes(read(&mut f)?));
+            let available = FixedFloat::from_raw(i64::from_le_bytes(read(&mut f)?));
+            let held = FixedFloat::from_raw(i64::from_le_bytes(read(&mut f)?));
+            let account = match read::<1>(&mut f)? {
+                [flags @ 0..=3] => AccountState {
+                    available,
+                    held,
+                    locked: flags & 1 != 0,
+                    closed: flags & 2 != 0,
+                },
+                [other] => return Err(invalid(format!("Invalid account flags {}", other))),
+            };
+            *state.storage.account_mut(client_id) = account;
+        }
+
+        let transactions = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..transactions {
+            let mut txn = read_transaction(&mut f)?;
+            let flags = read::<1>(&mut f)?[0];
+            if flags & PARTIAL != 0 {
+                txn.disputed = Some(FixedFloat::from_raw(i64::from_le_bytes(read(&mut f)?)));
+            }
+            if flags & REASON != 0 {
+                let mut reason = vec![0; u16::from_le_bytes(read(&mut f)?) as usize];
+                f.read_exact(&mut reason)?;
+                txn.reason = Some(
+                    String::from_utf8(reason).map_err(|_| invalid("Invalid reason code".into()))?,
+                );
+            }
+            if flags & DISPUTES != 0 {
+                txn.disputes = u32::from_le_bytes(read(&mut f)?);
+            }
+            if flags & TIMESTAMP != 0 {
+                txn.timestamp = Some(u64::from_le_bytes(read(&mut f)?));
+            }
+            txn.state = match flags & !(PARTIAL | REASON | DISPUTES | TIMESTAMP) {
+                0 => TransactionState::Alive,
+                1 => TransactionState::Disputed,
+                2 => TransactionState::ChargedBack,
+                3 => TransactionState::Unlocked,
+                4 => TransactionState::Reversed,
+                5 => TransactionState::Represented,
+                6 => TransactionState::Declined,
+                other => return Err(invalid(format!("Invalid transaction state {}", other))),
+            };
+            state.storage.transactions_mut().insert(txn);
+        }
+
+        let expiries = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..expiries {
+            let transaction_id = TransactionId(u32::from_le_bytes(read(&mut f)?));
+            let deadline = u64::from_le_bytes(read(&mut f)?);
+            state.expiries.insert(transaction_id, deadline);
+        }
+
+        let queued = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..queued {
+            let mut txn = read_transaction(&mut f)?;
+            txn.timestamp = match read::<1>(&mut f)? {
+                [0] => None,
+                [1] => Some(u64::from_le_bytes(read(&mut f)?)),
+                [other] => return Err(invalid(format!("Invalid flag {}", other))),
+            };
+            state.queued.entry(txn.client_id).or_default().push(txn);
+        }
+
+        let clients = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..clients {
+            let client_id = ClientId(u16::from_le_bytes(read(&mut f)?));
+            let transaction_id = TransactionId(u32::from_le_bytes(read(&mut f)?));
+            state
+                .client_transaction_ids
+                .insert(client_id, transaction_id);
+        }
+
+        let expiries = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..expiries {
+            let transaction_id = TransactionId(u32::from_le_bytes(read(&mut f)?));
+            let deadline = u64::from_le_bytes(read(&mut f)?);
+            state.timed_expiries.insert(transaction_id, deadline);
+        }
+
+        let taken_ids = u64::from_le_bytes(read(&mut f)?);
+        for _ in 0..taken_ids {
+            let txn = read_transaction(&mut f)?;
+            let mut outcome = vec![0; read::<1>(&mut f)?[0] as usize];
+            f.read_exact(&mut outcome)?;
+            let outcome = core::str::from_utf8(&outcome)
+                .ok()
+                .and_then(|outcome| outcome.parse().ok())
+                .ok_or_else(|| invalid("Invalid outcome".into()))?;
+            state.taken_ids.insert(
+                txn.transaction_id,
+                TakenId {
+                    client_id: txn.client_id,
+                    inner: txn.inner,
+                    outcome,
+                },
+            );
+        }
+
+        Ok(state)
+    }
+}
+
+// Writes the transaction's id, client, type, amount and any recipient or reference
+fn write_transaction(f: &mut impl Write, txn: &Transaction) -> Result<(), Error> {
+    let (type_, amount) = match &txn.inner {
+        TransactionInner::Deposit(amount) => (0, *amount),
+        TransactionInner::Withdrawal(amount) => (1, *amount),
+        TransactionInner::Transfer { amount, .. } => (2, *amount),
+        TransactionInner::Authorize(amount) => (3, *amount),
+        TransactionInner::Adjustment { amount, .. } => (4, *amount),
+        TransactionInner::Close => (5, FixedFloat::default()),
+        // Only the types that take an id are cached, queued or recorded
+        _ => unreachable!(),
+    };
+    f.write_all(&txn.transaction_id.0.to_le_bytes())?;
+    f.write_all(&txn.client_id.0.to_le_bytes())?;
+    f.write_all(&[type_])?;
+    f.write_all(&amount.raw().to_le_bytes())?;
+    match &txn.inner {
+        TransactionInner::Transfer { to, .. } => f.write_all(&to.0.to_le_bytes())?,
+        TransactionInner::Adjustment { reference, .. } => {
+            let len = u16::try_from(reference.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Reference is too long"))?;
+            f.write_all(&len.to_le_bytes())?;
+            f.write_all(reference.as_bytes())?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Reads what `write_transaction` wrote
+fn read_transaction(f: &mut impl Read) -> Result<Transaction, Error> {
+    let transaction_id = TransactionId(u32::from_le_bytes(read(f)?));
+    let client_id = ClientId(u16::from_le_bytes(read(f)?));
+    let type_ = read::<1>(f)?[0];
+    let amount = FixedFloat::from_raw(i64::from_le_bytes(read(f)?));
+    let inner = match type_ {
+        0 => TransactionInner::Deposit(amount),
+        1 => TransactionInner::Withdrawal(amount),
+        2 => TransactionInner::Transfer {
+            to: ClientId(u16::from_le_bytes(read(f)?)),
+            amount,
+        },
+        3 => TransactionInner::Authorize(amount),
+        4 => {
+            let mut reference = vec![0; u16::from_le_bytes(read(f)?) as usize];
+            f.read_exact(&mut reference)?;
+            TransactionInner::Adjustment {
+                amount,
+                reference: String::from_utf8(reference)
+                    .map_err(|_| invalid("Invalid reference".into()))?,
+            }
+        }
+        5 => TransactionInner::Close,
+        other => return Err(invalid(format!("Invalid transaction type {}", other))),
+    };
+    Ok(Transaction::new(transaction_id, client_id, inner))
+}
+
+fn read<const N: usize>(f: &mut impl Read) -> Result<[u8; N], Error> {
+    let mut bytes = [0; N];
+    f.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::{Options, Outcome, State},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut state = State::default();
+        for (transaction_id, client_id, inner) in [
+            (1, 1, TransactionInner::Deposit(1.5.into())),
+            (2, 2, TransactionInner::Deposit(2.0.into())),
+            (3, 2, TransactionInner::Withdrawal(0.5.into())),
+            (1, 1, TransactionInner::Dispute),
+            (2, 2, TransactionInner::PartialDispute(1.0.into())),
+        ] {
+            let mut txn =
+                Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner);
+            txn.timestamp = Some(1_700_000_000 + transaction_id as u64);
+            state.process(txn).unwrap();
+        }
+        let mut chargeback =
+            Transaction::new(TransactionId(2), ClientId(2), TransactionInner::Chargeback);
+        chargeback.reason = Some("fraud".into());
+        state.process(chargeback).unwrap();
+        // Queued on the account the chargeback locked
+        state.set_options(Options {
+            locked_accounts: "withdrawal=queue".parse().unwrap(),
+            ..Default::default()
+        });
+        let mut withdrawal = Transaction::new(
+            TransactionId(4),
+            ClientId(2),
+            TransactionInner::Withdrawal(0.25.into()),
+        );
+        withdrawal.timestamp = Some(1_700_000_004);
+        assert_eq!(state.process(withdrawal.clone()).unwrap(), Outcome::Queued);
+        // Ids that are taken without being cached
+        for (transaction_id, inner) in [
+            (
+                5,
+                TransactionInner::Adjustment {
+                    amount: 1.0.into(),
+                    reference: "T-5".into(),
+                },
+            ),
+            (6, TransactionInner::Withdrawal(100.0.into())),
+            (7, TransactionInner::Close),
+        ] {
+            state
+                .process(Transaction::new(
+                    TransactionId(transaction_id),
+                    ClientId(3),
+                    inner,
+                ))
+                .unwrap();
+        }
+
+        let mut bytes = Vec::new();
+        state.write_snapshot(&mut bytes).unwrap();
+        let mut loaded = State::read_snapshot(&bytes[..]).unwrap();
+
+        assert_eq!(loaded.sorted_accounts(), state.sorted_accounts());
+        assert_eq!(loaded.stats().disputed_transactions, 1);
+        let charged_back = loaded.transaction(TransactionId(2)).unwrap();
+        assert_eq!(charged_back.disputed, Some(1.0.into()));
+        assert_eq!(charged_back.reason, Some("fraud".into()));
+        assert_eq!(charged_back.disputes, 1);
+        assert_eq!(charged_back.timestamp, Some(1_700_000_002));
+        assert_eq!(
+            loaded.transaction(TransactionId(3)).unwrap().timestamp,
+            Some(1_700_000_003)
+        );
+        let queued = loaded
+            .queued()
+            .map(|txn| {
+                (
+                    txn.transaction_id,
+                    txn.client_id,
+                    txn.inner.clone(),
+                    txn.timestamp,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            queued,
+            [(
+                withdrawal.transaction_id,
+                withdrawal.client_id,
+                withdrawal.inner,
+                withdrawal.timestamp
+            )]
+        );
+        let mut again = Vec::new();
+        loaded.write_snapshot(&mut again).unwrap();
+        assert_eq!(again, bytes);
+        assert_eq!(loaded.client_transaction_ids, state.client_transaction_ids);
+        assert_eq!(loaded.taken_ids, state.taken_ids);
+
+        // Carries on where it left off, e.g. resolving the open dispute
+        loaded
+            .process(Transaction::new(
+                TransactionId(1),
+                ClientId(1),
+                TransactionInner::Resolve,
+            ))
+            .unwrap();
+        assert_eq!(loaded.account(ClientId(1)).unwrap().available(), 1.5.into());
+
+        assert!(State::read_snapshot(&b"OCTSTATE\x02\0\0\0"[..]).is_err());
+        assert!(State::read_snapshot(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
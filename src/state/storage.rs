@@ -0,0 +1,176 @@
+//! Where `State` keeps the accounts and the transaction cache, so that the business logic in
+//! `State::process` doesn't depend on how they're stored. The default, `MemoryStorage`, keeps the
+//! accounts in a map and the transactions in a `TransactionStore`; a `Storage` of an embedder's
+//! own (e.g. one sharding the transactions across machines) can be swapped in with
+//! `State::set_storage`.
+
+use super::{map_bytes, AccountState, HashMap, TransactionStore};
+use crate::types::{Amount, ClientId, FixedFloat};
+use alloc::boxed::Box;
+
+/// The accounts, by client id, and the transaction cache.
+///
+/// Accounts are handed out by reference, as there can only ever be 65536 of them, so a storage
+/// keeps them in memory however it keeps the transactions.
+pub trait Storage<A: Amount = FixedFloat>: Send {
+    fn account(&self, client_id: ClientId) -> Option<&AccountState<A>>;
+
+    /// The account, opened with nothing in it if it doesn't exist yet.
+    fn account_mut(&mut self, client_id: ClientId) -> &mut AccountState<A>;
+
+    /// Every account, in no particular order.
+    fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountState<A>)> + '_>;
+
+    fn account_count(&self) -> usize;
+
+    fn transactions(&self) -> &dyn TransactionStore<A>;
+
+    fn transactions_mut(&mut self) -> &mut dyn TransactionStore<A>;
+
+    /// Roughly how much memory the accounts and the transaction cache are using, in bytes.
+    fn memory_usage(&self) -> usize;
+}
+
+/// The accounts in a map, and the transactions in any `TransactionStore`, by default also in
+/// memory.
+pub struct MemoryStorage<A: Amount = FixedFloat> {
+    accounts: HashMap<ClientId, AccountState<A>>,
+    transactions: Box<dyn TransactionStore<A>>,
+}
+
+impl<A: Amount> MemoryStorage<A> {
+    /// Keeps the transactions in `transactions`, which should be empty.
+    pub fn with_transaction_store(transactions: Box<dyn TransactionStore<A>>) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            transactions,
+        }
+    }
+}
+
+impl<A: Amount> Default for MemoryStorage<A> {
+    fn default() -> Self {
+        Self::with_transaction_store(Box::default())
+    }
+}
+
+impl<A: Amount> Storage<A> for MemoryStorage<A> {
+    fn account(&self, client_id: ClientId) -> Option<&AccountState<A>> {
+        self.accounts.get(&client_id)
+    }
+
+    fn account_mut(&mut self, client_id: ClientId) -> &mut AccountState<A> {
+        self.accounts.entry(client_id).or_default()
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountState<A>)> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(client_id, account)| (*client_id, account)),
+        )
+    }
+
+    fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn transactions(&self) -> &dyn TransactionStore<A> {
+        self.transactions.as_ref()
+    }
+
+    fn transactions_mut(&mut self) -> &mut dyn TransactionStore<A> {
+        self.transactions.as_mut()
+    }
+
+    /// What the account map has allocated, which is up to twice what's in it, as it grows by
+    /// doubling, plus what the transaction store is using.
+    fn memory_usage(&self) -> usize {
+        map_bytes(&self.accounts) + self.transactions.memory_usage()
+    }
+}
+
+impl<A: Amount> Default for Box<dyn Storage<A>> {
+    fn default() -> Self {
+        Box::new(MemoryStorage::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryStorage, Storage};
+    use crate::{
+        state::{AccountState, State, TransactionStore},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // Keeps everything in memory, counting the account writes
+    #[derive(Default)]
+    struct Counting {
+        inner: MemoryStorage,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl Storage for Counting {
+        fn account(&self, client_id: ClientId) -> Option<&AccountState> {
+            self.inner.account(client_id)
+        }
+
+        fn account_mut(&mut self, client_id: ClientId) -> &mut AccountState {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            self.inner.account_mut(client_id)
+        }
+
+        fn accounts(&self) -> Box<dyn Iterator<Item = (ClientId, &AccountState)> + '_> {
+            self.inner.accounts()
+        }
+
+        fn account_count(&self) -> usize {
+            self.inner.account_count()
+        }
+
+        fn transactions(&self) -> &dyn TransactionStore {
+            self.inner.transactions()
+        }
+
+        fn transactions_mut(&mut self) -> &mut dyn TransactionStore {
+            self.inner.transactions_mut()
+        }
+
+        fn memory_usage(&self) -> usize {
+            self.inner.memory_usage()
+        }
+    }
+
+    #[test]
+    fn test_set_storage() {
+        let txn = |transaction_id, client_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(client_id), inner)
+        };
+        let mut state = State::default();
+        state
+            .process(txn(1, 1, TransactionInner::Deposit(2.0.into())))
+            .unwrap();
+
+        let storage = Counting::default();
+        let writes = storage.writes.clone();
+        state.set_storage(Box::new(storage));
+        // Moving the account in is one write
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+
+        for transaction in [
+            txn(2, 2, TransactionInner::Deposit(1.0.into())),
+            txn(1, 1, TransactionInner::Dispute),
+        ] {
+            state.process(transaction).unwrap();
+        }
+        assert_eq!(writes.load(Ordering::Relaxed), 4);
+        assert_eq!(state.stats().accounts, 2);
+        assert_eq!(state.account(ClientId(1)).unwrap().held(), 2.0.into());
+        assert!(state.transaction(TransactionId(2)).is_some());
+    }
+}
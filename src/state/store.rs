@@ -0,0 +1,85 @@
+//! Where `State` keeps its transaction cache. The accounts are always in memory, as there can only
+//! ever be 65536 of them, but every deposit and withdrawal is cached in case it's disputed later,
+//! so on a big enough input the cache won't fit in memory. A `TransactionStore` other than the
+//! default in-memory map can be swapped in with `State::set_transaction_store`.
+
+use super::{map_bytes, HashMap};
+use crate::types::{Amount, FixedFloat, Transaction, TransactionId, TransactionState};
+use alloc::boxed::Box;
+
+#[cfg(feature = "sled")]
+mod sled;
+
+#[cfg(feature = "sled")]
+pub use self::sled::SledStore;
+
+/// The cached transactions, by id. Only deposits, withdrawals, transfers and authorizations are
+/// ever cached.
+///
+/// Transactions are handed in and out by value, so that a store doesn't need to keep them in
+/// memory. A store that can fail, such as one on disk, panics if it does: a transaction cache that
+/// has lost transactions can't carry on correctly anyway.
+pub trait TransactionStore<A: Amount = FixedFloat>: Send {
+    fn get(&self, transaction_id: TransactionId) -> Option<Transaction<A>>;
+
+    fn contains(&self, transaction_id: TransactionId) -> bool {
+        self.get(transaction_id).is_some()
+    }
+
+    /// Caches `txn`, replacing any transaction with the same id.
+    fn insert(&mut self, txn: Transaction<A>);
+
+    /// Changes the state of a cached transaction. Does nothing if it isn't cached.
+    fn set_state(&mut self, transaction_id: TransactionId, state: TransactionState);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every cached transaction, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = Transaction<A>> + '_>;
+
+    /// Roughly how much memory the store is using, in bytes.
+    fn memory_usage(&self) -> usize;
+}
+
+impl<A: Amount> TransactionStore<A> for HashMap<TransactionId, Transaction<A>> {
+    fn get(&self, transaction_id: TransactionId) -> Option<Transaction<A>> {
+        HashMap::get(self, &transaction_id).cloned()
+    }
+
+    fn contains(&self, transaction_id: TransactionId) -> bool {
+        self.contains_key(&transaction_id)
+    }
+
+    fn insert(&mut self, txn: Transaction<A>) {
+        HashMap::insert(self, txn.transaction_id, txn);
+    }
+
+    fn set_state(&mut self, transaction_id: TransactionId, state: TransactionState) {
+        if let Some(txn) = self.get_mut(&transaction_id) {
+            txn.state = state;
+        }
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Transaction<A>> + '_> {
+        Box::new(self.values().cloned())
+    }
+
+    /// What the map has allocated, which is up to twice what's in it, as it grows by doubling.
+    fn memory_usage(&self) -> usize {
+        map_bytes(self)
+    }
+}
+
+impl<A: Amount> Default for Box<dyn TransactionStore<A>> {
+    fn default() -> Self {
+        Box::new(HashMap::<TransactionId, Transaction<A>>::new())
+    }
+}
@@ -0,0 +1,219 @@
+use super::TransactionStore;
+use crate::types::{
+    ClientId, FixedFloat, Transaction, TransactionId, TransactionInner, TransactionState,
+};
+use std::path::{Path, PathBuf};
+
+// How much of the store sled keeps cached in memory
+const CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// A transaction cache on disk, in a sled database, so that it's only limited by disk space.
+/// It's scratch space for one run: whatever is at the path when it's opened is cleared, and it's
+/// deleted when the store is dropped. To keep the transactions, save the state with
+/// `State::save_snapshot`.
+///
+/// Keys are transaction ids (big-endian `u32`, so that they sort). Values are the client id
+/// (`u16`), type (`u8`: 0 for a deposit, 1 for a withdrawal, 2 for a transfer, 3 for an
+/// authorization), amount in ten-thousandths (`i64`), state (`u8`: 0 for alive, 1 for disputed,
+/// 2 for charged back, 3 for unlocked, 4 for reversed, 5 for represented), the client id a
+/// transfer is to (`u16`, 0 for anything else) and the part of it disputed, if only part of it
+/// is (`u8` of 1 then an `i64`, or all zeros) and the number of times it's been disputed
+/// (`u32`), little-endian, followed by its reason code in UTF-8, if it has one.
+pub struct SledStore {
+    // Fields are dropped in order, so the database is closed before its directory is removed
+    db: ::sled::Db,
+    len: usize,
+    _dir: RemoveOnDrop,
+}
+
+// sled's own `temporary` mode removes the directory from a background thread, which may not get
+// to it before the process exits
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl SledStore {
+    pub fn open(path: &Path) -> Result<Self, std::io::Error> {
+        let db = ::sled::Config::new()
+            .path(path)
+            .cache_capacity(CACHE_BYTES)
+            .mode(::sled::Mode::HighThroughput)
+            .flush_every_ms(None)
+            .open()?;
+        db.clear()?;
+        Ok(Self {
+            db,
+            len: 0,
+            _dir: RemoveOnDrop(path.into()),
+        })
+    }
+}
+
+impl TransactionStore for SledStore {
+    fn get(&self, transaction_id: TransactionId) -> Option<Transaction> {
+        self.db
+            .get(transaction_id.0.to_be_bytes())
+            .expect("Failed to read from the transaction store")
+            .map(|value| decode(transaction_id, &value))
+    }
+
+    fn contains(&self, transaction_id: TransactionId) -> bool {
+        self.db
+            .contains_key(transaction_id.0.to_be_bytes())
+            .expect("Failed to read from the transaction store")
+    }
+
+    fn insert(&mut self, txn: Transaction) {
+        let previous = self
+            .db
+            .insert(txn.transaction_id.0.to_be_bytes(), encode(&txn))
+            .expect("Failed to write to the transaction store");
+        if previous.is_none() {
+            self.len += 1;
+        }
+    }
+
+    fn set_state(&mut self, transaction_id: TransactionId, state: TransactionState) {
+        if let Some(mut txn) = self.get(transaction_id) {
+            txn.state = state;
+            self.insert(txn);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Transaction> + '_> {
+        Box::new(self.db.iter().map(|entry| {
+            let (key, value) = entry.expect("Failed to read from the transaction store");
+            let transaction_id =
+                TransactionId(u32::from_be_bytes(key.as_ref().try_into().unwrap()));
+            decode(transaction_id, &value)
+        }))
+    }
+
+    /// sled's cache, which is most of what it uses.
+    fn memory_usage(&self) -> usize {
+        CACHE_BYTES as usize
+    }
+}
+
+fn encode(txn: &Transaction) -> Vec<u8> {
+    let (type_, amount, to) = match txn.inner {
+        TransactionInner::Deposit(amount) => (0, amount, ClientId(0)),
+        TransactionInner::Withdrawal(amount) => (1, amount, ClientId(0)),
+        TransactionInner::Transfer { to, amount } => (2, amount, to),
+        TransactionInner::Authorize(amount) => (3, amount, ClientId(0)),
+        // Only deposits, withdrawals, transfers and authorizations are cached
+        _ => unreachable!(),
+    };
+    let mut value = vec![0; 27];
+    value[..2].copy_from_slice(&txn.client_id.0.to_le_bytes());
+    value[2] = type_;
+    value[3..11].copy_from_slice(&amount.raw().to_le_bytes());
+    value[11] = match txn.state {
+        TransactionState::Alive => 0,
+        TransactionState::Disputed => 1,
+        TransactionState::ChargedBack => 2,
+        TransactionState::Unlocked => 3,
+        TransactionState::Reversed => 4,
+        TransactionState::Represented => 5,
+        TransactionState::Declined => 6,
+    };
+    value[12..14].copy_from_slice(&to.0.to_le_bytes());
+    if let Some(disputed) = txn.disputed {
+        value[14] = 1;
+        value[15..23].copy_from_slice(&disputed.raw().to_le_bytes());
+    }
+    value[23..27].copy_from_slice(&txn.disputes.to_le_bytes());
+    if let Some(reason) = &txn.reason {
+        value.extend_from_slice(reason.as_bytes());
+    }
+    value
+}
+
+// Only ever given what `encode` wrote
+fn decode(transaction_id: TransactionId, value: &[u8]) -> Transaction {
+    let amount = FixedFloat::from_raw(i64::from_le_bytes(value[3..11].try_into().unwrap()));
+    let inner = match value[2] {
+        0 => TransactionInner::Deposit(amount),
+        1 => TransactionInner::Withdrawal(amount),
+        2 => TransactionInner::Transfer {
+            to: ClientId(u16::from_le_bytes(value[12..14].try_into().unwrap())),
+            amount,
+        },
+        _ => TransactionInner::Authorize(amount),
+    };
+    let mut txn = Transaction::new(
+        transaction_id,
+        ClientId(u16::from_le_bytes(value[..2].try_into().unwrap())),
+        inner,
+    );
+    txn.state = match value[11] {
+        0 => TransactionState::Alive,
+        1 => TransactionState::Disputed,
+        2 => TransactionState::ChargedBack,
+        3 => TransactionState::Unlocked,
+        4 => TransactionState::Reversed,
+        5 => TransactionState::Represented,
+        _ => TransactionState::Declined,
+    };
+    if value[14] == 1 {
+        txn.disputed = Some(FixedFloat::from_raw(i64::from_le_bytes(
+            value[15..23].try_into().unwrap(),
+        )));
+    }
+    txn.disputes = u32::from_le_bytes(value[23..27].try_into().unwrap());
+    if value.len() > 27 {
+        txn.reason = Some(String::from_utf8_lossy(&value[27..]).into_owned());
+    }
+    txn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SledStore;
+    use crate::{
+        state::State,
+        types::{ClientId, Transaction, TransactionId, TransactionInner, TransactionState},
+    };
+
+    #[test]
+    fn test_sled_store() {
+        let path = std::env::temp_dir().join(format!("sled-store-{}", std::process::id()));
+        let mut state = State::default();
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        state
+            .process(txn(1, TransactionInner::Deposit(3.0.into())))
+            .unwrap();
+
+        // What was cached in memory moves over
+        state.set_transaction_store(Box::new(SledStore::open(&path).unwrap()));
+        for txn in [
+            txn(2, TransactionInner::Withdrawal(1.0.into())),
+            txn(1, TransactionInner::Dispute),
+            txn(1, TransactionInner::Chargeback),
+        ] {
+            state.process(txn).unwrap();
+        }
+
+        assert_eq!(state.stats().cached_transactions, 2);
+        assert_eq!(
+            state.transaction(TransactionId(1)).unwrap().state,
+            TransactionState::ChargedBack
+        );
+        let account = state.account(ClientId(1)).unwrap();
+        assert_eq!(account.available(), (-1.0).into());
+        assert!(account.locked());
+
+        drop(state);
+        assert!(!path.exists());
+    }
+}
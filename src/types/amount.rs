@@ -0,0 +1,68 @@
+use super::FixedFloat;
+use core::{
+    fmt::Debug,
+    ops::{Add, AddAssign, Neg, SubAssign},
+};
+
+/// The numeric type that `State` keeps balances and transaction amounts in. `FixedFloat` is the
+/// default, and what every input and output format uses; a library user who needs other semantics
+/// (e.g. integer cents, or `rust_decimal::Decimal` with the `decimal` feature) can build
+/// transactions with their own type and process them with a `State` of it, without the amounts
+/// ever going through an `f64`.
+///
+/// The default, i.e. `A::default()`, must be zero.
+pub trait Amount:
+    Copy
+    + Default
+    + PartialOrd
+    + Debug
+    + Add<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Neg<Output = Self>
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl Amount for FixedFloat {}
+
+/// Amounts in whole minor units, e.g. cents.
+impl Amount for i64 {}
+
+#[cfg(feature = "decimal")]
+impl Amount for rust_decimal::Decimal {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        state::{IgnoreReason, Outcome, State},
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+
+    #[test]
+    fn test_integer_amounts() {
+        let mut state = State::<i64>::new();
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+        for transaction in [
+            txn(1, TransactionInner::Deposit(150)),
+            txn(2, TransactionInner::Withdrawal(200)),
+            txn(3, TransactionInner::Withdrawal(50)),
+            txn(1, TransactionInner::Dispute),
+        ] {
+            state.process(transaction).unwrap();
+        }
+
+        let account = state.account(ClientId(1)).unwrap();
+        assert_eq!((account.available(), account.held()), (-50, 150));
+        assert_eq!(
+            state
+                .process(txn(4, TransactionInner::Withdrawal(1)))
+                .unwrap(),
+            Outcome::Ignored(IgnoreReason::InsufficientFunds)
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! The glue between the input formats and `Transaction`: each format deserializes its rows into
+//! `TransactionFields` with serde, which are then checked and turned into transactions.
+
+use super::{ClientId, FixedFloat, Transaction, TransactionId, TransactionInner};
+use std::{convert::TryFrom, error::Error};
+
+impl TryFrom<TransactionFields> for Transaction {
+    type Error = TransactionFieldsError;
+
+    fn try_from(fields: TransactionFields) -> Result<Self, Self::Error> {
+        // Exactly, so that an amount with more decimal places than are kept isn't rounded
+        let amount = fields
+            .amount
+            .as_deref()
+            .map(str::parse::<FixedFloat>)
+            .transpose()
+            .map_err(TransactionFieldsError::InvalidAmount)?;
+        let mut txn = Transaction::new(
+            TransactionId(fields.transaction_id),
+            ClientId(fields.client_id),
+            match fields.type_.as_str() {
+                "deposit" => TransactionInner::Deposit(
+                    amount.ok_or(TransactionFieldsError::DepositMissingAmount)?,
+                ),
+                "withdrawal" => TransactionInner::Withdrawal(
+                    amount.ok_or(TransactionFieldsError::WithdrawalMissingAmount)?,
+                ),
+                "transfer" => TransactionInner::Transfer {
+                    to: ClientId(
+                        fields
+                            .to
+                            .ok_or(TransactionFieldsError::TransferMissingRecipient)?,
+                    ),
+                    amount: amount.ok_or(TransactionFieldsError::TransferMissingAmount)?,
+                },
+                "adjustment" => TransactionInner::Adjustment {
+                    amount: amount.ok_or(TransactionFieldsError::AdjustmentMissingAmount)?,
+                    reference: fields
+                        .reference
+                        .filter(|reference| !reference.trim().is_empty())
+                        .ok_or(TransactionFieldsError::AdjustmentMissingReference)?,
+                },
+                "dispute" => match amount {
+                    Some(amount) => TransactionInner::PartialDispute(amount),
+                    None => TransactionInner::Dispute,
+                },
+                "resolve" => TransactionInner::Resolve,
+                "chargeback" => TransactionInner::Chargeback,
+                "unlock" => TransactionInner::Unlock,
+                "refund" => TransactionInner::Refund,
+                "representment" => TransactionInner::Representment,
+                "authorize" => TransactionInner::Authorize(
+                    amount.ok_or(TransactionFieldsError::AuthorizeMissingAmount)?,
+                ),
+                "capture" => TransactionInner::Capture,
+                "void" => TransactionInner::Void,
+                "close" => TransactionInner::Close,
+                other => return Err(TransactionFieldsError::UnrecognisedType(other.into())),
+            },
+        );
+        check_amount(&txn.inner)?;
+        if txn.inner.takes_reason() {
+            txn.reason = fields.reason.filter(|reason| !reason.trim().is_empty());
+        }
+        txn.timestamp = fields.timestamp;
+        Ok(txn)
+    }
+}
+
+/// Checks that a deposit, withdrawal, transfer or authorization moves a positive amount, since a
+/// negative one would move the funds the wrong way. Adjustments can be negative, and a partial
+/// dispute's amount is checked against the transaction it disputes.
+pub(crate) fn check_amount(inner: &TransactionInner) -> Result<(), TransactionFieldsError> {
+    match *inner {
+        TransactionInner::Deposit(amount)
+        | TransactionInner::Withdrawal(amount)
+        | TransactionInner::Transfer { amount, .. }
+        | TransactionInner::Authorize(amount)
+            if amount <= FixedFloat::default() =>
+        {
+            Err(TransactionFieldsError::NonPositiveAmount {
+                type_name: inner.type_name(),
+                amount,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// An intermediate type to leverage the serde deserialisation provided by the csv crate.
+/// We save a bit of memory by not storing these in the `State`, but instead storing the slimmer
+/// `Transaction` type. It should be possible to avoid this intermediate type by overloading
+/// various `serde` functions, but it would probably be quite fiddly.
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct TransactionFields {
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    #[serde(rename = "tx")]
+    pub transaction_id: u32,
+    /// The amount as it was written, e.g. `1.5`, so that it's parsed exactly rather than going
+    /// through a float, which would quietly drop decimal places that don't fit in one. Formats
+    /// that give a number rather than text have it written out as its shortest decimal.
+    #[serde(default, deserialize_with = "amount_text")]
+    pub amount: Option<String>,
+    /// The client a transfer is to. Optional, so that inputs without transfers can leave the
+    /// column out altogether.
+    #[serde(default)]
+    pub to: Option<u16>,
+    /// The operator reference an adjustment must have, and is optional in the same way.
+    #[serde(default)]
+    pub reference: Option<String>,
+    /// The reason code a dispute or chargeback may give, and is optional in the same way.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// When the transaction happened, in seconds since the Unix epoch. Optional for every type.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+fn amount_text<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    struct Visitor;
+
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a decimal amount")
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: serde::Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<Self::Value, D::Error> {
+            deserializer.deserialize_any(self)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            Ok(Some(v.into()))
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            Ok(Some(v.to_string()))
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(Some(v.to_string()))
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+            Ok(Some(v.to_string()))
+        }
+    }
+
+    deserializer.deserialize_option(Visitor)
+}
+
+/// This error is returned when the fields of the transaction as parsed don't make sense.
+#[derive(Debug)]
+pub enum TransactionFieldsError {
+    DepositMissingAmount,
+    WithdrawalMissingAmount,
+    TransferMissingAmount,
+    TransferMissingRecipient,
+    AdjustmentMissingAmount,
+    AdjustmentMissingReference,
+    AuthorizeMissingAmount,
+    /// An amount that can't be held exactly, e.g. one with more than `FixedFloat::SCALE` decimal
+    /// places, saying what's wrong with it.
+    InvalidAmount(String),
+    /// A deposit, withdrawal, transfer or authorization of zero or less.
+    NonPositiveAmount {
+        type_name: &'static str,
+        amount: FixedFloat,
+    },
+    UnrecognisedType(String),
+}
+
+impl std::fmt::Display for TransactionFieldsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DepositMissingAmount => write!(f, "Deposit \"amount\" field is blank"),
+            Self::WithdrawalMissingAmount => write!(f, "Withdrawal \"amount\" field is blank"),
+            Self::TransferMissingAmount => write!(f, "Transfer \"amount\" field is blank"),
+            Self::TransferMissingRecipient => write!(f, "Transfer \"to\" field is blank"),
+            Self::AdjustmentMissingAmount => write!(f, "Adjustment \"amount\" field is blank"),
+            Self::AdjustmentMissingReference => {
+                write!(f, "Adjustment \"reference\" field is blank")
+            }
+            Self::AuthorizeMissingAmount => write!(f, "Authorize \"amount\" field is blank"),
+            Self::InvalidAmount(message) => write!(f, "{}", message),
+            Self::NonPositiveAmount { type_name, amount } => write!(
+                f,
+                "The \"amount\" field of a {} must be positive, not {}",
+                type_name, amount
+            ),
+            Self::UnrecognisedType(other) => {
+                write!(f, "Unrecognised transaction type \"{}\"", other)
+            }
+        }
+    }
+}
+
+impl Error for TransactionFieldsError {}
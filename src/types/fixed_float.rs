@@ -1,13 +1,47 @@
+use alloc::{format, string::String};
+
 #[derive(Debug, Default, PartialEq, PartialOrd, Clone, Copy)]
 pub struct FixedFloat(i64);
 
+impl FixedFloat {
+    /// The number of decimal places kept.
+    pub const SCALE: u32 = 4;
+
+    /// The underlying integer, i.e. the amount in ten-thousandths.
+    pub fn raw(self) -> i64 {
+        self.0
+    }
+
+    /// The inverse of `raw`.
+    pub fn from_raw(raw: i64) -> Self {
+        Self(raw)
+    }
+
+    /// Converts `value` exactly, unlike `From<f64>`, which rounds: it's parsed from the shortest
+    /// decimal that reads back as the same `f64` (i.e. what it was most likely parsed from), so
+    /// that more than `SCALE` decimal places is an error, as with `FromStr`.
+    pub fn from_f64_exact(value: f64) -> Result<Self, String> {
+        format!("{}", value).parse()
+    }
+}
+
 impl From<f64> for FixedFloat {
     fn from(value: f64) -> Self {
-        Self((value * 10000.0).round() as i64)
+        // Rounds half away from zero, as `f64::round` would, but that needs std
+        let scaled = value * 10000.0;
+        let truncated = scaled as i64;
+        let fraction = scaled - truncated as f64;
+        Self(if fraction >= 0.5 {
+            truncated.saturating_add(1)
+        } else if fraction <= -0.5 {
+            truncated.saturating_sub(1)
+        } else {
+            truncated
+        })
     }
 }
 
-impl std::ops::Add for FixedFloat {
+impl core::ops::Add for FixedFloat {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
@@ -15,19 +49,19 @@ impl std::ops::Add for FixedFloat {
     }
 }
 
-impl std::ops::AddAssign for FixedFloat {
+impl core::ops::AddAssign for FixedFloat {
     fn add_assign(&mut self, other: Self) {
         self.0 += other.0;
     }
 }
 
-impl std::ops::SubAssign for FixedFloat {
+impl core::ops::SubAssign for FixedFloat {
     fn sub_assign(&mut self, other: Self) {
         self.0 -= other.0;
     }
 }
 
-impl std::ops::Neg for FixedFloat {
+impl core::ops::Neg for FixedFloat {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
@@ -35,9 +69,126 @@ impl std::ops::Neg for FixedFloat {
     }
 }
 
-impl std::fmt::Display for FixedFloat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0 as f64 / 10000.0)
+/// Without a precision, amounts are written as briefly as possible (`0`, `1.5`). With one of at
+/// least `SCALE` (e.g. `{:.4}`), they're written exactly, padded with zeros (`0.0000`, `1.5000`).
+impl core::fmt::Display for FixedFloat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match f.precision() {
+            Some(precision) if precision >= Self::SCALE as usize => {
+                let scale = 10u64.pow(Self::SCALE);
+                let abs = self.0.unsigned_abs();
+                write!(
+                    f,
+                    "{}{}.{:0<precision$}",
+                    if self.0 < 0 { "-" } else { "" },
+                    abs / scale,
+                    format!("{:0width$}", abs % scale, width = Self::SCALE as usize),
+                    precision = precision
+                )
+            }
+            Some(precision) => write!(f, "{:.*}", precision, self.0 as f64 / 10000.0),
+            None => write!(f, "{}", self.0 as f64 / 10000.0),
+        }
+    }
+}
+
+/// Parses a decimal exactly, e.g. `-1.5` or `12.3456`. More than `SCALE` decimal places is an
+/// error rather than being rounded away.
+impl core::str::FromStr for FixedFloat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid amount \"{}\"", s);
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        if fraction.len() > Self::SCALE as usize {
+            return Err(format!(
+                "Amount \"{}\" has more than {} decimal places",
+                s,
+                Self::SCALE
+            ));
+        }
+
+        let scale = 10i64.pow(Self::SCALE);
+        let whole = match whole {
+            "" => 0,
+            whole => whole.parse::<i64>().map_err(|_| invalid())?,
+        };
+        let fraction = format!("{:0<width$}", fraction, width = Self::SCALE as usize)
+            .parse::<i64>()
+            .map_err(|_| invalid())?;
+        let raw = whole
+            .checked_mul(scale)
+            .and_then(|raw| raw.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(Self(if negative { -raw } else { raw }))
+    }
+}
+
+/// In human-readable formats such as JSON, amounts are exact decimal strings with `SCALE` places
+/// (`"1.5000"`), since a float could lose precision on the way through. In binary formats,
+/// they're the raw integer (see `raw`).
+impl serde::Serialize for FixedFloat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&format_args!("{:.4}", self))
+        } else {
+            serializer.serialize_i64(self.0)
+        }
+    }
+}
+
+/// The inverse of the `Serialize` implementation, except that human-readable formats also accept
+/// plain numbers, e.g. `1.5`.
+impl<'de> serde::Deserialize<'de> for FixedFloat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = FixedFloat;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "a decimal amount")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                v.checked_mul(10i64.pow(FixedFloat::SCALE))
+                    .map(FixedFloat)
+                    .ok_or_else(|| E::custom("Amount out of range"))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                let v = i64::try_from(v).map_err(|_| E::custom("Amount out of range"))?;
+                self.visit_i64(v)
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                FixedFloat::from_f64_exact(v).map_err(E::custom)
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(Visitor)
+        } else {
+            i64::deserialize(deserializer).map(Self)
+        }
     }
 }
 
@@ -46,6 +197,7 @@ mod tests {
     use super::FixedFloat;
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_creating_from_f64() {
         // Basic
         assert_eq!(FixedFloat::from(0.0), FixedFloat(0));
@@ -62,6 +214,20 @@ mod tests {
         // Rounding (shouldn't be needed)
         assert_eq!(FixedFloat::from(-123.45671), FixedFloat(-1234567));
         assert_eq!(FixedFloat::from(-123.45679), FixedFloat(-1234568));
+
+        // Halves round away from zero
+        assert_eq!(FixedFloat::from(0.00005), FixedFloat(1));
+        assert_eq!(FixedFloat::from(-0.00005), FixedFloat(-1));
+    }
+
+    #[test]
+    fn test_creating_from_f64_out_of_range() {
+        // Saturates, like a cast, rather than wrapping around when rounding up
+        assert_eq!(FixedFloat::from(1e30), FixedFloat(i64::MAX));
+        assert_eq!(FixedFloat::from(-1e30), FixedFloat(i64::MIN));
+        assert_eq!(FixedFloat::from(f64::INFINITY), FixedFloat(i64::MAX));
+        assert_eq!(FixedFloat::from(f64::NEG_INFINITY), FixedFloat(i64::MIN));
+        assert_eq!(FixedFloat::from(f64::NAN), FixedFloat(0));
     }
 
     #[test]
@@ -98,5 +264,60 @@ mod tests {
             FixedFloat(-9999888877776).to_string().as_str(),
             "-999988887.7776"
         );
+
+        // With a precision
+        assert_eq!(format!("{:.4}", FixedFloat(0)), "0.0000");
+        assert_eq!(format!("{:.4}", FixedFloat(15000)), "1.5000");
+        assert_eq!(format!("{:.4}", FixedFloat(-5)), "-0.0005");
+        assert_eq!(format!("{:.6}", FixedFloat(12345)), "1.234500");
+        assert_eq!(format!("{:.2}", FixedFloat(12345)), "1.23");
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("1.5".parse(), Ok(FixedFloat(15000)));
+        assert_eq!("-0.0005".parse(), Ok(FixedFloat(-5)));
+        assert_eq!("12".parse(), Ok(FixedFloat(120000)));
+        assert_eq!(".25".parse(), Ok(FixedFloat(2500)));
+        assert_eq!("999988887.7776".parse(), Ok(FixedFloat(9999888877776)));
+
+        for invalid in ["", "-", ".", "1.2.3", "1e5", "+1", " 1", "abc"] {
+            assert!(invalid.parse::<FixedFloat>().is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            "1.23456".parse::<FixedFloat>(),
+            Err("Amount \"1.23456\" has more than 4 decimal places".into())
+        );
+    }
+
+    #[test]
+    fn test_from_f64_exact() {
+        assert_eq!(FixedFloat::from_f64_exact(1.1), Ok(FixedFloat(11000)));
+        assert_eq!(FixedFloat::from_f64_exact(-0.0005), Ok(FixedFloat(-5)));
+        assert_eq!(
+            FixedFloat::from_f64_exact(123456789.1234),
+            Ok(FixedFloat(1234567891234))
+        );
+        assert_eq!(
+            FixedFloat::from_f64_exact(1.00005),
+            Err("Amount \"1.00005\" has more than 4 decimal places".into())
+        );
+        assert!(FixedFloat::from_f64_exact(f64::NAN).is_err());
+        assert!(FixedFloat::from_f64_exact(1e300).is_err());
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(
+            serde_json::to_string(&FixedFloat(-15000)).unwrap(),
+            "\"-1.5000\""
+        );
+        for (json, expected) in [("\"-1.5000\"", -15000), ("2", 20000), ("0.25", 2500)] {
+            assert_eq!(
+                serde_json::from_str::<FixedFloat>(json).unwrap(),
+                FixedFloat(expected)
+            );
+        }
+        assert!(serde_json::from_str::<FixedFloat>("0.00001").is_err());
     }
 }
@@ -1,7 +1,13 @@
+mod amount;
+#[cfg(feature = "io")]
+mod fields;
 mod fixed_float;
 mod transaction;
 
+pub use amount::Amount;
+#[cfg(feature = "protobuf")]
+pub(crate) use fields::check_amount;
+#[cfg(feature = "io")]
+pub use fields::{TransactionFields, TransactionFieldsError};
 pub use fixed_float::FixedFloat;
-pub use transaction::{
-    ClientId, Transaction, TransactionFields, TransactionId, TransactionInner, TransactionState,
-};
+pub use transaction::{ClientId, Transaction, TransactionId, TransactionInner, TransactionState};
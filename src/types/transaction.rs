@@ -1,127 +1,247 @@
-use super::FixedFloat;
-use std::{convert::TryFrom, error::Error};
+use super::{Amount, FixedFloat};
+use alloc::string::String;
 
 // A "type-safe" transaction id. Probably overkill!
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
 pub struct TransactionId(pub u32);
 
-impl std::fmt::Display for TransactionId {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
 // A "type-safe" client id. Probably overkill!
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
 pub struct ClientId(pub u16);
 
-impl std::fmt::Display for ClientId {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
-#[derive(Clone)]
-pub struct Transaction {
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Transaction<A: Amount = FixedFloat> {
+    #[serde(rename = "tx")]
     pub transaction_id: TransactionId,
+    #[serde(rename = "client")]
     pub client_id: ClientId,
-    pub inner: TransactionInner,
+    #[serde(rename = "type")]
+    pub inner: TransactionInner<A>,
     pub state: TransactionState,
+    /// If only part of the amount is disputed (or was, before it was charged back), that part.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disputed: Option<A>,
+    /// The reason code a dispute or chargeback gives, e.g. a card scheme's, for telling fraud from
+    /// goods not received. On a cached transaction, the one its latest dispute or chargeback gave.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// How many times it's been disputed, for `Options::max_disputes`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disputes: u32,
+    /// When it happened, in seconds since the Unix epoch, if the input said. It's kept on the
+    /// cached (or queued) copy, along with everything else here, though not by events, which
+    /// don't carry it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
 }
 
-impl Transaction {
+impl<A: Amount> Transaction<A> {
     pub fn new(
         transaction_id: TransactionId,
         client_id: ClientId,
-        inner: TransactionInner,
+        inner: TransactionInner<A>,
     ) -> Self {
         Self {
             transaction_id,
             client_id,
             inner,
             state: TransactionState::Alive,
+            disputed: None,
+            reason: None,
+            disputes: 0,
+            timestamp: None,
         }
     }
-}
 
-impl TryFrom<TransactionFields> for Transaction {
-    type Error = TransactionFieldsError;
-
-    fn try_from(fields: TransactionFields) -> Result<Self, Self::Error> {
-        Ok(Transaction::new(
-            TransactionId(fields.transaction_id),
-            ClientId(fields.client_id),
-            match fields.type_.as_str() {
-                "deposit" => TransactionInner::Deposit(
-                    fields
-                        .amount
-                        .ok_or(TransactionFieldsError::DepositMissingAmount)?
-                        .into(),
-                ),
-                "withdrawal" => TransactionInner::Withdrawal(
-                    fields
-                        .amount
-                        .ok_or(TransactionFieldsError::WithdrawalMissingAmount)?
-                        .into(),
-                ),
-                "dispute" => TransactionInner::Dispute,
-                "resolve" => TransactionInner::Resolve,
-                "chargeback" => TransactionInner::Chargeback,
-                other => return Err(TransactionFieldsError::UnrecognisedType(other.into())),
-            },
-        ))
+    /// The transaction as far as its dispute goes: itself, or if only part of it is disputed (see
+    /// `disputed`), the same transaction for just that part.
+    pub fn disputed_part(&self) -> Self {
+        let mut part = self.clone();
+        if let Some(disputed) = self.disputed {
+            if let TransactionInner::Deposit(amount)
+            | TransactionInner::Withdrawal(amount)
+            | TransactionInner::Transfer { amount, .. } = &mut part.inner
+            {
+                *amount = disputed;
+            }
+        }
+        part
+    }
+
+    /// The client whose funds a dispute of this transaction holds: the recipient of a transfer,
+    /// or the transaction's own client for anything else.
+    pub fn disputed_client_id(&self) -> ClientId {
+        match self.inner {
+            TransactionInner::Transfer { to, .. } => to,
+            _ => self.client_id,
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionState {
     Alive,
     Disputed,
     ChargedBack,
+    /// Charged back, and then the account lock that caused administratively lifted by an unlock.
+    /// It still can't be disputed again.
+    Unlocked,
+    /// Undone by a refund, or for an authorization, a void. It can't be disputed, or refunded
+    /// again.
+    Reversed,
+    /// Charged back, and then won back by a representment, restoring its funds. It can't be
+    /// disputed or represented again.
+    Represented,
+    /// A withdrawal declined for insufficient funds, which moved nothing, so it can't be
+    /// disputed or refunded.
+    Declined,
+}
+
+impl TransactionState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Alive => "alive",
+            Self::Disputed => "disputed",
+            Self::ChargedBack => "charged_back",
+            Self::Unlocked => "unlocked",
+            Self::Reversed => "reversed",
+            Self::Represented => "represented",
+            Self::Declined => "declined",
+        }
+    }
 }
 
-#[derive(Clone)]
-pub enum TransactionInner {
-    Deposit(FixedFloat),
-    Withdrawal(FixedFloat),
+/// Serialized with serde as e.g. `{"deposit": "1.5000"}`, or just `"dispute"` for the types without
+/// an amount. A transfer is `{"transfer": {"to": 2, "amount": "1.5000"}}`, and an adjustment
+/// `{"adjustment": {"amount": "-1.5000", "reference": "TICKET-1"}}`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionInner<A: Amount = FixedFloat> {
+    Deposit(A),
+    Withdrawal(A),
+    /// Moves `amount` from the transaction's client to `to`, all at once or not at all.
+    Transfer {
+        to: ClientId,
+        amount: A,
+    },
+    /// An operator's correction, crediting `amount` (or debiting it, if it's negative) to the
+    /// transaction's client, even if their account is locked. `reference` says who made it and
+    /// why, e.g. a ticket number.
+    Adjustment {
+        amount: A,
+        reference: String,
+    },
     Dispute,
+    /// A dispute of only `amount` of the transaction with this id, which can't be more than its
+    /// amount. In the input, it's a `dispute` row with an amount.
+    PartialDispute(A),
     Resolve,
     Chargeback,
+    /// Unfreezes the account that charging back the transaction with this id locked.
+    Unlock,
+    /// Undoes the deposit or withdrawal with this id, without a dispute or a lock.
+    Refund,
+    /// Restores the funds that charging back the deposit or withdrawal with this id took, as
+    /// the partner won the chargeback.
+    Representment,
+    /// Moves `amount` from available to held, pending a capture or void of this id.
+    Authorize(A),
+    /// Turns the authorization with this id into a withdrawal of what it held.
+    Capture,
+    /// Releases what the authorization with this id held back into available funds.
+    Void,
+    /// Closes the transaction's client's account. What's left available is owed to the client
+    /// (see `AccountState::pending_payout`), and depending on `Options::closed_accounts`, later
+    /// deposits and withdrawals are refused.
+    Close,
 }
 
-/// An intermediate type to leverage the serde deserialisation provided by the csv crate.
-/// We save a bit of memory by not storing these in the `State`, but instead storing the slimmer
-/// `Transaction` type. It should be possible to avoid this intermediate type by overloading
-/// various `serde` functions, but it would probably be quite fiddly.
-#[derive(serde::Deserialize, Debug)]
-pub struct TransactionFields {
-    #[serde(rename = "type")]
-    pub type_: String,
-    #[serde(rename = "client")]
-    pub client_id: u16,
-    #[serde(rename = "tx")]
-    pub transaction_id: u32,
-    pub amount: Option<f64>,
-}
-
-/// This error is returned when the fields of the transaction as parsed don't make sense.
-#[derive(Debug)]
-pub enum TransactionFieldsError {
-    DepositMissingAmount,
-    WithdrawalMissingAmount,
-    UnrecognisedType(String),
-}
+impl<A: Amount> TransactionInner<A> {
+    /// The name used for this type in the `type` column of the input.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Deposit(_) => "deposit",
+            Self::Withdrawal(_) => "withdrawal",
+            Self::Transfer { .. } => "transfer",
+            Self::Adjustment { .. } => "adjustment",
+            Self::Dispute | Self::PartialDispute(_) => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Unlock => "unlock",
+            Self::Refund => "refund",
+            Self::Representment => "representment",
+            Self::Authorize(_) => "authorize",
+            Self::Capture => "capture",
+            Self::Void => "void",
+            Self::Close => "close",
+        }
+    }
 
-impl std::fmt::Display for TransactionFieldsError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// The amount, for the types that carry one.
+    pub fn amount(&self) -> Option<A> {
         match self {
-            Self::DepositMissingAmount => write!(f, "Deposit \"amount\" field is blank"),
-            Self::WithdrawalMissingAmount => write!(f, "Withdrawal \"amount\" field is blank"),
-            Self::UnrecognisedType(other) => {
-                write!(f, "Unrecognised transaction type \"{}\"", other)
-            }
+            Self::Deposit(amount)
+            | Self::Withdrawal(amount)
+            | Self::Transfer { amount, .. }
+            | Self::Adjustment { amount, .. }
+            | Self::PartialDispute(amount)
+            | Self::Authorize(amount) => Some(*amount),
+            Self::Dispute
+            | Self::Resolve
+            | Self::Chargeback
+            | Self::Unlock
+            | Self::Refund
+            | Self::Representment
+            | Self::Capture
+            | Self::Void
+            | Self::Close => None,
         }
     }
-}
 
-impl Error for TransactionFieldsError {}
+    /// Whether a row of this type can give a reason code (see `Transaction::reason`): only
+    /// disputes and chargebacks do.
+    pub fn takes_reason(&self) -> bool {
+        matches!(
+            self,
+            Self::Dispute | Self::PartialDispute(_) | Self::Chargeback
+        )
+    }
+
+    /// Whether a row of this type has a new transaction id of its own: deposits, withdrawals,
+    /// transfers, adjustments, authorizations and closes do, and every other type refers back to
+    /// an earlier transaction's.
+    pub fn introduces_id(&self) -> bool {
+        matches!(
+            self,
+            Self::Deposit(_)
+                | Self::Withdrawal(_)
+                | Self::Transfer { .. }
+                | Self::Adjustment { .. }
+                | Self::Authorize(_)
+                | Self::Close
+        )
+    }
+}
@@ -0,0 +1,335 @@
+//! A write-ahead log, so that a long-running engine (see `daemon::run`) can recover everything it
+//! had processed after a crash, rather than only what was in its last snapshot.
+//!
+//! Each transaction is appended to the log, and synced to disk, before it's processed. Every so
+//! often the log is compacted: the whole state is written to a checkpoint next to the log (at its
+//! path with `.state` appended), and the log is started afresh. Recovery loads that checkpoint,
+//! if there is one, and processes every transaction in the log that it doesn't already include.
+//!
+//! The log starts with the magic bytes `OCTWAL01` and the number of transactions logged before it
+//! was started (`u64`), so that it can be matched up with the checkpoint even if a crash came
+//! between writing one and starting the other. Each transaction is then a record: its id (`u32`),
+//! client id (`u16`), type (`u8`, in the order deposit, withdrawal, dispute, resolve, chargeback,
+//! transfer, adjustment, unlock, close, refund, authorize, capture, void, representment, partial
+//! dispute), amount in ten-thousandths (`i64`, 0 if it has none), the client id a transfer is to
+//! (`u16`, 0 for anything else), its timestamp (a `u8` of 1 if it has one, else 0, then the
+//! seconds since the Unix epoch as a `u64`, 0 if it has none), for an adjustment its reference and
+//! for a dispute or chargeback its reason code (its length in bytes as a `u16`, 0 for a dispute or
+//! chargeback without one, then UTF-8), and an FNV-1a hash of all that (`u32`). Integers are
+//! little-endian. A partly written record at the end, from a crash part way through an append, is
+//! ignored.
+
+use super::{
+    checkpoint,
+    state::{Options, State},
+    types::{ClientId, FixedFloat, Transaction, TransactionId, TransactionInner},
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Error, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"OCTWAL01";
+const HEADER_LEN: usize = 16;
+// The length of a record's fields before any text, and before the hash
+const FIELDS_LEN: usize = 26;
+const ADJUSTMENT: u8 = 6;
+// The types whose records have a reason code
+const REASONED: [u8; 3] = [2, 4, 14];
+
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    // The number of transactions logged, including those before the current log was started
+    logged: u64,
+}
+
+impl Wal {
+    /// Recovers the state from the log at `path` (and its checkpoint), and opens the log for
+    /// appending. If there's no log, it's created, and the state is empty. The state processes
+    /// with `options`, both during recovery and afterwards.
+    pub fn recover(path: &Path, options: Options) -> Result<(Self, State), Error> {
+        let (mut state, checkpointed) = match checkpoint::load(&state_path(path)) {
+            Ok(checkpoint) => (checkpoint.state, checkpoint.transactions),
+            Err(err) if err.kind() == ErrorKind::NotFound => (State::default(), 0),
+            Err(err) => return Err(err),
+        };
+        state.set_options(options);
+
+        let (base, records) = match File::open(path) {
+            Ok(file) => read_log(file)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => (checkpointed, Vec::new()),
+            Err(err) => return Err(err),
+        };
+        if base > checkpointed {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} starts after its checkpoint, which must be missing",
+                    path.display()
+                ),
+            ));
+        }
+
+        let mut logged = base;
+        for txn in records {
+            logged += 1;
+            if logged > checkpointed {
+                // A transaction that failed had no effect the first time either
+                let _ = state.process(txn);
+            }
+        }
+
+        // Starting the log afresh also drops any partly written record at its end
+        let mut wal = Self::start(path, logged)?;
+        if logged > checkpointed {
+            wal.compact(&state)?;
+        }
+        Ok((wal, state))
+    }
+
+    /// Appends `txn` to the log, returning once it's on disk.
+    pub fn append(&mut self, txn: &Transaction) -> Result<(), Error> {
+        let (type_, amount, to) = match txn.inner {
+            TransactionInner::Deposit(amount) => (0, amount, ClientId(0)),
+            TransactionInner::Withdrawal(amount) => (1, amount, ClientId(0)),
+            TransactionInner::Dispute => (2, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Resolve => (3, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Chargeback => (4, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Transfer { to, amount } => (5, amount, to),
+            TransactionInner::Adjustment { amount, .. } => (ADJUSTMENT, amount, ClientId(0)),
+            TransactionInner::Unlock => (7, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Close => (8, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Refund => (9, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Authorize(amount) => (10, amount, ClientId(0)),
+            TransactionInner::Capture => (11, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Void => (12, FixedFloat::default(), ClientId(0)),
+            TransactionInner::Representment => (13, FixedFloat::default(), ClientId(0)),
+            TransactionInner::PartialDispute(amount) => (14, amount, ClientId(0)),
+        };
+
+        let mut record = Vec::with_capacity(FIELDS_LEN + 4);
+        record.extend_from_slice(&txn.transaction_id.0.to_le_bytes());
+        record.extend_from_slice(&txn.client_id.0.to_le_bytes());
+        record.push(type_);
+        record.extend_from_slice(&amount.raw().to_le_bytes());
+        record.extend_from_slice(&to.0.to_le_bytes());
+        record.push(txn.timestamp.is_some() as u8);
+        record.extend_from_slice(&txn.timestamp.unwrap_or_default().to_le_bytes());
+        let text = match &txn.inner {
+            TransactionInner::Adjustment { reference, .. } => Some(reference.as_str()),
+            inner if inner.takes_reason() => Some(txn.reason.as_deref().unwrap_or_default()),
+            _ => None,
+        };
+        if let Some(text) = text {
+            let len = u16::try_from(text.len()).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "Reference or reason code is too long",
+                )
+            })?;
+            record.extend_from_slice(&len.to_le_bytes());
+            record.extend_from_slice(text.as_bytes());
+        }
+        let hash = fnv1a(&record);
+        record.extend_from_slice(&hash.to_le_bytes());
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.logged += 1;
+        Ok(())
+    }
+
+    /// Checkpoints `state`, which must include everything logged, and starts the log afresh.
+    pub fn compact(&mut self, state: &State) -> Result<(), Error> {
+        checkpoint::save(&state_path(&self.path), None, self.logged, None, state)?;
+        *self = Self::start(&self.path, self.logged)?;
+        Ok(())
+    }
+
+    // Replaces the log with an empty one starting after `logged` transactions
+    fn start(path: &Path, logged: u64) -> Result<Self, Error> {
+        crate::io::write_file_atomically(path, |f| {
+            f.write_all(MAGIC)?;
+            f.write_all(&logged.to_le_bytes())
+        })?;
+
+        Ok(Self {
+            path: path.into(),
+            file: OpenOptions::new().append(true).open(path)?,
+            logged,
+        })
+    }
+}
+
+fn state_path(path: &Path) -> PathBuf {
+    let mut state_path = path.as_os_str().to_owned();
+    state_path.push(".state");
+    PathBuf::from(state_path)
+}
+
+// Returns the number of transactions logged before the log was started, and those in it
+fn read_log(file: File) -> Result<(u64, Vec<Transaction>), Error> {
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message);
+
+    let mut bytes = Vec::new();
+    BufReader::new(file).read_to_end(&mut bytes)?;
+    if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+        return Err(invalid("Not a write-ahead log"));
+    }
+    let base = u64::from_le_bytes(bytes[8..HEADER_LEN].try_into().unwrap());
+
+    let mut records = Vec::new();
+    let mut rest = &bytes[HEADER_LEN..];
+    while rest.len() >= FIELDS_LEN + 4 {
+        // An adjustment's reference or a reason code comes before the hash
+        let has_text = rest[6] == ADJUSTMENT || REASONED.contains(&rest[6]);
+        let len = if has_text {
+            let text_len = rest[FIELDS_LEN..FIELDS_LEN + 2].try_into().unwrap();
+            FIELDS_LEN + 2 + u16::from_le_bytes(text_len) as usize + 4
+        } else {
+            FIELDS_LEN + 4
+        };
+        if rest.len() < len {
+            break;
+        }
+        let (record, remainder) = rest.split_at(len);
+        rest = remainder;
+
+        let (fields, hash) = record.split_at(len - 4);
+        if fnv1a(fields).to_le_bytes() != hash {
+            return Err(invalid("Corrupt record in write-ahead log"));
+        }
+        let amount = FixedFloat::from_raw(i64::from_le_bytes(record[7..15].try_into().unwrap()));
+        let text = || {
+            String::from_utf8(fields[FIELDS_LEN + 2..].to_vec())
+                .map_err(|_| invalid("Invalid reference or reason code in write-ahead log"))
+        };
+        let inner = match record[6] {
+            0 => TransactionInner::Deposit(amount),
+            1 => TransactionInner::Withdrawal(amount),
+            2 => TransactionInner::Dispute,
+            3 => TransactionInner::Resolve,
+            4 => TransactionInner::Chargeback,
+            5 => TransactionInner::Transfer {
+                to: ClientId(u16::from_le_bytes(record[15..17].try_into().unwrap())),
+                amount,
+            },
+            ADJUSTMENT => TransactionInner::Adjustment {
+                amount,
+                reference: text()?,
+            },
+            7 => TransactionInner::Unlock,
+            8 => TransactionInner::Close,
+            9 => TransactionInner::Refund,
+            10 => TransactionInner::Authorize(amount),
+            11 => TransactionInner::Capture,
+            12 => TransactionInner::Void,
+            13 => TransactionInner::Representment,
+            14 => TransactionInner::PartialDispute(amount),
+            _ => return Err(invalid("Invalid transaction type in write-ahead log")),
+        };
+        let mut txn = Transaction::new(
+            TransactionId(u32::from_le_bytes(record[..4].try_into().unwrap())),
+            ClientId(u16::from_le_bytes(record[4..6].try_into().unwrap())),
+            inner,
+        );
+        if REASONED.contains(&record[6]) {
+            txn.reason = Some(text()?).filter(|reason| !reason.is_empty());
+        }
+        txn.timestamp = match record[17] {
+            0 => None,
+            1 => Some(u64::from_le_bytes(record[18..26].try_into().unwrap())),
+            _ => return Err(invalid("Invalid timestamp flag in write-ahead log")),
+        };
+        records.push(txn);
+    }
+
+    Ok((base, records))
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{state_path, Wal};
+    use crate::{
+        state::Options,
+        types::{ClientId, Transaction, TransactionId, TransactionInner},
+    };
+    use std::io::Write;
+
+    #[test]
+    fn test_recover() {
+        let path = std::env::temp_dir().join(format!("wal-{}", std::process::id()));
+        let txn = |transaction_id, inner| {
+            Transaction::new(TransactionId(transaction_id), ClientId(1), inner)
+        };
+
+        let (mut wal, mut state) = Wal::recover(&path, Options::default()).unwrap();
+        for txn in [
+            txn(1, TransactionInner::Deposit(1.0.into())),
+            txn(2, TransactionInner::Deposit(2.0.into())),
+        ] {
+            wal.append(&txn).unwrap();
+            state.process(txn).unwrap();
+        }
+        wal.compact(&state).unwrap();
+        // Only in the log, so its timestamp has to come back from there
+        let deposit = Transaction {
+            timestamp: Some(1_700_000_000),
+            ..txn(4, TransactionInner::Deposit(0.25.into()))
+        };
+        for txn in [
+            Transaction {
+                reason: Some("10.4".into()),
+                ..txn(2, TransactionInner::Dispute)
+            },
+            txn(
+                3,
+                TransactionInner::Adjustment {
+                    amount: (-0.5).into(),
+                    reference: "OPS-1".into(),
+                },
+            ),
+            deposit,
+        ] {
+            wal.append(&txn).unwrap();
+            state.process(txn).unwrap();
+        }
+        drop(wal);
+
+        // A crash part way through appending a record
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[1, 2, 3])
+            .unwrap();
+
+        let (wal, recovered) = Wal::recover(&path, Options::default()).unwrap();
+        assert_eq!(wal.logged, 5);
+        assert_eq!(recovered.sorted_accounts(), state.sorted_accounts());
+        assert_eq!(recovered.account(ClientId(1)).unwrap().held(), 2.0.into());
+        assert_eq!(
+            recovered.transaction(TransactionId(2)).unwrap().reason,
+            Some("10.4".into())
+        );
+        assert_eq!(
+            recovered.account(ClientId(1)).unwrap().available(),
+            0.75.into()
+        );
+        assert_eq!(
+            recovered.transaction(TransactionId(4)).unwrap().timestamp,
+            Some(1_700_000_000)
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(state_path(&path)).unwrap();
+    }
+}